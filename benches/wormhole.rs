@@ -0,0 +1,129 @@
+//! Benchmarks for code generation, encryption and full transfers
+//!
+//! ```sh
+//! cargo bench --features all,benchmarks,testing
+//! ```
+//!
+//! Full transfers run against local servers, see [`magic_wormhole::testing`]. They measure the
+//! protocol overhead and the throughput over the loopback interface, not the network.
+
+use criterion::{
+    async_executor::AsyncStdExecutor, criterion_group, criterion_main, BenchmarkId, Criterion,
+    Throughput,
+};
+use magic_wormhole::{benchmarks, testing::TestServer, transfer, transit, Wormhole};
+
+fn codes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codes");
+    for num_words in [2, 4] {
+        let wordlist = benchmarks::Wordlist::new(num_words);
+        group.bench_with_input(
+            BenchmarkId::new("choose_words", num_words),
+            &wordlist,
+            |b, wordlist| b.iter(|| wordlist.choose_words()),
+        );
+    }
+    let wordlist = benchmarks::Wordlist::new(2);
+    for prefix in ["", "pu", "purple-sa"] {
+        group.bench_with_input(
+            BenchmarkId::new("get_completions", prefix),
+            prefix,
+            |b, prefix| b.iter(|| wordlist.get_completions(prefix)),
+        );
+    }
+    group.finish();
+}
+
+/** Encryption of differently sized chunks, a file transfer sends one per record */
+fn encryption(c: &mut Criterion) {
+    let key = [0x42; 32];
+    let mut group = c.benchmark_group("encryption");
+    for chunk_size in [512, 4096, 64 * 1024, 1024 * 1024] {
+        let plaintext = vec![0x23; chunk_size];
+        let encrypted = benchmarks::encrypt(&key, &plaintext);
+        group.throughput(Throughput::Bytes(chunk_size as u64));
+        group.bench_with_input(
+            BenchmarkId::new("encrypt", chunk_size),
+            &plaintext,
+            |b, plaintext| b.iter(|| benchmarks::encrypt(&key, plaintext)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("decrypt", chunk_size),
+            &encrypted,
+            |b, encrypted| b.iter(|| benchmarks::decrypt(&key, encrypted).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+async fn send_file(server: &TestServer, data: &[u8], abilities: transit::Abilities) {
+    let config = server.app_config(transfer::APP_CONFIG);
+    let pending = Wormhole::builder(config.clone())
+        .allocate_code()
+        .await
+        .unwrap();
+    let code = pending.code().clone();
+    let (sender, receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config).connect_with_code(code),
+    )
+    .unwrap();
+
+    let send = async {
+        #[allow(deprecated)]
+        transfer::send_file(
+            sender,
+            server.relay_hints(),
+            &mut &data[..],
+            "bench.bin",
+            data.len() as u64,
+            abilities,
+            |_info| {},
+            |_sent, _total| {},
+            futures::future::pending(),
+        )
+        .await
+    };
+    let receive = async {
+        let request = transfer::request_file(
+            receiver,
+            server.relay_hints(),
+            abilities,
+            futures::future::pending(),
+        )
+        .await?
+        .unwrap();
+        request
+            .accept(
+                |_info| {},
+                |_received, _total| {},
+                &mut futures::io::sink(),
+                futures::future::pending(),
+            )
+            .await
+    };
+    futures::try_join!(send, receive).unwrap();
+}
+
+fn loopback_transfer(c: &mut Criterion) {
+    let server = async_std::task::block_on(TestServer::start()).unwrap();
+    let mut group = c.benchmark_group("loopback_transfer");
+    group.sample_size(10);
+    for size in [0, 1024 * 1024, 16 * 1024 * 1024] {
+        let data = vec![0x23; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        for (name, abilities) in [
+            ("direct", transit::Abilities::FORCE_DIRECT),
+            ("relay", transit::Abilities::FORCE_RELAY),
+        ] {
+            group.bench_with_input(BenchmarkId::new(name, size), &data, |b, data| {
+                b.to_async(AsyncStdExecutor)
+                    .iter(|| send_file(&server, data, abilities))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, codes, encryption, loopback_transfer);
+criterion_main!(benches);
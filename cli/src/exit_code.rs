@@ -0,0 +1,106 @@
+use color_eyre::eyre;
+use magic_wormhole::{transfer::TransferError, WormholeError};
+
+/// Process exit codes of the CLI
+///
+/// These are part of the CLI's interface so that wrapper scripts can branch on the reason
+/// of a failure. Don't change the values of existing variants.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExitCode {
+    /// Any error that does not fit into one of the other categories
+    Error = 1,
+    /// Invalid command line usage. Also used by clap
+    Usage = 2,
+    /// The code does not exist (anymore), was already used by someone else, or is wrong
+    Code = 3,
+    /// The peer rejected the transfer or reported an error
+    PeerRejected = 4,
+    /// Could not establish a transit connection with the peer, or it broke
+    TransitFailed = 5,
+    /// The received data did not match what the peer sent
+    IntegrityMismatch = 6,
+    /// Some operation timed out
+    Timeout = 7,
+    /// The operation was cancelled with Ctrl-C
+    Cancelled = 130,
+}
+
+impl ExitCode {
+    /// Categorize an error by looking at the first well-known error type in its chain
+    pub fn from_error(error: &eyre::Report) -> Self {
+        error
+            .chain()
+            .find_map(|error| {
+                if let Some(error) = error.downcast_ref::<TransferError>() {
+                    Self::from_transfer_error(error)
+                } else if let Some(error) = error.downcast_ref::<WormholeError>() {
+                    Self::from_wormhole_error(error)
+                } else if let Some(error) = error.downcast_ref::<std::io::Error>() {
+                    (error.kind() == std::io::ErrorKind::TimedOut).then_some(Self::Timeout)
+                } else if error.is::<async_std::future::TimeoutError>() {
+                    Some(Self::Timeout)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(Self::Error)
+    }
+
+    fn from_transfer_error(error: &TransferError) -> Option<Self> {
+        match error {
+            TransferError::PeerError(_) | TransferError::AckError => Some(Self::PeerRejected),
+            TransferError::TransitConnect(_) | TransferError::Transit(_) => {
+                Some(Self::TransitFailed)
+            },
+            TransferError::Checksum
+            | TransferError::FileSize { .. }
+            | TransferError::FilesystemSkew => Some(Self::IntegrityMismatch),
+            /* Look further down the chain */
+            _ => None,
+        }
+    }
+
+    fn from_wormhole_error(error: &WormholeError) -> Option<Self> {
+        match error {
+            WormholeError::PakeFailed
+            | WormholeError::UnclaimedNameplate(_)
+            | WormholeError::InvalidCode(_) => Some(Self::Code),
+            WormholeError::ServerError(error) if error.is_crowded() => Some(Self::Code),
+            WormholeError::Crypto => Some(Self::IntegrityMismatch),
+            WormholeError::PeerClosed(_) => Some(Self::PeerRejected),
+            WormholeError::Timeout => Some(Self::Timeout),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExitCode> for i32 {
+    fn from(code: ExitCode) -> Self {
+        code as i32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use color_eyre::eyre::Context;
+
+    #[test]
+    fn test_exit_codes() {
+        let error = eyre::Report::new(TransferError::Checksum);
+        assert_eq!(ExitCode::from_error(&error), ExitCode::IntegrityMismatch);
+
+        let error: eyre::Result<()> =
+            Err(TransferError::PeerError("transfer rejected".into())).context("Send failed");
+        assert_eq!(
+            ExitCode::from_error(&error.unwrap_err()),
+            ExitCode::PeerRejected
+        );
+
+        let error = eyre::Report::new(TransferError::Wormhole(WormholeError::PakeFailed));
+        assert_eq!(ExitCode::from_error(&error), ExitCode::Code);
+
+        let error = eyre::format_err!("Something else");
+        assert_eq!(ExitCode::from_error(&error), ExitCode::Error);
+    }
+}
@@ -1,4 +1,7 @@
 #![allow(clippy::too_many_arguments)]
+mod exit_code;
+#[cfg(feature = "tui")]
+mod tui;
 mod util;
 
 use std::time::{Duration, Instant};
@@ -10,15 +13,18 @@ use console::{style, Term};
 use futures::{future::Either, Future, FutureExt};
 use indicatif::{MultiProgress, ProgressBar};
 use magic_wormhole::{
-    forwarding, transfer,
+    forwarding,
+    seeds::{Seed, SeedStore},
+    transfer,
     transit::{self, TransitInfo},
     MailboxConnection, Wormhole,
 };
-use std::{io::Write, path::PathBuf};
-use tracing_subscriber::EnvFilter;
+use std::{io::Write, num::NonZeroU64, path::PathBuf};
+use tracing_subscriber::{
+    filter::Targets, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
 
-#[cfg(feature = "clipboard")]
-use arboard::Clipboard;
+use exit_code::ExitCode;
 
 fn install_ctrlc_handler(
 ) -> eyre::Result<impl Fn() -> futures::future::BoxFuture<'static, ()> + Clone> {
@@ -67,7 +73,6 @@ struct CommonSenderArgs {
     file_name: Option<String>,
     #[arg(
         index = 1,
-        required = true,
         num_args = 1..,
         value_name = "FILENAME|DIRNAME",
         value_hint = clap::ValueHint::AnyPath,
@@ -84,6 +89,9 @@ struct CommonLeaderArgs {
     /// Length of code (in bytes/words)
     #[arg(short = 'c', long, value_name = "NUMWORDS", default_value = "2")]
     code_length: usize,
+    /// Don't copy the generated code to the clipboard
+    #[arg(long = "no-copy-code", action = clap::ArgAction::SetFalse)]
+    copy_code: bool,
 }
 
 // receive
@@ -102,6 +110,22 @@ struct CommonFollowerArgs {
     code: Option<String>,
 }
 
+// send, send-text, receive
+#[derive(Debug, Default, Args)]
+struct CommonSeedArgs {
+    /// Connect to a peer remembered with --remember-as, instead of using a code
+    #[arg(
+        long = "to",
+        visible_alias = "from",
+        value_name = "NAME",
+        conflicts_with = "code"
+    )]
+    peer: Option<String>,
+    /// Remember the peer under this name, to connect again later without a code
+    #[arg(long, value_name = "NAME", conflicts_with = "peer")]
+    remember_as: Option<String>,
+}
+
 // send, send-mane, receive, serve, connect
 #[derive(Debug, Clone, Args)]
 struct CommonArgs {
@@ -117,12 +141,25 @@ struct CommonArgs {
     /// Use a custom rendezvous server. Both sides need to use the same value in order to find each other.
     #[arg(long, value_name = "ws://example.org", value_hint = clap::ValueHint::Url)]
     rendezvous_server: Option<url::Url>,
+    /// Use a custom application ID instead of the default one for this subcommand. Both sides need to use the same value in order to find each other.
+    /// This is mostly useful for testing other applications and protocols.
+    #[arg(long, value_name = "APPID", value_hint = clap::ValueHint::Other)]
+    appid: Option<String>,
     /// Disable the relay server support and force a direct connection.
     #[arg(long)]
     force_direct: bool,
     /// Always route traffic over a relay server. This hides your IP address from the peer (but not from the server operators. Use Tor for that).
     #[arg(long, conflicts_with = "force_direct")]
     force_relay: bool,
+    /// Limit the upload speed of the transfer, in KiB per second.
+    #[arg(long, value_name = "KIB_PER_SECOND")]
+    max_upload_rate: Option<NonZeroU64>,
+    /// Limit the download speed of the transfer, in KiB per second.
+    #[arg(long, value_name = "KIB_PER_SECOND")]
+    max_download_rate: Option<NonZeroU64>,
+    /// Lowercase the words of the entered code and accept spaces between them. Both sides derive the key from the exact code, so this only helps if the other side's code is normalized.
+    #[arg(long)]
+    normalize_code: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -166,30 +203,80 @@ enum ForwardCommand {
     },
 }
 
+#[derive(Debug, Subcommand)]
+#[command(arg_required_else_help = true)]
+enum SeedsCommand {
+    /// List the remembered peers
+    #[command(visible_alias = "ls")]
+    List,
+    /// Forget a peer. You need to exchange a code again to connect to them.
+    #[command(visible_alias = "rm")]
+    Remove {
+        /// The name the peer was remembered as
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+}
+
 #[derive(Debug, Subcommand)]
 enum WormholeCommand {
     /// Send a file or a folder
-    #[command(visible_alias = "tx")]
+    #[command(
+        visible_alias = "tx",
+        mut_arg("files", |arg| arg.required_unless_present_any(["text", "clipboard"]))
+    )]
     Send {
+        /// Send a short text message instead, like the send-text subcommand
+        #[arg(long, value_name = "TEXT", conflicts_with_all = ["files", "file_name"])]
+        text: Option<String>,
+        /// Send the text contents of the clipboard, like `send-text --clipboard`
+        #[arg(long, conflicts_with_all = ["files", "file_name", "text"])]
+        clipboard: bool,
         #[clap(flatten)]
         common: CommonArgs,
         #[clap(flatten)]
         common_leader: CommonLeaderArgs,
         #[clap(flatten)]
         common_send: CommonSenderArgs,
+        #[clap(flatten)]
+        common_seed: CommonSeedArgs,
+    },
+    /// Send a short text message
+    #[command()]
+    SendText {
+        /// The text to send
+        #[arg(value_name = "TEXT", required_unless_present = "clipboard")]
+        text: Option<String>,
+        /// Send the text contents of the clipboard
+        #[arg(long, conflicts_with = "text")]
+        clipboard: bool,
+        #[command(flatten)]
+        common: CommonArgs,
+        #[command(flatten)]
+        common_leader: CommonLeaderArgs,
+        #[command(flatten)]
+        common_seed: CommonSeedArgs,
     },
-    /// Receive a file or a folder
+    /// Receive a file, a folder or a text message
     #[command(visible_alias = "rx")]
     Receive {
         /// Accept file transfer without asking for confirmation
         #[arg(long, visible_alias = "yes")]
         noconfirm: bool,
+        /// Only accept text messages, reject any file offer
+        #[arg(long)]
+        only_text: bool,
+        /// Copy received text messages to the clipboard instead of printing them
+        #[arg(long)]
+        to_clipboard: bool,
         #[command(flatten)]
         common: CommonArgs,
         #[command(flatten)]
         common_follower: CommonFollowerArgs,
         #[command(flatten)]
         common_receiver: CommonReceiverArgs,
+        #[command(flatten)]
+        common_seed: CommonSeedArgs,
     },
     /// Send a file to many recipients
     #[command(
@@ -199,7 +286,8 @@ enum WormholeCommand {
         countered by using a longer than usual code (default 4 bytes entropy).\n\n\
         The application terminates on interruption, after a timeout or after a
         number of sent files, whichever comes first. It will always try to send
-        at least one file, regardless of the limits."
+        at least one file, regardless of the limits.",
+        mut_arg("files", |arg| arg.required(true))
     )]
     SendMany {
         /// Only send the file up to n times, limiting the number of people that may receive it.
@@ -219,6 +307,41 @@ enum WormholeCommand {
     /// Forward ports from one machine to another
     #[command(subcommand)]
     Forward(ForwardCommand),
+    /// Manage the peers remembered with --remember-as
+    #[command(subcommand)]
+    Seeds(SeedsCommand),
+    /// Run a rendezvous server and optionally a transit relay, for self-hosting
+    #[cfg(feature = "server")]
+    #[command()]
+    Serve {
+        /// The address to listen on for clients
+        #[arg(long, value_name = "ADDRESS", default_value = "0.0.0.0:4000")]
+        listen: String,
+        /// Also run a transit relay, listening on this address
+        #[arg(long, value_name = "ADDRESS")]
+        relay_listen: Option<String>,
+        /// Only run the transit relay, without a rendezvous server
+        #[arg(long, requires = "relay_listen")]
+        relay_only: bool,
+        /// Close relayed connections once they sent more than this many bytes
+        #[arg(long, value_name = "BYTES", requires = "relay_listen")]
+        relay_max_bytes: Option<u64>,
+        /// Message of the day, shown to users by most clients
+        #[arg(long)]
+        motd: Option<String>,
+        /// Advertise this as the current version of the Python CLI, so that older clients suggest upgrading
+        #[arg(long, value_name = "VERSION")]
+        advertise_version: Option<String>,
+        /// Send this error to all clients, which makes them refuse to continue
+        #[arg(long, value_name = "MESSAGE")]
+        signal_error: Option<String>,
+        /// Keep nameplates, mailboxes and statistics in this file across restarts
+        #[arg(long, value_name = "FILE")]
+        state_file: Option<PathBuf>,
+        /// Delete mailboxes nobody used for that long
+        #[arg(long, value_name = "MINUTES", default_value = "660")]
+        expiry: u64,
+    },
     /// Generate shell completions for the wormhole CLI
     #[command(hide = true)]
     Completion {
@@ -251,6 +374,13 @@ struct WormholeCli {
         display_order = 100
     )]
     log: bool,
+    /// Show a full-screen terminal interface instead of log output. Only supported when sending or receiving.
+    #[arg(long, global = true, display_order = 101)]
+    #[cfg_attr(not(feature = "tui"), arg(hide = true))]
+    tui: bool,
+    /// Write a trace of the protocol to this file, for attaching it to bug reports. It contains no keys or message contents.
+    #[arg(long, global = true, value_name = "FILE", display_order = 102)]
+    protocol_trace: Option<PathBuf>,
     #[clap(subcommand)]
     command: WormholeCommand,
 }
@@ -262,41 +392,133 @@ async fn main() -> eyre::Result<()> {
 
     let app = WormholeCli::parse();
 
-    let mut term = Term::stdout();
-
-    if app.log {
-        tracing_subscriber::fmt()
-            .with_max_level(tracing::Level::TRACE)
-            .with_env_filter(EnvFilter::new(
-                "magic_wormhole::core=trace,mio=debug,ws=error",
-            ))
+    /* Any log output would mess up the terminal interface */
+    let console = (!app.tui).then(|| {
+        let filter = if app.log {
+            EnvFilter::new("magic_wormhole::core=trace,mio=debug,ws=error")
+        } else {
+            EnvFilter::new("mio=debug")
+        };
+        tracing_subscriber::fmt::layer()
             .with_target(false)
-            .init();
+            .with_filter(filter)
+    });
+    let protocol_trace = match &app.protocol_trace {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Could not create protocol trace {}", path.display()))?;
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(std::sync::Mutex::new(file))
+                    .with_ansi(false)
+                    .with_filter(
+                        Targets::new()
+                            .with_target("magic_wormhole::protocol", tracing::Level::TRACE),
+                    ),
+            )
+        },
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(console)
+        .with(protocol_trace)
+        .init();
+    if app.log {
         tracing::trace!("Logging enabled.");
-    } else {
-        tracing_subscriber::fmt()
-            .with_max_level(tracing::Level::INFO)
-            .with_env_filter(EnvFilter::new("mio=debug"))
-            .with_target(false)
-            .init();
+    }
+
+    let result = run(app.command, app.tui, Term::stdout(), ctrl_c.clone()).await;
+
+    /* Most operations end gracefully when cancelled, so check the Ctrl-C state ourselves */
+    if ctrl_c().now_or_never().is_some() {
+        if let Err(error) = result {
+            tracing::debug!("Error after cancellation: {:?}", error);
+        }
+        std::process::exit(ExitCode::Cancelled.into());
+    }
+    if let Err(error) = result {
+        eprintln!("Error: {:?}", error);
+        std::process::exit(ExitCode::from_error(&error).into());
+    }
+
+    Ok(())
+}
+
+async fn run(
+    command: WormholeCommand,
+    tui: bool,
+    mut term: Term,
+    ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()> + Clone,
+) -> eyre::Result<()> {
+    /* `send --text` like in the Python CLI, and `send --clipboard` */
+    let command = match command {
+        WormholeCommand::Send {
+            text,
+            clipboard,
+            common,
+            common_leader,
+            common_seed,
+            ..
+        } if text.is_some() || clipboard => WormholeCommand::SendText {
+            text,
+            clipboard,
+            common,
+            common_leader,
+            common_seed,
+        },
+        command => command,
     };
+    eyre::ensure!(
+        !tui || matches!(
+            command,
+            WormholeCommand::Send { .. } | WormholeCommand::Receive { .. }
+        ),
+        "The terminal interface is only supported by the send and receive commands"
+    );
 
-    match app.command {
+    match command {
         WormholeCommand::Send {
             common,
-            common_leader: CommonLeaderArgs { code, code_length },
+            common_leader:
+                CommonLeaderArgs {
+                    code,
+                    code_length,
+                    copy_code,
+                },
             common_send: CommonSenderArgs { file_name, files },
+            common_seed,
             ..
         } => {
             let offer = make_send_offer(files, file_name).await?;
 
             let transit_abilities = parse_transit_args(&common);
+            if tui {
+                eyre::ensure!(
+                    common_seed.peer.is_none() && common_seed.remember_as.is_none(),
+                    "Remembered peers are not supported by the terminal interface"
+                );
+                #[cfg(feature = "tui")]
+                return Box::pin(tui::send(
+                    common,
+                    code,
+                    code_length,
+                    copy_code,
+                    offer,
+                    transit_abilities,
+                    ctrl_c,
+                ))
+                .await;
+                #[cfg(not(feature = "tui"))]
+                eyre::bail!("wormhole-rs was built without the terminal interface (feature `tui`)");
+            }
             let (wormhole, _code, relay_hints) = match util::cancellable(
                 Box::pin(parse_and_connect(
                     &mut term,
                     common,
+                    Some(common_seed),
                     code,
                     Some(code_length),
+                    copy_code,
                     true,
                     transfer::APP_CONFIG,
                     Some(&sender_print_code),
@@ -318,22 +540,74 @@ async fn main() -> eyre::Result<()> {
             ))
             .await?;
         },
+        WormholeCommand::SendText {
+            text,
+            clipboard,
+            common,
+            common_leader:
+                CommonLeaderArgs {
+                    code,
+                    code_length,
+                    copy_code,
+                },
+            common_seed,
+        } => {
+            let text = match text {
+                Some(text) => text,
+                None => {
+                    debug_assert!(clipboard, "Already checked by CLI parser");
+                    util::read_clipboard()?
+                },
+            };
+            let (wormhole, _code, _relay_hints) = match util::cancellable(
+                Box::pin(parse_and_connect(
+                    &mut term,
+                    common,
+                    Some(common_seed),
+                    code,
+                    Some(code_length),
+                    copy_code,
+                    true,
+                    transfer::APP_CONFIG,
+                    Some(&sender_print_code),
+                )),
+                ctrl_c(),
+            )
+            .await
+            {
+                Ok(result) => result?,
+                Err(_) => return Ok(()),
+            };
+
+            transfer::send_text(wormhole, text, ctrl_c())
+                .await
+                .context("Send process failed")?;
+            writeln!(term, "Text message sent")?;
+        },
         #[allow(unused_variables)]
         WormholeCommand::SendMany {
             tries,
             timeout,
             common,
-            common_leader: CommonLeaderArgs { code, code_length },
+            common_leader:
+                CommonLeaderArgs {
+                    code,
+                    code_length,
+                    copy_code,
+                },
             common_send: CommonSenderArgs { file_name, files },
             ..
         } => {
             let transit_abilities = parse_transit_args(&common);
+            let app_config = make_app_config(&common, transfer::APP_CONFIG);
             let (wormhole, code, relay_hints) = {
                 let connect_fut = Box::pin(parse_and_connect(
                     &mut term,
                     common,
+                    None,
                     code,
                     Some(code_length),
+                    copy_code,
                     true,
                     transfer::APP_CONFIG,
                     Some(&sender_print_code),
@@ -347,6 +621,7 @@ async fn main() -> eyre::Result<()> {
 
             Box::pin(send_many(
                 relay_hints,
+                app_config,
                 &code,
                 files,
                 file_name,
@@ -361,19 +636,44 @@ async fn main() -> eyre::Result<()> {
         },
         WormholeCommand::Receive {
             noconfirm,
+            only_text,
+            to_clipboard,
             common,
             common_follower: CommonFollowerArgs { code },
             common_receiver: CommonReceiverArgs { file_path },
-            ..
+            common_seed,
         } => {
             let transit_abilities = parse_transit_args(&common);
+            if tui {
+                eyre::ensure!(
+                    common_seed.peer.is_none() && common_seed.remember_as.is_none(),
+                    "Remembered peers are not supported by the terminal interface"
+                );
+                #[cfg(feature = "tui")]
+                return Box::pin(tui::receive(
+                    term,
+                    common,
+                    code,
+                    &file_path,
+                    noconfirm,
+                    only_text,
+                    to_clipboard,
+                    transit_abilities,
+                    ctrl_c,
+                ))
+                .await;
+                #[cfg(not(feature = "tui"))]
+                eyre::bail!("wormhole-rs was built without the terminal interface (feature `tui`)");
+            }
             let (wormhole, _code, relay_hints) = {
                 let connect_fut = Box::pin(parse_and_connect(
                     &mut term,
                     common,
+                    Some(common_seed),
                     code,
                     None,
                     false,
+                    false,
                     transfer::APP_CONFIG,
                     None,
                 ));
@@ -388,6 +688,8 @@ async fn main() -> eyre::Result<()> {
                 relay_hints,
                 &file_path,
                 noconfirm,
+                only_text,
+                to_clipboard,
                 transit_abilities,
                 ctrl_c,
             ))
@@ -396,7 +698,12 @@ async fn main() -> eyre::Result<()> {
         WormholeCommand::Forward(ForwardCommand::Serve {
             targets,
             common,
-            common_leader: CommonLeaderArgs { code, code_length },
+            common_leader:
+                CommonLeaderArgs {
+                    code,
+                    code_length,
+                    copy_code,
+                },
             ..
         }) => {
             // TODO make fancy
@@ -444,8 +751,10 @@ async fn main() -> eyre::Result<()> {
                 let connect_fut = Box::pin(parse_and_connect(
                     &mut term,
                     common.clone(),
+                    None,
                     code.clone(),
                     Some(code_length),
+                    copy_code,
                     true,
                     app_config,
                     Some(&server_print_code),
@@ -476,8 +785,10 @@ async fn main() -> eyre::Result<()> {
             tracing::warn!("This is an unstable feature. Make sure that your peer is running the exact same version of the program as you. Also, please report all bugs and crashes.");
             let mut app_config = forwarding::APP_CONFIG;
             app_config.app_version.transit_abilities = parse_transit_args(&common);
-            let (wormhole, _code, relay_hints) =
-                parse_and_connect(&mut term, common, code, None, false, app_config, None).await?;
+            let (wormhole, _code, relay_hints) = parse_and_connect(
+                &mut term, common, None, code, None, false, false, app_config, None,
+            )
+            .await?;
 
             let offer = forwarding::connect(
                 wormhole,
@@ -498,6 +809,95 @@ async fn main() -> eyre::Result<()> {
                 offer.reject().await?;
             }
         },
+        WormholeCommand::Seeds(command) => {
+            let path = util::seeds_path()?;
+            let mut seeds =
+                SeedStore::load(&path).context("Failed to read the remembered peers")?;
+            match command {
+                SeedsCommand::List => {
+                    for name in seeds.names() {
+                        writeln!(term, "{name}")?;
+                    }
+                },
+                SeedsCommand::Remove { name } => {
+                    eyre::ensure!(seeds.remove(&name).is_some(), "Unknown peer '{name}'");
+                    seeds
+                        .save(&path)
+                        .context("Failed to save the remembered peers")?;
+                    writeln!(term, "Forgot {}", style(&name).bold())?;
+                },
+            }
+        },
+        #[cfg(feature = "server")]
+        WormholeCommand::Serve {
+            listen,
+            relay_listen,
+            relay_only,
+            relay_max_bytes,
+            motd,
+            advertise_version,
+            signal_error,
+            state_file,
+            expiry,
+        } => {
+            use magic_wormhole::server::{relay, rendezvous};
+
+            let relay_server = relay::RelayServer::new(relay::Config {
+                max_bytes_per_connection: relay_max_bytes,
+                ..relay::Config::default()
+            });
+            let relay_listener = match &relay_listen {
+                Some(relay_listen) => Some(
+                    async_std::net::TcpListener::bind(relay_listen)
+                        .await
+                        .with_context(|| format!("Could not listen on {}", relay_listen))?,
+                ),
+                None => None,
+            };
+            let (mailbox_server, listener) = if relay_only {
+                (None, None)
+            } else {
+                let server = rendezvous::MailboxServer::new(rendezvous::Config {
+                    motd,
+                    advertise_version,
+                    signal_error,
+                    state_file,
+                    expiry: Duration::from_secs(expiry * 60),
+                    ..rendezvous::Config::default()
+                })
+                .context("Could not load the state file")?;
+                let listener = async_std::net::TcpListener::bind(&listen)
+                    .await
+                    .with_context(|| format!("Could not listen on {}", listen))?;
+                (Some(server), Some(listener))
+            };
+
+            let serving = async {
+                match (&mailbox_server, listener) {
+                    (Some(server), Some(listener)) => Ok(server.serve(listener).await?),
+                    _ => futures::future::pending::<eyre::Result<()>>().await,
+                }
+            };
+            let relaying = async {
+                match relay_listener {
+                    Some(listener) => Ok(relay_server.serve(listener).await?),
+                    None => futures::future::pending::<eyre::Result<()>>().await,
+                }
+            };
+            let servers = futures::future::try_join(serving, relaying);
+            futures::pin_mut!(servers);
+            if let Either::Left((result, _)) = futures::future::select(servers, ctrl_c()).await {
+                result?;
+            }
+
+            if let Some(server) = &mailbox_server {
+                server.save().context("Could not write the state file")?;
+                tracing::info!("Shutting down: {:?}", server.stats());
+            }
+            if relay_listen.is_some() {
+                tracing::info!("Shutting down: {:?}", relay_server.stats());
+            }
+        },
         WormholeCommand::Completion { shell } => {
             let mut cmd = WormholeCli::command();
             let binary_name = cmd.get_name().to_string();
@@ -527,7 +927,7 @@ async fn main() -> eyre::Result<()> {
         },
         WormholeCommand::Help => {
             println!("Use --help to get help");
-            std::process::exit(2);
+            std::process::exit(ExitCode::Usage.into());
         },
     }
 
@@ -543,6 +943,30 @@ fn parse_transit_args(args: &CommonArgs) -> transit::Abilities {
     }
 }
 
+fn parse_rate_limit(args: &CommonArgs) -> transit::RateLimit {
+    let kib = |rate: NonZeroU64| rate.saturating_mul(NonZeroU64::new(1024).unwrap());
+    transit::RateLimit {
+        upload: args.max_upload_rate.map(kib),
+        download: args.max_download_rate.map(kib),
+    }
+}
+
+/**
+ * Apply the server and app ID related command line arguments to an [`AppConfig`](magic_wormhole::AppConfig)
+ */
+fn make_app_config<V>(
+    common_args: &CommonArgs,
+    mut app_config: magic_wormhole::AppConfig<V>,
+) -> magic_wormhole::AppConfig<V> {
+    if let Some(rendezvous_server) = &common_args.rendezvous_server {
+        app_config = app_config.rendezvous_url(rendezvous_server.to_string().into());
+    }
+    if let Some(appid) = &common_args.appid {
+        app_config = app_config.id(magic_wormhole::AppID::new(appid.clone()));
+    }
+    app_config
+}
+
 type PrintCodeFn = dyn Fn(&mut Term, &magic_wormhole::Code, &Option<url::Url>) -> eyre::Result<()>;
 
 /**
@@ -551,17 +975,24 @@ type PrintCodeFn = dyn Fn(&mut Term, &magic_wormhole::Code, &Option<url::Url>) -
  *
  * If this `is_send` and the code is not specified via the CLI, then a code will be allocated.
  * Otherwise, the user will be prompted interactively to enter it.
+ *
+ * With `copy_code`, an allocated code will also be copied to the clipboard.
  */
 #[allow(deprecated)]
 async fn parse_and_connect(
     term: &mut Term,
     common_args: CommonArgs,
+    seed_args: Option<CommonSeedArgs>,
     code: Option<String>,
     code_length: Option<usize>,
+    copy_code: bool,
     is_send: bool,
-    mut app_config: magic_wormhole::AppConfig<impl serde::Serialize + Send + Sync + 'static>,
+    app_config: magic_wormhole::AppConfig<impl serde::Serialize + Send + Sync + 'static>,
     print_code: Option<&PrintCodeFn>,
 ) -> eyre::Result<(Wormhole, magic_wormhole::Code, Vec<transit::RelayHint>)> {
+    let app_config = make_app_config(&common_args, app_config);
+    let rate_limit = parse_rate_limit(&common_args);
+    let normalize_code = common_args.normalize_code;
     // TODO handle relay servers with multiple endpoints better
     let mut relay_hints: Vec<transit::RelayHint> = common_args
         .relay_server
@@ -576,18 +1007,19 @@ async fn parse_and_connect(
                 .unwrap()],
         )?)
     }
+    let CommonSeedArgs { peer, remember_as } = seed_args.unwrap_or_default();
+    if let Some(peer) = peer {
+        let (mut wormhole, code) = connect_to_peer(term, &peer, app_config).await?;
+        wormhole.set_rate_limit(rate_limit);
+        return Ok((wormhole, code, relay_hints));
+    }
+
     let code = code
-        .map(Result::Ok)
-        .or_else(|| (!is_send).then(enter_code))
-        .transpose()?
-        .map(magic_wormhole::Code);
+        .map(|code| parse_code(&code, normalize_code))
+        .transpose()?;
 
     /* We need to track that information for when we generate a QR code */
-    let mut uri_rendezvous = None;
-    if let Some(rendezvous_server) = common_args.rendezvous_server {
-        uri_rendezvous = Some(rendezvous_server.clone());
-        app_config = app_config.rendezvous_url(rendezvous_server.to_string().into());
-    }
+    let uri_rendezvous = common_args.rendezvous_server.clone();
     let mailbox_connection = match code {
         Some(code) => {
             if is_send {
@@ -597,27 +1029,27 @@ async fn parse_and_connect(
                     &uri_rendezvous,
                 )?;
             }
-            MailboxConnection::connect(app_config, code, true).await?
+            /* Only the sending side may create the nameplate, a receiver has probably mistyped it */
+            MailboxConnection::connect(app_config, code, is_send).await?
+        },
+        None if !is_send => {
+            let pending = enter_code(term, app_config, normalize_code).await?;
+            let code = pending.code().clone();
+            let mut wormhole = pending.wait_for_peer().await?;
+            wormhole.set_rate_limit(rate_limit);
+            remember_peer(term, &wormhole, remember_as)?;
+            return Ok((wormhole, code, relay_hints));
         },
         None => {
             let mailbox_connection =
                 MailboxConnection::create(app_config, code_length.unwrap()).await?;
 
-            /* Print code and also copy it to clipboard */
+            /* Print code and maybe also copy it to clipboard */
             if is_send {
-                #[cfg(feature = "clipboard")]
-                {
-                    let clipboard = Clipboard::new()
-                        .map_err(|err| {
-                            tracing::warn!("Failed to initialize clipboard support: {}", err);
-                        })
-                        .ok();
-
-                    if let Some(mut clipboard) = clipboard {
-                        match clipboard.set_text(mailbox_connection.code().to_string()) {
-                            Ok(()) => tracing::info!("Code copied to clipboard"),
-                            Err(err) => tracing::warn!("Failed to copy code to clipboard: {}", err),
-                        }
+                if copy_code {
+                    match util::copy_to_clipboard(mailbox_connection.code().to_string()) {
+                        Ok(()) => tracing::info!("Code copied to clipboard"),
+                        Err(err) => tracing::warn!("{:#}", err),
                     }
                 }
 
@@ -632,10 +1064,64 @@ async fn parse_and_connect(
     };
     print_welcome(term, mailbox_connection.welcome())?;
     let code = mailbox_connection.code().clone();
-    let wormhole = Wormhole::connect(mailbox_connection).await?;
+    let mut wormhole = Wormhole::connect(mailbox_connection).await?;
+    wormhole.set_rate_limit(rate_limit);
+    remember_peer(term, &wormhole, remember_as)?;
     eyre::Result::<_>::Ok((wormhole, code, relay_hints))
 }
 
+/** Remember the peer under `name`, as requested with `--remember-as` */
+fn remember_peer(term: &mut Term, wormhole: &Wormhole, name: Option<String>) -> eyre::Result<()> {
+    if let Some(name) = name {
+        let path = util::seeds_path()?;
+        let mut seeds = SeedStore::load(&path).context("Failed to read the remembered peers")?;
+        seeds.insert(name.clone(), Seed::new(wormhole));
+        seeds
+            .save(&path)
+            .context("Failed to save the remembered peers")?;
+        writeln!(
+            term,
+            "Remembered the peer as {}. Next time, connect with --to {0} or --from {0}",
+            style(&name).bold()
+        )?;
+    }
+    Ok(())
+}
+
+/**
+ * Connect to a peer remembered with `--remember-as`, and rotate the seed shared with them
+ *
+ * Both sides rotate the seed once they are connected, so the next connection uses a new mailbox.
+ * The returned code is derived from the seed and must not be shown.
+ */
+async fn connect_to_peer(
+    term: &mut Term,
+    peer: &str,
+    app_config: magic_wormhole::AppConfig<impl serde::Serialize + Send + Sync + 'static>,
+) -> eyre::Result<(Wormhole, magic_wormhole::Code)> {
+    let path = util::seeds_path()?;
+    let mut seeds = SeedStore::load(&path).context("Failed to read the remembered peers")?;
+    let mut seed = seeds.get(peer).cloned().ok_or_else(|| {
+        eyre::format_err!(
+            "Unknown peer '{peer}'. Connect with a code and --remember-as {peer} first"
+        )
+    })?;
+
+    let server = Wormhole::builder(app_config).connect_to_server().await?;
+    print_welcome(term, server.welcome())?;
+    let pending = server.open_seed(&seed).await?;
+    writeln!(term, "Waiting for {} to connect…", style(peer).bold())?;
+    let code = pending.code().clone();
+    let wormhole = pending.wait_for_peer().await?;
+
+    seed.rotate(&wormhole);
+    seeds.insert(peer, seed);
+    seeds
+        .save(&path)
+        .context("Failed to save the remembered peers")?;
+    Ok((wormhole, code))
+}
+
 async fn make_send_offer(
     mut files: Vec<PathBuf>,
     file_name: Option<String>,
@@ -710,13 +1196,70 @@ fn create_progress_handler(pb: ProgressBar) -> impl FnMut(u64, u64) {
     }
 }
 
-fn enter_code() -> eyre::Result<String> {
-    use dialoguer::Input;
+/**
+ * Check the structure of a code entered by the user
+ *
+ * Codes don't need to come from the wordlist, so unknown words are only a warning. The key is
+ * derived from the exact code, so it is only normalized with `normalize`. Otherwise we only warn
+ * if normalizing would change it.
+ */
+fn parse_code(code: &str, normalize: bool) -> eyre::Result<magic_wormhole::Code> {
+    use magic_wormhole::{wordlist::default_wordlist, Code, CodeParseError};
+
+    let normalized = match Code::parse_with_wordlist(code, &default_wordlist(2)) {
+        Err(CodeParseError::UnknownWord { word, suggestion }) => {
+            match suggestion {
+                Some(suggestion) => {
+                    tracing::warn!("'{word}' is not in the wordlist. Did you mean '{suggestion}'?")
+                },
+                None => tracing::warn!("'{word}' is not in the wordlist"),
+            }
+            Code::parse(code)?
+        },
+        result => result?,
+    };
+    if normalize {
+        Ok(normalized)
+    } else {
+        if normalized.as_ref() != code {
+            tracing::warn!(
+                "The code contains uppercase letters or spaces, which the other side probably didn't type. Use --normalize-code to remove them"
+            );
+        }
+        Ok(Code::from(code.to_owned()))
+    }
+}
+
+/**
+ * Connect to the server and let the user enter the code
+ *
+ * Tab completes the nameplates currently claimed on the server, then the words. Claiming fails
+ * if the nameplate does not exist, instead of waiting for a peer that will never come.
+ */
+async fn enter_code<V: serde::Serialize + Send + Sync + 'static>(
+    term: &mut Term,
+    app_config: magic_wormhole::AppConfig<V>,
+    normalize_code: bool,
+) -> eyre::Result<magic_wormhole::PendingWormhole<V>> {
+    use magic_wormhole::{completer::CodeCompleter, wordlist::default_wordlist};
+    use rustyline::{history::DefaultHistory, Editor};
+
+    let mut server = Wormhole::builder(app_config).connect_to_server().await?;
+    print_welcome(term, server.welcome())?;
+    let completer = CodeCompleter::new(default_wordlist(2));
+    if let Err(err) = completer.refresh_nameplates(&mut server).await {
+        tracing::warn!("Failed to list the nameplates for completion: {}", err);
+    }
 
-    Input::new()
-        .with_prompt("Enter code")
-        .interact_text()
-        .map_err(From::from)
+    let code = async_std::task::spawn_blocking(move || {
+        let mut editor = Editor::<CodeCompleter, DefaultHistory>::new()?;
+        editor.set_helper(Some(completer));
+        editor.readline("Enter code: ")
+    })
+    .await?;
+    Ok(server
+        .claim_code(parse_code(code.trim(), normalize_code)?)
+        .await?)
 }
 
 fn print_welcome(term: &mut Term, welcome: Option<&str>) -> eyre::Result<()> {
@@ -739,15 +1282,7 @@ fn sender_print_code(
     }
     .to_string();
 
-    if cfg!(feature = "clipboard") {
-        writeln!(
-            term,
-            "\nThis wormhole's code is: {} (it has been copied to your clipboard)",
-            style(&code).bold()
-        )?;
-    } else {
-        writeln!(term, "\nThis wormhole's code is: {}", style(&code).bold())?;
-    }
+    writeln!(term, "\nThis wormhole's code is: {}", style(&code).bold())?;
 
     writeln!(term, "This is equivalent to the following link: \u{001B}]8;;{}\u{001B}\\{}\u{001B}]8;;\u{001B}\\", &uri, &uri)?;
     let qr =
@@ -773,15 +1308,7 @@ fn server_print_code(
     code: &magic_wormhole::Code,
     _: &Option<url::Url>,
 ) -> eyre::Result<()> {
-    if cfg!(feature = "clipboard") {
-        writeln!(
-            term,
-            "\nThis wormhole's code is: {} (it has been copied to your clipboard)",
-            style(&code).bold()
-        )?;
-    } else {
-        writeln!(term, "\nThis wormhole's code is: {}", style(&code).bold())?;
-    }
+    writeln!(term, "\nThis wormhole's code is: {}", style(&code).bold())?;
 
     writeln!(
         term,
@@ -822,6 +1349,7 @@ async fn send(
 
 async fn send_many(
     relay_hints: Vec<transit::RelayHint>,
+    app_config: magic_wormhole::AppConfig<transfer::AppVersion>,
     code: &magic_wormhole::Code,
     files: Vec<PathBuf>,
     file_name: Option<String>,
@@ -867,7 +1395,7 @@ async fn send_many(
         }
 
         let wormhole = Wormhole::connect(
-            MailboxConnection::connect(transfer::APP_CONFIG, code.clone(), false).await?,
+            MailboxConnection::connect(app_config.clone(), code.clone(), false).await?,
         )
         .await?;
 
@@ -932,28 +1460,55 @@ async fn receive(
     relay_hints: Vec<transit::RelayHint>,
     target_dir: &std::path::Path,
     noconfirm: bool,
+    only_text: bool,
+    to_clipboard: bool,
     transit_abilities: transit::Abilities,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
 ) -> eyre::Result<()> {
     #[cfg(not(feature = "experimental-transfer-v2"))]
     {
-        let req = transfer::request_file(wormhole, relay_hints, transit_abilities, ctrl_c())
-            .await
-            .context("Could not get an offer")?;
+        let req =
+            transfer::request_text_or_file(wormhole, relay_hints, transit_abilities, ctrl_c())
+                .await
+                .context("Could not get an offer")?;
         /* If None, the task got cancelled */
-        if let Some(req) = req {
-            receive_inner_v1(req, target_dir, noconfirm, ctrl_c).await
-        } else {
-            Ok(())
+        match req {
+            Some(transfer::TextOrFileRequest::Text(text)) if to_clipboard => {
+                util::copy_to_clipboard(text)?;
+                tracing::info!("Received text message copied to clipboard");
+                Ok(())
+            },
+            Some(transfer::TextOrFileRequest::Text(text)) => {
+                println!("{}", text);
+                Ok(())
+            },
+            Some(transfer::TextOrFileRequest::File(req)) if only_text => {
+                tracing::warn!(
+                    "Rejecting file offer '{}', only accepting text",
+                    req.file_name()
+                );
+                req.reject().await.context("Could not reject offer")
+            },
+            Some(transfer::TextOrFileRequest::File(req)) => {
+                receive_inner_v1(req, target_dir, noconfirm, ctrl_c).await
+            },
+            Some(_) => eyre::bail!("Received an unsupported offer"),
+            None => Ok(()),
         }
     }
     #[cfg(feature = "experimental-transfer-v2")]
     {
+        /* Transfer v2 does not support text messages */
+        let _ = to_clipboard;
         let req = transfer::request(wormhole, relay_hints, transit_abilities, ctrl_c())
             .await
             .context("Could not get an offer")?;
 
         match req {
+            Some(req) if only_text => {
+                tracing::warn!("Rejecting file offer, only accepting text");
+                req.reject().await.context("Could not reject offer")
+            },
             Some(transfer::ReceiveRequest::V1(req)) => {
                 receive_inner_v1(req, target_dir, noconfirm, ctrl_c).await
             },
@@ -961,19 +1516,40 @@ async fn receive(
             Some(transfer::ReceiveRequest::V2(req)) => {
                 receive_inner_v2(req, target_dir, noconfirm, ctrl_c).await
             },
+            Some(_) => eyre::bail!("Received an unsupported offer"),
             None => Ok(()),
         }
     }
 }
 
+/**
+ * Where to store an offered file or directory
+ *
+ * Fails with the offered name if the other side picked a path outside of the target directory.
+ */
+fn destination(
+    req: &transfer::ReceiveRequestV1,
+    target_dir: &std::path::Path,
+) -> Result<PathBuf, String> {
+    let file_name = req.file_name();
+    let file_name = if req.is_directory() {
+        /* Directories get unpacked into a new directory, named without the `.zip` */
+        file_name.strip_suffix(".zip").unwrap_or(&file_name)
+    } else {
+        &file_name
+    };
+    match transfer::sanitize_file_name(file_name) {
+        Some(name) if name == file_name => Ok(target_dir.join(name)),
+        _ => Err(file_name.to_owned()),
+    }
+}
+
 async fn receive_inner_v1(
     req: transfer::ReceiveRequestV1,
     target_dir: &std::path::Path,
     noconfirm: bool,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
 ) -> eyre::Result<()> {
-    use async_std::fs::OpenOptions;
-
     /*
      * Control flow is a bit tricky here:
      * - First of all, we ask if we want to receive the file at all
@@ -1001,24 +1577,25 @@ async fn receive_inner_v1(
         return req.reject().await.context("Could not reject offer");
     }
 
-    // TODO validate untrusted input here
-    let file_path = std::path::Path::new(target_dir).join(req.file_name());
+    let file_path = match destination(&req, target_dir) {
+        Ok(file_path) => file_path,
+        Err(file_name) => {
+            req.reject().await.context("Could not reject offer")?;
+            eyre::bail!("Refusing to receive a file named {:?}", file_name);
+        },
+    };
 
     let pb = create_progress_bar(req.file_size());
 
-    /* Then, accept if the file exists */
-    if !file_path.exists() || noconfirm {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&file_path)
+    if req.is_directory() {
+        async_std::fs::create_dir(&file_path)
             .await
-            .context("Failed to create destination file")?;
+            .context("Failed to create destination directory")?;
         return req
-            .accept(
+            .accept_directory(
                 &transit_handler,
                 create_progress_handler(pb),
-                &mut file,
+                &file_path,
                 ctrl_c(),
             )
             .await
@@ -1026,29 +1603,28 @@ async fn receive_inner_v1(
     }
 
     /* If there is a collision, ask whether to overwrite */
-    if !util::ask_user(
-        format!("Override existing file {}?", file_path.display()),
-        false,
-    )
-    .await
+    let overwrite = file_path.exists() && !noconfirm;
+    if overwrite
+        && !util::ask_user(
+            format!("Override existing file {}?", file_path.display()),
+            false,
+        )
+        .await
     {
         return req.reject().await.context("Could not reject offer");
     }
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&file_path)
-        .await?;
-    req.accept(
+    /* The file is received next to its destination and only moved there once complete */
+    req.accept_to_path(
         &transit_handler,
         create_progress_handler(pb),
-        &mut file,
+        &file_path,
+        overwrite,
         ctrl_c(),
     )
     .await
-    .context("Receive process failed")
+    .context("Receive process failed")?;
+    Ok(())
 }
 
 #[cfg(feature = "experimental-transfer-v2")]
@@ -1063,36 +1639,54 @@ async fn receive_inner_v2(
     let offer_name = offer.offer_name();
 
     use number_prefix::NumberPrefix;
+    let format_size = |size: u64| match NumberPrefix::binary(size as f64) {
+        NumberPrefix::Standalone(bytes) => format!("{} bytes", bytes),
+        NumberPrefix::Prefixed(prefix, n) => format!("{:.1} {}B in size", n, prefix.symbol()),
+    };
+    /* `None` means all of them */
+    let mut selected_files = None;
     if !(noconfirm
         || util::ask_user(
-            format!(
-                "Receive {} ({})?",
-                offer_name,
-                match NumberPrefix::binary(file_size as f64) {
-                    NumberPrefix::Standalone(bytes) => format!("{} bytes", bytes),
-                    NumberPrefix::Prefixed(prefix, n) =>
-                        format!("{:.1} {}B in size", n, prefix.symbol()),
-                },
-            ),
+            format!("Receive {} ({})?", offer_name, format_size(file_size)),
             true,
         )
         .await)
     {
-        return req.reject().await.context("Could not reject offer");
+        /* With several files, the user may still want some of them */
+        if offer.iter_files().nth(1).is_none()
+            || !util::ask_user("Choose the files to receive?", false).await
+        {
+            return req.reject().await.context("Could not reject offer");
+        }
+        let mut selected = Vec::new();
+        for (path, _, size) in offer.iter_files() {
+            if util::ask_user(
+                format!("Receive {} ({})?", path.join("/"), format_size(size)),
+                true,
+            )
+            .await
+            {
+                selected.push(path);
+            }
+        }
+        if selected.is_empty() {
+            return req.reject().await.context("Could not reject offer");
+        }
+        selected_files = Some(selected);
     }
 
-    let pb = create_progress_bar(file_size);
-
-    let on_progress = move |received, _total| {
-        pb.set_position(received);
+    /* Create a temporary directory for receiving. It is named after the offer, so that receiving
+     * the same files again resumes where the last attempt stopped.
+     */
+    let offer_hash = {
+        use sha2::Digest;
+        let listing = offer
+            .iter_files()
+            .map(|(path, _, size)| format!("{}:{size}\n", path.join("/")))
+            .collect::<String>();
+        hex::encode(&sha2::Sha256::digest(listing.as_bytes())[..6])
     };
-
-    /* Create a temporary directory for receiving */
-    use rand::Rng;
-    let tmp_dir = target_dir.join(format!(
-        "wormhole-tmp-{:06}",
-        rand::thread_rng().gen_range(0..1_000_000)
-    ));
+    let tmp_dir = target_dir.join(format!("wormhole-tmp-{offer_hash}"));
     async_std::fs::create_dir_all(&tmp_dir)
         .await
         .context("Failed to create temporary directory for receiving")?;
@@ -1100,11 +1694,28 @@ async fn receive_inner_v2(
     /* Prepare the receive by creating all directories */
     offer.create_directories(&tmp_dir).await?;
 
-    /* Accept the offer and receive it */
-    let answer = offer.accept_all(&tmp_dir);
+    /* Accept the offer and receive it, continuing partially received files */
+    let mut answer = offer
+        .resume_all(&tmp_dir)
+        .await
+        .context("Failed to check for partially received files")?;
+    if let Some(selected) = &selected_files {
+        answer.retain_files(|path, _size| selected.iter().any(|selected| selected == path));
+    }
+
+    let pb = create_progress_bar(answer.total_size());
+    let on_progress = move |received, _total| {
+        pb.set_position(received);
+    };
+
     req.accept(&transit_handler, answer, on_progress, ctrl_c())
         .await
-        .context("Receive process failed")?;
+        .with_context(|| {
+            format!(
+                "Receive process failed. Receive the same files again to continue, or delete {}",
+                tmp_dir.display()
+            )
+        })?;
 
     // /* Put in all the symlinks last, this greatly reduces the attack surface */
     // offer.create_symlinks(&tmp_dir).await?;
@@ -1175,4 +1786,60 @@ mod test {
     fn verify_cli() {
         WormholeCli::command().debug_assert();
     }
+
+    #[test]
+    fn test_send_text_flag() {
+        let parse =
+            |args: &[&str]| WormholeCli::try_parse_from([&["wormhole-rs"][..], args].concat());
+
+        assert!(matches!(
+            parse(&["send", "--text", "hello"]).unwrap().command,
+            WormholeCommand::Send { text: Some(text), .. } if text == "hello"
+        ));
+        assert!(parse(&["send", "file.txt"]).is_ok());
+        assert!(parse(&["send"]).is_err());
+        assert!(parse(&["send", "--text", "hello", "file.txt"]).is_err());
+        assert!(parse(&["send-many"]).is_err());
+    }
+
+    #[test]
+    fn test_clipboard_flags() {
+        let parse =
+            |args: &[&str]| WormholeCli::try_parse_from([&["wormhole-rs"][..], args].concat());
+
+        assert!(matches!(
+            parse(&["send", "--clipboard"]).unwrap().command,
+            WormholeCommand::Send {
+                clipboard: true,
+                text: None,
+                ..
+            }
+        ));
+        assert!(parse(&["send", "--clipboard", "file.txt"]).is_err());
+        assert!(parse(&["send", "--clipboard", "--text", "hello"]).is_err());
+
+        /* The code gets copied unless disabled */
+        assert!(matches!(
+            parse(&["send", "file.txt"]).unwrap().command,
+            WormholeCommand::Send {
+                common_leader: CommonLeaderArgs {
+                    copy_code: true,
+                    ..
+                },
+                ..
+            }
+        ));
+        assert!(matches!(
+            parse(&["send", "--no-copy-code", "file.txt"])
+                .unwrap()
+                .command,
+            WormholeCommand::Send {
+                common_leader: CommonLeaderArgs {
+                    copy_code: false,
+                    ..
+                },
+                ..
+            }
+        ));
+    }
 }
@@ -0,0 +1,395 @@
+//! A full-screen terminal interface for interactive users
+//!
+//! Instead of scrolling log output, it shows the code, the connection state, the verifier
+//! and the transfer progress. Works for sending and receiving. Only available with the `tui` feature.
+
+use std::{
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{eyre, eyre::Context};
+use console::Term;
+use indicatif::HumanBytes;
+use magic_wormhole::{transfer, transit, Wormhole};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor, execute,
+        terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    style::Stylize,
+    text::Line,
+    widgets::{Block, Gauge, Paragraph},
+    Frame, Terminal,
+};
+
+use crate::{destination, parse_and_connect, util, CommonArgs};
+
+/// Minimum time between two redraws caused by progress updates
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct State {
+    code: Option<String>,
+    status: String,
+    connection: Option<String>,
+    verifier: Option<String>,
+    progress: Option<(u64, u64)>,
+}
+
+struct Inner {
+    terminal: Terminal<CrosstermBackend<io::Stdout>>,
+    state: State,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let _ = execute!(
+            self.terminal.backend_mut(),
+            LeaveAlternateScreen,
+            cursor::Show
+        );
+    }
+}
+
+/// Handle to the terminal interface. All clones draw on the same screen, which is restored
+/// once the last one is dropped.
+#[derive(Clone)]
+struct Tui(Arc<Mutex<Inner>>);
+
+impl Tui {
+    /// Switch to the alternate screen
+    ///
+    /// Raw mode is deliberately not enabled, so that Ctrl-C keeps working as usual.
+    fn new() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen, cursor::Hide)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        terminal.clear()?;
+        Ok(Self(Arc::new(Mutex::new(Inner {
+            terminal,
+            state: State::default(),
+        }))))
+    }
+
+    /// Modify the state and redraw the screen
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        let mut inner = self.0.lock().unwrap();
+        let Inner { terminal, state } = &mut *inner;
+        f(state);
+        if let Err(err) = terminal.draw(|frame| render(frame, state)) {
+            tracing::debug!("Failed to draw the terminal interface: {}", err);
+        }
+    }
+
+    /// Show the verifier of an established connection
+    fn connected(&self, wormhole: &Wormhole) {
+        let verifier: String = wormhole
+            .verifier()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        self.update(|state| {
+            state.status = "Connected to the other side".into();
+            state.verifier = Some(verifier);
+        });
+    }
+
+    /// Show how the transit connection got established
+    fn transit_handler(&self) -> impl FnOnce(transit::TransitInfo) {
+        let tui = self.clone();
+        move |info: transit::TransitInfo| {
+            tui.update(|state| state.connection = Some(info.to_string()))
+        }
+    }
+
+    /// Show the progress of the transfer, without redrawing for every chunk
+    fn progress_handler(&self) -> impl FnMut(u64, u64) + 'static {
+        let tui = self.clone();
+        let mut last_draw = Instant::now();
+        move |transferred: u64, total: u64| {
+            if transferred == total || last_draw.elapsed() >= PROGRESS_REDRAW_INTERVAL {
+                last_draw = Instant::now();
+                tui.update(|state| state.progress = Some((transferred, total)));
+            }
+        }
+    }
+
+    /**
+     * Ask a yes or no question in the status box
+     *
+     * Raw mode is off, so the answer is typed like on a normal terminal and confirmed with Enter.
+     */
+    async fn ask(&self, question: String, default_answer: bool) -> bool {
+        let choices = if default_answer { "Y/n" } else { "y/N" };
+        let mut status = format!("{} ({})", question, choices);
+        let stdin = async_std::io::stdin();
+        loop {
+            self.update(|state| state.status = status.clone());
+            let mut answer = String::new();
+            if stdin.read_line(&mut answer).await.is_err() {
+                return false;
+            }
+            /* Get rid of the echoed answer */
+            let _ = self.0.lock().unwrap().terminal.clear();
+            match answer.to_lowercase().trim() {
+                "y" | "yes" => return true,
+                "n" | "no" => return false,
+                "" => return default_answer,
+                _ => status = format!("Please type y or n. {} ({})", question, choices),
+            }
+        }
+    }
+}
+
+fn render(frame: &mut Frame, state: &State) {
+    let [code_area, status_area, progress_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(5),
+        Constraint::Length(3),
+    ])
+    .areas(frame.area());
+
+    let code = state.code.as_deref().unwrap_or("…");
+    frame.render_widget(
+        Paragraph::new(Line::from(code).bold()).block(Block::bordered().title("Wormhole code")),
+        code_area,
+    );
+
+    let mut status = vec![Line::from(state.status.as_str())];
+    if let Some(connection) = &state.connection {
+        status.push(Line::from(connection.as_str()));
+    }
+    if let Some(verifier) = &state.verifier {
+        status.push(Line::from(format!("Verifier: {}", verifier)));
+    }
+    frame.render_widget(
+        Paragraph::new(status).block(Block::bordered().title("Status")),
+        status_area,
+    );
+
+    if let Some((sent, total)) = state.progress {
+        let ratio = if total == 0 {
+            1.0
+        } else {
+            sent as f64 / total as f64
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title("Progress"))
+                .ratio(ratio.clamp(0.0, 1.0))
+                .label(format!("{} / {}", HumanBytes(sent), HumanBytes(total))),
+            progress_area,
+        );
+    }
+}
+
+/// Like `send`, but using the terminal interface
+pub async fn send(
+    common: CommonArgs,
+    code: Option<String>,
+    code_length: usize,
+    copy_code: bool,
+    offer: transfer::offer::OfferSend,
+    transit_abilities: transit::Abilities,
+    ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()> {
+    let tui = Tui::new().context("Failed to initialize the terminal interface")?;
+    tui.update(|state| state.status = "Connecting to the rendezvous server…".into());
+
+    let print_code = {
+        let tui = tui.clone();
+        move |_: &mut Term, code: &magic_wormhole::Code, _: &Option<url::Url>| {
+            tui.update(|state| {
+                state.code = Some(code.to_string());
+                state.status = "Waiting for the other side to connect…".into();
+            });
+            eyre::Result::<_>::Ok(())
+        }
+    };
+    /* Buffered, so that the welcome message does not mess up our screen */
+    let mut term = Term::buffered_stdout();
+    let (wormhole, _code, relay_hints) = match util::cancellable(
+        Box::pin(parse_and_connect(
+            &mut term,
+            common,
+            None,
+            code,
+            Some(code_length),
+            copy_code,
+            true,
+            transfer::APP_CONFIG,
+            Some(&print_code),
+        )),
+        ctrl_c(),
+    )
+    .await
+    {
+        Ok(result) => result?,
+        Err(_) => return Ok(()),
+    };
+    drop(print_code);
+    tui.connected(&wormhole);
+
+    transfer::send(
+        wormhole,
+        relay_hints,
+        transit_abilities,
+        offer,
+        tui.transit_handler(),
+        tui.progress_handler(),
+        ctrl_c(),
+    )
+    .await
+    .context("Send process failed")?;
+
+    /* Restore the screen before printing the result */
+    drop(tui);
+    println!("Transfer complete");
+    Ok(())
+}
+
+/**
+ * Like `receive`, but using the terminal interface
+ *
+ * The code is entered before switching to the full screen, like without the terminal interface.
+ * Text messages are printed once the screen is restored. Only transfer protocol version 1 is
+ * supported.
+ */
+pub async fn receive(
+    mut term: Term,
+    common: CommonArgs,
+    code: Option<String>,
+    target_dir: &Path,
+    noconfirm: bool,
+    only_text: bool,
+    to_clipboard: bool,
+    transit_abilities: transit::Abilities,
+    ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()> {
+    let (wormhole, code, relay_hints) = match util::cancellable(
+        Box::pin(parse_and_connect(
+            &mut term,
+            common,
+            None,
+            code,
+            None,
+            false,
+            false,
+            transfer::APP_CONFIG,
+            None,
+        )),
+        ctrl_c(),
+    )
+    .await
+    {
+        Ok(result) => result?,
+        Err(_) => return Ok(()),
+    };
+
+    let tui = Tui::new().context("Failed to initialize the terminal interface")?;
+    tui.update(|state| state.code = Some(code.to_string()));
+    tui.connected(&wormhole);
+    tui.update(|state| state.status = "Waiting for the offer…".into());
+
+    let request =
+        transfer::request_text_or_file(wormhole, relay_hints, transit_abilities, ctrl_c())
+            .await
+            .context("Could not get an offer")?;
+    let req = match request {
+        Some(transfer::TextOrFileRequest::File(req)) if !only_text => req,
+        Some(transfer::TextOrFileRequest::File(req)) => {
+            req.reject().await.context("Could not reject offer")?;
+            drop(tui);
+            println!("Rejected file offer, only accepting text");
+            return Ok(());
+        },
+        Some(transfer::TextOrFileRequest::Text(text)) => {
+            drop(tui);
+            if to_clipboard {
+                util::copy_to_clipboard(text)?;
+                println!("Received text message copied to clipboard");
+            } else {
+                println!("{}", text);
+            }
+            return Ok(());
+        },
+        Some(_) => eyre::bail!("Received an unsupported offer"),
+        None => return Ok(()),
+    };
+
+    if !(noconfirm
+        || tui
+            .ask(
+                format!(
+                    "Receive file '{}' ({})?",
+                    req.file_name(),
+                    HumanBytes(req.file_size())
+                ),
+                true,
+            )
+            .await)
+    {
+        req.reject().await.context("Could not reject offer")?;
+        drop(tui);
+        println!("Transfer rejected");
+        return Ok(());
+    }
+
+    let file_path = match destination(&req, target_dir) {
+        Ok(file_path) => file_path,
+        Err(file_name) => {
+            req.reject().await.context("Could not reject offer")?;
+            eyre::bail!("Refusing to receive a file named {:?}", file_name);
+        },
+    };
+    tui.update(|state| state.status = format!("Receiving {}", file_path.display()));
+
+    if req.is_directory() {
+        async_std::fs::create_dir(&file_path)
+            .await
+            .context("Failed to create destination directory")?;
+        req.accept_directory(
+            tui.transit_handler(),
+            tui.progress_handler(),
+            &file_path,
+            ctrl_c(),
+        )
+        .await
+        .context("Receive process failed")?;
+    } else {
+        /* If there is a collision, ask whether to overwrite */
+        let overwrite = file_path.exists() && !noconfirm;
+        if overwrite
+            && !tui
+                .ask(
+                    format!("Override existing file {}?", file_path.display()),
+                    false,
+                )
+                .await
+        {
+            req.reject().await.context("Could not reject offer")?;
+            drop(tui);
+            println!("Transfer rejected");
+            return Ok(());
+        }
+        req.accept_to_path(
+            tui.transit_handler(),
+            tui.progress_handler(),
+            &file_path,
+            overwrite,
+            ctrl_c(),
+        )
+        .await
+        .context("Receive process failed")?;
+    }
+
+    /* Restore the screen before printing the result */
+    drop(tui);
+    println!("Received {}", file_path.display());
+    Ok(())
+}
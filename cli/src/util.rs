@@ -61,3 +61,64 @@ impl std::fmt::Display for Cancelled {
         write!(f, "Task has been cancelled")
     }
 }
+
+/// Copy some text to the system clipboard
+#[cfg(feature = "clipboard")]
+pub fn copy_to_clipboard(text: impl Into<String>) -> color_eyre::eyre::Result<()> {
+    use color_eyre::eyre::Context;
+
+    arboard::Clipboard::new()
+        .context("Failed to initialize clipboard support")?
+        .set_text(text.into())
+        .context("Failed to copy to the clipboard")
+}
+
+/// Copy some text to the system clipboard
+#[cfg(not(feature = "clipboard"))]
+pub fn copy_to_clipboard(_text: impl Into<String>) -> color_eyre::eyre::Result<()> {
+    color_eyre::eyre::bail!("wormhole-rs was built without clipboard support (feature `clipboard`)")
+}
+
+/// Read some text from the system clipboard
+#[cfg(feature = "clipboard")]
+pub fn read_clipboard() -> color_eyre::eyre::Result<String> {
+    use color_eyre::eyre::Context;
+
+    arboard::Clipboard::new()
+        .context("Failed to initialize clipboard support")?
+        .get_text()
+        .context("Failed to read text from the clipboard")
+}
+
+/// Read some text from the system clipboard
+#[cfg(not(feature = "clipboard"))]
+pub fn read_clipboard() -> color_eyre::eyre::Result<String> {
+    color_eyre::eyre::bail!("wormhole-rs was built without clipboard support (feature `clipboard`)")
+}
+
+/// Where the peers remembered with `--remember-as` are stored
+///
+/// This is `wormhole-rs/seeds.json` in the user's configuration directory, unless overridden with
+/// the `WORMHOLE_SEEDS` environment variable.
+pub fn seeds_path() -> color_eyre::eyre::Result<std::path::PathBuf> {
+    use std::{env::var_os, path::PathBuf};
+
+    if let Some(path) = var_os("WORMHOLE_SEEDS") {
+        return Ok(path.into());
+    }
+    let config_dir = var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            cfg!(windows)
+                .then(|| var_os("APPDATA"))
+                .flatten()
+                .map(PathBuf::from)
+        })
+        .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok_or_else(|| {
+            color_eyre::eyre::format_err!(
+                "Can't find a configuration directory to remember peers in. Set WORMHOLE_SEEDS"
+            )
+        })?;
+    Ok(config_dir.join("wormhole-rs").join("seeds.json"))
+}
@@ -0,0 +1,151 @@
+/*
+ * C bindings for magic-wormhole.rs
+ *
+ * Keep in sync with src/lib.rs. All strings are NUL terminated UTF-8, strings returned by the
+ * library must be freed with wormhole_string_free().
+ */
+
+#ifndef MAGIC_WORMHOLE_H
+#define MAGIC_WORMHOLE_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The outcome of a call */
+typedef enum {
+    /* The call succeeded */
+    WORMHOLE_OK = 0,
+    /* The code does not exist (anymore), was already used, or is wrong */
+    WORMHOLE_INVALID_CODE = 1,
+    /* The other side rejected the transfer or reported an error */
+    WORMHOLE_REJECTED = 2,
+    /* The transfer got cancelled with wormhole_cancel() */
+    WORMHOLE_CANCELLED = 3,
+    /* Everything else */
+    WORMHOLE_FAILED = 4,
+    /* A required argument was NULL or not valid UTF-8 */
+    WORMHOLE_INVALID_ARGUMENT = 5,
+} WormholeStatus;
+
+/*
+ * Gets informed about the progress of a transfer
+ *
+ * Every callback may be NULL. They are called from the thread running the transfer, with
+ * user_data as first argument. The strings passed to them are only valid during the call.
+ */
+typedef struct {
+    void *user_data;
+    /* The code to show to the user. Only called when sending */
+    void (*on_code)(void *user_data, const char *code);
+    /* The other side connected. Compare the hex encoded verifier for extra security */
+    void (*on_connected)(void *user_data, const char *verifier);
+    /* The other side offers a file. Return whether to accept it. Only called when receiving,
+     * offers are accepted if this is NULL */
+    bool (*on_offer)(void *user_data, const char *name, uint64_t size);
+    /* Some more bytes have been sent or received */
+    void (*on_progress)(void *user_data, uint64_t transferred, uint64_t total);
+} WormholeCallbacks;
+
+/* Cancels a running transfer from another thread */
+typedef struct WormholeCancel WormholeCancel;
+
+/* Create a handle to cancel a transfer. Free it with wormhole_cancel_free() once the transfer is done. */
+WormholeCancel *wormhole_cancel_new(void);
+
+/* Cancel the transfer using this handle. Calling this more than once has no effect. */
+void wormhole_cancel(const WormholeCancel *cancel);
+
+/* Free a handle created with wormhole_cancel_new(). Passing NULL does nothing. */
+void wormhole_cancel_free(WormholeCancel *cancel);
+
+/* Free a string returned by this library. Passing NULL does nothing. */
+void wormhole_string_free(char *string);
+
+/*
+ * Send a file or folder. Blocks until the transfer is done, so call it from a background thread.
+ *
+ * callbacks, cancel and error may be NULL. On failure, a description of the error is written to
+ * error.
+ */
+WormholeStatus wormhole_send_file(const char *path,
+                                  uint8_t code_length,
+                                  const WormholeCallbacks *callbacks,
+                                  const WormholeCancel *cancel,
+                                  char **error);
+
+/*
+ * Receive a file into target_dir. Blocks until the transfer is done, so call it from a background
+ * thread.
+ *
+ * callbacks, cancel, received_path and error may be NULL. On success, the path of the received
+ * file is written to received_path, or NULL if the offer was rejected in on_offer. On failure, a
+ * description of the error is written to error.
+ */
+WormholeStatus wormhole_receive_file(const char *code,
+                                     const char *target_dir,
+                                     const WormholeCallbacks *callbacks,
+                                     const WormholeCancel *cancel,
+                                     char **received_path,
+                                     char **error);
+
+/* A wormhole with a code, to transfer one file with */
+typedef struct WormholeConnection WormholeConnection;
+
+/*
+ * Allocate a new code, typically on the sending side. Blocks until the server answered.
+ *
+ * cancel and error may be NULL. On success, the connection is written to connection and the code
+ * to show to the user to code. On failure, a description of the error is written to error.
+ */
+WormholeStatus wormhole_allocate_code(uint8_t code_length,
+                                      const WormholeCancel *cancel,
+                                      WormholeConnection **connection,
+                                      char **code,
+                                      char **error);
+
+/*
+ * Connect to the other side with the code it allocated. Blocks until the other side is there, so
+ * call it from a background thread.
+ *
+ * cancel and error may be NULL. On success, the connection is written to connection. On failure,
+ * a description of the error is written to error.
+ */
+WormholeStatus wormhole_connect(const char *code,
+                                const WormholeCancel *cancel,
+                                WormholeConnection **connection,
+                                char **error);
+
+/*
+ * Like wormhole_send_file(), but over a connection from wormhole_allocate_code() or
+ * wormhole_connect(). Takes ownership of the connection, even if the call fails.
+ */
+WormholeStatus wormhole_connection_send_file(WormholeConnection *connection,
+                                             const char *path,
+                                             const WormholeCallbacks *callbacks,
+                                             const WormholeCancel *cancel,
+                                             char **error);
+
+/*
+ * Like wormhole_receive_file(), but over a connection from wormhole_allocate_code() or
+ * wormhole_connect(). Takes ownership of the connection, even if the call fails.
+ */
+WormholeStatus wormhole_connection_receive_file(WormholeConnection *connection,
+                                                const char *target_dir,
+                                                const WormholeCallbacks *callbacks,
+                                                const WormholeCancel *cancel,
+                                                char **received_path,
+                                                char **error);
+
+/* Close a connection without transferring anything, and release its code. Passing NULL does
+ * nothing. */
+void wormhole_connection_free(WormholeConnection *connection);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MAGIC_WORMHOLE_H */
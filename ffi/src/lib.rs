@@ -0,0 +1,473 @@
+//! C bindings for the file transfer
+//!
+//! This exposes the small, blocking API of the Kotlin and Swift bindings in `magic-wormhole-uniffi`
+//! as plain C functions, for GUI apps in C, C++ or any other language that can call into C. The
+//! functions are declared in `include/magic_wormhole.h`, see the README for how to use them.
+//!
+//! All strings are NUL terminated UTF-8. Strings returned by the library must be freed with
+//! [`wormhole_string_free`].
+
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    panic::AssertUnwindSafe,
+    ptr,
+    sync::Arc,
+};
+
+use magic_wormhole_uniffi::{CancelHandle, Connection, TransferError, TransferListener};
+
+/// The outcome of a call, mirroring the variants of [`TransferError`]
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WormholeStatus {
+    /// The call succeeded
+    Ok = 0,
+    /// The code does not exist (anymore), was already used, or is wrong
+    InvalidCode = 1,
+    /// The other side rejected the transfer or reported an error
+    Rejected = 2,
+    /// The transfer got cancelled with [`wormhole_cancel`]
+    Cancelled = 3,
+    /// Everything else
+    Failed = 4,
+    /// A required argument was `NULL` or not valid UTF-8
+    InvalidArgument = 5,
+}
+
+/**
+ * Gets informed about the progress of a transfer
+ *
+ * Every callback may be `NULL`. They are called from the thread running the transfer, with
+ * `user_data` as first argument. The strings passed to them are only valid during the call.
+ */
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct WormholeCallbacks {
+    /// Passed to all callbacks
+    pub user_data: *mut c_void,
+    /// The code to show to the user. Only called when sending
+    pub on_code: Option<unsafe extern "C" fn(user_data: *mut c_void, code: *const c_char)>,
+    /// The other side connected. Compare the hex encoded verifier for extra security
+    pub on_connected: Option<unsafe extern "C" fn(user_data: *mut c_void, verifier: *const c_char)>,
+    /// The other side offers a file. Return whether to accept it. Only called when receiving,
+    /// offers are accepted if this is `NULL`
+    pub on_offer: Option<
+        unsafe extern "C" fn(user_data: *mut c_void, name: *const c_char, size: u64) -> bool,
+    >,
+    /// Some more bytes have been sent or received
+    pub on_progress:
+        Option<unsafe extern "C" fn(user_data: *mut c_void, transferred: u64, total: u64)>,
+}
+
+impl WormholeCallbacks {
+    const NONE: Self = Self {
+        user_data: ptr::null_mut(),
+        on_code: None,
+        on_connected: None,
+        on_offer: None,
+        on_progress: None,
+    };
+}
+
+/* The transfer functions only return once they are done, and call the callbacks from the calling
+ * thread. So whatever `user_data` points to outlives them and is not accessed concurrently.
+ */
+unsafe impl Send for WormholeCallbacks {}
+unsafe impl Sync for WormholeCallbacks {}
+
+impl TransferListener for WormholeCallbacks {
+    fn on_code(&self, code: String) {
+        if let Some(on_code) = self.on_code {
+            let code = c_string(code);
+            unsafe { on_code(self.user_data, code.as_ptr()) }
+        }
+    }
+
+    fn on_connected(&self, verifier: String) {
+        if let Some(on_connected) = self.on_connected {
+            let verifier = c_string(verifier);
+            unsafe { on_connected(self.user_data, verifier.as_ptr()) }
+        }
+    }
+
+    fn on_offer(&self, name: String, size: u64) -> bool {
+        match self.on_offer {
+            Some(on_offer) => {
+                let name = c_string(name);
+                unsafe { on_offer(self.user_data, name.as_ptr(), size) }
+            },
+            None => true,
+        }
+    }
+
+    fn on_progress(&self, transferred: u64, total: u64) {
+        if let Some(on_progress) = self.on_progress {
+            unsafe { on_progress(self.user_data, transferred, total) }
+        }
+    }
+}
+
+/// Cancels a running transfer from another thread, see [`wormhole_cancel_new`]
+pub struct WormholeCancel(Arc<CancelHandle>);
+
+/// Create a handle to cancel a transfer. Free it with [`wormhole_cancel_free`] once the transfer is done.
+#[no_mangle]
+pub extern "C" fn wormhole_cancel_new() -> *mut WormholeCancel {
+    Box::into_raw(Box::new(WormholeCancel(Arc::new(CancelHandle::new()))))
+}
+
+/**
+ * Cancel the transfer using this handle. Calling this more than once has no effect.
+ *
+ * # Safety
+ *
+ * `cancel` must come from [`wormhole_cancel_new`] and not be freed yet.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_cancel(cancel: *const WormholeCancel) {
+    if let Some(cancel) = cancel.as_ref() {
+        cancel.0.cancel();
+    }
+}
+
+/**
+ * Free a handle created with [`wormhole_cancel_new`]. Passing `NULL` does nothing.
+ *
+ * # Safety
+ *
+ * `cancel` must come from [`wormhole_cancel_new`], and must not be used afterwards. Freeing it
+ * while a transfer is still running is fine, but it can't be cancelled anymore then.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_cancel_free(cancel: *mut WormholeCancel) {
+    if !cancel.is_null() {
+        drop(Box::from_raw(cancel));
+    }
+}
+
+/**
+ * Free a string returned by this library. Passing `NULL` does nothing.
+ *
+ * # Safety
+ *
+ * `string` must come from this library, and must not be used afterwards.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/**
+ * Send a file or folder. Blocks until the transfer is done, so call it from a background thread.
+ *
+ * On failure, a description of the error is written to `error` unless it is `NULL`.
+ *
+ * # Safety
+ *
+ * `path` must be a valid C string. `callbacks` and `cancel` may be `NULL`, otherwise they must
+ * point to valid values for the whole call. `error` may be `NULL`, otherwise it must be valid for
+ * writes.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_send_file(
+    path: *const c_char,
+    code_length: u8,
+    callbacks: *const WormholeCallbacks,
+    cancel: *const WormholeCancel,
+    error: *mut *mut c_char,
+) -> WormholeStatus {
+    let Some(path) = string_arg(path) else {
+        return invalid_argument("path", error);
+    };
+    let listener = callbacks
+        .as_ref()
+        .copied()
+        .unwrap_or(WormholeCallbacks::NONE);
+    let cancel = cancel_handle(cancel);
+    let result = catch_panic(|| {
+        magic_wormhole_uniffi::send_file(path, code_length, Box::new(listener), cancel)
+    });
+    report(result, error)
+}
+
+/**
+ * Receive a file into `target_dir`. Blocks until the transfer is done, so call it from a
+ * background thread.
+ *
+ * On success, the path of the received file is written to `received_path`, or `NULL` if the offer
+ * was rejected in `on_offer`. On failure, a description of the error is written to `error`. Both
+ * may be `NULL` if the caller isn't interested.
+ *
+ * # Safety
+ *
+ * `code` and `target_dir` must be valid C strings. `callbacks` and `cancel` may be `NULL`,
+ * otherwise they must point to valid values for the whole call. `received_path` and `error` may
+ * be `NULL`, otherwise they must be valid for writes.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_receive_file(
+    code: *const c_char,
+    target_dir: *const c_char,
+    callbacks: *const WormholeCallbacks,
+    cancel: *const WormholeCancel,
+    received_path: *mut *mut c_char,
+    error: *mut *mut c_char,
+) -> WormholeStatus {
+    let Some(code) = string_arg(code) else {
+        return invalid_argument("code", error);
+    };
+    let Some(target_dir) = string_arg(target_dir) else {
+        return invalid_argument("target_dir", error);
+    };
+    let listener = callbacks
+        .as_ref()
+        .copied()
+        .unwrap_or(WormholeCallbacks::NONE);
+    let cancel = cancel_handle(cancel);
+    let result = catch_panic(|| {
+        magic_wormhole_uniffi::receive_file(code, target_dir, Box::new(listener), cancel)
+    });
+    let result = result.map(|path| {
+        if !received_path.is_null() {
+            *received_path = path.map_or(ptr::null_mut(), |path| c_string(path).into_raw());
+        }
+    });
+    report(result, error)
+}
+
+/// A wormhole with a code, see [`wormhole_allocate_code`] and [`wormhole_connect`]
+pub struct WormholeConnection(Connection);
+
+/**
+ * Allocate a new code, typically on the sending side. Blocks until the server answered.
+ *
+ * On success, the connection is written to `connection` and the code to show to the user to
+ * `code`. Transfer a file with [`wormhole_connection_send_file`] or
+ * [`wormhole_connection_receive_file`], or close it with [`wormhole_connection_free`]. On
+ * failure, a description of the error is written to `error` unless it is `NULL`.
+ *
+ * # Safety
+ *
+ * `cancel` may be `NULL`, otherwise it must point to a valid value for the whole call.
+ * `connection` and `code` must be valid for writes, `error` may be `NULL`, otherwise it must be
+ * valid for writes.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_allocate_code(
+    code_length: u8,
+    cancel: *const WormholeCancel,
+    connection: *mut *mut WormholeConnection,
+    code: *mut *mut c_char,
+    error: *mut *mut c_char,
+) -> WormholeStatus {
+    if connection.is_null() {
+        return invalid_argument("connection", error);
+    }
+    if code.is_null() {
+        return invalid_argument("code", error);
+    }
+    let cancel = cancel_handle(cancel);
+    let result = catch_panic(|| magic_wormhole_uniffi::allocate_code(code_length, &cancel));
+    let result = result.map(|allocated| {
+        *code = c_string(allocated.code().expect("We allocated the code")).into_raw();
+        *connection = Box::into_raw(Box::new(WormholeConnection(allocated)));
+    });
+    report(result, error)
+}
+
+/**
+ * Connect to the other side with the code it allocated. Blocks until the other side is there, so
+ * call it from a background thread.
+ *
+ * On success, the connection is written to `connection`, to be used like the one of
+ * [`wormhole_allocate_code`]. On failure, a description of the error is written to `error` unless
+ * it is `NULL`.
+ *
+ * # Safety
+ *
+ * `code` must be a valid C string. `cancel` may be `NULL`, otherwise it must point to a valid
+ * value for the whole call. `connection` must be valid for writes, `error` may be `NULL`,
+ * otherwise it must be valid for writes.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_connect(
+    code: *const c_char,
+    cancel: *const WormholeCancel,
+    connection: *mut *mut WormholeConnection,
+    error: *mut *mut c_char,
+) -> WormholeStatus {
+    let Some(code) = string_arg(code) else {
+        return invalid_argument("code", error);
+    };
+    if connection.is_null() {
+        return invalid_argument("connection", error);
+    }
+    let cancel = cancel_handle(cancel);
+    let result = catch_panic(|| magic_wormhole_uniffi::connect(code, &cancel));
+    let result = result.map(|connected| {
+        *connection = Box::into_raw(Box::new(WormholeConnection(connected)));
+    });
+    report(result, error)
+}
+
+/**
+ * Send a file or folder over a connection. Blocks until the transfer is done, so call it from a
+ * background thread.
+ *
+ * This takes ownership of `connection`, even if the call fails. Otherwise like
+ * [`wormhole_send_file`].
+ *
+ * # Safety
+ *
+ * `connection` must come from [`wormhole_allocate_code`] or [`wormhole_connect`], and must not be
+ * used afterwards. For the other arguments, see [`wormhole_send_file`].
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_connection_send_file(
+    connection: *mut WormholeConnection,
+    path: *const c_char,
+    callbacks: *const WormholeCallbacks,
+    cancel: *const WormholeCancel,
+    error: *mut *mut c_char,
+) -> WormholeStatus {
+    let Some(connection) = take_connection(connection) else {
+        return invalid_argument("connection", error);
+    };
+    let Some(path) = string_arg(path) else {
+        close_connection(connection);
+        return invalid_argument("path", error);
+    };
+    let listener = callbacks
+        .as_ref()
+        .copied()
+        .unwrap_or(WormholeCallbacks::NONE);
+    let cancel = cancel_handle(cancel);
+    let result = catch_panic(|| connection.send_file(path, Box::new(listener), cancel));
+    report(result, error)
+}
+
+/**
+ * Receive a file into `target_dir` over a connection. Blocks until the transfer is done, so call
+ * it from a background thread.
+ *
+ * This takes ownership of `connection`, even if the call fails. Otherwise like
+ * [`wormhole_receive_file`].
+ *
+ * # Safety
+ *
+ * `connection` must come from [`wormhole_allocate_code`] or [`wormhole_connect`], and must not be
+ * used afterwards. For the other arguments, see [`wormhole_receive_file`].
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_connection_receive_file(
+    connection: *mut WormholeConnection,
+    target_dir: *const c_char,
+    callbacks: *const WormholeCallbacks,
+    cancel: *const WormholeCancel,
+    received_path: *mut *mut c_char,
+    error: *mut *mut c_char,
+) -> WormholeStatus {
+    let Some(connection) = take_connection(connection) else {
+        return invalid_argument("connection", error);
+    };
+    let Some(target_dir) = string_arg(target_dir) else {
+        close_connection(connection);
+        return invalid_argument("target_dir", error);
+    };
+    let listener = callbacks
+        .as_ref()
+        .copied()
+        .unwrap_or(WormholeCallbacks::NONE);
+    let cancel = cancel_handle(cancel);
+    let result = catch_panic(|| connection.receive_file(target_dir, Box::new(listener), cancel));
+    let result = result.map(|path| {
+        if !received_path.is_null() {
+            *received_path = path.map_or(ptr::null_mut(), |path| c_string(path).into_raw());
+        }
+    });
+    report(result, error)
+}
+
+/**
+ * Close a connection without transferring anything, and release its code. Blocks until the
+ * server answered. Passing `NULL` does nothing.
+ *
+ * # Safety
+ *
+ * `connection` must come from [`wormhole_allocate_code`] or [`wormhole_connect`], and must not be
+ * used afterwards.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn wormhole_connection_free(connection: *mut WormholeConnection) {
+    if let Some(connection) = take_connection(connection) {
+        close_connection(connection);
+    }
+}
+
+unsafe fn take_connection(connection: *mut WormholeConnection) -> Option<Connection> {
+    if connection.is_null() {
+        return None;
+    }
+    Some(Box::from_raw(connection).0)
+}
+
+/** Nobody to report failures to, the server cleans up after us in that case */
+fn close_connection(connection: Connection) {
+    let _ = catch_panic(|| connection.close());
+}
+
+/** Strings from the peer could contain NUL bytes, which C can't represent */
+fn c_string(string: String) -> CString {
+    CString::new(string.replace('\0', "\u{FFFD}")).expect("All NUL bytes have been replaced")
+}
+
+unsafe fn string_arg(string: *const c_char) -> Option<String> {
+    if string.is_null() {
+        return None;
+    }
+    CStr::from_ptr(string).to_str().ok().map(ToOwned::to_owned)
+}
+
+/** Without a handle, the transfer can't be cancelled */
+unsafe fn cancel_handle(cancel: *const WormholeCancel) -> Arc<CancelHandle> {
+    match cancel.as_ref() {
+        Some(cancel) => cancel.0.clone(),
+        None => Arc::new(CancelHandle::new()),
+    }
+}
+
+/** Unwinding into C is undefined behavior */
+fn catch_panic<T>(call: impl FnOnce() -> Result<T, TransferError>) -> Result<T, TransferError> {
+    std::panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|_| {
+        Err(TransferError::Failed(
+            "The library panicked, please open a bug report".into(),
+        ))
+    })
+}
+
+unsafe fn invalid_argument(name: &str, error: *mut *mut c_char) -> WormholeStatus {
+    set_error(error, format!("Invalid argument: {name}"));
+    WormholeStatus::InvalidArgument
+}
+
+unsafe fn report(result: Result<(), TransferError>, error: *mut *mut c_char) -> WormholeStatus {
+    let Err(failure) = result else {
+        return WormholeStatus::Ok;
+    };
+    let status = match &failure {
+        TransferError::Code(_) => WormholeStatus::InvalidCode,
+        TransferError::Rejected(_) => WormholeStatus::Rejected,
+        TransferError::Cancelled => WormholeStatus::Cancelled,
+        TransferError::Failed(_) => WormholeStatus::Failed,
+    };
+    set_error(error, failure.to_string());
+    status
+}
+
+unsafe fn set_error(error: *mut *mut c_char, message: String) {
+    if !error.is_null() {
+        *error = c_string(message).into_raw();
+    }
+}
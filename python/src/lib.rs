@@ -0,0 +1,449 @@
+//! Python bindings
+//!
+//! All functions return awaitables for use with `asyncio`. The transfers themselves run on a
+//! separate thread, so they never block the event loop. Cancelling the awaitable cancels the
+//! transfer.
+//!
+//! For applications with their own protocol, `create()` returns a wormhole with the most important
+//! methods of the one of the Python implementation.
+//!
+//! Only built with the `python` feature, usually through `maturin`.
+
+#![cfg(feature = "python")]
+
+use std::{borrow::Cow, collections::HashMap, path::PathBuf};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{BoxFuture, Shared},
+    Future, FutureExt, StreamExt,
+};
+use magic_wormhole::{
+    rendezvous, transfer, transit, AppConfig, AppID, Code, PendingWormhole, Wormhole,
+};
+use pyo3::{create_exception, exceptions::PyException, prelude::*};
+
+create_exception!(
+    magic_wormhole_rs,
+    WormholeError,
+    PyException,
+    "Some part of the transfer failed"
+);
+
+/** Resolves once the awaitable got cancelled */
+type Cancel = Shared<BoxFuture<'static, ()>>;
+
+fn py_err(error: impl std::fmt::Display) -> PyErr {
+    WormholeError::new_err(error.to_string())
+}
+
+/**
+ * Run a transfer on its own thread and wrap it into an awaitable
+ *
+ * `f` gets a future that resolves once the awaitable got cancelled. Our futures are not
+ * `Send`, which is why they are created and driven on the spawned thread.
+ */
+fn spawn<F, Fut, T>(py: Python<'_>, f: F) -> PyResult<Bound<'_, PyAny>>
+where
+    F: FnOnce(Cancel) -> Fut + Send + 'static,
+    Fut: Future<Output = PyResult<T>>,
+    T: IntoPy<PyObject> + Send + 'static,
+{
+    let (guard, cancelled) = oneshot::channel::<()>();
+    pyo3_async_runtimes::async_std::future_into_py(py, async move {
+        /* Gets dropped when asyncio cancels us */
+        let _guard = guard;
+        async_std::task::spawn_blocking(move || {
+            let cancel = async move {
+                let _ = cancelled.await;
+            };
+            async_std::task::block_on(f(cancel.boxed().shared()))
+        })
+        .await
+    })
+}
+
+/** Call an optional Python callback from a non-Python thread */
+fn call(callback: &Option<PyObject>, args: impl IntoPy<Py<pyo3::types::PyTuple>>) -> PyResult<()> {
+    if let Some(callback) = callback {
+        Python::with_gil(|py| callback.call1(py, args))?;
+    }
+    Ok(())
+}
+
+fn progress_handler(on_progress: Option<PyObject>) -> impl FnMut(u64, u64) + 'static {
+    move |transferred: u64, total: u64| {
+        if let Err(error) = call(&on_progress, (transferred, total)) {
+            tracing::warn!("Progress callback failed: {}", error);
+        }
+    }
+}
+
+async fn allocate_and_connect(
+    code_length: usize,
+    on_code: &Option<PyObject>,
+) -> PyResult<Wormhole> {
+    let pending = Wormhole::builder(transfer::APP_CONFIG)
+        .code_length(code_length)
+        .allocate_code()
+        .await
+        .map_err(py_err)?;
+    if let Err(error) = call(on_code, (pending.code().to_string(),)) {
+        let _ = pending.cancel().await;
+        return Err(error);
+    }
+    pending.wait_for_peer().await.map_err(py_err)
+}
+
+async fn connect(code: String) -> PyResult<Wormhole> {
+    Wormhole::builder(transfer::APP_CONFIG)
+        .connect_with_code(Code::from(code))
+        .await
+        .map_err(py_err)
+}
+
+/// Send a file or folder
+///
+/// `on_code(code)` gets called with the code to share with the other side,
+/// `on_progress(sent, total)` while the transfer is running.
+#[pyfunction]
+#[pyo3(signature = (path, code_length = 2, on_code = None, on_progress = None))]
+fn send_file(
+    py: Python<'_>,
+    path: PathBuf,
+    code_length: usize,
+    on_code: Option<PyObject>,
+    on_progress: Option<PyObject>,
+) -> PyResult<Bound<'_, PyAny>> {
+    spawn(py, move |cancel| async move {
+        let name = path
+            .file_name()
+            .ok_or_else(|| py_err(format!("Invalid path: {}", path.display())))?
+            .to_string_lossy()
+            .into_owned();
+        let offer = transfer::offer::OfferSend::new_file_or_folder(name, &path).await?;
+
+        let wormhole = allocate_and_connect(code_length, &on_code).await?;
+        let relay_hints = wormhole.relay_hints().to_vec();
+        transfer::send(
+            wormhole,
+            relay_hints,
+            transit::Abilities::ALL_ABILITIES,
+            offer,
+            |info| tracing::info!("{info}"),
+            progress_handler(on_progress),
+            cancel,
+        )
+        .await
+        .map_err(py_err)
+    })
+}
+
+/// Receive a file or folder into `target_dir`
+///
+/// `on_offer(name, size)` decides whether to accept the offer. Returns the path of the
+/// received file, or `None` if it was rejected. Existing files are never overwritten.
+#[pyfunction]
+#[pyo3(signature = (code, target_dir = PathBuf::from("."), on_offer = None, on_progress = None))]
+fn receive_file(
+    py: Python<'_>,
+    code: String,
+    target_dir: PathBuf,
+    on_offer: Option<PyObject>,
+    on_progress: Option<PyObject>,
+) -> PyResult<Bound<'_, PyAny>> {
+    spawn(py, move |cancel| async move {
+        let wormhole = connect(code).await?;
+        let relay_hints = wormhole.relay_hints().to_vec();
+        let mut offer_error = None;
+        let policy = |info: transfer::OfferInfo| {
+            /* The name gets sanitized again when saving, this is only what we show */
+            let name = transfer::sanitize_file_name(&info.file_name).unwrap_or(&info.file_name);
+            let accept = match &on_offer {
+                Some(on_offer) => Python::with_gil(|py| {
+                    on_offer
+                        .call1(py, (name, info.file_size))?
+                        .bind(py)
+                        .is_truthy()
+                }),
+                None => Ok(true),
+            };
+            let decision = match accept {
+                Ok(true) => transfer::AcceptDecision::Accept,
+                Ok(false) => transfer::AcceptDecision::Reject,
+                Err(error) => {
+                    offer_error = Some(error);
+                    transfer::AcceptDecision::Reject
+                },
+            };
+            async move { decision }
+        };
+        /* Only transfer v1 for now, it is the only one interoperable with all other clients */
+        let received = transfer::receive_file(
+            wormhole,
+            relay_hints,
+            transit::Abilities::ALL_ABILITIES,
+            &target_dir,
+            policy,
+            |info| tracing::info!("{info}"),
+            progress_handler(on_progress),
+            cancel,
+        )
+        .await
+        .map_err(py_err)?;
+        if let Some(error) = offer_error {
+            return Err(error);
+        }
+        Ok(received.map(|(path, _receipt)| path))
+    })
+}
+
+/// Send a short text message
+#[pyfunction]
+#[pyo3(signature = (text, code_length = 2, on_code = None))]
+fn send_text(
+    py: Python<'_>,
+    text: String,
+    code_length: usize,
+    on_code: Option<PyObject>,
+) -> PyResult<Bound<'_, PyAny>> {
+    spawn(py, move |cancel| async move {
+        let wormhole = allocate_and_connect(code_length, &on_code).await?;
+        transfer::send_text(wormhole, text, cancel)
+            .await
+            .map_err(py_err)
+    })
+}
+
+/// Receive a short text message. File offers get rejected
+#[pyfunction]
+fn receive_text(py: Python<'_>, code: String) -> PyResult<Bound<'_, PyAny>> {
+    spawn(py, move |cancel| async move {
+        let wormhole = connect(code).await?;
+        let relay_hints = wormhole.relay_hints().to_vec();
+        match transfer::request_text_or_file(
+            wormhole,
+            relay_hints,
+            transit::Abilities::ALL_ABILITIES,
+            cancel,
+        )
+        .await
+        .map_err(py_err)?
+        {
+            Some(transfer::TextOrFileRequest::Text(text)) => Ok(Some(text)),
+            Some(transfer::TextOrFileRequest::File(request)) => {
+                request.reject().await.map_err(py_err)?;
+                Err(py_err("Expected a text message, but got a file offer"))
+            },
+            Some(_) => Err(py_err("Received an unsupported offer")),
+            None => Ok(None),
+        }
+    })
+}
+
+/** A request to the thread of a [`PyWormhole`], with the channel for the result */
+enum Command {
+    AllocateCode(usize, oneshot::Sender<PyResult<String>>),
+    SetCode(String, oneshot::Sender<PyResult<()>>),
+    GetVerifier(oneshot::Sender<PyResult<String>>),
+    SendMessage(Vec<u8>, oneshot::Sender<PyResult<()>>),
+    GetMessage(oneshot::Sender<PyResult<Cow<'static, [u8]>>>),
+    Close(oneshot::Sender<PyResult<()>>),
+}
+
+/** No app versions, like the Python implementation sends by default */
+type Versions = HashMap<String, String>;
+
+enum State {
+    Idle,
+    Pending(PendingWormhole<Versions>),
+    Connected(Wormhole),
+    Closed,
+}
+
+impl State {
+    /** Wait for the peer, unless that already happened */
+    async fn connected(&mut self) -> PyResult<&mut Wormhole> {
+        if let State::Pending(_) = self {
+            let State::Pending(pending) = std::mem::replace(self, State::Closed) else {
+                unreachable!()
+            };
+            *self = State::Connected(pending.wait_for_peer().await.map_err(py_err)?);
+        }
+        match self {
+            State::Connected(wormhole) => Ok(wormhole),
+            State::Idle => Err(py_err("Allocate or set a code first")),
+            State::Pending(_) => unreachable!(),
+            State::Closed => Err(py_err("The wormhole is closed")),
+        }
+    }
+
+    async fn close(&mut self) -> PyResult<()> {
+        match std::mem::replace(self, State::Closed) {
+            State::Pending(pending) => pending.cancel().await.map_err(py_err),
+            State::Connected(wormhole) => wormhole.close().await.map_err(py_err),
+            State::Idle | State::Closed => Ok(()),
+        }
+    }
+}
+
+/** Serve the requests of a [`PyWormhole`] one after another, until it gets dropped */
+async fn run_wormhole(config: AppConfig<Versions>, mut commands: mpsc::UnboundedReceiver<Command>) {
+    let mut state = State::Idle;
+    while let Some(command) = commands.next().await {
+        match command {
+            Command::AllocateCode(code_length, reply) => {
+                let result = match state {
+                    State::Idle => Wormhole::builder(config.clone())
+                        .code_length(code_length)
+                        .allocate_code()
+                        .await
+                        .map_err(py_err)
+                        .map(|pending| {
+                            let code = pending.code().to_string();
+                            state = State::Pending(pending);
+                            code
+                        }),
+                    _ => Err(py_err("The code has already been set")),
+                };
+                let _ = reply.send(result);
+            },
+            Command::SetCode(code, reply) => {
+                let result = match state {
+                    State::Idle => async {
+                        Wormhole::builder(config.clone())
+                            .connect_to_server()
+                            .await?
+                            .claim_code(Code::from(code))
+                            .await
+                    }
+                    .await
+                    .map_err(py_err)
+                    .map(|pending| state = State::Pending(pending)),
+                    _ => Err(py_err("The code has already been set")),
+                };
+                let _ = reply.send(result);
+            },
+            Command::GetVerifier(reply) => {
+                let result = state
+                    .connected()
+                    .await
+                    .map(|wormhole| hex::encode(wormhole.verifier()));
+                let _ = reply.send(result);
+            },
+            Command::SendMessage(message, reply) => {
+                let result = match state.connected().await {
+                    Ok(wormhole) => wormhole.send(message).await.map_err(py_err),
+                    Err(error) => Err(error),
+                };
+                let _ = reply.send(result);
+            },
+            Command::GetMessage(reply) => {
+                let result = match state.connected().await {
+                    Ok(wormhole) => wormhole.receive().await.map(Cow::Owned).map_err(py_err),
+                    Err(error) => Err(error),
+                };
+                let _ = reply.send(result);
+            },
+            Command::Close(reply) => {
+                let _ = reply.send(state.close().await);
+            },
+        }
+    }
+    if let Err(error) = state.close().await {
+        tracing::warn!("Failed to close the dropped wormhole: {}", error);
+    }
+}
+
+/// A wormhole to another client, created with `create()`
+///
+/// All methods return awaitables and are processed in the order they are called. So don't wait
+/// for a message while sending one.
+#[pyclass(name = "Wormhole")]
+struct PyWormhole {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl PyWormhole {
+    fn request<'py, T: IntoPy<PyObject> + Send + 'static>(
+        &self,
+        py: Python<'py>,
+        command: impl FnOnce(oneshot::Sender<PyResult<T>>) -> Command,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .unbounded_send(command(reply))
+            .map_err(|_| py_err("The wormhole is closed"))?;
+        pyo3_async_runtimes::async_std::future_into_py(py, async move {
+            result.await.map_err(|_| py_err("The wormhole is closed"))?
+        })
+    }
+}
+
+#[pymethods]
+impl PyWormhole {
+    /// Allocate a new code on the server, and return it
+    #[pyo3(signature = (code_length = 2))]
+    fn allocate_code<'py>(
+        &self,
+        py: Python<'py>,
+        code_length: usize,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.request(py, |reply| Command::AllocateCode(code_length, reply))
+    }
+
+    /// Use the code the other side allocated
+    fn set_code<'py>(&self, py: Python<'py>, code: String) -> PyResult<Bound<'py, PyAny>> {
+        self.request(py, |reply| Command::SetCode(code, reply))
+    }
+
+    /// Wait for the other side, and return the hex encoded verifier to compare with it
+    fn get_verifier<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.request(py, Command::GetVerifier)
+    }
+
+    /// Send some bytes to the other side
+    fn send_message<'py>(&self, py: Python<'py>, message: Vec<u8>) -> PyResult<Bound<'py, PyAny>> {
+        self.request(py, |reply| Command::SendMessage(message, reply))
+    }
+
+    /// Wait for the next message of the other side
+    fn get_message<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.request(py, Command::GetMessage)
+    }
+
+    /// Close the wormhole. Dropping it closes it too, but without telling about failures
+    fn close<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.request(py, Command::Close)
+    }
+}
+
+/// Create a wormhole for the given application ID, like `wormhole.create()` of the Python
+/// implementation. Both sides must use the same `appid` and `relay_url`.
+#[pyfunction]
+#[pyo3(signature = (appid, relay_url = rendezvous::DEFAULT_RENDEZVOUS_SERVER.to_owned()))]
+fn create(appid: String, relay_url: String) -> PyResult<PyWormhole> {
+    let config = AppConfig {
+        id: AppID::new(appid),
+        rendezvous_url: relay_url.into(),
+        app_version: Versions::new(),
+    };
+    let (commands, receiver) = mpsc::unbounded();
+    /* Our futures are not `Send`, so the wormhole lives on a thread of its own */
+    std::thread::Builder::new()
+        .name("wormhole".into())
+        .spawn(move || async_std::task::block_on(run_wormhole(config, receiver)))?;
+    Ok(PyWormhole { commands })
+}
+
+#[pymodule]
+fn magic_wormhole_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("WormholeError", m.py().get_type_bound::<WormholeError>())?;
+    m.add_class::<PyWormhole>()?;
+    m.add_function(wrap_pyfunction!(create, m)?)?;
+    m.add_function(wrap_pyfunction!(send_file, m)?)?;
+    m.add_function(wrap_pyfunction!(receive_file, m)?)?;
+    m.add_function(wrap_pyfunction!(send_text, m)?)?;
+    m.add_function(wrap_pyfunction!(receive_text, m)?)?;
+    Ok(())
+}
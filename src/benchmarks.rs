@@ -0,0 +1,42 @@
+//! Entry points for the benchmarks in `benches/`
+//!
+//! Code generation and message encryption are private, this module exposes them so that they can
+//! be measured on their own. Full transfers are measured with the public API instead.
+//!
+//! Not part of the public API, only available with the `benchmarks` feature.
+
+#![allow(deprecated)]
+
+use crate::core::{key, wordlist};
+use crypto_secretbox as secretbox;
+
+/// A wordlist to generate or complete codes with that many words
+pub struct Wordlist(wordlist::Wordlist);
+
+impl Wordlist {
+    /// The default PGP wordlist
+    pub fn new(num_words: usize) -> Self {
+        Self(wordlist::default_wordlist(num_words))
+    }
+
+    /// Generate the password part of a code
+    pub fn choose_words(&self) -> String {
+        self.0.choose_words()
+    }
+
+    /// Tab completion of a partially typed password
+    #[cfg(feature = "completion")]
+    pub fn get_completions(&self, prefix: &str) -> Vec<String> {
+        self.0.get_completions(prefix)
+    }
+}
+
+/// Encrypt with a secretbox, like every message to the other side
+pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+    key::encrypt_data(secretbox::Key::from_slice(key), plaintext).1
+}
+
+/// The reverse of [`encrypt`]
+pub fn decrypt(key: &[u8; 32], encrypted: &[u8]) -> Option<Vec<u8>> {
+    key::decrypt_data(secretbox::Key::from_slice(key), encrypted)
+}
@@ -0,0 +1,191 @@
+//! Synchronous wrappers for the common file transfer use cases
+//!
+//! Not everybody wants to pull in an async runtime just to send a file. The functions in here
+//! block the calling thread until they are done, driving the transfer on an internal executor.
+//! They are meant for command line tools, plugins and scripts. Applications that need more
+//! control should use the async [`transfer`](crate::transfer) API instead.
+//!
+//! All functions take a [`WormholeBuilder`], which is where the rendezvous server, the code
+//! length and timeouts can be configured. Start from
+//! [`transfer::APP_CONFIG`](crate::transfer::APP_CONFIG) for the default settings:
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> {
+//! use magic_wormhole::{blocking, transfer, Wormhole};
+//!
+//! blocking::send_file_blocking(
+//!     Wormhole::builder(transfer::APP_CONFIG),
+//!     "example.txt",
+//!     |code| println!("Wormhole code is: {code}"),
+//!     |sent, total| println!("{sent} of {total} bytes sent"),
+//! )?;
+//! # Ok(()) }
+//! ```
+//!
+//! Receiving only supports the file transfer protocol version 1, which is understood by all other
+//! clients.
+
+use crate::{
+    transfer::{self, AppVersion, TextOrFileRequest, TransferError},
+    transit, Code, Wormhole, WormholeBuilder,
+};
+use std::path::{Path, PathBuf};
+
+/// What [`receive_blocking`] received
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Received {
+    /// A text message
+    ///
+    /// **Security warning:** this is untrusted and unverified input
+    Text(String),
+    /// A file, stored at that path
+    File(PathBuf),
+}
+
+/** Blocking transfers can't be cancelled */
+fn never() -> futures::future::Pending<()> {
+    futures::future::pending()
+}
+
+async fn allocate_and_connect(
+    builder: WormholeBuilder<AppVersion>,
+    on_code: impl FnOnce(&Code),
+) -> Result<Wormhole, TransferError> {
+    let pending = builder.allocate_code().await?;
+    on_code(pending.code());
+    Ok(pending.wait_for_peer().await?)
+}
+
+/**
+ * Send a file or a folder
+ *
+ * Allocates a new code and passes it to `on_code`, which should show it to the user.
+ * Returns once the other side received everything or an error occurred.
+ */
+pub fn send_file_blocking(
+    builder: WormholeBuilder<AppVersion>,
+    path: impl AsRef<Path>,
+    on_code: impl FnOnce(&Code),
+    progress_handler: impl FnMut(u64, u64) + 'static,
+) -> Result<(), TransferError> {
+    let path = path.as_ref();
+    async_std::task::block_on(async {
+        let name = path
+            .file_name()
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid path: {}", path.display()),
+                )
+            })?
+            .to_string_lossy()
+            .into_owned();
+        let offer = transfer::offer::OfferSend::new_file_or_folder(name, path).await?;
+
+        let wormhole = allocate_and_connect(builder, on_code).await?;
+        let relay_hints = wormhole.relay_hints().to_vec();
+        transfer::send(
+            wormhole,
+            relay_hints,
+            transit::Abilities::ALL,
+            offer,
+            |info| tracing::info!("{info}"),
+            progress_handler,
+            never(),
+        )
+        .await
+    })
+}
+
+/**
+ * Send a short text message
+ *
+ * Allocates a new code and passes it to `on_code`, which should show it to the user.
+ * Returns once the other side acknowledged the message.
+ */
+pub fn send_text_blocking(
+    builder: WormholeBuilder<AppVersion>,
+    text: impl Into<String>,
+    on_code: impl FnOnce(&Code),
+) -> Result<(), TransferError> {
+    async_std::task::block_on(async {
+        let wormhole = allocate_and_connect(builder, on_code).await?;
+        transfer::send_text(wormhole, text, never()).await
+    })
+}
+
+/**
+ * Receive a text message or a file into `target_dir`
+ *
+ * File offers are passed to `accept` with their name and size first. Returns `None` if the
+ * offer got rejected. Existing files are never overwritten, and files are received atomically,
+ * see [`ReceiveRequestV1::accept_to_path`](transfer::ReceiveRequestV1::accept_to_path).
+ */
+pub fn receive_blocking(
+    builder: WormholeBuilder<AppVersion>,
+    code: Code,
+    target_dir: impl AsRef<Path>,
+    accept: impl FnOnce(&str, u64) -> bool,
+    progress_handler: impl FnMut(u64, u64) + 'static,
+) -> Result<Option<Received>, TransferError> {
+    async_std::task::block_on(async {
+        let wormhole = builder.connect_with_code(code).await?;
+        let relay_hints = wormhole.relay_hints().to_vec();
+        let request = match transfer::request_text_or_file(
+            wormhole,
+            relay_hints,
+            transit::Abilities::ALL,
+            never(),
+        )
+        .await?
+        {
+            Some(TextOrFileRequest::Text(text)) => return Ok(Some(Received::Text(text))),
+            Some(TextOrFileRequest::File(request)) => request,
+            None => unreachable!("Blocking transfers can't be cancelled"),
+        };
+
+        /* Never trust the peer with paths */
+        let offered_name = request.file_name();
+        let Some(file_name) = transfer::sanitize_file_name(&offered_name) else {
+            request.reject().await?;
+            return Err(TransferError::Protocol(
+                format!("Invalid file name: {}", offered_name).into(),
+            ));
+        };
+        if !accept(file_name, request.file_size()) {
+            request.reject().await?;
+            return Ok(None);
+        }
+
+        let file_path = target_dir.as_ref().join(file_name);
+        request
+            .accept_to_path(
+                |info| tracing::info!("{info}"),
+                progress_handler,
+                &file_path,
+                false,
+                never(),
+            )
+            .await?
+            .expect("Blocking transfers can't be cancelled");
+        Ok(Some(Received::File(file_path)))
+    })
+}
+
+/**
+ * Receive a text message
+ *
+ * File offers are rejected with [`TransferError::UnsupportedOffer`].
+ */
+pub fn receive_text_blocking(
+    builder: WormholeBuilder<AppVersion>,
+    code: Code,
+) -> Result<String, TransferError> {
+    async_std::task::block_on(async {
+        let wormhole = builder.connect_with_code(code).await?;
+        Ok(transfer::receive_text(wormhole, never())
+            .await?
+            .expect("Blocking transfers can't be cancelled"))
+    })
+}
@@ -0,0 +1,191 @@
+//! Tab completion of codes in interactive prompts
+//!
+//! [`CodeCompleter`] implements the traits of [`rustyline`](https://docs.rs/rustyline), so it can
+//! be used as the helper of an editor reading a code. Before the first dash, it completes the
+//! nameplates currently claimed on the rendezvous server. After that, it completes the words of
+//! the [`Wordlist`].
+//!
+//! The nameplates are fetched with [`ServerConnection::list_nameplates`]. Refresh them with
+//! [`CodeCompleter::refresh_nameplates`] before reading the code, or feed them in from elsewhere
+//! with [`CodeCompleter::set_nameplates`]. All clones of a completer share the same nameplates,
+//! so the editor sees an update made from another task.
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::{
+//!     completer::CodeCompleter, transfer::APP_CONFIG, wordlist, Code, Wormhole,
+//! };
+//!
+//! let mut server = Wormhole::builder(APP_CONFIG).connect_to_server().await?;
+//! let completer = CodeCompleter::new(wordlist::default_wordlist(2));
+//! completer.refresh_nameplates(&mut server).await?;
+//!
+//! let mut editor = rustyline::Editor::new()?;
+//! editor.set_helper(Some(completer));
+//! let code = editor.readline("Enter the code: ")?;
+//! let wormhole = server
+//!     .claim_code(Code::parse(&code)?)
+//!     .await?
+//!     .wait_for_peer()
+//!     .await?;
+//! # Ok(()) })}
+//! ```
+
+use crate::{core::wordlist::Wordlist, Nameplate, ServerConnection, WormholeError};
+use rustyline::{
+    completion::{Completer, Pair},
+    highlight::Highlighter,
+    hint::Hinter,
+    validate::Validator,
+    Context, Helper,
+};
+use std::sync::{Arc, Mutex};
+
+/// Completes codes from the claimed nameplates and a [`Wordlist`], see the [module documentation](self)
+#[derive(Clone)]
+pub struct CodeCompleter {
+    wordlist: Wordlist,
+    nameplates: Arc<Mutex<Vec<Nameplate>>>,
+}
+
+impl CodeCompleter {
+    /// Complete the words of codes from `wordlist`, without any nameplates yet
+    pub fn new(wordlist: Wordlist) -> Self {
+        Self {
+            wordlist,
+            nameplates: Arc::default(),
+        }
+    }
+
+    /// Fetch the currently claimed nameplates from the rendezvous server
+    pub async fn refresh_nameplates<V: serde::Serialize + Send + Sync + 'static>(
+        &self,
+        server: &mut ServerConnection<V>,
+    ) -> Result<(), WormholeError> {
+        let nameplates = server.list_nameplates().await?;
+        self.set_nameplates(nameplates);
+        Ok(())
+    }
+
+    /// Replace the nameplates to complete
+    pub fn set_nameplates(&self, mut nameplates: Vec<Nameplate>) {
+        nameplates.sort_by(|a, b| {
+            let (a, b): (&str, &str) = (a.as_ref(), b.as_ref());
+            (a.len(), a).cmp(&(b.len(), b))
+        });
+        *self.nameplates.lock().unwrap() = nameplates;
+    }
+
+    /**
+     * The candidates for the code typed so far
+     *
+     * Returns the position from which on the candidates replace the input, like
+     * [`Completer::complete`].
+     */
+    pub fn complete_code(&self, line: &str) -> (usize, Vec<Pair>) {
+        match line.find('-') {
+            None => {
+                let candidates = self
+                    .nameplates
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(AsRef::<str>::as_ref)
+                    .filter(|nameplate| nameplate.starts_with(line))
+                    .map(|nameplate| Pair {
+                        display: nameplate.to_owned(),
+                        replacement: format!("{nameplate}-"),
+                    })
+                    .collect();
+                (0, candidates)
+            },
+            Some(dash) => {
+                let start = dash + 1;
+                let candidates = self
+                    .wordlist
+                    .get_completions(&line[start..])
+                    .into_iter()
+                    .map(|completion| Pair {
+                        display: completion.clone(),
+                        replacement: completion,
+                    })
+                    .collect();
+                (start, candidates)
+            },
+        }
+    }
+}
+
+impl std::fmt::Debug for CodeCompleter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CodeCompleter")
+            .field("nameplates", &self.nameplates.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Completer for CodeCompleter {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(self.complete_code(&line[..pos]))
+    }
+}
+
+impl Hinter for CodeCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for CodeCompleter {}
+
+impl Validator for CodeCompleter {}
+
+impl Helper for CodeCompleter {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn replacements(completions: (usize, Vec<Pair>)) -> (usize, Vec<String>) {
+        let (start, candidates) = completions;
+        (
+            start,
+            candidates
+                .into_iter()
+                .map(|pair| pair.replacement)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_complete_code() {
+        let completer = CodeCompleter::new(Wordlist::new(
+            2,
+            vec![vec!["purple".into(), "green".into(), "yellow".into()]],
+        ));
+        assert_eq!(replacements(completer.complete_code("1")), (0, vec![]));
+
+        completer.set_nameplates(["12", "3", "1"].map(Nameplate::new).to_vec());
+        assert_eq!(
+            replacements(completer.complete_code("")),
+            (0, vec!["1-".into(), "3-".into(), "12-".into()])
+        );
+        assert_eq!(
+            replacements(completer.complete_code("1")),
+            (0, vec!["1-".into(), "12-".into()])
+        );
+        assert_eq!(
+            replacements(completer.complete_code("12-p")),
+            (3, vec!["purple-".into()])
+        );
+        assert_eq!(
+            replacements(completer.complete_code("12-purple-ye")),
+            (3, vec!["purple-yellow".into()])
+        );
+    }
+}
@@ -1,16 +1,32 @@
 #![allow(deprecated)]
 
+mod app_messages;
+mod builder;
+mod confirm;
+mod events;
 pub(super) mod key;
+mod machine;
 pub mod rendezvous;
 mod server_messages;
 #[cfg(test)]
 mod test;
-mod wordlist;
+mod timeouts;
+mod typed;
+mod versions;
+pub mod wordlist;
 
+use crate::protocol;
 use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
 
-use self::{rendezvous::*, server_messages::EncryptedMessage};
+use self::{app_messages::AppMessages, rendezvous::*, server_messages::EncryptedMessage};
+pub use app_messages::AppMessageSender;
+pub use builder::{PendingWormhole, ServerConnection, WormholeBuilder};
+pub use confirm::ConfirmationHandler;
+pub(crate) use events::EventSender;
+pub use events::WormholeEvent;
+pub use timeouts::Timeouts;
+pub use versions::AppVersions;
 
 use crypto_secretbox as secretbox;
 
@@ -51,12 +67,64 @@ pub enum WormholeError {
     /// Nameplate is unclaimed
     #[error("Nameplate is unclaimed: {}", _0)]
     UnclaimedNameplate(Nameplate),
+    /// The operation did not complete within the configured timeout
+    #[error("The operation timed out")]
+    Timeout,
+    /// The other side closed the connection with [`Wormhole::close_with`]
+    #[error(
+        "The other side closed the connection ({}){}",
+        _0.mood,
+        _0.reason.as_deref().map(|reason| format!(": {}", reason)).unwrap_or_default()
+    )]
+    PeerClosed(CloseReason),
+    /// The entered code does not match the [`CodeFormat`](wordlist::CodeFormat)
+    #[error("Invalid code")]
+    InvalidCode(
+        #[from]
+        #[source]
+        wordlist::CodeFormatError,
+    ),
+    /// The user did not confirm the verifier, see [`ConfirmationHandler`]
+    #[error("The verifier was rejected, the connection might be under attack")]
+    VerifierRejected,
 }
 
+#[cfg(feature = "serde-types")]
+impl_serialize_error!(WormholeError);
+
 impl WormholeError {
     /** Should we tell the server that we are "errory" or "scared"? */
     pub fn is_scared(&self) -> bool {
-        matches!(self, Self::PakeFailed)
+        matches!(self, Self::PakeFailed | Self::VerifierRejected)
+    }
+
+    /**
+     * Whether trying again might succeed
+     *
+     * This is the case for network failures and timeouts. A wrong or crowded code, a rejected
+     * verifier or an error on the other side need the user to do something about it first.
+     */
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ServerError(error) => error.is_retryable(),
+            Self::Timeout => true,
+            _ => false,
+        }
+    }
+
+    /** A short label for the [`Metrics`](crate::metrics::Metrics) */
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            Self::ProtocolJson(_) | Self::Protocol(_) => "protocol",
+            Self::ServerError(_) => "server",
+            Self::PakeFailed => "pake_failed",
+            Self::Crypto => "crypto",
+            Self::UnclaimedNameplate(_) => "unclaimed_nameplate",
+            Self::Timeout => "timeout",
+            Self::PeerClosed(_) => "peer_closed",
+            Self::InvalidCode(_) => "invalid_code",
+            Self::VerifierRejected => "verifier_rejected",
+        }
     }
 }
 
@@ -92,7 +160,21 @@ pub struct WormholeWelcome {
  *
  * # Clean shutdown
  *
- * TODO
+ * Call [`Wormhole::close`] once you are done, which tells the server to free the mailbox.
+ * Before that, a [`MailboxConnection`] can be released using [`MailboxConnection::shutdown`].
+ *
+ * # Cancellation
+ *
+ * All futures of this crate are safe to drop at any time. Dropping them closes the connection to
+ * the rendezvous server and aborts all pending transit connection attempts. The only work done in
+ * the background is minting a hashcash stamp for servers that require one, which runs on the
+ * blocking thread pool until it is done, and the tasks of the port forwarding, one per forwarded
+ * connection, which get aborted together with the future.
+ *
+ * However, the server won't know why the connection got lost: the claimed nameplate and the
+ * mailbox stay allocated until the server cleans them up by itself. Prefer the explicit methods
+ * mentioned above, [`PendingWormhole::wait_for_peer_or_cancel`], or the `cancel` arguments of the
+ * `transfer` and `forwarding` functions, which close everything gracefully.
  */
 /* TODO
  * Maybe a better way to handle application level protocols is to create a trait for them and then
@@ -111,6 +193,8 @@ pub struct MailboxConnection<V: serde::Serialize + Send + Sync + 'static> {
     mailbox: Mailbox,
     /// The Code which is required to connect to the mailbox.
     code: Code,
+    /// Subscribers to the events of this connection, carried over into the `Wormhole`
+    events: EventSender,
 }
 
 impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
@@ -158,8 +242,19 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         config: AppConfig<V>,
         password: &str,
     ) -> Result<Self, WormholeError> {
-        let (mut server, welcome) =
+        let (server, welcome) =
             RendezvousServer::connect(&config.id, &config.rendezvous_url).await?;
+        Self::allocate_on(config, server, welcome, password).await
+    }
+
+    /// Like [`create_with_password`](Self::create_with_password), on an existing server connection
+    #[tracing::instrument(name = "mailbox", skip_all, fields(appid = %config.id))]
+    pub(crate) async fn allocate_on(
+        config: AppConfig<V>,
+        mut server: RendezvousServer,
+        welcome: Option<String>,
+        password: &str,
+    ) -> Result<Self, WormholeError> {
         let (nameplate, mailbox) = server.allocate_claim_open().await?;
         let code = Code::new(&nameplate, password);
 
@@ -169,6 +264,28 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
             mailbox,
             code,
             welcome,
+            events: EventSender::default(),
+        })
+    }
+
+    /// Open the mailbox of a [`Seed`](crate::seeds::Seed) directly, without any nameplate
+    #[tracing::instrument(name = "mailbox", skip_all, fields(appid = %config.id, nameplate = "seed"))]
+    pub(crate) async fn open_seed_on(
+        config: AppConfig<V>,
+        mut server: RendezvousServer,
+        welcome: Option<String>,
+        seed: &crate::seeds::Seed,
+    ) -> Result<Self, WormholeError> {
+        let mailbox = seed.mailbox();
+        server.open_directly(mailbox.clone()).await?;
+
+        Ok(MailboxConnection {
+            config,
+            server,
+            mailbox,
+            code: seed.code(),
+            welcome,
+            events: EventSender::default(),
         })
     }
 
@@ -197,8 +314,24 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         code: Code,
         allocate: bool,
     ) -> Result<Self, WormholeError> {
-        let (mut server, welcome) =
+        let (server, welcome) =
             RendezvousServer::connect(&config.id, &config.rendezvous_url).await?;
+        Self::claim_on(config, server, welcome, code, allocate).await
+    }
+
+    /// Like [`connect`](Self::connect), on an existing server connection
+    #[tracing::instrument(
+        name = "mailbox",
+        skip_all,
+        fields(appid = %config.id, nameplate = %code.nameplate())
+    )]
+    pub(crate) async fn claim_on(
+        config: AppConfig<V>,
+        mut server: RendezvousServer,
+        welcome: Option<String>,
+        code: Code,
+        allocate: bool,
+    ) -> Result<Self, WormholeError> {
         let nameplate = code.nameplate();
         if !allocate {
             let nameplates = server.list_nameplates().await?;
@@ -215,6 +348,7 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
             mailbox,
             code,
             welcome,
+            events: EventSender::default(),
         })
     }
 
@@ -240,7 +374,16 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         self.server
             .shutdown(mood)
             .await
-            .map_err(WormholeError::ServerError)
+            .map_err(WormholeError::ServerError)?;
+        self.events.emit(WormholeEvent::Closed { mood });
+        Ok(())
+    }
+
+    /// Subscribe to the events of this connection
+    ///
+    /// The stream continues with the events of the [`Wormhole`] created from this connection.
+    pub fn events(&self) -> impl futures::Stream<Item = WormholeEvent> + Send + Unpin + 'static {
+        self.events.subscribe()
     }
 
     /// The welcome message received from the mailbox server
@@ -248,6 +391,16 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         self.welcome.as_deref()
     }
 
+    /// Everything the mailbox server told us in its welcome message, see [`ServerWelcome`]
+    pub fn server_welcome(&self) -> &ServerWelcome {
+        self.server.server_welcome()
+    }
+
+    /// Our side, see [`Wormhole::side`]
+    pub fn side(&self) -> &str {
+        self.server.side().as_str()
+    }
+
     /// The mailbox id of the created mailbox
     pub fn mailbox(&self) -> &Mailbox {
         &self.mailbox
@@ -278,6 +431,19 @@ pub struct Wormhole {
     /// The app version of the peer
     #[deprecated(since = "0.7.0", note = "Use the peer_version() method")]
     pub peer_version: serde_json::Value,
+    events: EventSender,
+    timeouts: Timeouts,
+    #[cfg(feature = "transit")]
+    rate_limit: crate::transit::RateLimit,
+    #[cfg(feature = "transit")]
+    relay_hints: Vec<crate::transit::RelayHint>,
+    #[cfg(feature = "transfer")]
+    offer_digests: bool,
+    app_messages: AppMessages,
+    /** Regular messages that arrived while we were only looking for application messages */
+    received: std::collections::VecDeque<Vec<u8>>,
+    /** Set once the peer told us it is going away */
+    peer_closed: Option<CloseReason>,
 }
 
 impl Wormhole {
@@ -343,109 +509,332 @@ impl Wormhole {
     pub async fn connect(
         mailbox_connection: MailboxConnection<impl serde::Serialize + Send + Sync + 'static>,
     ) -> Result<Self, WormholeError> {
+        Ok(
+            Self::connect_or_cancel(mailbox_connection, futures::future::pending())
+                .await?
+                .expect("Never cancelled"),
+        )
+    }
+
+    /**
+     * Like [`connect`](Self::connect), but give up once `cancel` completes
+     *
+     * Cancelling releases the nameplate, closes the mailbox and disconnects from the server, then
+     * returns `None`.
+     */
+    #[tracing::instrument(
+        name = "pake",
+        skip_all,
+        fields(
+            appid = %mailbox_connection.config.id,
+            side = %**mailbox_connection.server.side(),
+            nameplate = %mailbox_connection.code.nameplate()
+        )
+    )]
+    pub async fn connect_or_cancel(
+        mailbox_connection: MailboxConnection<impl serde::Serialize + Send + Sync + 'static>,
+        cancel: impl futures::Future<Output = ()>,
+    ) -> Result<Option<Self>, WormholeError> {
+        use futures::future::Either;
+
         let MailboxConnection {
             config,
             mut server,
             mailbox: _mailbox,
             code,
             welcome: _welcome,
+            events,
         } = mailbox_connection;
 
-        /* Send PAKE */
-        let (pake_state, pake_msg_ser) = key::make_pake(code.as_ref(), &config.id);
-        server.send_peer_message(Phase::PAKE, pake_msg_ser).await?;
-
-        /* Receive PAKE */
-        let peer_pake = key::extract_pake_msg(&server.next_peer_message_some().await?.body)?;
-        let key = pake_state
-            .finish(&peer_pake)
-            .map_err(|_| WormholeError::PakeFailed)
-            .map(|key| *secretbox::Key::from_slice(&key))?;
-
-        /* Send versions message */
-        let mut versions = key::VersionsMessage::new();
-        versions.set_app_versions(serde_json::to_value(&config.app_version).unwrap());
-        let (version_phase, version_msg) = key::build_version_msg(server.side(), &key, &versions);
-        server.send_peer_message(version_phase, version_msg).await?;
-        let peer_version = server.next_peer_message_some().await?;
-
-        /* Handle received message */
-        let versions: key::VersionsMessage = peer_version
-            .decrypt(&key)
-            .ok_or(WormholeError::PakeFailed)
-            .and_then(|plaintext| {
-                serde_json::from_slice(&plaintext).map_err(WormholeError::ProtocolJson)
-            })?;
-
-        let peer_version = versions.app_versions;
+        /* Do the key exchange, by driving the state machine over the mailbox */
+        let (mut machine, actions) = machine::KeyMachine::start(
+            code.as_ref(),
+            &config.id,
+            server.side().clone(),
+            serde_json::to_value(&config.app_version)?,
+            server.rng(),
+        );
+        let exchanged = {
+            let exchange = async {
+                let mut actions = std::collections::VecDeque::from(actions);
+                loop {
+                    match actions.pop_front() {
+                        Some(machine::Action::Send(phase, body)) => {
+                            server.send_peer_message(phase, body).await?
+                        },
+                        Some(machine::Action::Emit(event)) => events.emit(event),
+                        Some(machine::Action::Established {
+                            key,
+                            verifier,
+                            peer_versions,
+                        }) => break Ok::<_, WormholeError>((key, verifier, peer_versions)),
+                        None => {
+                            actions.extend(machine.receive(server.next_peer_message_some().await?)?)
+                        },
+                    }
+                }
+            };
+            futures::pin_mut!(exchange, cancel);
+            match futures::future::select(exchange, cancel).await {
+                Either::Left((result, _)) => Some(result?),
+                Either::Right(((), _)) => None,
+            }
+        };
+        let Some((key, verifier, peer_version)) = exchanged else {
+            tracing::info!("Cancelled waiting for the peer.");
+            server.shutdown(Mood::Lonely).await?;
+            events.emit(WormholeEvent::Closed { mood: Mood::Lonely });
+            return Ok(None);
+        };
 
         if server.needs_nameplate_release() {
             server.release_nameplate().await?;
+            events.emit(WormholeEvent::NameplateReleased);
         }
 
         tracing::info!("Found peer on the rendezvous server.");
 
         /* We are now fully initialized! Up and running! :tada: */
         #[allow(deprecated)]
-        Ok(Self {
+        Ok(Some(Self {
             server,
             appid: config.id,
             phase: 0,
-            key: key::Key::new(key.into()),
-            verifier: Box::new(key::derive_verifier(&key)),
+            key,
+            verifier,
             our_version: Box::new(config.app_version),
             peer_version,
-        })
+            events,
+            timeouts: Timeouts::default(),
+            #[cfg(feature = "transit")]
+            rate_limit: Default::default(),
+            #[cfg(feature = "transit")]
+            relay_hints: crate::transit::default_relay_hints(),
+            #[cfg(feature = "transfer")]
+            offer_digests: false,
+            app_messages: AppMessages::default(),
+            received: Default::default(),
+            peer_closed: None,
+        }))
     }
 
     /** Send an encrypted message to peer */
     pub async fn send(&mut self, plaintext: Vec<u8>) -> Result<(), WormholeError> {
-        let phase_string = Phase::numeric(self.phase);
+        self.flush_app_messages().await?;
+        let phase = Phase::numeric(self.phase);
         self.phase += 1;
-        let data_key = key::derive_phase_key(self.server.side(), &self.key, &phase_string);
-        let (_nonce, encrypted) = key::encrypt_data(&data_key, &plaintext);
-        self.server
-            .send_peer_message(phase_string, encrypted)
-            .await?;
+        self.send_phase(phase, &plaintext).await
+    }
+
+    async fn send_phase(&mut self, phase: Phase, plaintext: &[u8]) -> Result<(), WormholeError> {
+        let data_key =
+            zeroize::Zeroizing::new(key::derive_phase_key(self.server.side(), &self.key, &phase));
+        let (_nonce, encrypted) = key::encrypt_data(&data_key, plaintext);
+        self.server.send_peer_message(phase, encrypted).await?;
         Ok(())
     }
 
     /**
-     * Serialize and send an encrypted message to peer
+     * Send an application message to the peer
      *
-     * This will serialize the message as `json` string, which is most commonly
-     * used by upper layer protocols. The serialization may not fail
+     * Application messages are small encrypted messages, independent of the protocol using the
+     * wormhole. Use them for chat, metadata or anything else your application wants to tell
+     * the other side. The peer receives them with [`app_messages`](Self::app_messages). Other
+     * implementations of Magic Wormhole ignore them.
      *
-     * ## Panics
+     * To send application messages while the wormhole is in use by a file transfer, use an
+     * [`app_message_sender`](Self::app_message_sender) instead.
+     */
+    pub async fn send_app_message(
+        &mut self,
+        message: impl Into<Vec<u8>>,
+    ) -> Result<(), WormholeError> {
+        let phase = Phase::app(self.app_messages.phase);
+        self.app_messages.phase += 1;
+        self.send_phase(phase, &message.into()).await
+    }
+
+    /** Send the application messages queued with an [`AppMessageSender`] */
+    async fn flush_app_messages(&mut self) -> Result<(), WormholeError> {
+        while let Some(message) = self.app_messages.try_next_outgoing() {
+            self.send_app_message(message).await?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Subscribe to the application messages sent by the peer
      *
-     * If the serialization fails
+     * Messages are only received while the wormhole is in use, i.e. while waiting for a regular
+     * message or during a file transfer. Messages arriving before subscribing are lost.
+     *
+     * **Security warning:** this is untrusted input
+     */
+    pub fn app_messages(&self) -> impl futures::Stream<Item = Vec<u8>> + Send + Unpin + 'static {
+        self.app_messages.subscribe()
+    }
+
+    /** Get a handle to queue application messages, even while a file transfer owns the wormhole */
+    pub fn app_message_sender(&self) -> AppMessageSender {
+        self.app_messages.sender()
+    }
+
+    /**
+     * Run a future, while sending and receiving application messages in the background
+     *
+     * Regular messages arriving in the meantime are kept for [`receive`](Self::receive).
+     * If the connection to the server fails, the future continues to run but application messages
+     * stop working.
+     */
+    pub(crate) async fn drive_app_messages<T>(
+        &mut self,
+        future: impl futures::Future<Output = T>,
+    ) -> T {
+        use futures::future::Either;
+
+        futures::pin_mut!(future);
+        loop {
+            let next = {
+                let step = async {
+                    let incoming = self.server.next_peer_message();
+                    let outgoing = self.app_messages.next_outgoing();
+                    futures::pin_mut!(incoming, outgoing);
+                    match futures::future::select(incoming, outgoing).await {
+                        Either::Left((message, _)) => Either::Left(message),
+                        Either::Right((message, _)) => Either::Right(message),
+                    }
+                };
+                futures::pin_mut!(step);
+                match futures::future::select(&mut future, step).await {
+                    Either::Left((value, _)) => return value,
+                    Either::Right((next, _)) => next,
+                }
+            };
+            let result = match next {
+                /* Sending is done outside of the `select`, so that it doesn't get interrupted */
+                Either::Right(outgoing) => self.send_app_message(outgoing).await,
+                Either::Left(incoming) => {
+                    incoming.map_err(WormholeError::from).and_then(|message| {
+                        if let Some(message) = self.handle_message(message)? {
+                            self.received.push_back(message);
+                        }
+                        Ok(())
+                    })
+                },
+            };
+            if let Err(error) = result {
+                tracing::warn!("Application messages stopped working: {}", error);
+                return future.await;
+            }
+        }
+    }
+
+    /**
+     * Serialize and send an encrypted message to peer
+     *
+     * This will serialize the message as `json` string, which is most commonly
+     * used by upper layer protocols. Fails with [`WormholeError::ProtocolJson`] if the message
+     * cannot be serialized.
      */
     pub async fn send_json<T: serde::Serialize>(
         &mut self,
         message: &T,
     ) -> Result<(), WormholeError> {
-        self.send(serde_json::to_vec(message).unwrap()).await
+        self.send(serde_json::to_vec(message)?).await
+    }
+
+    /**
+     * Serialize and send a typed message to peer
+     *
+     * Unlike [`send_json`](Self::send_json), the message is wrapped into an envelope carrying
+     * the encoding version. Use this for custom application protocols, and receive the messages
+     * with [`receive_typed`](Self::receive_typed) on the other side. Fails with
+     * [`WormholeError::ProtocolJson`] if the message cannot be serialized.
+     */
+    pub async fn send_typed<T: serde::Serialize>(
+        &mut self,
+        message: &T,
+    ) -> Result<(), WormholeError> {
+        let data = typed::encode(message)?;
+        self.send(data).await
+    }
+
+    /**
+     * Receive and deserialize a typed message sent with [`send_typed`](Self::send_typed)
+     *
+     * Fails with [`WormholeError::ProtocolJson`] if the message does not match `T`, and with
+     * [`WormholeError::Protocol`] if the peer uses an unsupported encoding version.
+     *
+     * **Security warning:** this is untrusted input
+     */
+    pub async fn receive_typed<T: serde::de::DeserializeOwned>(
+        &mut self,
+    ) -> Result<T, WormholeError> {
+        let data = self.receive().await?;
+        typed::decode(&data).map_err(|error| {
+            tracing::error!(
+                "Received invalid typed message from peer: '{}'",
+                String::from_utf8_lossy(&data)
+            );
+            error
+        })
     }
 
     /** Receive an encrypted message from peer */
     pub async fn receive(&mut self) -> Result<Vec<u8>, WormholeError> {
+        if let Some(message) = self.received.pop_front() {
+            return Ok(message);
+        }
+        if let Some(reason) = &self.peer_closed {
+            bail!(WormholeError::PeerClosed(reason.clone()));
+        }
+        self.flush_app_messages().await?;
         loop {
             let peer_message = match self.server.next_peer_message().await? {
                 Some(peer_message) => peer_message,
                 None => continue,
             };
-            if peer_message.phase.to_num().is_none() {
-                // TODO: log and ignore, for future expansion
-                todo!("log and ignore, for future expansion");
+            if let Some(message) = self.handle_message(peer_message)? {
+                return Ok(message);
             }
+        }
+    }
 
-            // TODO maybe reorder incoming messages by phase numeral?
-            let decrypted_message = peer_message
-                .decrypt(&self.key)
-                .ok_or(WormholeError::Crypto)?;
+    /**
+     * Decrypt a message from the peer
+     *
+     * Application messages are dispatched to their subscribers, regular ones returned.
+     */
+    fn handle_message(
+        &mut self,
+        peer_message: EncryptedMessage,
+    ) -> Result<Option<Vec<u8>>, WormholeError> {
+        if peer_message.phase.to_num().is_none()
+            && !peer_message.phase.is_app()
+            && !peer_message.phase.is_close()
+        {
+            tracing::warn!(
+                "Ignoring message with unknown phase '{}'",
+                peer_message.phase.as_ref()
+            );
+            return Ok(None);
+        }
 
-            // Send to client
-            return Ok(decrypted_message);
+        // TODO maybe reorder incoming messages by phase numeral?
+        let decrypted_message = peer_message
+            .decrypt(&self.key)
+            .ok_or(WormholeError::Crypto)?;
+
+        if peer_message.phase.is_app() {
+            self.app_messages.dispatch(decrypted_message);
+            Ok(None)
+        } else if peer_message.phase.is_close() {
+            let reason: CloseReason = serde_json::from_slice(&decrypted_message)?;
+            tracing::debug!("Peer closed the connection: {:?}", reason);
+            self.peer_closed = Some(reason.clone());
+            Err(WormholeError::PeerClosed(reason))
+        } else {
+            Ok(Some(decrypted_message))
         }
     }
 
@@ -472,9 +861,218 @@ impl Wormhole {
     }
 
     /// Close the wormhole
+    ///
+    /// Fails with [`WormholeError::Timeout`] if the server does not respond within [`Timeouts::close`].
     pub async fn close(self) -> Result<(), WormholeError> {
         tracing::debug!("Closing Wormhole…");
-        self.server.shutdown(Mood::Happy).await.map_err(Into::into)
+        crate::util::timeout_opt(self.timeouts.close, self.server.shutdown(Mood::Happy))
+            .await
+            .map_err(|()| WormholeError::Timeout)??;
+        self.events
+            .emit(WormholeEvent::Closed { mood: Mood::Happy });
+        Ok(())
+    }
+
+    /**
+     * Close the wormhole, telling the peer why
+     *
+     * Sends a final message with the `mood` and a human readable `reason`, like "cancelled by
+     * user" or "disk full", and reports the `mood` to the server. If the peer is waiting for a
+     * message, it gets a [`WormholeError::PeerClosed`] with them. Other implementations of Magic
+     * Wormhole ignore the message.
+     *
+     * Fails with [`WormholeError::Timeout`] if the server does not respond within [`Timeouts::close`].
+     */
+    pub async fn close_with(
+        mut self,
+        mood: Mood,
+        reason: impl Into<String>,
+    ) -> Result<(), WormholeError> {
+        tracing::debug!("Closing Wormhole ({})…", mood);
+        let reason = CloseReason {
+            mood,
+            reason: Some(reason.into()),
+        };
+        let message = serde_json::to_vec(&reason)?;
+        crate::util::timeout_opt(self.timeouts.close, async {
+            self.flush_app_messages().await?;
+            self.send_phase(Phase::CLOSE, &message).await
+        })
+        .await
+        .map_err(|()| WormholeError::Timeout)??;
+        crate::util::timeout_opt(self.timeouts.close, self.server.shutdown(mood))
+            .await
+            .map_err(|()| WormholeError::Timeout)??;
+        self.events.emit(WormholeEvent::Closed { mood });
+        Ok(())
+    }
+
+    /**
+     * Subscribe to the events of this wormhole
+     *
+     * The events of the transfers done with this wormhole are included too. The stream ends once
+     * the wormhole and everything using it are gone, so a single `while let` loop can drive a UI:
+     *
+     * ```no_run
+     * # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+     * use futures::StreamExt;
+     * use magic_wormhole::{transfer::APP_CONFIG, Wormhole, WormholeEvent};
+     *
+     * let pending = Wormhole::builder(APP_CONFIG).allocate_code().await?;
+     * let mut events = pending.events();
+     * async_std::task::spawn(async move {
+     *     while let Some(event) = events.next().await {
+     *         println!("{:?}", event);
+     *     }
+     * });
+     * let wormhole = pending.wait_for_peer().await?;
+     * # Ok(()) })}
+     * ```
+     */
+    pub fn events(&self) -> impl futures::Stream<Item = WormholeEvent> + Send + Unpin + 'static {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn event_sender(&self) -> &EventSender {
+        &self.events
+    }
+
+    /**
+     * Dump the internal state as JSON, to attach to bug reports
+     *
+     * Covers the mailbox on the rendezvous server with its queued and processed messages, the
+     * phase counters and the configured timeouts. Transit connections only live as long as a
+     * transfer and are not included. Keys, the verifier and the contents of messages are left
+     * out, messages are only described by their side, phase and length.
+     *
+     * The format is meant for humans and may change in any release.
+     */
+    pub fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "appid": self.appid.to_string(),
+            "rendezvous": self.server.debug_state(),
+            "key": "established",
+            "phase": self.phase,
+            "app_message_phase": self.app_messages.phase,
+            "received_messages": self.received.len(),
+            "peer_closed": self.peer_closed.as_ref().map(|reason| reason.mood.to_string()),
+            "timeouts": format!("{:?}", self.timeouts),
+        })
+    }
+
+    /**
+     * The state machines of the protocol as a graphviz graph
+     *
+     * Render it with `dot -Tsvg`. This describes the protocol in general, see
+     * [`debug_state`](Self::debug_state) for the current state of a connection.
+     */
+    pub fn machines_graphviz() -> String {
+        use std::fmt::Write;
+
+        let mut graph = String::from("digraph wormhole {\n");
+        for (name, transitions) in [
+            ("mailbox", rendezvous::MAILBOX_TRANSITIONS),
+            ("key", machine::KEY_TRANSITIONS),
+        ] {
+            writeln!(graph, "  subgraph cluster_{name} {{").unwrap();
+            writeln!(graph, "    label=\"{name}\";").unwrap();
+            for (from, to, trigger) in transitions {
+                writeln!(
+                    graph,
+                    "    \"{name}_{from}\" -> \"{name}_{to}\" [label=\"{trigger}\"];"
+                )
+                .unwrap();
+            }
+            graph += "  }\n";
+        }
+        graph += "}\n";
+        graph
+    }
+
+    /// The timeouts configured with [`WormholeBuilder::timeouts`]
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    /// The speed limit of the transit connections made with this wormhole
+    #[cfg(feature = "transit")]
+    pub fn rate_limit(&self) -> crate::transit::RateLimit {
+        self.rate_limit
+    }
+
+    /**
+     * Limit the speed of the transit connections made with this wormhole
+     *
+     * This applies to the transfers and port forwardings started afterwards, so that each of them
+     * can have its own limit. Unlimited by default.
+     */
+    #[cfg(feature = "transit")]
+    pub fn set_rate_limit(&mut self, rate_limit: crate::transit::RateLimit) {
+        self.rate_limit = rate_limit;
+    }
+
+    /**
+     * The transit relays configured with [`WormholeBuilder::relay_hints`]
+     *
+     * Defaults to [`DEFAULT_RELAY_SERVER`](crate::transit::DEFAULT_RELAY_SERVER). Pass them to
+     * the transfers and port forwardings made with this wormhole.
+     */
+    #[cfg(feature = "transit")]
+    pub fn relay_hints(&self) -> &[crate::transit::RelayHint] {
+        &self.relay_hints
+    }
+
+    /// Whether file offers sent with this wormhole announce the SHA-256 of the file
+    #[cfg(feature = "transfer")]
+    pub fn offer_digests(&self) -> bool {
+        self.offer_digests
+    }
+
+    /**
+     * Announce the SHA-256 of files in the offers sent with this wormhole
+     *
+     * The receiver then checks the received file against it. Computing it reads the file once more
+     * before the transfer, so this is off by default. It only applies to single files sent with
+     * [`transfer::send`](crate::transfer::send); the file transfer protocol checks the integrity
+     * of the transfer either way, but only the sender learns about a mismatch otherwise.
+     */
+    #[cfg(feature = "transfer")]
+    pub fn set_offer_digests(&mut self, offer_digests: bool) {
+        self.offer_digests = offer_digests;
+    }
+
+    /// The proxy configured with [`WormholeBuilder::proxy`], which transit connections go through
+    pub fn proxy(&self) -> Option<&crate::proxy::ProxyConfig> {
+        self.server.proxy()
+    }
+
+    /**
+     * Restrict the transit `abilities` to what the proxy allows
+     *
+     * Behind Tor, only relays are used. Apply this before [`transit::init`](crate::transit::init),
+     * so that it neither looks up our external IP address nor puts our addresses into the hints.
+     */
+    #[cfg(feature = "transit")]
+    pub(crate) fn transit_abilities(
+        &self,
+        abilities: crate::transit::Abilities,
+    ) -> crate::transit::Abilities {
+        match self.proxy() {
+            Some(proxy) if proxy.hides_address() => {
+                abilities.intersect(&crate::transit::Abilities::FORCE_RELAY)
+            },
+            _ => abilities,
+        }
+    }
+
+    /**
+     * Our side, a random string identifying this client on the rendezvous server
+     *
+     * It is generated anew for each connection and sent to the server in the clear, so it is
+     * safe to log. It helps to correlate client logs with the ones of the server.
+     */
+    pub fn side(&self) -> &str {
+        self.server.side().as_str()
     }
 
     /**
@@ -493,6 +1091,44 @@ impl Wormhole {
         &self.key
     }
 
+    /**
+     * Derive a key for an application-specific purpose, like encrypting a follow-up protocol
+     *
+     * Both sides get the same key for the same purpose, and independent keys for different
+     * purposes. The purpose is namespaced with the app ID: the key is derived with HKDF from
+     * `"{appid}/{purpose}"`, so that different applications never share keys. Python peers
+     * get the same key with `derive_key(f"{appid}/{purpose}", length)`. Use
+     * [`derive_key_unprefixed`](Self::derive_key_unprefixed) to talk to Python applications
+     * that pass other purposes.
+     *
+     * Fails if `length` is more than [`MAX_DERIVED_KEY_LENGTH`](key::MAX_DERIVED_KEY_LENGTH),
+     * the limit of HKDF-SHA256. The key is wiped from memory when dropped.
+     */
+    pub fn derive_key(
+        &self,
+        purpose: &str,
+        length: usize,
+    ) -> Result<zeroize::Zeroizing<Vec<u8>>, key::KeyLengthError> {
+        self.derive_key_unprefixed(&format!("{}/{}", self.appid, purpose), length)
+    }
+
+    /**
+     * Derive a key from the purpose as is, without the app ID
+     *
+     * This is what `derive_key(purpose, length)` of the Python implementation does, so both
+     * sides get the same key for any purpose string. Prefer [`derive_key`](Self::derive_key)
+     * for new protocols, it keeps the keys of different applications apart.
+     *
+     * Fails if `length` is more than [`MAX_DERIVED_KEY_LENGTH`](key::MAX_DERIVED_KEY_LENGTH).
+     */
+    pub fn derive_key_unprefixed(
+        &self,
+        purpose: &str,
+        length: usize,
+    ) -> Result<zeroize::Zeroizing<Vec<u8>>, key::KeyLengthError> {
+        key::derive_key_with_length(&self.key, purpose.as_bytes(), length)
+    }
+
     /**
      * If you're paranoid, let both sides check that they calculated the same verifier.
      *
@@ -527,6 +1163,16 @@ impl Wormhole {
         #[allow(deprecated)]
         &self.peer_version
     }
+
+    /**
+     * Parse the [`peer_version`](Self::peer_version) into [`AppVersions`]
+     *
+     * Only makes sense for protocols following its conventions. Fails with
+     * [`WormholeError::ProtocolJson`] if the peer sent something else.
+     */
+    pub fn peer_app_versions(&self) -> Result<AppVersions, WormholeError> {
+        Ok(serde_json::from_value(self.peer_version().clone())?)
+    }
 }
 
 /// The close command accepts an optional "mood" string: this allows clients to tell the server
@@ -534,6 +1180,7 @@ impl Wormhole {
 /// the mood in its "usage" record, so the server operator can get a sense of how many connections
 /// are succeeding and failing. The moods currently recognized by the Mailbox server are:
 #[derive(Debug, PartialEq, Copy, Clone, Deserialize, Serialize, derive_more::Display)]
+#[non_exhaustive]
 pub enum Mood {
     /// The PAKE key-establishment worked, and the client saw at least one valid encrypted message from its peer
     #[serde(rename = "happy")]
@@ -554,6 +1201,18 @@ pub enum Mood {
     Unwelcome,
 }
 
+/**
+ * Why the other side closed the connection, see [`Wormhole::close_with`]
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CloseReason {
+    /// The mood the peer reported to the server
+    pub mood: Mood,
+    /// A human readable explanation, like "cancelled by user" or "disk full"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
 /**
  * Wormhole configuration corresponding to an uppler layer protocol
  *
@@ -645,10 +1304,12 @@ pub struct MySide(EitherSide);
 
 impl MySide {
     pub fn generate() -> MySide {
-        use rand::{rngs::OsRng, RngCore};
+        Self::generate_with(&mut crate::util::rng())
+    }
 
+    pub(crate) fn generate_with(rng: &mut (impl rand::RngCore + rand::CryptoRng)) -> MySide {
         let mut bytes: [u8; 5] = [0; 5];
-        OsRng.fill_bytes(&mut bytes);
+        rng.fill_bytes(&mut bytes);
 
         MySide(EitherSide(hex::encode(bytes)))
     }
@@ -706,13 +1367,30 @@ impl<S: Into<String>> From<S> for EitherSide {
 pub struct Phase(Cow<'static, str>);
 
 impl Phase {
-    pub const VERSION: Self = Phase(Cow::Borrowed("version"));
-    pub const PAKE: Self = Phase(Cow::Borrowed("pake"));
+    pub const VERSION: Self = Phase(Cow::Borrowed(protocol::v1::PHASE_VERSION));
+    pub const PAKE: Self = Phase(Cow::Borrowed(protocol::v1::PHASE_PAKE));
+    /** The final message of [`Wormhole::close_with`] */
+    pub const CLOSE: Self = Phase(Cow::Borrowed(protocol::v1::PHASE_CLOSE));
 
     pub fn numeric(phase: u64) -> Self {
         Phase(phase.to_string().into())
     }
 
+    /** The phases of application messages, see [`Wormhole::send_app_message`] */
+    pub fn app(phase: u64) -> Self {
+        Phase(format!("{}{}", protocol::v1::APP_PHASE_PREFIX, phase).into())
+    }
+
+    pub fn is_app(&self) -> bool {
+        self.0
+            .strip_prefix(protocol::v1::APP_PHASE_PREFIX)
+            .is_some_and(|num| num.parse::<u64>().is_ok())
+    }
+
+    pub fn is_close(&self) -> bool {
+        self == &Self::CLOSE
+    }
+
     #[allow(dead_code)]
     pub fn is_version(&self) -> bool {
         self == &Self::VERSION
@@ -783,13 +1461,46 @@ impl AsRef<str> for Nameplate {
     }
 }
 
+/// A code entered by the user is malformed, see [`Code::parse`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum CodeParseError {
+    /// Nothing was entered
+    #[error("The code is empty")]
+    Empty,
+    /// The code does not start with a number
+    #[error("The code must start with a number, like the 4 in 4-purple-sausages")]
+    InvalidNameplate,
+    /// There is nothing after the nameplate
+    #[error("The code is missing the words after the number")]
+    MissingPassword,
+    /// A word is not in the wordlist, see [`Code::parse_with_wordlist`]
+    #[error(
+        "Unknown word '{}' in the code{}",
+        word,
+        suggestion.as_deref().map(|suggestion| format!(", did you mean '{}'?", suggestion)).unwrap_or_default()
+    )]
+    UnknownWord {
+        /// The word that is not in the wordlist
+        word: String,
+        /// The most similar word of the wordlist, if any is similar enough to be a typo
+        suggestion: Option<String>,
+    },
+}
+
+#[cfg(feature = "serde-types")]
+impl_serialize_error!(CodeParseError);
+
 /** A wormhole code à la 15-foo-bar
  *
  * The part until the first dash is called the "nameplate" and is purely numeric.
  * The rest is the password and may be arbitrary, although dash-joining words from
  * a wordlist is a common convention.
+ *
+ * The code is wiped from memory when dropped. Its `Debug` output only shows the nameplate,
+ * so that it doesn't end up in logs by accident.
  */
-#[derive(PartialEq, Eq, Clone, Debug, derive_more::Display, derive_more::Deref)]
+#[derive(PartialEq, Eq, Clone, derive_more::Display, derive_more::Deref)]
 #[display("{}", _0)]
 pub struct Code(
     #[deprecated(since = "0.7.0", note = "use the AsRef<str> implementation")] pub String,
@@ -805,8 +1516,8 @@ impl Code {
     /// Split the code into nameplate and password
     pub fn split(&self) -> (Nameplate, String) {
         let mut iter = self.0.splitn(2, '-');
-        let nameplate = Nameplate::new(iter.next().unwrap());
-        let password = iter.next().unwrap();
+        let nameplate = Nameplate::new(iter.next().unwrap_or_default());
+        let password = iter.next().unwrap_or_default();
         (nameplate, password.to_string())
     }
 
@@ -814,12 +1525,75 @@ impl Code {
     pub fn nameplate(&self) -> Nameplate {
         Nameplate::new(self.0.split('-').next().unwrap())
     }
+
+    /**
+     * Parse a code entered by a user
+     *
+     * Surrounding whitespace is removed and the words are lowercased. The parts may be separated
+     * by dashes or whitespace, the result always uses single dashes. The nameplate must be a number.
+     */
+    pub fn parse(code: &str) -> Result<Self, CodeParseError> {
+        let mut parts = code
+            .split(|c: char| c == '-' || c.is_whitespace())
+            .filter(|part| !part.is_empty());
+        let nameplate = parts.next().ok_or(CodeParseError::Empty)?;
+        if !nameplate.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(CodeParseError::InvalidNameplate);
+        }
+        let words = zeroize::Zeroizing::new(parts.map(str::to_lowercase).collect::<Vec<_>>());
+        if words.is_empty() {
+            return Err(CodeParseError::MissingPassword);
+        }
+        Ok(Code::new(
+            &Nameplate::new(nameplate),
+            &zeroize::Zeroizing::new(words.join("-")),
+        ))
+    }
+
+    /**
+     * Like [`parse`](Self::parse), but also check that the words are in the wordlist
+     *
+     * Fails with [`CodeParseError::UnknownWord`] on the first word that isn't, suggesting the most
+     * similar word of the list. Only use this for codes generated from that wordlist: codes may
+     * be arbitrary, for example when chosen by the user.
+     */
+    pub fn parse_with_wordlist(
+        code: &str,
+        wordlist: &wordlist::Wordlist,
+    ) -> Result<Self, CodeParseError> {
+        let code = Self::parse(code)?;
+        let password = zeroize::Zeroizing::new(code.split().1);
+        for (position, word) in password.split('-').enumerate() {
+            if !wordlist.contains(position, word) {
+                return Err(CodeParseError::UnknownWord {
+                    word: word.to_owned(),
+                    suggestion: wordlist.closest_word(position, word).map(str::to_owned),
+                });
+            }
+        }
+        Ok(code)
+    }
+}
+
+impl std::fmt::Debug for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Code")
+            .field(&format_args!("{}-<redacted>", self.nameplate()))
+            .finish()
+    }
+}
+
+#[allow(deprecated)]
+impl Drop for Code {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.0);
+    }
 }
 
 #[allow(deprecated)]
 impl From<Code> for String {
-    fn from(value: Code) -> Self {
-        value.0
+    fn from(mut value: Code) -> Self {
+        std::mem::take(&mut value.0)
     }
 }
 
@@ -835,3 +1609,23 @@ impl AsRef<str> for Code {
         &self.0
     }
 }
+
+/**
+ * `Code` doesn't implement `Serialize`, since deriving it on a type that contains a code would
+ * write the secret password into logs or files by accident. Opt in with this function as
+ * `#[serde(serialize_with = "Code::serialize_secret")]` where you really need it.
+ */
+#[cfg(feature = "serde-types")]
+impl Code {
+    /// Serialize the full code, including the password
+    pub fn serialize_secret<S: serde::Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(self.as_ref())
+    }
+}
+
+#[cfg(feature = "serde-types")]
+impl<'de> serde::Deserialize<'de> for Code {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        String::deserialize(de).map(Self::from)
+    }
+}
@@ -0,0 +1,90 @@
+use futures::channel::mpsc;
+use std::sync::{Arc, Mutex};
+
+/**
+ * Queue application messages for sending, from anywhere
+ *
+ * Created by [`Wormhole::app_message_sender`](super::Wormhole::app_message_sender). Unlike
+ * [`Wormhole::send_app_message`](super::Wormhole::send_app_message), this keeps working while
+ * the [`Wormhole`](super::Wormhole) is owned by a file transfer. The messages are sent the next
+ * time the wormhole talks to the rendezvous server, which file transfers do continuously.
+ */
+#[derive(Clone, Debug)]
+pub struct AppMessageSender(mpsc::UnboundedSender<Vec<u8>>);
+
+impl AppMessageSender {
+    /**
+     * Queue a message for sending
+     *
+     * Returns `false` if the [`Wormhole`](super::Wormhole) is already gone, and thus the message
+     * won't be sent.
+     */
+    pub fn send(&self, message: impl Into<Vec<u8>>) -> bool {
+        self.0.unbounded_send(message.into()).is_ok()
+    }
+}
+
+/**
+ * The application messages of a [`Wormhole`](super::Wormhole), in both directions
+ *
+ * Application messages are sent over the mailbox next to the regular messages, using their own
+ * phases (`app-0`, `app-1`, …). Thus they never get mixed up with the messages of the protocol
+ * currently using the wormhole.
+ */
+#[derive(Debug)]
+pub(crate) struct AppMessages {
+    /** Counter for the phases of the messages we send */
+    pub phase: u64,
+    outgoing_tx: mpsc::UnboundedSender<Vec<u8>>,
+    outgoing_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<Vec<u8>>>>>,
+}
+
+impl Default for AppMessages {
+    fn default() -> Self {
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded();
+        Self {
+            phase: 0,
+            outgoing_tx,
+            outgoing_rx,
+            subscribers: Default::default(),
+        }
+    }
+}
+
+impl AppMessages {
+    pub fn sender(&self) -> AppMessageSender {
+        AppMessageSender(self.outgoing_tx.clone())
+    }
+
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<Vec<u8>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /** Hand a received message to all subscribers */
+    pub fn dispatch(&self, message: Vec<u8>) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            tracing::debug!("Dropping application message, nobody is listening");
+        }
+        /* Forget about subscribers who dropped their stream */
+        subscribers.retain(|tx| tx.unbounded_send(message.clone()).is_ok());
+    }
+
+    /** Take the next message queued with an [`AppMessageSender`], if any */
+    pub fn try_next_outgoing(&mut self) -> Option<Vec<u8>> {
+        self.outgoing_rx.try_next().ok().flatten()
+    }
+
+    /** Wait for the next message queued with an [`AppMessageSender`] */
+    pub async fn next_outgoing(&mut self) -> Vec<u8> {
+        use futures::StreamExt;
+        /* We hold a sender ourselves, so the stream never ends */
+        self.outgoing_rx
+            .next()
+            .await
+            .expect("The queue can't be closed while we hold a sender")
+    }
+}
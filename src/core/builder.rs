@@ -0,0 +1,795 @@
+#[cfg(all(feature = "tls", not(target_family = "wasm")))]
+use super::rendezvous::TlsConfig;
+use super::{
+    confirm::{Confirmation, ConfirmationHandler},
+    rendezvous::{ConnectOptions, ReconnectPolicy, RendezvousServer, ServerWelcome},
+    wordlist::{CodeFormat, CodeStrength, Wordlist},
+    AppConfig, AppID, Code, MailboxConnection, Mood, Nameplate, Timeouts, Wormhole, WormholeError,
+    WormholeEvent,
+};
+use crate::{
+    metrics::{Metrics, NoMetrics},
+    proxy::ProxyConfig,
+    seeds::Seed,
+    util,
+};
+use futures::{future::Either, Future};
+use std::{borrow::Cow, sync::Arc, time::Duration};
+
+/// The number of words of an allocated code, if not configured otherwise
+const DEFAULT_CODE_LENGTH: usize = 2;
+
+impl Wormhole {
+    /**
+     * Configure a new wormhole connection
+     *
+     * See [`WormholeBuilder`] for all the options.
+     */
+    pub fn builder<V: serde::Serialize + Send + Sync + 'static>(
+        config: AppConfig<V>,
+    ) -> WormholeBuilder<V> {
+        WormholeBuilder::new(config)
+    }
+
+    /**
+     * Connect to a peer remembered with a [`Seed`], without a code
+     *
+     * Shorthand for [`WormholeBuilder::connect_with_seed`] with the default options.
+     */
+    pub async fn connect_with_seed(
+        config: AppConfig<impl serde::Serialize + Send + Sync + 'static>,
+        seed: &Seed,
+    ) -> Result<Self, WormholeError> {
+        Self::builder(config).connect_with_seed(seed).await
+    }
+}
+
+/**
+ * Set up a [`Wormhole`] connection
+ *
+ * Start from the [`AppConfig`] of the protocol you want to speak, adapt it, and then either
+ * allocate a new code with [`allocate_code`](Self::allocate_code) (typically the sending side)
+ * or use an existing one with [`connect_with_code`](Self::connect_with_code) (typically the
+ * receiving side, who got the code from the sender).
+ *
+ * ```no_run
+ * # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+ * use magic_wormhole::{transfer::APP_CONFIG, Wormhole};
+ * use std::time::Duration;
+ *
+ * let pending = Wormhole::builder(APP_CONFIG)
+ *     .timeout(Duration::from_secs(30))
+ *     .allocate_code()
+ *     .await?;
+ * println!("The code is {}", pending.code());
+ * let wormhole = pending.wait_for_peer().await?;
+ * # Ok(()) })}
+ * ```
+ */
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct WormholeBuilder<V> {
+    config: AppConfig<V>,
+    format: CodeFormat,
+    code_strength: Option<CodeStrength>,
+    timeouts: Timeouts,
+    reconnect: Option<ReconnectPolicy>,
+    proxy: Option<ProxyConfig>,
+    proxy_from_env: bool,
+    #[cfg(all(feature = "tls", not(target_family = "wasm")))]
+    tls: Option<TlsConfig>,
+    confirmation: Option<Confirmation>,
+    #[cfg(feature = "transit")]
+    relay_hints: Vec<crate::transit::RelayHint>,
+    metrics: Arc<dyn Metrics>,
+    rng: util::SharedRng,
+    clock: util::Clock,
+}
+
+impl<V: serde::Serialize + Send + Sync + 'static> WormholeBuilder<V> {
+    /// Start from an application's configuration
+    pub fn new(config: AppConfig<V>) -> Self {
+        Self {
+            config,
+            format: CodeFormat::default().with_length(DEFAULT_CODE_LENGTH),
+            code_strength: None,
+            timeouts: Timeouts::default(),
+            reconnect: None,
+            proxy: None,
+            proxy_from_env: false,
+            #[cfg(all(feature = "tls", not(target_family = "wasm")))]
+            tls: None,
+            confirmation: None,
+            #[cfg(feature = "transit")]
+            relay_hints: crate::transit::default_relay_hints(),
+            metrics: Arc::new(NoMetrics),
+            rng: util::SharedRng::default(),
+            clock: util::Clock::default(),
+        }
+    }
+
+    /// Bind the connection to a different app ID. Both sides must use the same one.
+    pub fn appid(mut self, appid: AppID) -> Self {
+        self.config.id = appid;
+        self
+    }
+
+    /// Use a different rendezvous server. Both sides must use the same one.
+    pub fn rendezvous_url(mut self, rendezvous_url: impl Into<Cow<'static, str>>) -> Self {
+        self.config.rendezvous_url = rendezvous_url.into();
+        self
+    }
+
+    /// Set the app version information that will be sent to the other side
+    pub fn versions<W: serde::Serialize + Send + Sync + 'static>(
+        self,
+        app_version: W,
+    ) -> WormholeBuilder<W> {
+        WormholeBuilder {
+            config: AppConfig {
+                id: self.config.id,
+                rendezvous_url: self.config.rendezvous_url,
+                app_version,
+            },
+            format: self.format,
+            code_strength: self.code_strength,
+            timeouts: self.timeouts,
+            reconnect: self.reconnect,
+            proxy: self.proxy,
+            proxy_from_env: self.proxy_from_env,
+            #[cfg(all(feature = "tls", not(target_family = "wasm")))]
+            tls: self.tls,
+            confirmation: self.confirmation,
+            #[cfg(feature = "transit")]
+            relay_hints: self.relay_hints,
+            metrics: self.metrics,
+            rng: self.rng,
+            clock: self.clock,
+        }
+    }
+
+    /**
+     * Number of words of an allocated code (excluding the nameplate). Defaults to 2.
+     *
+     * With a [`code_format`](Self::code_format) other than words, this is the number of characters.
+     */
+    pub fn code_length(mut self, code_length: usize) -> Self {
+        self.format = self.format.with_length(code_length);
+        self
+    }
+
+    /**
+     * Allocate codes that are at least this hard to guess
+     *
+     * The number of words or characters is computed from the [`code_format`](Self::code_format),
+     * and takes precedence over the [`code_length`](Self::code_length).
+     */
+    pub fn code_strength(mut self, strength: CodeStrength) -> Self {
+        self.code_strength = Some(strength);
+        self
+    }
+
+    /**
+     * Allocate codes from a different [`Wordlist`] than the PGP one
+     *
+     * This replaces the [`code_length`](Self::code_length) with the one of the wordlist. Only the
+     * side allocating the code needs to set this.
+     */
+    pub fn wordlist(mut self, wordlist: Wordlist) -> Self {
+        self.format = CodeFormat::Words(wordlist);
+        self
+    }
+
+    /**
+     * Use codes made of digits or characters instead of words
+     *
+     * Unlike the wordlist, both sides should set the same format: the side claiming the code uses
+     * it to [`canonicalize`](CodeFormat::canonicalize) what the user entered. This replaces the
+     * [`wordlist`](Self::wordlist).
+     */
+    pub fn code_format(mut self, format: CodeFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /**
+     * Give up with [`WormholeError::Timeout`] if a step takes longer than this
+     *
+     * The timeout applies separately to talking to the rendezvous server and to waiting for
+     * the other side. This is a shorthand for setting [`Timeouts::rendezvous_connect`] and
+     * [`Timeouts::peer_wait`].
+     */
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.rendezvous_connect = Some(timeout);
+        self.timeouts.peer_wait = Some(timeout);
+        self
+    }
+
+    /**
+     * Configure the timeouts of all phases of the connection
+     *
+     * The timeouts of the later phases are remembered by the resulting [`Wormhole`], and applied
+     * to the transit connections and transfers made with it.
+     */
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /**
+     * Reconnect to the rendezvous server if the connection drops
+     *
+     * Without this, a dropped connection fails the [`Wormhole`] with
+     * [`WormholeError::ServerError`]. Reconnecting only starts once the mailbox is open, that is
+     * after allocating or claiming a code. See [`ReconnectPolicy`] for the details.
+     */
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /**
+     * Connect through a proxy, see the [`proxy`](crate::proxy) module
+     *
+     * This applies to the connection to the rendezvous server, and to the transit connections of
+     * the resulting [`Wormhole`].
+     */
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /**
+     * Connect through the proxy set in the environment, if any
+     *
+     * The proxy is read from `HTTPS_PROXY` or `ALL_PROXY` when connecting, see
+     * [`ProxyConfig::from_env`]. A proxy set with [`proxy`](Self::proxy) takes precedence.
+     */
+    pub fn proxy_from_env(mut self) -> Self {
+        self.proxy_from_env = true;
+        self
+    }
+
+    /**
+     * Connect to `wss://` rendezvous servers with custom TLS settings
+     *
+     * Use this to trust the private CA of a self-hosted server, to present a client certificate,
+     * or to override the server name. See [`TlsConfig`] for the details. This requires the `tls`
+     * feature, and fails to connect if the `native-tls` feature is enabled too.
+     */
+    #[cfg(all(feature = "tls", not(target_family = "wasm")))]
+    pub fn tls_config(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /**
+     * Let the user confirm the verifier before the wormhole gets used
+     *
+     * See [`ConfirmationHandler`] for the details. The time the user takes doesn't count against
+     * [`Timeouts::peer_wait`].
+     */
+    pub fn confirm_verifier(mut self, handler: impl ConfirmationHandler + 'static) -> Self {
+        self.confirmation = Some(Confirmation(Arc::new(handler)));
+        self
+    }
+
+    /**
+     * Use these transit relays instead of the default one
+     *
+     * The resulting [`Wormhole`] remembers them, see [`Wormhole::relay_hints`]. Pass them on to
+     * the transfers and port forwardings made with it. Without relays, only direct connections
+     * are possible.
+     */
+    #[cfg(feature = "transit")]
+    pub fn relay_hints(mut self, relay_hints: Vec<crate::transit::RelayHint>) -> Self {
+        self.relay_hints = relay_hints;
+        self
+    }
+
+    /**
+     * Report what happens on this connection to some [`Metrics`]
+     *
+     * The resulting [`Wormhole`] and the transfers made with it report to them too.
+     */
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /**
+     * Draw the side, the allocated code and the PAKE from this RNG instead of the operating system's
+     *
+     * With a seeded RNG like [`StdRng`](rand::rngs::StdRng), every run allocates the same code
+     * and connects with the same [`side`](Wormhole::side), so tests can assert on them. Never use
+     * this outside of tests. Only available with the `testing` feature.
+     */
+    #[cfg(feature = "testing")]
+    pub fn rng(mut self, rng: impl rand::RngCore + rand::CryptoRng + Send + 'static) -> Self {
+        self.rng = util::SharedRng::new(rng);
+        self
+    }
+
+    /**
+     * Take the current time from this clock instead of the system's
+     *
+     * It dates the hashcash stamps some rendezvous servers require. Together with
+     * [`rng`](Self::rng), this makes the whole connection setup deterministic. Only available with
+     * the `testing` feature.
+     */
+    #[cfg(feature = "testing")]
+    pub fn clock(
+        mut self,
+        now: impl Fn() -> std::time::SystemTime + Send + Sync + 'static,
+    ) -> Self {
+        self.clock = util::Clock::new(move || now().into());
+        self
+    }
+
+    /// The resulting configuration
+    pub fn config(&self) -> &AppConfig<V> {
+        &self.config
+    }
+
+    /**
+     * Connect to the rendezvous server, without allocating or claiming a code yet
+     *
+     * This is the first step of setting up a connection one step at a time. Most applications
+     * don't need that level of control, and can use [`allocate_code`](Self::allocate_code) or
+     * [`connect_with_code`](Self::connect_with_code) instead.
+     *
+     * ```no_run
+     * # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+     * use magic_wormhole::{transfer::APP_CONFIG, Wormhole};
+     *
+     * let server = Wormhole::builder(APP_CONFIG).connect_to_server().await?;
+     * if let Some(welcome) = server.welcome() {
+     *     println!("{welcome}");
+     * }
+     * let pending = server.allocate_code().await?;
+     * println!("The code is {}", pending.code());
+     * let wormhole = pending.wait_for_peer().await?;
+     * # Ok(()) })}
+     * ```
+     */
+    pub async fn connect_to_server(self) -> Result<ServerConnection<V>, WormholeError> {
+        let options = ConnectOptions {
+            proxy: self
+                .proxy
+                .clone()
+                .or_else(|| self.proxy_from_env.then(ProxyConfig::from_env).flatten()),
+            #[cfg(all(feature = "tls", not(target_family = "wasm")))]
+            tls: self.tls.clone(),
+            rng: self.rng.clone(),
+            clock: self.clock.clone(),
+        };
+        let (mut server, welcome) = report(
+            &*self.metrics,
+            with_timeout(self.timeouts.rendezvous_connect, async {
+                Ok(RendezvousServer::connect_via(
+                    &self.config.id,
+                    &self.config.rendezvous_url,
+                    options,
+                )
+                .await?)
+            })
+            .await,
+        )?;
+        server.set_keepalive(self.timeouts.keepalive);
+        server.set_reconnect(self.reconnect);
+        self.metrics.rendezvous_connected();
+        Ok(ServerConnection {
+            config: self.config,
+            format: match self.code_strength {
+                Some(strength) => self.format.with_strength(strength),
+                None => self.format,
+            },
+            timeouts: self.timeouts,
+            confirmation: self.confirmation,
+            #[cfg(feature = "transit")]
+            relay_hints: self.relay_hints,
+            metrics: self.metrics,
+            server,
+            welcome,
+        })
+    }
+
+    /**
+     * Allocate a new code on the rendezvous server
+     *
+     * Show the code of the returned [`PendingWormhole`] to the user, then wait for the
+     * other side with [`PendingWormhole::wait_for_peer`].
+     */
+    pub async fn allocate_code(self) -> Result<PendingWormhole<V>, WormholeError> {
+        self.connect_to_server().await?.allocate_code().await
+    }
+
+    /**
+     * Like [`allocate_code`](Self::allocate_code), but give up once `cancel` completes
+     *
+     * Use this to wire up a "Cancel" button. A code allocated in the meantime is released again,
+     * then this returns `None`. Pass the same kind of future on to
+     * [`PendingWormhole::wait_for_peer_or_cancel`].
+     */
+    pub async fn allocate_code_or_cancel(
+        self,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<PendingWormhole<V>>, WormholeError> {
+        futures::pin_mut!(cancel);
+        let Some(server) = until_cancelled(self.connect_to_server(), &mut cancel).await? else {
+            return Ok(None);
+        };
+        let (pending, cancelled) = finish_step(server.allocate_code(), &mut cancel).await?;
+        if cancelled {
+            pending.cancel().await?;
+            return Ok(None);
+        }
+        Ok(Some(pending))
+    }
+
+    /**
+     * Connect to the other side using an existing code
+     *
+     * Fails with [`WormholeError::UnclaimedNameplate`] if nobody is waiting on that code.
+     */
+    pub async fn connect_with_code(self, code: Code) -> Result<Wormhole, WormholeError> {
+        self.connect_to_server()
+            .await?
+            .claim_code(code)
+            .await?
+            .wait_for_peer()
+            .await
+    }
+
+    /**
+     * Like [`connect_with_code`](Self::connect_with_code), but give up once `cancel` completes
+     *
+     * Cancelling at any step releases the code and closes the mailbox on the server, then returns
+     * `None`. In contrast, dropping the future leaves them to the server to clean up.
+     */
+    pub async fn connect_with_code_or_cancel(
+        self,
+        code: Code,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<Wormhole>, WormholeError> {
+        futures::pin_mut!(cancel);
+        let Some(server) = until_cancelled(self.connect_to_server(), &mut cancel).await? else {
+            return Ok(None);
+        };
+        let (pending, cancelled) = finish_step(server.claim_code(code), &mut cancel).await?;
+        if cancelled {
+            pending.cancel().await?;
+            return Ok(None);
+        }
+        pending.wait_for_peer_or_cancel(cancel).await
+    }
+
+    /**
+     * Connect to a peer remembered with a [`Seed`], without a code
+     *
+     * Both sides call this with their copy of the seed, see the [`seeds`](crate::seeds) module.
+     */
+    pub async fn connect_with_seed(self, seed: &Seed) -> Result<Wormhole, WormholeError> {
+        self.connect_to_server()
+            .await?
+            .open_seed(seed)
+            .await?
+            .wait_for_peer()
+            .await
+    }
+}
+
+/**
+ * A connection to the rendezvous server, without a code yet
+ *
+ * Created by [`WormholeBuilder::connect_to_server`]. You *should* consume this object, either
+ * by calling [`allocate_code`](Self::allocate_code), [`claim_code`](Self::claim_code),
+ * [`open_seed`](Self::open_seed) or [`cancel`](Self::cancel).
+ *
+ * A connection carries exactly one wormhole: rendezvous servers, including the Python one, only
+ * allow one claimed nameplate and one open mailbox per connection. To run several wormholes at the
+ * same time, for example to serve many receivers, connect once for each of them.
+ */
+#[must_use]
+pub struct ServerConnection<V: serde::Serialize + Send + Sync + 'static> {
+    config: AppConfig<V>,
+    format: CodeFormat,
+    timeouts: Timeouts,
+    confirmation: Option<Confirmation>,
+    #[cfg(feature = "transit")]
+    relay_hints: Vec<crate::transit::RelayHint>,
+    metrics: Arc<dyn Metrics>,
+    server: RendezvousServer,
+    welcome: Option<String>,
+}
+
+impl<V: serde::Serialize + Send + Sync + 'static> ServerConnection<V> {
+    /// The welcome message of the rendezvous server, if any. Should be displayed to the user.
+    pub fn welcome(&self) -> Option<&str> {
+        self.welcome.as_deref()
+    }
+
+    /// Everything the rendezvous server told us in its welcome message, see [`ServerWelcome`]
+    pub fn server_welcome(&self) -> &ServerWelcome {
+        self.server.server_welcome()
+    }
+
+    /// Our side, see [`Wormhole::side`]
+    pub fn side(&self) -> &str {
+        self.server.side().as_str()
+    }
+
+    /**
+     * The nameplates currently claimed on the server
+     *
+     * Used to complete the nameplate of a code while the user types it. Can be called any number
+     * of times before claiming a code.
+     */
+    pub async fn list_nameplates(&mut self) -> Result<Vec<Nameplate>, WormholeError> {
+        let server = &mut self.server;
+        with_timeout(self.timeouts.rendezvous_connect, async {
+            Ok(server.list_nameplates().await?)
+        })
+        .await
+    }
+
+    /// Allocate a new code, typically on the sending side
+    pub async fn allocate_code(self) -> Result<PendingWormhole<V>, WormholeError> {
+        let password = self.format.generate_with(&mut self.server.rng());
+        let mut mailbox = report(
+            &*self.metrics,
+            with_timeout(
+                self.timeouts.rendezvous_connect,
+                MailboxConnection::allocate_on(self.config, self.server, self.welcome, &password),
+            )
+            .await,
+        )?;
+        mailbox.events.set_metrics(self.metrics);
+        Ok(PendingWormhole {
+            mailbox,
+            timeouts: self.timeouts,
+            confirmation: self.confirmation,
+            #[cfg(feature = "transit")]
+            relay_hints: self.relay_hints,
+        })
+    }
+
+    /**
+     * Claim an existing code, typically on the receiving side
+     *
+     * Fails with [`WormholeError::UnclaimedNameplate`] if nobody is waiting on that code, and with
+     * [`WormholeError::InvalidCode`] if it doesn't match the [`CodeFormat`].
+     */
+    pub async fn claim_code(self, code: Code) -> Result<PendingWormhole<V>, WormholeError> {
+        let code = report(&*self.metrics, canonicalize(&self.format, code))?;
+        let mut mailbox = report(
+            &*self.metrics,
+            with_timeout(
+                self.timeouts.rendezvous_connect,
+                MailboxConnection::claim_on(self.config, self.server, self.welcome, code, false),
+            )
+            .await,
+        )?;
+        mailbox.events.set_metrics(self.metrics);
+        Ok(PendingWormhole {
+            mailbox,
+            timeouts: self.timeouts,
+            confirmation: self.confirmation,
+            #[cfg(feature = "transit")]
+            relay_hints: self.relay_hints,
+        })
+    }
+
+    /**
+     * Open the mailbox of a peer remembered with a [`Seed`]
+     *
+     * Unlike with codes, both sides use this method, in any order. The code of the returned
+     * [`PendingWormhole`] is derived from the seed, don't show it to anybody.
+     */
+    pub async fn open_seed(self, seed: &Seed) -> Result<PendingWormhole<V>, WormholeError> {
+        let mut mailbox = report(
+            &*self.metrics,
+            with_timeout(
+                self.timeouts.rendezvous_connect,
+                MailboxConnection::open_seed_on(self.config, self.server, self.welcome, seed),
+            )
+            .await,
+        )?;
+        mailbox.events.set_metrics(self.metrics);
+        Ok(PendingWormhole {
+            mailbox,
+            timeouts: self.timeouts,
+            confirmation: self.confirmation,
+            #[cfg(feature = "transit")]
+            relay_hints: self.relay_hints,
+        })
+    }
+
+    /// Disconnect from the server
+    pub async fn cancel(self) -> Result<(), WormholeError> {
+        with_timeout(self.timeouts.close, async {
+            Ok(self.server.shutdown(Mood::Lonely).await?)
+        })
+        .await
+    }
+}
+
+/**
+ * A wormhole with an allocated or claimed code, waiting for the other side
+ *
+ * Created by [`WormholeBuilder::allocate_code`], [`ServerConnection::allocate_code`],
+ * [`ServerConnection::claim_code`] or [`ServerConnection::open_seed`]. You *should* consume this object, either by calling
+ * [`wait_for_peer`](Self::wait_for_peer) or [`cancel`](Self::cancel).
+ */
+#[must_use]
+pub struct PendingWormhole<V: serde::Serialize + Send + Sync + 'static> {
+    mailbox: MailboxConnection<V>,
+    timeouts: Timeouts,
+    confirmation: Option<Confirmation>,
+    #[cfg(feature = "transit")]
+    relay_hints: Vec<crate::transit::RelayHint>,
+}
+
+impl<V: serde::Serialize + Send + Sync + 'static> PendingWormhole<V> {
+    /// The code to share with the other side
+    pub fn code(&self) -> &Code {
+        self.mailbox.code()
+    }
+
+    /// The welcome message of the rendezvous server, if any. Should be displayed to the user.
+    pub fn welcome(&self) -> Option<&str> {
+        self.mailbox.welcome()
+    }
+
+    /// Everything the rendezvous server told us in its welcome message, see [`ServerWelcome`]
+    pub fn server_welcome(&self) -> &ServerWelcome {
+        self.mailbox.server_welcome()
+    }
+
+    /// Our side, see [`Wormhole::side`]
+    pub fn side(&self) -> &str {
+        self.mailbox.side()
+    }
+
+    /// Subscribe to the events of the key exchange and of the resulting [`Wormhole`]
+    pub fn events(&self) -> impl futures::Stream<Item = WormholeEvent> + Send + Unpin + 'static {
+        self.mailbox.events()
+    }
+
+    /// Dump the internal state as JSON, see [`Wormhole::debug_state`]
+    pub fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "appid": self.mailbox.config.id.to_string(),
+            "rendezvous": self.mailbox.server.debug_state(),
+            "key": "waiting_for_peer",
+            "timeouts": format!("{:?}", self.timeouts),
+        })
+    }
+
+    /// Wait for the other side to connect and do the key exchange
+    pub async fn wait_for_peer(self) -> Result<Wormhole, WormholeError> {
+        Ok(connect(self, futures::future::pending())
+            .await?
+            .expect("Never cancelled"))
+    }
+
+    /**
+     * Like [`wait_for_peer`](Self::wait_for_peer), but give up once `cancel` completes
+     *
+     * Use this to wire up a "Cancel" button. Cancelling releases the code and closes the mailbox
+     * on the server like [`cancel`](Self::cancel) does, and returns `None`. In contrast, dropping
+     * the future leaves them to the server to clean up.
+     */
+    pub async fn wait_for_peer_or_cancel(
+        self,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<Wormhole>, WormholeError> {
+        connect(self, cancel).await
+    }
+
+    /// Give up waiting, release the code and disconnect from the server
+    pub async fn cancel(self) -> Result<(), WormholeError> {
+        with_timeout(self.timeouts.close, self.mailbox.shutdown(Mood::Lonely)).await
+    }
+}
+
+async fn connect<V: serde::Serialize + Send + Sync + 'static>(
+    pending: PendingWormhole<V>,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<Wormhole>, WormholeError> {
+    let PendingWormhole {
+        mailbox,
+        timeouts,
+        confirmation,
+        #[cfg(feature = "transit")]
+        relay_hints,
+    } = pending;
+    let metrics = mailbox.events.metrics().clone();
+    let wormhole = report(
+        &*metrics,
+        with_timeout(
+            timeouts.peer_wait,
+            Wormhole::connect_or_cancel(mailbox, cancel),
+        )
+        .await,
+    )?;
+    let Some(mut wormhole) = wormhole else {
+        return Ok(None);
+    };
+    wormhole.timeouts = timeouts;
+    #[cfg(feature = "transit")]
+    {
+        wormhole.relay_hints = relay_hints;
+    }
+
+    if let Some(Confirmation(handler)) = confirmation {
+        if !handler.confirm(wormhole.verifier().as_slice()).await {
+            tracing::warn!("The verifier was rejected, closing the wormhole.");
+            wormhole
+                .close_with(Mood::Scared, "verifier rejected")
+                .await?;
+            return report(&*metrics, Err(WormholeError::VerifierRejected));
+        }
+    }
+    Ok(Some(wormhole))
+}
+
+/**
+ * Run a step that leaves no state on the server, unless `cancel` completes first
+ *
+ * Connecting to the server is such a step: dropping it only closes the connection.
+ */
+async fn until_cancelled<T>(
+    step: impl Future<Output = Result<T, WormholeError>>,
+    cancel: &mut (impl Future<Output = ()> + Unpin),
+) -> Result<Option<T>, WormholeError> {
+    futures::pin_mut!(step);
+    match futures::future::select(step, cancel).await {
+        Either::Left((result, _)) => result.map(Some),
+        Either::Right(((), _)) => Ok(None),
+    }
+}
+
+/**
+ * Run a step that claims something on the server to completion, noting whether `cancel` completed
+ *
+ * Interrupting the step could leave a claimed nameplate behind, so the caller releases it
+ * properly instead when cancelled.
+ */
+async fn finish_step<T>(
+    step: impl Future<Output = Result<T, WormholeError>>,
+    cancel: &mut (impl Future<Output = ()> + Unpin),
+) -> Result<(T, bool), WormholeError> {
+    futures::pin_mut!(step);
+    match futures::future::select(step, cancel).await {
+        Either::Left((result, _)) => Ok((result?, false)),
+        Either::Right(((), step)) => Ok((step.await?, true)),
+    }
+}
+
+/** Bring the password of an entered code into the canonical form of the format */
+fn canonicalize(format: &CodeFormat, code: Code) -> Result<Code, WormholeError> {
+    if let CodeFormat::Words(_) = format {
+        return Ok(code);
+    }
+    let (nameplate, password) = code.split();
+    let password = zeroize::Zeroizing::new(password);
+    Ok(Code::new(&nameplate, &format.canonicalize(&password)?))
+}
+
+/** Report a failed connection step to the metrics, passing the result through */
+fn report<T>(metrics: &dyn Metrics, result: Result<T, WormholeError>) -> Result<T, WormholeError> {
+    if let Err(error) = &result {
+        metrics.connection_failed(error.reason());
+    }
+    result
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<T, WormholeError>>,
+) -> Result<T, WormholeError> {
+    util::timeout_opt(timeout, future)
+        .await
+        .map_err(|()| WormholeError::Timeout)?
+}
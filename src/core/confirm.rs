@@ -0,0 +1,53 @@
+use futures::future::BoxFuture;
+use std::sync::Arc;
+
+/**
+ * Asks the user to compare the verifier with the other side, before the wormhole gets used
+ *
+ * Set it with [`WormholeBuilder::confirm_verifier`](super::WormholeBuilder::confirm_verifier).
+ * After the key exchange, the handler gets the bytes of the [`verifier`](super::Wormhole::verifier)
+ * and returns whether the user approved it. Until then, no application data is exchanged. If the
+ * user rejects it, the wormhole gets closed as [`Mood::Scared`](super::Mood::Scared) and
+ * connecting fails with [`WormholeError::VerifierRejected`](super::WormholeError::VerifierRejected).
+ * This is what the `--verify` option of the Python implementation does.
+ *
+ * Closures returning a future of a `bool` implement this trait:
+ *
+ * ```no_run
+ * # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+ * use magic_wormhole::{transfer::APP_CONFIG, Wormhole};
+ *
+ * let pending = Wormhole::builder(APP_CONFIG)
+ *     .confirm_verifier(|verifier: &[u8]| {
+ *         println!("Verifier: {}", hex::encode(verifier));
+ *         async { true }
+ *     })
+ *     .allocate_code()
+ *     .await?;
+ * # Ok(()) })}
+ * ```
+ */
+pub trait ConfirmationHandler: Send + Sync {
+    /// Whether the user approves the `verifier`. This may take as long as the user needs.
+    fn confirm(&self, verifier: &[u8]) -> BoxFuture<'static, bool>;
+}
+
+impl<F, Fut> ConfirmationHandler for F
+where
+    F: Fn(&[u8]) -> Fut + Send + Sync,
+    Fut: std::future::Future<Output = bool> + Send + 'static,
+{
+    fn confirm(&self, verifier: &[u8]) -> BoxFuture<'static, bool> {
+        Box::pin(self(verifier))
+    }
+}
+
+/** A shared [`ConfirmationHandler`], so that the builders stay `Clone` and `Debug` */
+#[derive(Clone)]
+pub(crate) struct Confirmation(pub Arc<dyn ConfirmationHandler>);
+
+impl std::fmt::Debug for Confirmation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Confirmation")
+    }
+}
@@ -0,0 +1,169 @@
+use super::{secretbox, Mood};
+#[cfg(feature = "transfer")]
+use crate::metrics::Direction;
+use crate::metrics::{Metrics, NoMetrics};
+use futures::channel::mpsc;
+#[cfg(feature = "serde-types")]
+use serde_derive::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/**
+ * Something that happened during the lifetime of a [`Wormhole`](super::Wormhole)
+ *
+ * Subscribe to them with [`Wormhole::events`](super::Wormhole::events). To also get the events
+ * of the key exchange, subscribe earlier with [`MailboxConnection::events`](super::MailboxConnection::events)
+ * or [`PendingWormhole::events`](super::PendingWormhole::events).
+ */
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde-types", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde-types",
+    serde(rename_all = "kebab-case", tag = "type")
+)]
+#[non_exhaustive]
+pub enum WormholeEvent {
+    /// The other side showed up on the rendezvous server and started the key exchange
+    PeerConnected,
+    /// The key exchange succeeded. Now is a good time to show the verifier to the user
+    KeyConfirmed {
+        /// Same as [`Wormhole::verifier`](super::Wormhole::verifier)
+        #[cfg_attr(feature = "serde-types", serde(with = "hex_key"))]
+        verifier: Box<secretbox::Key>,
+    },
+    /// The nameplate got released, the code can no longer be used by anybody else
+    NameplateReleased,
+    /// A transit connection to the other side has been established
+    #[cfg(feature = "transit")]
+    TransitConnected(crate::transit::TransitInfo),
+    /// Some more bytes of a file transfer have been sent or received
+    #[cfg(feature = "transfer")]
+    TransferProgress {
+        /// The number of bytes transferred so far
+        transferred: u64,
+        /// The total number of bytes of the transfer
+        total: u64,
+    },
+    /// The connection to the rendezvous server got closed
+    Closed {
+        /// The mood reported to the server
+        mood: Mood,
+    },
+}
+
+/**
+ * Dispatches [`WormholeEvent`]s to all subscribers and to the [`Metrics`]
+ *
+ * All clones share the same subscribers. Subscriber streams end once the last clone is dropped.
+ */
+#[derive(Clone, Debug)]
+pub(crate) struct EventSender {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<WormholeEvent>>>>,
+    metrics: Arc<dyn Metrics>,
+}
+
+impl Default for EventSender {
+    fn default() -> Self {
+        Self {
+            subscribers: Default::default(),
+            metrics: Arc::new(NoMetrics),
+        }
+    }
+}
+
+impl EventSender {
+    pub(crate) fn subscribe(&self) -> mpsc::UnboundedReceiver<WormholeEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub(crate) fn emit(&self, event: WormholeEvent) {
+        self.metrics.event(&event);
+        /* Forget about subscribers who dropped their stream */
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    pub(crate) fn metrics(&self) -> &Arc<dyn Metrics> {
+        &self.metrics
+    }
+
+    /** Only affects clones made after this */
+    pub(crate) fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /** Wrap a transit handler so that it also emits [`WormholeEvent::TransitConnected`] */
+    #[cfg(feature = "transit")]
+    pub(crate) fn transit_handler(
+        &self,
+        transit_handler: impl FnOnce(crate::transit::TransitInfo),
+    ) -> impl FnOnce(crate::transit::TransitInfo) {
+        let events = self.clone();
+        move |info: crate::transit::TransitInfo| {
+            events.emit(WormholeEvent::TransitConnected(info.clone()));
+            transit_handler(info)
+        }
+    }
+
+    /**
+     * Wrap a progress handler so that it also emits [`WormholeEvent::TransferProgress`]
+     *
+     * The bytes are counted in the [`Metrics`] too.
+     */
+    #[cfg(feature = "transfer")]
+    pub(crate) fn progress_handler(
+        &self,
+        direction: Direction,
+        mut progress_handler: impl FnMut(u64, u64) + 'static,
+    ) -> impl FnMut(u64, u64) + 'static {
+        let events = self.clone();
+        let mut last = 0;
+        move |transferred: u64, total: u64| {
+            events
+                .metrics
+                .bytes_transferred(direction, transferred.saturating_sub(last));
+            last = transferred;
+            events.emit(WormholeEvent::TransferProgress { transferred, total });
+            progress_handler(transferred, total)
+        }
+    }
+
+    /** Report a finished transfer to the [`Metrics`], passing the result through */
+    #[cfg(feature = "transfer")]
+    pub(crate) fn transfer_finished<T>(
+        &self,
+        direction: Direction,
+        started: crate::util::Instant,
+        result: Result<T, crate::transfer::TransferError>,
+    ) -> Result<T, crate::transfer::TransferError> {
+        self.metrics.transfer_finished(
+            direction,
+            started.elapsed(),
+            result.as_ref().err().map(|error| error.reason()),
+        );
+        result
+    }
+}
+
+/** Keys are serialized as hex strings, like the verifier in the CLI */
+#[cfg(feature = "serde-types")]
+mod hex_key {
+    use super::secretbox;
+
+    pub fn serialize<S: serde::Serializer>(
+        key: &secretbox::Key,
+        ser: S,
+    ) -> Result<S::Ok, S::Error> {
+        hex::serde::serialize(key, ser)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        de: D,
+    ) -> Result<Box<secretbox::Key>, D::Error> {
+        let key: [u8; 32] = hex::serde::deserialize(de)?;
+        Ok(Box::new(key.into()))
+    }
+}
@@ -8,6 +8,7 @@ use hkdf::Hkdf;
 use serde_derive::{Deserialize, Serialize};
 use sha2::{digest::FixedOutput, Digest, Sha256};
 use spake2::{Ed25519Group, Identity, Password, Spake2};
+use zeroize::{Zeroize, Zeroizing};
 
 /// Marker trait to give encryption keys a "purpose", to not confuse them
 ///
@@ -32,9 +33,11 @@ impl KeyPurpose for GenericKey {}
  * The symmetric encryption key used to communicate with the other side.
  *
  * You don't need to do any crypto, but you might need it to derive subkeys for sub-protocols.
+ *
+ * The key is wiped from memory when dropped, and its `Debug` and `Display` output is redacted. Use
+ * [`to_hex`](Self::to_hex) if you really need to print it.
  */
-#[derive(Debug, Clone, derive_more::Display, derive_more::Deref)]
-#[display("{:?}", _0)]
+#[derive(Clone, derive_more::Deref)]
 #[deref(forward)]
 pub struct Key<P: KeyPurpose>(
     #[deref]
@@ -46,6 +49,25 @@ pub struct Key<P: KeyPurpose>(
     #[deref(ignore)] std::marker::PhantomData<P>,
 );
 
+impl<P: KeyPurpose> std::fmt::Debug for Key<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(<redacted>)")
+    }
+}
+
+impl<P: KeyPurpose> std::fmt::Display for Key<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+#[allow(deprecated)]
+impl<P: KeyPurpose> Drop for Key<P> {
+    fn drop(&mut self) {
+        self.0.as_mut_slice().zeroize();
+    }
+}
+
 impl Key<WormholeKey> {
     /**
      * Derive the sub-key used for transit
@@ -63,12 +85,7 @@ impl Key<WormholeKey> {
     pub fn derive_transit_key(&self, appid: &AppID) -> Key<crate::transit::TransitKey> {
         let transit_purpose = format!("{}/transit-key", appid);
         let derived_key = self.derive_subkey_from_purpose(&transit_purpose);
-        tracing::trace!(
-            "Input key: {}, Transit key: {}, Transit purpose: '{}'",
-            self.to_hex(),
-            derived_key.to_hex(),
-            &transit_purpose
-        );
+        tracing::trace!("Derived the transit key, purpose: '{}'", &transit_purpose);
         derived_key
     }
 }
@@ -101,14 +118,20 @@ struct PhaseMessage {
     pake_v1: Vec<u8>,
 }
 
-/// TODO doc
+/// Start the PAKE, returning its state and our message to the peer
 ///
 /// The "password" usually is the code, but it needs not to. The only requirement
-/// is that both sides use the same value, and agree on that.
-pub fn make_pake(password: &str, appid: &AppID) -> (Spake2<Ed25519Group>, Vec<u8>) {
-    let (pake_state, msg1) = Spake2::<Ed25519Group>::start_symmetric(
+/// is that both sides use the same value, and agree on that. The RNG is usually
+/// [`util::rng`](crate::util::rng), unless a test wants to be deterministic.
+pub fn make_pake_with(
+    password: &str,
+    appid: &AppID,
+    rng: impl rand::RngCore + rand::CryptoRng,
+) -> (Spake2<Ed25519Group>, Vec<u8>) {
+    let (pake_state, msg1) = Spake2::<Ed25519Group>::start_symmetric_with_rng(
         &Password::new(password.as_bytes()),
         &Identity::new(appid.0.as_bytes()),
+        rng,
     );
     let pake_msg = PhaseMessage { pake_v1: msg1 };
     let pake_msg_ser = serde_json::to_vec(&pake_msg).unwrap();
@@ -144,7 +167,7 @@ pub fn build_version_msg(
     versions: &VersionsMessage,
 ) -> (Phase, Vec<u8>) {
     let phase = Phase::VERSION;
-    let data_key = derive_phase_key(side, key, &phase);
+    let data_key = Zeroizing::new(derive_phase_key(side, key, &phase));
     let plaintext = serde_json::to_vec(versions).unwrap();
     let (_nonce, encrypted) = encrypt_data(&data_key, &plaintext);
     (phase, encrypted)
@@ -171,7 +194,7 @@ fn encrypt_data_with_nonce(
 
 pub fn encrypt_data(key: &secretbox::Key, plaintext: &[u8]) -> (secretbox::Nonce, Vec<u8>) {
     let nonce = secretbox::SecretBox::<secretbox::XSalsa20Poly1305>::generate_nonce(
-        &mut rand::thread_rng(),
+        &mut crate::util::rng(),
     );
     let nonce_and_ciphertext = encrypt_data_with_nonce(key, plaintext, &nonce);
     (nonce, nonce_and_ciphertext)
@@ -181,8 +204,10 @@ pub fn encrypt_data(key: &secretbox::Key, plaintext: &[u8]) -> (secretbox::Nonce
 pub fn decrypt_data(key: &secretbox::Key, encrypted: &[u8]) -> Option<Vec<u8>> {
     use secretbox::aead::generic_array::typenum::marker_traits::Unsigned;
     let nonce_size = <XSalsa20Poly1305 as AeadCore>::NonceSize::to_usize();
+    if encrypted.len() < nonce_size {
+        return None;
+    }
     let (nonce, ciphertext) = encrypted.split_at(nonce_size);
-    assert_eq!(nonce.len(), nonce_size);
     let cipher = XSalsa20Poly1305::new(GenericArray::from_slice(key));
     cipher
         .decrypt(GenericArray::from_slice(nonce), ciphertext)
@@ -202,6 +227,34 @@ pub fn derive_key(key: &secretbox::Key, purpose: &[u8]) -> secretbox::Key {
     key
 }
 
+/// The longest key HKDF-SHA256 can derive, in bytes
+pub const MAX_DERIVED_KEY_LENGTH: usize = 255 * 32;
+
+/// A key longer than [`MAX_DERIVED_KEY_LENGTH`] was requested, see [`Wormhole::derive_key`](crate::Wormhole::derive_key)
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "Derived keys must be at most {} bytes long, not {}",
+    MAX_DERIVED_KEY_LENGTH,
+    _0
+)]
+pub struct KeyLengthError(pub usize);
+
+#[cfg(feature = "serde-types")]
+impl_serialize_error!(KeyLengthError);
+
+/// Like [`derive_key`], but with any length of up to [`MAX_DERIVED_KEY_LENGTH`]
+pub fn derive_key_with_length(
+    key: &secretbox::Key,
+    purpose: &[u8],
+    length: usize,
+) -> Result<zeroize::Zeroizing<Vec<u8>>, KeyLengthError> {
+    let hk = Hkdf::<Sha256>::new(None, key);
+    let mut derived = zeroize::Zeroizing::new(vec![0; length]);
+    hk.expand(purpose, derived.as_mut_slice())
+        .map_err(|_| KeyLengthError(length))?;
+    Ok(derived)
+}
+
 pub fn derive_phase_key(side: &EitherSide, key: &secretbox::Key, phase: &Phase) -> secretbox::Key {
     let side_digest: Vec<u8> = sha256_digest(side.0.as_bytes());
     let phase_digest: Vec<u8> = sha256_digest(phase.0.as_bytes());
@@ -253,11 +306,28 @@ mod test {
             "835b5df80ce9ca46908e8524fb308649122cfbcefbeaa7e65061c6ef08ee1b2a"
         );
 
-        /* The API doesn't support non-standard length keys anymore.
-         * But we may want to add that back in in the future.
-         */
-        // let dk2 = derive_key(&main, b"purpose2", 10);
-        // assert_eq!(hex::encode(dk2), "f2238e84315b47eb6279");
+        let dk2 = derive_key_with_length(&main, b"purpose2", 10).unwrap();
+        assert_eq!(hex::encode(dk2), "f2238e84315b47eb6279");
+
+        let dk3 = derive_key_with_length(&main, b"appid/sync-key", 64).unwrap();
+        assert_eq!(
+            hex::encode(dk3),
+            "32564f117b2cd3f62c22e4a2761780b1699d2963f6ea73cc2e2471d73e441b818573d46cfc44af590b130d467d49bfccbc533574b043afafc856e237a52a949c"
+        );
+
+        assert!(derive_key_with_length(&main, b"purpose3", MAX_DERIVED_KEY_LENGTH).is_ok());
+        assert_eq!(
+            derive_key_with_length(&main, b"purpose3", MAX_DERIVED_KEY_LENGTH + 1),
+            Err(KeyLengthError(MAX_DERIVED_KEY_LENGTH + 1))
+        );
+    }
+
+    #[test]
+    fn test_key_debug_redacted() {
+        let key = Key::<WormholeKey>::new(Box::new(secretbox::Key::from([0x42; 32])));
+        assert_eq!(format!("{:?}", key), "Key(<redacted>)");
+        assert_eq!(format!("{}", key), "Key(<redacted>)");
+        assert_eq!(key.to_hex(), "42".repeat(32));
     }
 
     #[test]
@@ -350,6 +420,9 @@ mod test {
                 panic!("failed to decrypt");
             },
         };
+
+        /* Shorter than a nonce */
+        assert_eq!(decrypt_data(&k, &encrypted[..10]), None);
     }
 
     /* This test is disabled for now because the used key length is not compatible with our API */
@@ -370,4 +443,55 @@ mod test {
     //         None => panic!(),
     //     }
     // }
+
+    /** Key derivation vectors computed independently of this crate, see `tests/golden` */
+    #[test]
+    #[allow(deprecated)]
+    #[cfg(feature = "transit")]
+    fn test_golden_keys() {
+        let golden: serde_json::Value =
+            serde_json::from_str(include_str!("../../tests/golden/keys.json")).unwrap();
+        let hex_key = |name: &str| {
+            secretbox::Key::from_exact_iter(hex::decode(golden[name].as_str().unwrap()).unwrap())
+                .unwrap()
+        };
+        let main = hex_key("main_key");
+
+        assert_eq!(
+            hex::encode(derive_verifier(&main)),
+            golden["verifier"].as_str().unwrap()
+        );
+        for phase_key in golden["phase_keys"].as_array().unwrap() {
+            let side = EitherSide::from(phase_key["side"].as_str().unwrap());
+            let phase = Phase(phase_key["phase"].as_str().unwrap().to_owned().into());
+            assert_eq!(
+                hex::encode(derive_phase_key(&side, &main, &phase)),
+                phase_key["key"].as_str().unwrap(),
+                "phase key for {:?}",
+                phase_key
+            );
+        }
+
+        let main = Key::<WormholeKey>::new(Box::new(main));
+        let transit_key =
+            main.derive_transit_key(&AppID::new(golden["appid"].as_str().unwrap().to_owned()));
+        assert_eq!(
+            transit_key.to_hex(),
+            golden["transit_key"].as_str().unwrap()
+        );
+        for purpose in [
+            "transit_relay_token",
+            "transit_record_sender_key",
+            "transit_record_receiver_key",
+        ] {
+            assert_eq!(
+                transit_key
+                    .derive_subkey_from_purpose::<GenericKey>(purpose)
+                    .to_hex(),
+                golden[purpose].as_str().unwrap(),
+                "{}",
+                purpose
+            );
+        }
+    }
 }
@@ -0,0 +1,236 @@
+//! The sans-IO parts of the client-client protocol
+//!
+//! The state machines in here don't do any I/O, don't spawn and don't wait for anything. They
+//! get fed with the messages that arrived from the peer and return a list of actions that the
+//! caller should perform. Driving them over an actual connection to the rendezvous server
+//! happens in [`Wormhole::connect`](super::Wormhole::connect).
+//!
+//! This keeps the protocol logic independent from the async runtime and easy to test.
+//!
+//! So far, only the key exchange lives in here. The mailbox and nameplate handling in
+//! [`rendezvous`](super::rendezvous), transit and the transfer protocols still do their own I/O.
+
+use super::{
+    key, secretbox, server_messages::EncryptedMessage, AppID, MySide, Phase, WormholeError,
+    WormholeEvent,
+};
+use crate::util::trace_transition;
+use spake2::{Ed25519Group, Spake2};
+
+/// Something a state machine wants its driver to do
+#[derive(Debug)]
+pub(crate) enum Action {
+    /// Add a message to the mailbox
+    Send(Phase, Vec<u8>),
+    /// Tell the event subscribers
+    Emit(WormholeEvent),
+    /// The key exchange is done, the wormhole is ready to be used
+    Established {
+        key: key::Key<key::WormholeKey>,
+        verifier: Box<secretbox::Key>,
+        peer_versions: serde_json::Value,
+    },
+}
+
+/**
+ * The states of the key exchange, for [`Wormhole::machines_graphviz`](super::Wormhole::machines_graphviz)
+ *
+ * Each entry is a transition `(from, to, trigger)`.
+ */
+pub(crate) const KEY_TRANSITIONS: &[(&str, &str, &str)] = &[
+    (
+        "WaitingForPake",
+        "WaitingForVersions",
+        "receive PAKE / send versions",
+    ),
+    (
+        "WaitingForVersions",
+        "Done",
+        "receive versions / established",
+    ),
+];
+
+enum KeyState {
+    WaitingForPake(Spake2<Ed25519Group>),
+    WaitingForVersions(key::Key<key::WormholeKey>),
+    Done,
+}
+
+/**
+ * The key exchange
+ *
+ * Both sides send their PAKE message right away. Once we got the one from our peer, we
+ * derive the key and send our (encrypted) versions. Receiving the versions of our peer
+ * confirms that both sides have the same key.
+ */
+pub(crate) struct KeyMachine {
+    side: MySide,
+    app_versions: serde_json::Value,
+    state: KeyState,
+}
+
+impl KeyMachine {
+    /// Start a key exchange with the password (usually the code) both sides agreed on
+    pub fn start(
+        password: &str,
+        appid: &AppID,
+        side: MySide,
+        app_versions: serde_json::Value,
+        rng: impl rand::RngCore + rand::CryptoRng,
+    ) -> (Self, Vec<Action>) {
+        let (pake_state, pake_msg_ser) = key::make_pake_with(password, appid, rng);
+        let machine = Self {
+            side,
+            app_versions,
+            state: KeyState::WaitingForPake(pake_state),
+        };
+        (machine, vec![Action::Send(Phase::PAKE, pake_msg_ser)])
+    }
+
+    /// Process a message of our peer
+    pub fn receive(&mut self, message: EncryptedMessage) -> Result<Vec<Action>, WormholeError> {
+        match std::mem::replace(&mut self.state, KeyState::Done) {
+            KeyState::WaitingForPake(pake_state) => {
+                let peer_pake = key::extract_pake_msg(&message.body)?;
+                let key = pake_state
+                    .finish(&peer_pake)
+                    .map_err(|_| WormholeError::PakeFailed)
+                    .map(|key| {
+                        let key = zeroize::Zeroizing::new(key);
+                        key::Key::new(Box::new(*secretbox::Key::from_slice(&key)))
+                    })?;
+
+                let mut versions = key::VersionsMessage::new();
+                versions.set_app_versions(self.app_versions.take());
+                let (version_phase, version_msg) =
+                    key::build_version_msg(&self.side, &key, &versions);
+
+                self.state = KeyState::WaitingForVersions(key);
+                trace_transition("key", "WaitingForPake", "WaitingForVersions");
+                Ok(vec![
+                    Action::Emit(WormholeEvent::PeerConnected),
+                    Action::Send(version_phase, version_msg),
+                ])
+            },
+            KeyState::WaitingForVersions(key) => {
+                let versions: key::VersionsMessage = message
+                    .decrypt(&key)
+                    .ok_or(WormholeError::PakeFailed)
+                    .and_then(|plaintext| {
+                        serde_json::from_slice(&plaintext).map_err(WormholeError::ProtocolJson)
+                    })?;
+
+                trace_transition("key", "WaitingForVersions", "Done");
+                let verifier = Box::new(key::derive_verifier(&key));
+                Ok(vec![
+                    Action::Emit(WormholeEvent::KeyConfirmed {
+                        verifier: verifier.clone(),
+                    }),
+                    Action::Established {
+                        key,
+                        verifier,
+                        peer_versions: versions.app_versions,
+                    },
+                ])
+            },
+            KeyState::Done => panic!("The key exchange is already done"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::TheirSide;
+
+    #[test]
+    fn test_key_transitions() {
+        crate::util::assert_transitions_traced(include_str!("machine.rs"), "key", KEY_TRANSITIONS);
+    }
+
+    /** Deliver all messages one side wants to send to the other side */
+    fn deliver(
+        actions: Vec<Action>,
+        from: &str,
+        to: &mut KeyMachine,
+    ) -> Result<(Vec<Action>, Vec<Action>), WormholeError> {
+        let mut replies = Vec::new();
+        let mut rest = Vec::new();
+        for action in actions {
+            match action {
+                Action::Send(phase, body) => replies.extend(to.receive(EncryptedMessage {
+                    side: TheirSide::from(from),
+                    phase,
+                    body,
+                })?),
+                other => rest.push(other),
+            }
+        }
+        Ok((replies, rest))
+    }
+
+    fn established(actions: &[Action]) -> Option<(&secretbox::Key, &serde_json::Value)> {
+        actions.iter().find_map(|action| match action {
+            Action::Established {
+                key, peer_versions, ..
+            } => Some((&**key, peer_versions)),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_key_exchange() {
+        let appid = AppID::new("appid");
+        let (mut a, a_pake) = KeyMachine::start(
+            "4-purple-sausages",
+            &appid,
+            MySide::unchecked_from_string("aaaa".into()),
+            serde_json::json!({"side": "a"}),
+            crate::util::rng(),
+        );
+        let (mut b, b_pake) = KeyMachine::start(
+            "4-purple-sausages",
+            &appid,
+            MySide::unchecked_from_string("bbbb".into()),
+            serde_json::json!({"side": "b"}),
+            crate::util::rng(),
+        );
+
+        let (b_versions, _) = deliver(a_pake, "aaaa", &mut b).unwrap();
+        let (a_versions, _) = deliver(b_pake, "bbbb", &mut a).unwrap();
+        let (a_done, _) = deliver(b_versions, "bbbb", &mut a).unwrap();
+        let (b_done, _) = deliver(a_versions, "aaaa", &mut b).unwrap();
+
+        let (a_key, a_peer) = established(&a_done).unwrap();
+        let (b_key, b_peer) = established(&b_done).unwrap();
+        assert_eq!(a_key, b_key);
+        assert_eq!(a_peer, &serde_json::json!({"side": "b"}));
+        assert_eq!(b_peer, &serde_json::json!({"side": "a"}));
+    }
+
+    #[test]
+    fn test_key_exchange_wrong_password() {
+        let appid = AppID::new("appid");
+        let (mut a, a_pake) = KeyMachine::start(
+            "4-purple-sausages",
+            &appid,
+            MySide::unchecked_from_string("aaaa".into()),
+            serde_json::Value::Null,
+            crate::util::rng(),
+        );
+        let (mut b, b_pake) = KeyMachine::start(
+            "4-purple-sausagez",
+            &appid,
+            MySide::unchecked_from_string("bbbb".into()),
+            serde_json::Value::Null,
+            crate::util::rng(),
+        );
+
+        let (b_versions, _) = deliver(a_pake, "aaaa", &mut b).unwrap();
+        deliver(b_pake, "bbbb", &mut a).unwrap();
+        assert!(matches!(
+            deliver(b_versions, "bbbb", &mut a),
+            Err(WormholeError::PakeFailed)
+        ));
+    }
+}
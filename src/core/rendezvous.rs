@@ -5,17 +5,21 @@
 #[cfg(not(target_family = "wasm"))]
 use async_tungstenite::tungstenite as ws2;
 use futures::prelude::*;
-use std::collections::VecDeque;
+use std::{collections::VecDeque, time::Duration};
 
-use crate::core::{
-    server_messages::{InboundMessage, OutboundMessage, PermissionRequired, SubmitPermission},
-    AppID, EncryptedMessage, Mailbox, Mood, MySide, Nameplate, Phase,
+use crate::{
+    core::{
+        server_messages::{InboundMessage, OutboundMessage, PermissionRequired, SubmitPermission},
+        AppID, EncryptedMessage, Mailbox, Mood, MySide, Nameplate, Phase,
+    },
+    proxy::ProxyConfig,
+    util::{trace_transition, Clock, SharedRng, PROTOCOL_TRACE},
 };
 
 /// Some rendezvous server you might use.
 ///
 /// Two applications that want to communicate with each other *must* use the same rendezvous server.
-pub const DEFAULT_RENDEZVOUS_SERVER: &str = "ws://relay.magic-wormhole.io:4000/v1";
+pub const DEFAULT_RENDEZVOUS_SERVER: &str = crate::protocol::v1::RENDEZVOUS_SERVER;
 
 /// An error occurred when connecting to the rendezvous server
 #[derive(Debug, thiserror::Error)]
@@ -35,12 +39,18 @@ pub enum RendezvousError {
     /// The server sent us an error message
     #[error("Received error message from server: {}", _0)]
     Server(Box<str>),
+    /// The server refused us in its welcome message, see [`ServerWelcome`]
+    #[error("The rendezvous server refused the connection: {}", _0)]
+    Welcome(Box<str>),
     /// Server wants a login permission, but we don't suppport any of these
     #[error(
         "Server wants one of {:?} for permissions, but we don't suppport any of these",
         _0
     )]
     Login(Vec<String>),
+    /// The server did not answer a ping in time, see [`Timeouts::keepalive`](crate::Timeouts::keepalive)
+    #[error("The rendezvous server stopped answering pings, the connection is probably lost")]
+    ConnectionLost,
     #[cfg(not(target_family = "wasm"))]
     /// Websocket I/O error
     #[error("Websocket I/O error")]
@@ -59,6 +69,9 @@ pub enum RendezvousError {
     ),
 }
 
+#[cfg(feature = "serde-types")]
+impl_serialize_error!(RendezvousError);
+
 impl RendezvousError {
     pub(self) fn protocol(error: impl Into<Box<str>>) -> Self {
         Self::Protocol(error.into())
@@ -74,6 +87,131 @@ impl RendezvousError {
     pub(self) fn server(error: impl Into<Box<str>>) -> Self {
         Self::Server(error.into())
     }
+
+    /** Whether the connection broke down, as opposed to the server rejecting something */
+    fn is_connection_lost(&self) -> bool {
+        matches!(self, Self::IO(_) | Self::ConnectionLost)
+    }
+
+    /**
+     * Whether the nameplate is already used by two other clients
+     *
+     * The code was probably mistyped, or somebody else guessed it. Either way, it can't be used.
+     */
+    pub fn is_crowded(&self) -> bool {
+        matches!(self, Self::Server(error) if &**error == "crowded")
+    }
+
+    /**
+     * Whether trying again might succeed
+     *
+     * This is the case if the connection to the server got lost or could not be established.
+     */
+    pub fn is_retryable(&self) -> bool {
+        self.is_connection_lost()
+    }
+}
+
+/**
+ * What the rendezvous server told us when we connected to it
+ *
+ * If the server sends an error in its welcome message instead, connecting fails with
+ * [`RendezvousError::Welcome`].
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-types",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+#[non_exhaustive]
+pub struct ServerWelcome {
+    /// The message of the day. Should be displayed to the user if present.
+    pub motd: Option<String>,
+    /// The newest version of the Python `magic-wormhole` CLI, as advertised by the server. Clients
+    /// may suggest upgrading if they are older.
+    pub current_cli_version: Option<String>,
+}
+
+/**
+ * How to reconnect when the connection to the rendezvous server drops
+ *
+ * Set it with [`WormholeBuilder::reconnect`](crate::WormholeBuilder::reconnect). Once the mailbox
+ * is open, a lost connection is re-established in the background of the next operation: the
+ * client connects again under the same side, re-claims its nameplate if it still holds one and
+ * re-opens the mailbox. The server then replays all messages of the mailbox, and the message
+ * that was being sent is sent again. The peer ignores the duplicates.
+ *
+ * Between attempts, the delay starts at `initial_delay` and doubles up to `max_delay`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// How many times to try connecting again before giving up with the original error
+    pub max_attempts: u32,
+    /// The delay before the first attempt
+    pub initial_delay: Duration,
+    /// The longest delay between two attempts
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    /** Five attempts, starting after one second and waiting at most 30 seconds */
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/**
+ * The TLS settings for `wss://` rendezvous servers, see [`WormholeBuilder::tls_config`](crate::WormholeBuilder::tls_config)
+ *
+ * This wraps a `rustls` 0.21 [`ClientConfig`](rustls::ClientConfig), which may trust other root
+ * certificates or present a client certificate. By default, the server name sent and verified is
+ * the host of the rendezvous URL.
+ */
+#[cfg(all(feature = "tls", not(target_family = "wasm")))]
+#[derive(Clone)]
+pub struct TlsConfig {
+    client_config: std::sync::Arc<rustls::ClientConfig>,
+    server_name: Option<String>,
+}
+
+#[cfg(all(feature = "tls", not(target_family = "wasm")))]
+impl TlsConfig {
+    /// Connect with a custom `rustls` configuration
+    pub fn new(client_config: impl Into<std::sync::Arc<rustls::ClientConfig>>) -> Self {
+        Self {
+            client_config: client_config.into(),
+            server_name: None,
+        }
+    }
+
+    /// Send and verify a different server name than the host of the rendezvous URL
+    pub fn with_server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+}
+
+#[cfg(all(feature = "tls", not(target_family = "wasm")))]
+impl std::fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+/** How to reach the rendezvous server, kept for reconnecting */
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConnectOptions {
+    pub proxy: Option<ProxyConfig>,
+    #[cfg(all(feature = "tls", not(target_family = "wasm")))]
+    pub tls: Option<TlsConfig>,
+    pub rng: SharedRng,
+    pub clock: Clock,
 }
 
 type MessageQueue = VecDeque<EncryptedMessage>;
@@ -82,9 +220,62 @@ type MessageQueue = VecDeque<EncryptedMessage>;
 #[display("{:?}", _0)]
 struct NameplateList(Vec<Nameplate>);
 
+/**
+ * Log a WebSocket frame to the protocol trace, with the secrets redacted
+ *
+ * The `body` of `add` and `message` frames holds the PAKE and encrypted messages of the peers,
+ * and the hashcash `stamp` could be replayed. Only their length gets logged.
+ */
+fn trace_frame(direction: &str, frame: &str) {
+    if tracing::enabled!(target: PROTOCOL_TRACE, tracing::Level::TRACE) {
+        let frame = redact_frame(frame);
+        tracing::trace!(target: PROTOCOL_TRACE, direction, %frame, "frame");
+    }
+}
+
+fn redact_frame(frame: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(frame) {
+        Ok(serde_json::Value::Object(mut frame)) => {
+            for field in ["body", "stamp"] {
+                if let Some(serde_json::Value::String(value)) = frame.get_mut(field) {
+                    *value = format!("<{} bytes>", value.len());
+                }
+            }
+            serde_json::Value::Object(frame).to_string()
+        },
+        _ => format!("<{} bytes of garbage>", frame.len()),
+    }
+}
+
+/**
+ * Parse a message from the server
+ *
+ * This is the transport independent part of receiving. Returns `None` for messages that
+ * should be ignored.
+ */
+pub(crate) fn parse_message(
+    message_plain: &str,
+) -> Result<Option<InboundMessage>, RendezvousError> {
+    trace_frame("in", message_plain);
+    let message = serde_json::from_str(message_plain)?;
+    tracing::debug!("Received {}", message);
+    match message {
+        InboundMessage::Unknown => {
+            tracing::warn!("Got unknown message, ignoring: '{}'", message_plain);
+            Ok(None)
+        },
+        InboundMessage::Error { error, orig: _ } => Err(RendezvousError::server(error)),
+        message => Ok(Some(message)),
+    }
+}
+
 #[cfg(not(target_family = "wasm"))]
 struct WsConnection {
     connection: async_tungstenite::WebSocketStream<async_tungstenite::async_std::ConnectStream>,
+    /// How long the server may be silent before we ping it
+    keepalive: Option<Duration>,
+    /// Whether the server has yet to answer our last ping
+    awaiting_pong: bool,
 }
 
 #[cfg(target_family = "wasm")]
@@ -93,7 +284,169 @@ struct WsConnection {
     meta: ws_stream_wasm::WsMeta,
 }
 
+#[cfg(not(target_family = "wasm"))]
 impl WsConnection {
+    /** Open the TCP connection, directly or through the proxy, and do the TLS and WebSocket handshakes */
+    async fn open_websocket(
+        relay_url: &str,
+        options: &ConnectOptions,
+    ) -> Result<
+        async_tungstenite::WebSocketStream<async_tungstenite::async_std::ConnectStream>,
+        ws2::Error,
+    > {
+        use ws2::client::IntoClientRequest;
+
+        let request = relay_url.into_client_request()?;
+        let host = request
+            .uri()
+            .host()
+            .ok_or(ws2::Error::Url(ws2::error::UrlError::NoHostName))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        let is_tls = request.uri().scheme_str() == Some("wss");
+        let port = request
+            .uri()
+            .port_u16()
+            .unwrap_or(if is_tls { 443 } else { 80 });
+        let stream = match &options.proxy {
+            Some(proxy) => proxy.connect(&host, port).await,
+            None => async_std::net::TcpStream::connect((host.as_str(), port)).await,
+        }
+        .map_err(ws2::Error::Io)?;
+
+        #[cfg(all(feature = "tls", not(feature = "native-tls")))]
+        if let Some(tls) = options.tls.as_ref().filter(|_| is_tls) {
+            let server_name = tls.server_name.as_deref().unwrap_or(&host);
+            let stream = async_tls::TlsConnector::from(tls.client_config.clone())
+                .connect(server_name, stream)
+                .await
+                .map_err(ws2::Error::Io)?;
+            let (stream, _) = async_tungstenite::client_async(
+                request,
+                async_tungstenite::stream::Stream::Tls(stream),
+            )
+            .await?;
+            return Ok(stream);
+        }
+        /* With `native-tls`, async-tungstenite does the TLS handshake using that */
+        #[cfg(all(feature = "tls", feature = "native-tls"))]
+        if options.tls.is_some() && is_tls {
+            return Err(ws2::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "A custom TLS configuration can't be used with the `native-tls` feature",
+            )));
+        }
+
+        let (stream, _) = async_tungstenite::async_std::client_async_tls_with_connector_and_config(
+            request, stream, None, None,
+        )
+        .await?;
+        Ok(stream)
+    }
+}
+
+impl WsConnection {
+    /**
+     * Open the WebSocket, get the permission if required and bind to `appid` as `side`
+     *
+     * Browsers use the proxy and the certificates of the system, so the `options` are ignored on
+     * WASM.
+     */
+    async fn connect(
+        appid: &AppID,
+        relay_url: &str,
+        side: &MySide,
+        options: &ConnectOptions,
+    ) -> Result<(Self, ServerWelcome), RendezvousError> {
+        let mut connection;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            connection = WsConnection {
+                connection: Self::open_websocket(relay_url, options).await?,
+                keepalive: crate::Timeouts::default().keepalive,
+                awaiting_pong: false,
+            };
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = options;
+            let (meta, stream) = ws_stream_wasm::WsMeta::connect(relay_url, None).await?;
+            connection = WsConnection {
+                meta,
+                connection: stream,
+            };
+        }
+
+        let welcome = match connection.receive_message_some().await? {
+            InboundMessage::Welcome { welcome } => welcome,
+            other => {
+                return Err(RendezvousError::protocol(format!(
+                    "First message server sends must be 'welcome', but was '{}'",
+                    other
+                )))
+            },
+        };
+
+        /* Abort before doing any work for the permissions */
+        #[allow(deprecated)]
+        if let Some(error) = welcome.error {
+            return Err(RendezvousError::Welcome(error.into()));
+        }
+        if let Some(motd) = &welcome.motd {
+            tracing::info!("Message of the day: {}", motd);
+        }
+
+        match welcome.permission_required {
+            Some(PermissionRequired {
+                hashcash: Some(hashcash),
+                ..
+            }) => {
+                let mut rng = options.rng.clone();
+                let now = options.clock.now();
+                /* Higher difficulties take a while, so don't block the executor meanwhile */
+                #[cfg(not(target_family = "wasm"))]
+                let token = async_std::task::spawn_blocking(move || {
+                    crate::util::hashcash_with(hashcash.resource, hashcash.bits, &mut rng, now)
+                })
+                .await;
+                #[cfg(target_family = "wasm")]
+                let token =
+                    crate::util::hashcash_with(hashcash.resource, hashcash.bits, &mut rng, now);
+                connection
+                    .send_message(
+                        &OutboundMessage::SubmitPermission(SubmitPermission::Hashcash {
+                            stamp: token.to_string(),
+                        }),
+                        None,
+                    )
+                    .await?;
+            },
+            Some(PermissionRequired { none: true, .. }) => (),
+            Some(PermissionRequired { other, .. }) => {
+                /* We can't actually log in :/ */
+                return Err(RendezvousError::Login(
+                    // TODO use `into_keys` once stable and remove the `cloned`
+                    other.keys().cloned().collect(),
+                ));
+            },
+            None => (),
+        }
+
+        connection
+            .send_message(&OutboundMessage::bind(appid.clone(), side.clone()), None)
+            .await?;
+
+        #[allow(deprecated)]
+        let welcome = ServerWelcome {
+            motd: welcome.motd,
+            current_cli_version: welcome.current_cli_version,
+        };
+        Ok((connection, welcome))
+    }
+
     #[cfg(not(target_family = "wasm"))]
     async fn send_message(
         &mut self,
@@ -101,9 +454,9 @@ impl WsConnection {
         queue: Option<&mut MessageQueue>,
     ) -> Result<(), RendezvousError> {
         tracing::debug!("Sending {}", message);
-        self.connection
-            .send(ws2::Message::Text(serde_json::to_string(message).unwrap()))
-            .await?;
+        let frame = serde_json::to_string(message)?;
+        trace_frame("out", &frame);
+        self.connection.send(ws2::Message::Text(frame)).await?;
         self.receive_ack(queue).await?;
         Ok(())
     }
@@ -115,10 +468,10 @@ impl WsConnection {
         queue: Option<&mut MessageQueue>,
     ) -> Result<(), RendezvousError> {
         tracing::debug!("Sending {:?}", message);
+        let frame = serde_json::to_string(message)?;
+        trace_frame("out", &frame);
         self.connection
-            .send(ws_stream_wasm::WsMessage::Text(
-                serde_json::to_string(message).unwrap(),
-            ))
+            .send(ws_stream_wasm::WsMessage::Text(frame))
             .await?;
         self.receive_ack(queue).await?;
         Ok(())
@@ -204,32 +557,52 @@ impl WsConnection {
         }
     }
 
+    /**
+     * The next WebSocket message, pinging the server whenever it is silent for too long
+     *
+     * Gives up with [`RendezvousError::ConnectionLost`] if the server doesn't answer a ping
+     * within another keepalive interval.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    async fn next_frame(&mut self) -> Result<ws2::Message, RendezvousError> {
+        loop {
+            let next = match self.keepalive {
+                Some(interval) => {
+                    match crate::util::timeout(interval, self.connection.next()).await {
+                        Ok(next) => next,
+                        Err(_) if self.awaiting_pong => {
+                            return Err(RendezvousError::ConnectionLost)
+                        },
+                        Err(_) => {
+                            tracing::trace!("Pinging the rendezvous server");
+                            self.connection.send(ws2::Message::Ping(Vec::new())).await?;
+                            self.awaiting_pong = true;
+                            continue;
+                        },
+                    }
+                },
+                None => self.connection.next().await,
+            };
+            return Ok(next.ok_or(ws2::Error::ConnectionClosed)??);
+        }
+    }
+
     #[cfg(not(target_family = "wasm"))]
     async fn receive_message(&mut self) -> Result<Option<InboundMessage>, RendezvousError> {
-        let message = self
-            .connection
-            .next()
-            .await
-            .expect("TODO this should always be Some")?;
-        match message {
-            ws2::Message::Text(message_plain) => {
-                let message = serde_json::from_str(&message_plain)?;
-                tracing::debug!("Received {}", message);
-                match message {
-                    InboundMessage::Unknown => {
-                        tracing::warn!("Got unknown message, ignoring: '{}'", message_plain);
-                        Ok(None)
-                    },
-                    InboundMessage::Error { error, orig: _ } => Err(RendezvousError::server(error)),
-                    message => Ok(Some(message)),
-                }
-            },
+        match self.next_frame().await? {
+            ws2::Message::Text(message_plain) => parse_message(&message_plain),
             ws2::Message::Binary(_) => Err(RendezvousError::protocol(
                 "WebSocket messages must be UTF-8 encoded text",
             )),
-            /* Ignore ping pong for now */
-            ws2::Message::Ping(_) => Ok(None),
-            ws2::Message::Pong(_) => Ok(None),
+            ws2::Message::Ping(_) => {
+                /* tungstenite queued the pong, make sure it goes out before we wait again */
+                self.connection.flush().await?;
+                Ok(None)
+            },
+            ws2::Message::Pong(_) => {
+                self.awaiting_pong = false;
+                Ok(None)
+            },
             ws2::Message::Close(_) => {
                 tracing::debug!("Received connection close");
                 Err(ws2::Error::ConnectionClosed.into())
@@ -247,20 +620,9 @@ impl WsConnection {
             .connection
             .next()
             .await
-            .expect("TODO this should always be Some");
+            .ok_or(ws_stream_wasm::WsErr::ConnectionNotOpen)?;
         match message {
-            ws_stream_wasm::WsMessage::Text(message_plain) => {
-                let message = serde_json::from_str(&message_plain)?;
-                tracing::debug!("Received {:?}", message);
-                match message {
-                    InboundMessage::Unknown => {
-                        tracing::warn!("Got unknown message, ignoring: '{}'", message_plain);
-                        Ok(None)
-                    },
-                    InboundMessage::Error { error, orig: _ } => Err(RendezvousError::server(error)),
-                    message => Ok(Some(message)),
-                }
-            },
+            ws_stream_wasm::WsMessage::Text(message_plain) => parse_message(&message_plain),
             ws_stream_wasm::WsMessage::Binary(_) => Err(RendezvousError::protocol(
                 "WebSocket messages must be UTF-8 encoded text",
             )),
@@ -295,6 +657,8 @@ enum RendezvousReply {
 )]
 struct MailboxMachine {
     nameplate: Option<Nameplate>,
+    /** Whether we released the nameplate, as opposed to opening the mailbox without one */
+    released: bool,
     mailbox: Mailbox,
     queue: MessageQueue,
     processed: std::collections::HashSet<Phase>,
@@ -317,6 +681,22 @@ impl MailboxMachine {
     }
 }
 
+/**
+ * The lifecycle of a mailbox, for [`Wormhole::machines_graphviz`](crate::Wormhole::machines_graphviz)
+ *
+ * Each entry is a transition `(from, to, trigger)`.
+ */
+pub(crate) const MAILBOX_TRANSITIONS: &[(&str, &str, &str)] = &[
+    ("connected", "allocated", "allocate"),
+    ("allocated", "claimed", "claim"),
+    ("connected", "claimed", "claim"),
+    ("claimed", "open", "open"),
+    ("connected", "open", "open directly"),
+    ("open", "released", "release nameplate"),
+    ("open", "closed", "close"),
+    ("released", "closed", "close"),
+];
+
 /// The rendezvous server is a central server used for connection establishment
 #[deprecated(
     since = "0.7.0",
@@ -326,6 +706,11 @@ pub struct RendezvousServer {
     connection: WsConnection,
     state: Option<MailboxMachine>,
     side: MySide,
+    appid: AppID,
+    relay_url: String,
+    reconnect: Option<ReconnectPolicy>,
+    options: ConnectOptions,
+    welcome: ServerWelcome,
 }
 
 #[allow(deprecated)]
@@ -351,74 +736,146 @@ impl RendezvousServer {
         appid: &AppID,
         relay_url: &str,
     ) -> Result<(Self, Option<String>), RendezvousError> {
-        let side = MySide::generate();
-        let mut connection;
+        Self::connect_via(appid, relay_url, ConnectOptions::default()).await
+    }
 
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let (stream, _) = async_tungstenite::async_std::connect_async(relay_url).await?;
-            connection = WsConnection { connection: stream };
-        }
+    /** Like [`connect`](Self::connect), but through a proxy or with custom TLS settings */
+    #[tracing::instrument(
+        name = "rendezvous",
+        skip_all,
+        fields(appid = %appid, url = %relay_url, side = tracing::field::Empty)
+    )]
+    pub(crate) async fn connect_via(
+        appid: &AppID,
+        relay_url: &str,
+        options: ConnectOptions,
+    ) -> Result<(Self, Option<String>), RendezvousError> {
+        let side = MySide::generate_with(&mut options.rng.clone());
+        tracing::Span::current().record("side", tracing::field::display(&*side));
+        let (connection, welcome) =
+            WsConnection::connect(appid, relay_url, &side, &options).await?;
+        tracing::info!("Connected to rendezvous server.");
 
-        #[cfg(target_arch = "wasm32")]
+        Ok((
+            Self {
+                connection,
+                state: None,
+                side,
+                appid: appid.clone(),
+                relay_url: relay_url.into(),
+                reconnect: None,
+                options,
+                welcome: welcome.clone(),
+            },
+            welcome.motd,
+        ))
+    }
+
+    /** The RNG for everything on top of this connection, see [`SharedRng`] */
+    pub(crate) fn rng(&self) -> SharedRng {
+        self.options.rng.clone()
+    }
+
+    /** What the server told us when we connected to it */
+    pub fn server_welcome(&self) -> &ServerWelcome {
+        &self.welcome
+    }
+
+    /**
+     * Ping the server whenever it is silent for `interval`, see [`Timeouts::keepalive`](crate::Timeouts::keepalive)
+     *
+     * Browsers answer and send pings on their own, so this does nothing on WASM.
+     */
+    pub(crate) fn set_keepalive(&mut self, interval: Option<Duration>) {
+        #[cfg(not(target_family = "wasm"))]
         {
-            let (meta, stream) = ws_stream_wasm::WsMeta::connect(relay_url, None).await?;
-            connection = WsConnection {
-                meta,
-                connection: stream,
-            };
+            self.connection.keepalive = interval;
         }
+        #[cfg(target_family = "wasm")]
+        let _ = interval;
+    }
 
-        let welcome = match connection.receive_message_some().await? {
-            InboundMessage::Welcome { welcome } => welcome,
-            other => {
-                return Err(RendezvousError::protocol(format!(
-                    "First message server sends must be 'welcome', but was '{}'",
-                    other
-                )))
-            },
+    /** Reconnect after the mailbox is open, see [`ReconnectPolicy`] */
+    pub(crate) fn set_reconnect(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect = policy;
+    }
+
+    /**
+     * Recover from `error` by connecting again and re-opening the mailbox
+     *
+     * Returns the last error if this is not possible, because the connection is still there, no
+     * mailbox is open, reconnecting is disabled or all attempts failed.
+     */
+    async fn try_reconnect(&mut self, mut error: RendezvousError) -> Result<(), RendezvousError> {
+        let Some(policy) = self.reconnect else {
+            return Err(error);
         };
+        if self.state.is_none() || !error.is_connection_lost() {
+            return Err(error);
+        }
 
-        match welcome.permission_required {
-            Some(PermissionRequired {
-                hashcash: Some(hashcash),
-                ..
-            }) => {
-                let token = crate::util::hashcash(hashcash.resource, hashcash.bits);
-                connection
-                    .send_message(
-                        &OutboundMessage::SubmitPermission(SubmitPermission::Hashcash {
-                            stamp: token.to_string(),
-                        }),
-                        None,
-                    )
-                    .await?;
-            },
-            Some(PermissionRequired { none: true, .. }) => (),
-            Some(PermissionRequired { other, .. }) => {
-                /* We can't actually log in :/ */
-                return Err(RendezvousError::Login(
-                    // TODO use `into_keys` once stable and remove the `cloned`
-                    other.keys().cloned().collect(),
-                ));
-            },
-            None => (),
+        let mut delay = policy.initial_delay;
+        for attempt in 1..=policy.max_attempts {
+            tracing::warn!(
+                "Lost the connection to the rendezvous server ({}), reconnecting in {:?} (attempt {} of {})",
+                error,
+                delay,
+                attempt,
+                policy.max_attempts
+            );
+            crate::util::sleep(delay).await;
+            match self.reattach().await {
+                Ok(()) => {
+                    tracing::info!("Reconnected to the rendezvous server.");
+                    return Ok(());
+                },
+                Err(new_error) if new_error.is_connection_lost() => error = new_error,
+                Err(new_error) => return Err(new_error),
+            }
+            delay = (delay * 2).min(policy.max_delay);
         }
+        Err(error)
+    }
 
+    /** Connect again under the same side, and re-open the mailbox */
+    async fn reattach(&mut self) -> Result<(), RendezvousError> {
+        let (mut connection, _welcome) =
+            WsConnection::connect(&self.appid, &self.relay_url, &self.side, &self.options).await?;
+        #[cfg(not(target_family = "wasm"))]
+        {
+            connection.keepalive = self.connection.keepalive;
+        }
+        let state = self
+            .state
+            .as_mut()
+            .expect("Can only reattach to an open mailbox");
+
+        if let Some(nameplate) = &state.nameplate {
+            connection
+                .send_message(
+                    &OutboundMessage::claim(nameplate.clone()),
+                    Some(&mut state.queue),
+                )
+                .await?;
+            match connection.receive_reply(Some(&mut state.queue)).await? {
+                RendezvousReply::Claimed(mailbox) if mailbox == state.mailbox => (),
+                other => return Err(RendezvousError::invalid_message("claimed", other)),
+            }
+        }
         connection
-            .send_message(&OutboundMessage::bind(appid.clone(), side.clone()), None)
+            .send_message(
+                &OutboundMessage::open(state.mailbox.clone()),
+                Some(&mut state.queue),
+            )
             .await?;
 
-        tracing::info!("Connected to rendezvous server.");
+        self.connection = connection;
+        Ok(())
+    }
 
-        Ok((
-            Self {
-                connection,
-                state: None,
-                side,
-            },
-            welcome.motd,
-        ))
+    /** The proxy the connection goes through */
+    pub(crate) fn proxy(&self) -> Option<&ProxyConfig> {
+        self.options.proxy.as_ref()
     }
 
     /** A random unique string for this session */
@@ -443,8 +900,13 @@ impl RendezvousServer {
         phase: Phase,
         body: Vec<u8>,
     ) -> Result<(), RendezvousError> {
-        self.send_message(&OutboundMessage::Add { body, phase })
-            .await
+        let message = OutboundMessage::Add { body, phase };
+        loop {
+            match self.send_message(&message).await {
+                Err(error) => self.try_reconnect(error).await?,
+                result => return result,
+            }
+        }
     }
 
     pub(crate) async fn next_peer_message_some(
@@ -471,7 +933,19 @@ impl RendezvousServer {
                 return Ok(None);
             }
         }
-        match self.connection.receive_message().await? {
+        let message = match self.connection.receive_message().await {
+            Ok(message) => message,
+            Err(error) => {
+                /* The server replays the mailbox, so the queue fills up again */
+                self.try_reconnect(error).await?;
+                return Ok(None);
+            },
+        };
+        let machine = self
+            .state
+            .as_mut()
+            .expect("Can only receive messages when having a claimed+open mailbox");
+        match message {
             Some(InboundMessage::Message(message)) => {
                 if machine.receive_message(&message, &self.side) {
                     Ok(Some(message))
@@ -488,6 +962,14 @@ impl RendezvousServer {
     }
 
     /** Allocate a nameplate, claim the mailbox and open it */
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            side = %*self.side,
+            nameplate = tracing::field::Empty,
+            mailbox = tracing::field::Empty
+        )
+    )]
     pub async fn allocate_claim_open(&mut self) -> Result<(Nameplate, Mailbox), RendezvousError> {
         assert!(
             self.state.is_none(),
@@ -499,6 +981,8 @@ impl RendezvousServer {
             RendezvousReply::Allocated(nameplate) => nameplate,
             other => return Err(RendezvousError::invalid_message("allocated", other)),
         };
+        tracing::Span::current().record("nameplate", tracing::field::display(&nameplate));
+        trace_transition("mailbox", "connected", "allocated");
 
         self.send_message(&OutboundMessage::claim(nameplate.clone()))
             .await?;
@@ -506,12 +990,16 @@ impl RendezvousServer {
             RendezvousReply::Claimed(mailbox) => mailbox,
             other => return Err(RendezvousError::invalid_message("claimed", other)),
         };
+        tracing::Span::current().record("mailbox", tracing::field::display(&mailbox));
+        trace_transition("mailbox", "allocated", "claimed");
 
         self.send_message(&OutboundMessage::open(mailbox.clone()))
             .await?;
+        trace_transition("mailbox", "claimed", "open");
 
         self.state = Some(MailboxMachine {
             nameplate: Some(nameplate.clone()),
+            released: false,
             mailbox: mailbox.clone(),
             queue: Default::default(),
             processed: Default::default(),
@@ -520,6 +1008,10 @@ impl RendezvousServer {
     }
 
     /** Claim a nameplate+mailbox and open it */
+    #[tracing::instrument(
+        skip_all,
+        fields(side = %*self.side, nameplate = %nameplate, mailbox = tracing::field::Empty)
+    )]
     pub async fn claim_open(&mut self, nameplate: Nameplate) -> Result<Mailbox, RendezvousError> {
         assert!(
             self.state.is_none(),
@@ -532,12 +1024,16 @@ impl RendezvousServer {
             RendezvousReply::Claimed(mailbox) => mailbox,
             other => return Err(RendezvousError::invalid_message("claimed", other)),
         };
+        tracing::Span::current().record("mailbox", tracing::field::display(&mailbox));
+        trace_transition("mailbox", "connected", "claimed");
 
         self.send_message(&OutboundMessage::open(mailbox.clone()))
             .await?;
+        trace_transition("mailbox", "claimed", "open");
 
         self.state = Some(MailboxMachine {
             nameplate: Some(nameplate.clone()),
+            released: false,
             mailbox: mailbox.clone(),
             queue: Default::default(),
             processed: Default::default(),
@@ -545,6 +1041,41 @@ impl RendezvousServer {
         Ok(mailbox)
     }
 
+    /** The state of the mailbox, see [`Wormhole::debug_state`](crate::Wormhole::debug_state) */
+    pub(crate) fn debug_state(&self) -> serde_json::Value {
+        let Some(state) = &self.state else {
+            return serde_json::json!({
+                "side": &**self.side,
+                "state": "connected",
+            });
+        };
+        let mut processed = state
+            .processed
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        processed.sort();
+        let queued = state
+            .queue
+            .iter()
+            .map(|message| {
+                serde_json::json!({
+                    "side": &**message.side,
+                    "phase": message.phase.to_string(),
+                    "length": message.body.len(),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "side": &**self.side,
+            "state": if state.nameplate.is_some() { "open" } else { "released" },
+            "nameplate": state.nameplate.as_ref().map(ToString::to_string),
+            "mailbox": state.mailbox.to_string(),
+            "queued_messages": queued,
+            "processed_phases": processed,
+        })
+    }
+
     pub fn needs_nameplate_release(&self) -> bool {
         self.state
             .as_ref()
@@ -565,6 +1096,7 @@ impl RendezvousServer {
         }
     }
 
+    #[tracing::instrument(skip_all, fields(side = %*self.side))]
     pub async fn release_nameplate(&mut self) -> Result<(), RendezvousError> {
         let nameplate = &mut self
             .state
@@ -579,7 +1111,10 @@ impl RendezvousServer {
             RendezvousReply::Released => (),
             other => return Err(RendezvousError::invalid_message("released", other)),
         };
-        self.state.as_mut().unwrap().nameplate = None;
+        let state = self.state.as_mut().unwrap();
+        state.nameplate = None;
+        state.released = true;
+        trace_transition("mailbox", "open", "released");
         Ok(())
     }
 
@@ -595,8 +1130,10 @@ impl RendezvousServer {
         );
         self.send_message(&OutboundMessage::open(mailbox.clone()))
             .await?;
+        trace_transition("mailbox", "connected", "open");
         self.state = Some(MailboxMachine {
             nameplate: None,
+            released: false,
             mailbox,
             queue: Default::default(),
             processed: Default::default(),
@@ -604,9 +1141,11 @@ impl RendezvousServer {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(side = %*self.side, mood = %mood))]
     pub async fn shutdown(mut self, mood: Mood) -> Result<(), RendezvousError> {
         if let Some(MailboxMachine {
             nameplate,
+            mut released,
             mailbox,
             mut queue,
             ..
@@ -620,6 +1159,8 @@ impl RendezvousServer {
                     RendezvousReply::Released => (),
                     other => return Err(RendezvousError::invalid_message("released", other)),
                 };
+                trace_transition("mailbox", "open", "released");
+                released = true;
             }
 
             self.connection
@@ -629,9 +1170,50 @@ impl RendezvousServer {
                 RendezvousReply::Closed => (),
                 other => return Err(RendezvousError::invalid_message("closed", other)),
             };
+            if released {
+                trace_transition("mailbox", "released", "closed");
+            } else {
+                trace_transition("mailbox", "open", "closed");
+            }
         }
 
         self.connection.close().await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn redacted(frame: serde_json::Value) -> serde_json::Value {
+        serde_json::from_str(&redact_frame(&frame.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_mailbox_transitions() {
+        crate::util::assert_transitions_traced(
+            include_str!("rendezvous.rs"),
+            "mailbox",
+            MAILBOX_TRANSITIONS,
+        );
+    }
+
+    #[test]
+    fn test_redact_frame() {
+        assert_eq!(
+            redacted(json!({"type": "add", "phase": "pake", "body": "deadbeef", "id": "1234"})),
+            json!({"type": "add", "phase": "pake", "body": "<8 bytes>", "id": "1234"})
+        );
+        assert_eq!(
+            redacted(json!({"type": "submit-permissions", "method": "hashcash", "stamp": "1:12:"})),
+            json!({"type": "submit-permissions", "method": "hashcash", "stamp": "<5 bytes>"})
+        );
+        assert_eq!(
+            redacted(json!({"type": "claim", "nameplate": "4"})),
+            json!({"type": "claim", "nameplate": "4"})
+        );
+        assert_eq!(redact_frame("not json"), "<8 bytes of garbage>");
+    }
+}
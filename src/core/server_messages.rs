@@ -119,7 +119,7 @@ pub(crate) struct EncryptedMessage {
 impl EncryptedMessage {
     pub fn decrypt(&self, key: &crypto_secretbox::Key) -> Option<Vec<u8>> {
         use super::key;
-        let data_key = key::derive_phase_key(&self.side, key, &self.phase);
+        let data_key = zeroize::Zeroizing::new(key::derive_phase_key(&self.side, key, &self.phase));
         key::decrypt_data(&data_key, &self.body)
     }
 }
@@ -131,6 +131,7 @@ impl EncryptedMessage {
 #[allow(dead_code)]
 pub enum OutboundMessage {
     #[display("SubmitPermission({})", _0)]
+    #[serde(rename = "submit-permissions")]
     SubmitPermission(SubmitPermission),
     #[display("Bind {{ appid: {}, side: {} }}", appid, side)]
     Bind {
@@ -440,7 +441,7 @@ mod test {
         let s = serde_json::to_string(&m).unwrap();
         assert_eq!(
             s,
-            r#"{"type":"submit-permission","method":"hashcash","stamp":"stamp"}"#
+            r#"{"type":"submit-permissions","method":"hashcash","stamp":"stamp"}"#
         );
     }
 
@@ -468,4 +469,80 @@ mod test {
             _ => panic!(),
         }
     }
+
+    /** The exact messages the reference implementation exchanges, see `tests/golden` */
+    #[test]
+    fn test_golden_outbound() {
+        let golden: Value =
+            serde_json::from_str(include_str!("../../tests/golden/rendezvous.json")).unwrap();
+        let mailbox = || Mailbox(String::from("hs6xmpj2a2mv4"));
+        let messages = [
+            (
+                "bind",
+                OutboundMessage::bind(
+                    AppID::new("lothar.com/wormhole/text-or-file-xfer"),
+                    MySide::unchecked_from_string(String::from("0123456789")),
+                ),
+            ),
+            ("list", OutboundMessage::List),
+            ("allocate", OutboundMessage::Allocate),
+            ("claim", OutboundMessage::claim("4")),
+            ("release", OutboundMessage::release("4")),
+            ("open", OutboundMessage::open(mailbox())),
+            (
+                "add",
+                OutboundMessage::add(Phase::PAKE, br#"{"pake_v1":"00ff"}"#.to_vec()),
+            ),
+            ("close", OutboundMessage::close(mailbox(), Mood::Happy)),
+            (
+                "submit-permissions",
+                OutboundMessage::SubmitPermission(SubmitPermission::Hashcash {
+                    stamp: "1:12:220101:appid::abc:def".into(),
+                }),
+            ),
+            ("ping", OutboundMessage::Ping { ping: 7 }),
+        ];
+        for (name, message) in messages {
+            assert_eq!(
+                &serde_json::to_value(&message).unwrap(),
+                &golden["outbound"][name],
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_golden_inbound() {
+        let golden: Value =
+            serde_json::from_str(include_str!("../../tests/golden/rendezvous.json")).unwrap();
+        for message in golden["inbound"].as_array().unwrap() {
+            let parsed: InboundMessage = serde_json::from_value(message.clone()).unwrap();
+            let ok = match (message["type"].as_str().unwrap(), &parsed) {
+                ("welcome", InboundMessage::Welcome { welcome }) => {
+                    welcome.motd.as_deref() == Some("Hello")
+                },
+                ("nameplates", InboundMessage::Nameplates { nameplates }) => {
+                    nameplates == &[Nameplate::new("4"), Nameplate::new("12")]
+                },
+                ("allocated", InboundMessage::Allocated { nameplate }) => {
+                    nameplate == &Nameplate::new("4")
+                },
+                ("claimed", InboundMessage::Claimed { mailbox }) => mailbox.0 == "hs6xmpj2a2mv4",
+                ("released", InboundMessage::Released) => true,
+                ("message", InboundMessage::Message(message)) => {
+                    message.side == TheirSide::from("0123456789")
+                        && message.phase == Phase::PAKE
+                        && message.body == br#"{"pake_v1":"00ff"}"#
+                },
+                ("closed", InboundMessage::Closed) => true,
+                ("ack", InboundMessage::Ack) => true,
+                ("pong", InboundMessage::Pong { pong }) => *pong == 7,
+                ("error", InboundMessage::Error { error, .. }) => error == "crowded",
+                _ => false,
+            };
+            assert!(ok, "{} parsed as {:?}", message, parsed);
+        }
+    }
 }
@@ -47,11 +47,20 @@ pub(crate) fn log_transit_connection(info: crate::transit::TransitInfo) {
     tracing::info!("{info}")
 }
 
+/**
+ * A local rendezvous server and transit relay, and a config for the test app ID pointing at them
+ *
+ * Keep the server alive until the end of the test, dropping it shuts it down.
+ */
+#[cfg(feature = "testing")]
+async fn local_server() -> std::io::Result<(crate::testing::TestServer, AppConfig<()>)> {
+    let server = crate::testing::TestServer::start().await?;
+    let config = server.app_config(APP_CONFIG);
+    Ok((server, config))
+}
+
 fn default_relay_hints() -> Vec<transit::RelayHint> {
-    vec![
-        transit::RelayHint::from_urls(None, [transit::DEFAULT_RELAY_SERVER.parse().unwrap()])
-            .unwrap(),
-    ]
+    transit::default_relay_hints()
 }
 
 #[test(async_std::test)]
@@ -350,6 +359,217 @@ pub async fn test_file_rust2rust() -> eyre::Result<()> {
     Ok(())
 }
 
+/** Send a text message using the Rust implementation. This does not guarantee compatibility with Python! ;) */
+#[cfg(feature = "transfer")]
+#[test(async_std::test)]
+pub async fn test_text_rust2rust() -> eyre::Result<()> {
+    let (code_tx, code_rx) = futures::channel::oneshot::channel();
+
+    let sender_task = async_std::task::Builder::new()
+        .name("sender".to_owned())
+        .spawn(async {
+            let mailbox_connection =
+                MailboxConnection::create(transfer::APP_CONFIG.id(TEST_APPID).clone(), 2).await?;
+            tracing::info!("This wormhole's code is: {}", &mailbox_connection.code);
+            code_tx.send(mailbox_connection.code.clone()).unwrap();
+            let wormhole = Wormhole::connect(mailbox_connection).await?;
+            transfer::send_text(wormhole, "Hello from Rust!", futures::future::pending()).await?;
+            eyre::Result::<_>::Ok(())
+        })?;
+    let receiver_task = async_std::task::Builder::new()
+        .name("receiver".to_owned())
+        .spawn(async {
+            let code = code_rx.await?;
+            let config = transfer::APP_CONFIG.id(TEST_APPID);
+            let mailbox = MailboxConnection::connect(config, code.clone(), false).await?;
+            let wormhole = Wormhole::connect(mailbox).await?;
+
+            match transfer::request_text_or_file(
+                wormhole,
+                default_relay_hints(),
+                magic_wormhole::transit::Abilities::ALL_ABILITIES,
+                futures::future::pending(),
+            )
+            .await?
+            .unwrap()
+            {
+                transfer::TextOrFileRequest::Text(text) => assert_eq!(text, "Hello from Rust!"),
+                transfer::TextOrFileRequest::File(_) => panic!("Expected a text message"),
+            }
+            eyre::Result::<_>::Ok(())
+        })?;
+
+    async_std::future::timeout(TIMEOUT, sender_task).await??;
+    async_std::future::timeout(TIMEOUT, receiver_task).await??;
+    Ok(())
+}
+
+/** `receive_text` takes text messages, but rejects file offers */
+#[cfg(feature = "transfer")]
+#[test(async_std::test)]
+pub async fn test_receive_text() -> eyre::Result<()> {
+    for send_file in [false, true] {
+        let mailbox = MailboxConnection::create(transfer::APP_CONFIG.id(TEST_APPID), 2).await?;
+        let code = mailbox.code.clone();
+
+        let sender_task = async_std::task::spawn(async move {
+            let wormhole = Wormhole::connect(mailbox).await?;
+            if send_file {
+                let offer = file_offers().await?.remove(0).0;
+                transfer::send(
+                    wormhole,
+                    default_relay_hints(),
+                    magic_wormhole::transit::Abilities::ALL_ABILITIES,
+                    offer,
+                    &log_transit_connection,
+                    |_, _| {},
+                    futures::future::pending(),
+                )
+                .await?;
+            } else {
+                transfer::send_text(wormhole, "Hello from Rust!", futures::future::pending())
+                    .await?;
+            }
+            eyre::Result::<_>::Ok(())
+        });
+
+        let config = transfer::APP_CONFIG.id(TEST_APPID);
+        let mailbox = MailboxConnection::connect(config, code, false).await?;
+        let wormhole = Wormhole::connect(mailbox).await?;
+        let received = transfer::receive_text(wormhole, futures::future::pending()).await;
+        let sent = async_std::future::timeout(TIMEOUT, sender_task).await?;
+
+        if send_file {
+            assert!(matches!(
+                received,
+                Err(transfer::TransferError::UnsupportedOffer)
+            ));
+            assert!(matches!(
+                sent.unwrap_err().downcast::<transfer::TransferError>()?,
+                transfer::TransferError::PeerError(_)
+            ));
+        } else {
+            assert_eq!(received?.as_deref(), Some("Hello from Rust!"));
+            sent?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * `receive_file` asks the policy, and saves accepted files under the name it chose. The offer
+ * announces the SHA-256, which ends up in the receipt. A stale, longer `.part` file of an
+ * interrupted transfer gets overwritten
+ */
+#[cfg(feature = "transfer")]
+#[test(async_std::test)]
+#[allow(deprecated)]
+pub async fn test_receive_file_policy() -> eyre::Result<()> {
+    let target_dir =
+        std::env::temp_dir().join(format!("wormhole-policy-test-{}", std::process::id()));
+    async_std::fs::create_dir_all(&target_dir).await?;
+    async_std::fs::write(
+        target_dir.join("renamed.bin.part"),
+        vec![0xff; 2 * 1024 * 1024],
+    )
+    .await?;
+
+    for accept in [false, true] {
+        let mailbox = MailboxConnection::create(transfer::APP_CONFIG.id(TEST_APPID), 2).await?;
+        let code = mailbox.code.clone();
+
+        let sender_task = async_std::task::spawn(async move {
+            let mut wormhole = Wormhole::connect(mailbox).await?;
+            wormhole.set_offer_digests(true);
+            transfer::send(
+                wormhole,
+                default_relay_hints(),
+                magic_wormhole::transit::Abilities::ALL_ABILITIES,
+                transfer::offer::OfferSend::new_file_or_folder(
+                    "example-file.bin".into(),
+                    "tests/example-file.bin",
+                )
+                .await?,
+                &log_transit_connection,
+                |_, _| {},
+                futures::future::pending(),
+            )
+            .await?;
+            eyre::Result::<_>::Ok(())
+        });
+
+        let config = transfer::APP_CONFIG.id(TEST_APPID);
+        let mailbox = MailboxConnection::connect(config, code, false).await?;
+        let wormhole = Wormhole::connect(mailbox).await?;
+        let verifier = *wormhole.verifier();
+        let content = async_std::fs::read("tests/example-file.bin").await?;
+        let sha256: [u8; 32] = <sha2::Sha256 as sha2::Digest>::digest(&content).into();
+        let received = transfer::receive_file(
+            wormhole,
+            default_relay_hints(),
+            magic_wormhole::transit::Abilities::ALL_ABILITIES,
+            &target_dir,
+            move |info| async move {
+                assert_eq!(info.file_name, "example-file.bin");
+                assert_eq!(*info.verifier, verifier);
+                assert_eq!(info.sha256, Some(sha256));
+                if accept {
+                    transfer::AcceptDecision::RenameTo("renamed.bin".into())
+                } else {
+                    transfer::AcceptDecision::Reject
+                }
+            },
+            &log_transit_connection,
+            |_, _| {},
+            futures::future::pending(),
+        )
+        .await?;
+        let sent = async_std::future::timeout(TIMEOUT, sender_task).await?;
+
+        if accept {
+            sent?;
+            let (path, receipt) = received.expect("The file should have been received");
+            assert_eq!(path, target_dir.join("renamed.bin"));
+            assert_eq!(async_std::fs::read(&path).await?, content);
+            assert_eq!(receipt.hash, sha256);
+            assert_eq!(receipt.bytes, content.len() as u64);
+            /* The temporary file got renamed */
+            assert!(!target_dir.join("renamed.bin.part").exists());
+        } else {
+            assert!(received.is_none());
+            assert!(matches!(
+                sent.unwrap_err().downcast::<transfer::TransferError>()?,
+                transfer::TransferError::PeerError(_)
+            ));
+        }
+    }
+
+    async_std::fs::remove_dir_all(&target_dir).await?;
+    Ok(())
+}
+
+#[cfg(feature = "transfer")]
+#[test]
+pub fn test_text_blocking() -> eyre::Result<()> {
+    let (code_tx, code_rx) = std::sync::mpsc::channel();
+
+    let sender = std::thread::spawn(move || {
+        crate::blocking::send_text_blocking(
+            Wormhole::builder(transfer::APP_CONFIG.id(TEST_APPID)).timeout(TIMEOUT),
+            "Hello from a thread!",
+            |code| code_tx.send(code.clone()).unwrap(),
+        )
+    });
+    let text = crate::blocking::receive_text_blocking(
+        Wormhole::builder(transfer::APP_CONFIG.id(TEST_APPID)).timeout(TIMEOUT),
+        code_rx.recv_timeout(TIMEOUT)?,
+    )?;
+    assert_eq!(text, "Hello from a thread!");
+
+    sender.join().unwrap()?;
+    Ok(())
+}
+
 /** Test the functionality used by the `send-many` subcommand.
  */
 #[cfg(feature = "transfer")]
@@ -530,6 +750,183 @@ pub async fn test_wrong_code() -> eyre::Result<()> {
     Ok(())
 }
 
+/** Set up a connection between two sides using the builder */
+#[test(async_std::test)]
+pub async fn test_builder() -> eyre::Result<()> {
+    let pending = Wormhole::builder(APP_CONFIG)
+        .code_length(3)
+        .timeout(TIMEOUT)
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    tracing::info!("This test's code is: {}", &code);
+    assert_eq!(code.as_ref().split('-').count(), 4);
+
+    let (mut sender, mut receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(APP_CONFIG)
+            .timeout(TIMEOUT)
+            .connect_with_code(code),
+    )?;
+    sender.send(b"hello".to_vec()).await?;
+    assert_eq!(receiver.receive().await?, b"hello");
+
+    futures::try_join!(sender.close(), receiver.close())?;
+    Ok(())
+}
+
+/** Set up a connection between two sides one stage at a time */
+#[test(async_std::test)]
+pub async fn test_builder_stages() -> eyre::Result<()> {
+    let server = Wormhole::builder(APP_CONFIG)
+        .timeout(TIMEOUT)
+        .connect_to_server()
+        .await?;
+    let pending = server.allocate_code().await?;
+    let code = pending.code().clone();
+    tracing::info!("This test's code is: {}", &code);
+
+    let receiver = async {
+        let server = Wormhole::builder(APP_CONFIG)
+            .timeout(TIMEOUT)
+            .connect_to_server()
+            .await?;
+        let pending = server.claim_code(code.clone()).await?;
+        assert_eq!(pending.code(), &code);
+        pending.wait_for_peer().await
+    };
+    let (mut sender, mut receiver) = futures::try_join!(pending.wait_for_peer(), receiver)?;
+    assert_eq!(sender.verifier(), receiver.verifier());
+    receiver.send(b"hello".to_vec()).await?;
+    assert_eq!(sender.receive().await?, b"hello");
+
+    futures::try_join!(sender.close(), receiver.close())?;
+    Ok(())
+}
+
+/** Application messages travel next to the regular ones without getting mixed up */
+#[test(async_std::test)]
+pub async fn test_app_messages() -> eyre::Result<()> {
+    use futures::StreamExt;
+
+    let pending = Wormhole::builder(APP_CONFIG)
+        .timeout(TIMEOUT)
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    tracing::info!("This test's code is: {}", &code);
+    let (mut sender, mut receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(APP_CONFIG)
+            .timeout(TIMEOUT)
+            .connect_with_code(code),
+    )?;
+    let mut app_messages = receiver.app_messages();
+
+    sender.send_app_message(b"direct".to_vec()).await?;
+    assert!(sender.app_message_sender().send(b"queued".to_vec()));
+    sender.send(b"regular".to_vec()).await?;
+
+    assert_eq!(receiver.receive().await?, b"regular");
+    assert_eq!(app_messages.next().await.unwrap(), b"direct");
+    assert_eq!(app_messages.next().await.unwrap(), b"queued");
+
+    futures::try_join!(sender.close(), receiver.close())?;
+    Ok(())
+}
+
+/** The reason given to `close_with` must show up on the other side */
+#[test(async_std::test)]
+pub async fn test_close_with_reason() -> eyre::Result<()> {
+    let pending = Wormhole::builder(APP_CONFIG)
+        .timeout(TIMEOUT)
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    tracing::info!("This test's code is: {}", &code);
+    let (sender, mut receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(APP_CONFIG)
+            .timeout(TIMEOUT)
+            .connect_with_code(code),
+    )?;
+
+    sender
+        .close_with(magic_wormhole::Mood::Errory, "disk full")
+        .await?;
+    match receiver.receive().await {
+        Err(WormholeError::PeerClosed(reason)) => {
+            assert_eq!(reason.mood, magic_wormhole::Mood::Errory);
+            assert_eq!(reason.reason.as_deref(), Some("disk full"));
+        },
+        other => panic!("Expected the peer to close, got {:?}", other),
+    }
+    /* Also on later attempts */
+    assert!(matches!(
+        receiver.receive().await,
+        Err(WormholeError::PeerClosed(_))
+    ));
+
+    receiver.close().await?;
+    Ok(())
+}
+
+/** Nobody shows up on our code, so we must give up waiting after `peer_wait` */
+#[test(async_std::test)]
+pub async fn test_peer_wait_timeout() -> eyre::Result<()> {
+    let timeouts = magic_wormhole::Timeouts {
+        peer_wait: Some(Duration::from_secs(1)),
+        ..magic_wormhole::Timeouts::default()
+    };
+    let pending = Wormhole::builder(APP_CONFIG)
+        .timeouts(timeouts)
+        .allocate_code()
+        .await?;
+    tracing::info!("This test's code is: {}", pending.code());
+
+    let result = async_std::future::timeout(TIMEOUT, pending.wait_for_peer()).await?;
+    assert!(matches!(result, Err(WormholeError::Timeout)));
+    Ok(())
+}
+
+/** Watch the events of a connection from the allocation of the code until it gets closed */
+#[test(async_std::test)]
+pub async fn test_events() -> eyre::Result<()> {
+    use futures::StreamExt;
+    use magic_wormhole::WormholeEvent;
+
+    let pending = Wormhole::builder(APP_CONFIG)
+        .timeout(TIMEOUT)
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    tracing::info!("This test's code is: {}", &code);
+    let events = pending.events();
+
+    let (sender, receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(APP_CONFIG)
+            .timeout(TIMEOUT)
+            .connect_with_code(code),
+    )?;
+    let verifier = sender.verifier().clone();
+    futures::try_join!(sender.close(), receiver.close())?;
+
+    /* The stream ends because the wormhole is gone */
+    let events = events.collect::<Vec<_>>().await;
+    tracing::debug!("Events: {:?}", events);
+    assert!(matches!(
+        &events[..],
+        [
+            WormholeEvent::PeerConnected,
+            WormholeEvent::KeyConfirmed { verifier: v },
+            WormholeEvent::NameplateReleased,
+            WormholeEvent::Closed { mood: Mood::Happy },
+        ] if **v == verifier
+    ));
+    Ok(())
+}
+
 /** Connect three people to the party and watch it explode … gracefully */
 #[test(async_std::test)]
 pub async fn test_crowded() -> eyre::Result<()> {
@@ -580,6 +977,20 @@ fn generate_random_code() -> Code {
     Code::new(&nameplate, "guitarist-revenge")
 }
 
+#[test]
+fn test_errors_are_send_sync() {
+    fn assert_error<E: std::error::Error + Send + Sync + 'static>() {}
+
+    assert_error::<WormholeError>();
+    assert_error::<crate::rendezvous::RendezvousError>();
+    assert_error::<transit::TransitConnectError>();
+    assert_error::<transit::TransitError>();
+    #[cfg(feature = "transfer")]
+    assert_error::<transfer::TransferError>();
+    #[cfg(feature = "forwarding")]
+    assert_error::<crate::forwarding::ForwardingError>();
+}
+
 #[test]
 fn test_phase() {
     let p = Phase::PAKE;
@@ -614,3 +1025,815 @@ fn test_mood() {
         serde_json::to_string(&Mood::Unwelcome).unwrap()
     );
 }
+
+#[test]
+fn test_code_split() {
+    let (nameplate, password) = Code::from("4-purple-sausages".to_owned()).split();
+    assert_eq!(nameplate, Nameplate::new("4"));
+    assert_eq!(password, "purple-sausages");
+
+    /* Invalid codes must not panic, they're checked later on */
+    let (nameplate, password) = Code::from("4".to_owned()).split();
+    assert_eq!(nameplate, Nameplate::new("4"));
+    assert_eq!(password, "");
+}
+
+#[test]
+fn test_machines_graphviz() {
+    let graph = Wormhole::machines_graphviz();
+    assert!(graph.starts_with("digraph wormhole {"));
+    assert!(graph.contains("\"mailbox_open\" -> \"mailbox_released\""));
+    assert!(graph.contains("\"key_WaitingForPake\" -> \"key_WaitingForVersions\""));
+}
+
+#[test]
+fn test_code_debug_redacted() {
+    let code = Code::from("4-purple-sausages".to_owned());
+    assert_eq!(format!("{:?}", code), "Code(4-<redacted>)");
+    assert_eq!(code.to_string(), "4-purple-sausages");
+    assert_eq!(String::from(code), "4-purple-sausages");
+}
+
+#[cfg(feature = "serde-types")]
+#[test]
+fn test_code_serialize_secret() {
+    #[derive(serde_derive::Serialize)]
+    struct Invite {
+        #[serde(serialize_with = "Code::serialize_secret")]
+        code: Code,
+    }
+
+    let code = Code::from("4-purple-sausages".to_owned());
+    let json = serde_json::to_string(&Invite { code }).unwrap();
+    assert_eq!(json, r#"{"code":"4-purple-sausages"}"#);
+    let invite: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let code: Code = serde_json::from_value(invite["code"].clone()).unwrap();
+    assert_eq!(code.as_ref(), "4-purple-sausages");
+}
+
+#[test]
+fn test_code_parse() {
+    use crate::{core::CodeParseError, wordlist::default_wordlist};
+
+    for entered in [
+        "4-purple-sausages",
+        "  4-Purple-SAUSAGES\n",
+        "4 purple sausages",
+        "4--purple - sausages",
+    ] {
+        assert_eq!(Code::parse(entered).unwrap().as_ref(), "4-purple-sausages");
+    }
+    assert_eq!(Code::parse(" \t"), Err(CodeParseError::Empty));
+    assert_eq!(
+        Code::parse("purple-sausages"),
+        Err(CodeParseError::InvalidNameplate)
+    );
+    assert_eq!(Code::parse("4-"), Err(CodeParseError::MissingPassword));
+
+    let wordlist = default_wordlist(2);
+    assert!(Code::parse_with_wordlist("7-Aardvark-Adroitness", &wordlist).is_err());
+    assert_eq!(
+        Code::parse_with_wordlist("7-armistice-ardvark", &wordlist)
+            .unwrap_err()
+            .to_string(),
+        "Unknown word 'ardvark' in the code, did you mean 'aardvark'?"
+    );
+    assert_eq!(
+        Code::parse_with_wordlist("7-armistice-aardvark", &wordlist)
+            .unwrap()
+            .as_ref(),
+        "7-armistice-aardvark"
+    );
+}
+
+/** An unreachable rendezvous server must be reported as an error, not a panic */
+#[test(async_std::test)]
+pub async fn test_unreachable_server() -> eyre::Result<()> {
+    let result = Wormhole::builder(APP_CONFIG)
+        .rendezvous_url("ws://rendezvous.invalid:4000/v1")
+        .connect_to_server()
+        .await;
+    assert!(matches!(
+        result,
+        Err(WormholeError::ServerError(
+            magic_wormhole::rendezvous::RendezvousError::IO(_)
+        ))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_is_retryable() {
+    use magic_wormhole::rendezvous::RendezvousError;
+
+    assert!(WormholeError::Timeout.is_retryable());
+    assert!(WormholeError::ServerError(RendezvousError::ConnectionLost).is_retryable());
+    assert!(!WormholeError::PakeFailed.is_retryable());
+    assert!(!WormholeError::VerifierRejected.is_retryable());
+
+    let crowded = RendezvousError::Server("crowded".into());
+    assert!(crowded.is_crowded());
+    assert!(!WormholeError::ServerError(crowded).is_retryable());
+}
+
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_messages() -> eyre::Result<()> {
+    let (server, config) = local_server().await?;
+
+    let pending = Wormhole::builder(config.clone()).allocate_code().await?;
+    let code = pending.code().clone();
+    let state = pending.debug_state();
+    assert_eq!(state["key"], "waiting_for_peer");
+    assert_eq!(
+        state["rendezvous"]["nameplate"],
+        code.nameplate().to_string()
+    );
+    let (mut sender, mut receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config).connect_with_code(code.clone()),
+    )?;
+    sender.send(b"hello".to_vec()).await?;
+    assert_eq!(receiver.receive().await?, b"hello");
+    assert_eq!(
+        sender.derive_key("sync-key", 64)?,
+        receiver.derive_key("sync-key", 64)?
+    );
+    assert_ne!(
+        sender.derive_key("sync-key", 32)?,
+        sender.derive_key("other-key", 32)?
+    );
+    assert_eq!(
+        sender.derive_key("sync-key", 32)?,
+        receiver.derive_key_unprefixed(&format!("{}/sync-key", receiver.appid()), 32)?
+    );
+    assert_ne!(
+        sender.derive_key("sync-key", 32)?,
+        sender.derive_key_unprefixed("sync-key", 32)?
+    );
+    assert_eq!(
+        sender.derive_key("sync-key", crate::MAX_DERIVED_KEY_LENGTH + 1),
+        Err(crate::KeyLengthError(crate::MAX_DERIVED_KEY_LENGTH + 1))
+    );
+
+    let state = sender.debug_state();
+    assert_eq!(state["phase"], 1);
+    assert_eq!(state["rendezvous"]["state"], "released");
+    assert!(state["rendezvous"]["processed_phases"]
+        .as_array()
+        .unwrap()
+        .contains(&"version".into()));
+    let dump = state.to_string();
+    assert!(!dump.contains(&sender.key().to_hex()));
+    assert!(!dump.contains(code.as_ref()));
+    futures::try_join!(sender.close(), receiver.close())?;
+
+    assert_eq!(server.mailbox_server().stats().connections_total, 2);
+    Ok(())
+}
+
+/** The wormhole remembers the relays configured on the builder, even across `versions` */
+#[cfg(all(feature = "testing", feature = "transit"))]
+#[test(async_std::test)]
+async fn test_relay_hints() -> eyre::Result<()> {
+    let (server, config) = local_server().await?;
+
+    let pending = Wormhole::builder(config.clone())
+        .relay_hints(server.relay_hints())
+        .versions(serde_json::json!({"hello": "world"}))
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    let (sender, receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config).connect_with_code(code),
+    )?;
+    assert_eq!(sender.relay_hints(), server.relay_hints());
+    assert_eq!(receiver.relay_hints(), transit::default_relay_hints());
+    assert_eq!(receiver.peer_version()["hello"], "world");
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_deterministic() -> eyre::Result<()> {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let (_server, config) = local_server().await?;
+
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        let pending = Wormhole::builder(config.clone())
+            .rng(StdRng::seed_from_u64(42))
+            .allocate_code()
+            .await?;
+        let (_nameplate, password) = pending.code().split();
+        let side = pending.side().to_owned();
+        let code = pending.code().clone();
+        let (sender, receiver) = futures::try_join!(
+            pending.wait_for_peer(),
+            Wormhole::builder(config.clone())
+                .rng(StdRng::seed_from_u64(43))
+                .connect_with_code(code),
+        )?;
+        runs.push((password, side, receiver.side().to_owned()));
+        futures::try_join!(sender.close(), receiver.close())?;
+    }
+    assert_eq!(runs[0], runs[1]);
+    assert_ne!(runs[0].1, runs[0].2);
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_custom_wordlist() -> eyre::Result<()> {
+    let (_server, config) = local_server().await?;
+
+    let wordlist = crate::wordlist::Wordlist::from_json(3, r#"["kiwi", "mango"]"#)?;
+    let pending = Wormhole::builder(config.clone())
+        .wordlist(wordlist)
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    let words = code.as_ref().split('-').skip(1).collect::<Vec<_>>();
+    assert_eq!(words.len(), 3);
+    assert!(words.iter().all(|word| ["kiwi", "mango"].contains(word)));
+
+    let (sender, receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config).connect_with_code(code),
+    )?;
+    futures::try_join!(sender.close(), receiver.close())?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_code_format() -> eyre::Result<()> {
+    use crate::wordlist::CodeFormat;
+
+    let (_server, config) = local_server().await?;
+    let builder = Wormhole::builder(config).code_format(CodeFormat::Base32(8));
+
+    let pending = builder.clone().allocate_code().await?;
+    let (nameplate, password) = pending.code().split();
+    assert_eq!(password.len(), 9);
+    /* Read out over the phone and typed in sloppily */
+    let entered = crate::Code::new(&nameplate, &password.replace('-', " ").to_uppercase());
+    let (sender, receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        builder.clone().connect_with_code(entered),
+    )?;
+    futures::try_join!(sender.close(), receiver.close())?;
+
+    let result = builder
+        .connect_with_code(crate::Code::new(&nameplate, "k3m9-x2pu"))
+        .await;
+    assert!(matches!(result, Err(crate::WormholeError::InvalidCode(_))));
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_seeds() -> eyre::Result<()> {
+    use crate::seeds::Seed;
+
+    let (server, config) = local_server().await?;
+
+    let pending = Wormhole::builder(config.clone()).allocate_code().await?;
+    let code = pending.code().clone();
+    let (sender, receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config.clone()).connect_with_code(code),
+    )?;
+    let (mut alice, mut bob) = (receiver.seed(), Seed::new(&sender));
+    assert_eq!(alice.mailbox(), bob.mailbox());
+    futures::try_join!(sender.close(), receiver.close())?;
+
+    /* Reconnect twice without a code, rotating the seed in between */
+    for generation in 0..2 {
+        let (mut to_bob, mut to_alice) = futures::try_join!(
+            Wormhole::builder(config.clone()).connect_with_seed(&bob),
+            Wormhole::connect_with_seed(config.clone(), &alice),
+        )?;
+        to_bob.send(b"hello bob".to_vec()).await?;
+        assert_eq!(to_alice.receive().await?, b"hello bob");
+        assert_eq!(alice.generation(), generation);
+
+        let mailbox = alice.mailbox();
+        alice.rotate(&to_alice);
+        bob.rotate(&to_bob);
+        assert_eq!(alice.mailbox(), bob.mailbox());
+        assert_ne!(alice.mailbox(), mailbox);
+        futures::try_join!(to_bob.close(), to_alice.close())?;
+    }
+
+    /* Both seeded mailboxes got deleted after use */
+    let stats = server.mailbox_server().stats();
+    assert_eq!(stats.connections_total, 6);
+    assert_eq!(stats.mailboxes, 0);
+    Ok(())
+}
+
+/** The waiting side pings the server several times, which must not cost it the connection */
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_keepalive() -> eyre::Result<()> {
+    let (_server, config) = local_server().await?;
+    let timeouts = crate::Timeouts {
+        keepalive: Some(Duration::from_millis(50)),
+        ..crate::Timeouts::default()
+    };
+
+    let pending = Wormhole::builder(config.clone())
+        .timeouts(timeouts)
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    let (sender, receiver) = futures::try_join!(pending.wait_for_peer(), async {
+        crate::util::sleep(Duration::from_millis(500)).await;
+        Wormhole::builder(config).connect_with_code(code).await
+    })?;
+    futures::try_join!(sender.close(), receiver.close())?;
+    Ok(())
+}
+
+/** Cancelling the wait for the peer releases the code on the server */
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_cancel_waiting() -> eyre::Result<()> {
+    use futures::StreamExt;
+
+    let (_server, config) = local_server().await?;
+
+    let pending = Wormhole::builder(config.clone()).allocate_code().await?;
+    let code = pending.code().clone();
+    let mut events = pending.events();
+    let wormhole = pending
+        .wait_for_peer_or_cancel(crate::util::sleep(std::time::Duration::from_millis(100)))
+        .await?;
+    assert!(wormhole.is_none());
+    assert!(
+        events
+            .any(|event| async move {
+                matches!(
+                    event,
+                    crate::WormholeEvent::Closed {
+                        mood: crate::Mood::Lonely
+                    }
+                )
+            })
+            .await
+    );
+
+    let result = Wormhole::builder(config).connect_with_code(code).await;
+    assert!(matches!(
+        result,
+        Err(crate::WormholeError::UnclaimedNameplate(_))
+    ));
+    Ok(())
+}
+
+/** Cancelling while allocating or claiming a code leaves nothing claimed on the server */
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_cancel_connecting() -> eyre::Result<()> {
+    let (server, config) = local_server().await?;
+    /* Cancel as soon as the server accepted one more connection, in the middle of the setup */
+    let connected = || {
+        let server = &server;
+        let before = server.mailbox_server().stats().connections_total;
+        futures::future::poll_fn(move |cx| {
+            if server.mailbox_server().stats().connections_total > before {
+                std::task::Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                std::task::Poll::Pending
+            }
+        })
+    };
+
+    let pending = Wormhole::builder(config.clone())
+        .allocate_code_or_cancel(connected())
+        .await?;
+    assert!(pending.is_none());
+    assert_eq!(server.mailbox_server().stats().nameplates, 0);
+
+    let pending = Wormhole::builder(config.clone()).allocate_code().await?;
+    let code = pending.code().clone();
+    let wormhole = Wormhole::builder(config)
+        .connect_with_code_or_cancel(code, connected())
+        .await?;
+    assert!(wormhole.is_none());
+    pending.cancel().await?;
+    assert_eq!(server.mailbox_server().stats().nameplates, 0);
+    Ok(())
+}
+
+/** Rejecting the verifier closes the wormhole before anything gets sent */
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_verifier_rejected() -> eyre::Result<()> {
+    let (_server, config) = local_server().await?;
+
+    let pending = Wormhole::builder(config.clone())
+        .confirm_verifier(|_verifier: &[u8]| async { false })
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    let (sender, receiver) = futures::join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config).connect_with_code(code),
+    );
+    assert!(matches!(
+        sender,
+        Err(crate::WormholeError::VerifierRejected)
+    ));
+    let result = receiver?.receive().await;
+    assert!(matches!(
+        result,
+        Err(crate::WormholeError::PeerClosed(crate::CloseReason {
+            mood: crate::Mood::Scared,
+            ..
+        }))
+    ));
+    Ok(())
+}
+
+/** The waiting side loses its connection to the server, and must pick up where it left off */
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_reconnect() -> eyre::Result<()> {
+    use async_std::net::{Shutdown, TcpStream};
+    use std::sync::{Arc, Mutex};
+
+    let (server, config) = local_server().await?;
+
+    /* A proxy in front of the rendezvous server, which can cut all connections made so far */
+    let listener = async_std::net::TcpListener::bind("127.0.0.1:0").await?;
+    let proxy_url = format!("ws://{}/v1", listener.local_addr()?);
+    let connections = Arc::new(Mutex::new(Vec::<TcpStream>::new()));
+    let accepted = connections.clone();
+    let target = server.rendezvous_addr();
+    async_std::task::spawn(async move {
+        while let Ok((client, _)) = listener.accept().await {
+            let Ok(upstream) = TcpStream::connect(target).await else {
+                continue;
+            };
+            accepted.lock().unwrap().push(client.clone());
+            let (mut from, mut to) = (client.clone(), upstream.clone());
+            async_std::task::spawn(async move { futures::io::copy(&mut from, &mut to).await });
+            let (mut from, mut to) = (upstream, client);
+            async_std::task::spawn(async move { futures::io::copy(&mut from, &mut to).await });
+        }
+    });
+
+    let pending = Wormhole::builder(config.clone())
+        .rendezvous_url(proxy_url)
+        .reconnect(crate::rendezvous::ReconnectPolicy {
+            initial_delay: Duration::from_millis(50),
+            ..Default::default()
+        })
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    for connection in connections.lock().unwrap().drain(..) {
+        connection.shutdown(Shutdown::Both)?;
+    }
+
+    let (mut sender, mut receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config).connect_with_code(code),
+    )?;
+    sender.send(b"hello".to_vec()).await?;
+    assert_eq!(receiver.receive().await?, b"hello");
+    assert_eq!(connections.lock().unwrap().len(), 1);
+    futures::try_join!(sender.close(), receiver.close())?;
+    Ok(())
+}
+
+/** A file transfer through the relay, with the transit connection forced to use it */
+#[cfg(all(feature = "testing", feature = "transfer"))]
+#[test(async_std::test)]
+async fn test_relayed_file_transfer() -> eyre::Result<()> {
+    let server = crate::testing::TestServer::start().await?;
+    let config = server.app_config(transfer::APP_CONFIG);
+    let data = b"Hello, wormhole!".repeat(1000);
+
+    let pending = Wormhole::builder(config.clone()).allocate_code().await?;
+    let code = pending.code().clone();
+    let (sender, receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config).connect_with_code(code),
+    )?;
+
+    let send = async {
+        #[allow(deprecated)]
+        transfer::send_file(
+            sender,
+            server.relay_hints(),
+            &mut &data[..],
+            "hello.txt",
+            data.len() as u64,
+            transit::Abilities::FORCE_RELAY,
+            |_info| {},
+            |_sent, _total| {},
+            futures::future::pending(),
+        )
+        .await?;
+        eyre::Result::<_>::Ok(())
+    };
+    let receive = async {
+        let request = transfer::request_file(
+            receiver,
+            server.relay_hints(),
+            transit::Abilities::FORCE_RELAY,
+            futures::future::pending(),
+        )
+        .await?
+        .expect("Not cancelled");
+        let mut received = Vec::new();
+        request
+            .accept(
+                |_info| {},
+                |_received, _total| {},
+                &mut received,
+                futures::future::pending(),
+            )
+            .await?;
+        eyre::Result::<_>::Ok(received)
+    };
+    let ((), received) = futures::try_join!(send, receive)?;
+    assert_eq!(received, data);
+
+    assert_eq!(server.relay_server().stats().sessions_total, 1);
+    Ok(())
+}
+
+/** A forwarded connection stays usable after being idle for longer than the inactivity timeout */
+#[cfg(all(feature = "testing", feature = "forwarding"))]
+#[test(async_std::test)]
+async fn test_forwarding_idle() -> eyre::Result<()> {
+    use crate::forwarding;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use std::net::Ipv4Addr;
+
+    let server = crate::testing::TestServer::start().await?;
+    let config = server.app_config(forwarding::APP_CONFIG);
+    let timeouts = crate::Timeouts {
+        transfer_inactivity: Some(Duration::from_millis(100)),
+        ..crate::Timeouts::default()
+    };
+
+    /* The forwarded service echoes everything back */
+    let echo = async_std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let echo_port = echo.local_addr()?.port();
+    async_std::task::spawn(async move {
+        while let Ok((stream, _)) = echo.accept().await {
+            let (mut reader, mut writer) = (stream.clone(), stream);
+            async_std::task::spawn(
+                async move { futures::io::copy(&mut reader, &mut writer).await },
+            );
+        }
+    });
+
+    let pending = Wormhole::builder(config.clone())
+        .timeouts(timeouts)
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    let (serving, connecting) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config)
+            .timeouts(timeouts)
+            .connect_with_code(code),
+    )?;
+
+    let (stop, stopped) = futures::channel::oneshot::channel::<()>();
+    let serve = async {
+        forwarding::serve(
+            serving,
+            |_info| {},
+            server.relay_hints(),
+            vec![(Some(url::Host::Ipv4(Ipv4Addr::LOCALHOST)), echo_port)],
+            async {
+                stopped.await.ok();
+            },
+        )
+        .await?;
+        eyre::Result::<_>::Ok(())
+    };
+    let connect = async {
+        let offer = forwarding::connect(
+            connecting,
+            |_info| {},
+            server.relay_hints(),
+            Some(Ipv4Addr::LOCALHOST.into()),
+            &[],
+        )
+        .await?;
+        let port = offer.mapping[0].0;
+        let talk = async move {
+            let mut stream =
+                async_std::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await?;
+            let mut buffer = [0; 5];
+            for _ in 0..2 {
+                stream.write_all(b"hello").await?;
+                stream.read_exact(&mut buffer).await?;
+                assert_eq!(&buffer, b"hello");
+                crate::util::sleep(Duration::from_millis(300)).await;
+            }
+            stop.send(()).ok();
+            eyre::Result::<_>::Ok(())
+        };
+        let accept = async {
+            offer.accept(futures::future::pending()).await?;
+            eyre::Result::<_>::Ok(())
+        };
+        futures::try_join!(accept, talk)?;
+        eyre::Result::<_>::Ok(())
+    };
+    futures::try_join!(serve, connect)?;
+    Ok(())
+}
+
+/** Counts what gets reported, to check the hook points */
+#[cfg(feature = "testing")]
+#[derive(Debug, Default)]
+struct CountingMetrics {
+    rendezvous_connected: std::sync::atomic::AtomicU64,
+    failures: std::sync::Mutex<Vec<&'static str>>,
+    #[cfg(feature = "transfer")]
+    bytes: std::sync::atomic::AtomicU64,
+    #[cfg(feature = "transfer")]
+    transfers: std::sync::Mutex<Vec<(crate::metrics::Direction, Option<&'static str>)>>,
+}
+
+#[cfg(feature = "testing")]
+impl crate::metrics::Metrics for CountingMetrics {
+    fn rendezvous_connected(&self) {
+        self.rendezvous_connected
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn connection_failed(&self, reason: &'static str) {
+        self.failures.lock().unwrap().push(reason);
+    }
+
+    #[cfg(feature = "transfer")]
+    fn bytes_transferred(&self, _direction: crate::metrics::Direction, bytes: u64) {
+        self.bytes
+            .fetch_add(bytes, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "transfer")]
+    fn transfer_finished(
+        &self,
+        direction: crate::metrics::Direction,
+        _duration: std::time::Duration,
+        failure: Option<&'static str>,
+    ) {
+        self.transfers.lock().unwrap().push((direction, failure));
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test(async_std::test)]
+async fn test_metrics_connection_failed() -> eyre::Result<()> {
+    let (_server, config) = local_server().await?;
+    let metrics = std::sync::Arc::new(CountingMetrics::default());
+
+    let result = Wormhole::builder(config)
+        .metrics(metrics.clone())
+        .connect_with_code(crate::Code::new(
+            &crate::Nameplate::new("42"),
+            "nobody-here",
+        ))
+        .await;
+    assert!(matches!(
+        result,
+        Err(crate::WormholeError::UnclaimedNameplate(_))
+    ));
+
+    assert_eq!(
+        metrics
+            .rendezvous_connected
+            .load(std::sync::atomic::Ordering::Relaxed),
+        1
+    );
+    assert_eq!(*metrics.failures.lock().unwrap(), ["unclaimed_nameplate"]);
+    Ok(())
+}
+
+#[cfg(all(feature = "testing", feature = "transfer"))]
+#[test(async_std::test)]
+async fn test_metrics_file_transfer() -> eyre::Result<()> {
+    use crate::metrics::Direction;
+
+    let server = crate::testing::TestServer::start().await?;
+    let config = server.app_config(transfer::APP_CONFIG);
+    let data = b"Hello, wormhole!".repeat(1000);
+    let sender_metrics = std::sync::Arc::new(CountingMetrics::default());
+    let receiver_metrics = std::sync::Arc::new(CountingMetrics::default());
+
+    let pending = Wormhole::builder(config.clone())
+        .metrics(sender_metrics.clone())
+        .allocate_code()
+        .await?;
+    let code = pending.code().clone();
+    let (sender, receiver) = futures::try_join!(
+        pending.wait_for_peer(),
+        Wormhole::builder(config)
+            .metrics(receiver_metrics.clone())
+            .connect_with_code(code),
+    )?;
+
+    let send = async {
+        #[allow(deprecated)]
+        transfer::send_file(
+            sender,
+            server.relay_hints(),
+            &mut &data[..],
+            "hello.txt",
+            data.len() as u64,
+            transit::Abilities::ALL_ABILITIES,
+            |_info| {},
+            |_sent, _total| {},
+            futures::future::pending(),
+        )
+        .await?;
+        eyre::Result::<_>::Ok(())
+    };
+    let receive = async {
+        let request = transfer::request_file(
+            receiver,
+            server.relay_hints(),
+            transit::Abilities::ALL_ABILITIES,
+            futures::future::pending(),
+        )
+        .await?
+        .expect("Not cancelled");
+        request
+            .accept(
+                |_info| {},
+                |_received, _total| {},
+                &mut futures::io::sink(),
+                futures::future::pending(),
+            )
+            .await?;
+        eyre::Result::<_>::Ok(())
+    };
+    futures::try_join!(send, receive)?;
+
+    for (metrics, direction) in [
+        (&sender_metrics, Direction::Send),
+        (&receiver_metrics, Direction::Receive),
+    ] {
+        assert_eq!(
+            metrics
+                .rendezvous_connected
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+        assert!(metrics.failures.lock().unwrap().is_empty());
+        assert_eq!(
+            metrics.bytes.load(std::sync::atomic::Ordering::Relaxed),
+            data.len() as u64
+        );
+        assert_eq!(*metrics.transfers.lock().unwrap(), [(direction, None)]);
+    }
+    Ok(())
+}
+
+/**
+ * A custom TLS configuration only applies to `wss://` servers. The test server doesn't speak
+ * TLS, so the handshake fails there.
+ */
+#[cfg(all(feature = "testing", feature = "tls", not(feature = "native-tls")))]
+#[test(async_std::test)]
+async fn test_tls_config() -> eyre::Result<()> {
+    use crate::rendezvous::TlsConfig;
+
+    let (_server, config) = local_server().await?;
+    let tls = TlsConfig::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth(),
+    )
+    .with_server_name("rendezvous.example.com");
+
+    Wormhole::builder(config.clone())
+        .tls_config(tls.clone())
+        .connect_to_server()
+        .await?;
+
+    let secure_url = config.rendezvous_url.replacen("ws://", "wss://", 1);
+    let result = Wormhole::builder(config)
+        .rendezvous_url(secure_url)
+        .tls_config(tls)
+        .connect_to_server()
+        .await;
+    assert!(matches!(result, Err(crate::WormholeError::ServerError(_))));
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+/**
+ * How long to wait for the different phases of a connection
+ *
+ * `None` means to wait forever. Configure them with
+ * [`WormholeBuilder::timeouts`](super::WormholeBuilder::timeouts). The resulting
+ * [`Wormhole`](super::Wormhole) remembers them, so that transit connections, file transfers and
+ * port forwarding made with it apply them too.
+ *
+ * ```
+ * use magic_wormhole::Timeouts;
+ * use std::time::Duration;
+ *
+ * let timeouts = Timeouts {
+ *     peer_wait: Some(Duration::from_secs(300)),
+ *     ..Timeouts::default()
+ * };
+ * ```
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Connecting to the rendezvous server and allocating or claiming a nameplate. Defaults to one minute.
+    pub rendezvous_connect: Option<Duration>,
+    /// Waiting for the other side to show up and to complete the key exchange. Defaults to forever,
+    /// as humans may take a while to type in a code.
+    pub peer_wait: Option<Duration>,
+    /// Establishing a transit connection with the other side. Defaults to one minute.
+    pub transit_negotiation: Option<Duration>,
+    /// Opening a single connection to one of the other side's addresses or to a relay server,
+    /// while establishing a transit connection. The others are tried meanwhile. Defaults to 15 seconds.
+    pub transit_hint_connect: Option<Duration>,
+    /// Sending or receiving a single record over an established transit connection. Defaults to two minutes.
+    /// Port forwarding doesn't apply it, as its tunnels stay idle as long as the forwarded connections do.
+    pub transfer_inactivity: Option<Duration>,
+    /// Closing the connection to the rendezvous server. Defaults to ten seconds.
+    pub close: Option<Duration>,
+    /// How long the rendezvous server may be silent before we ping it. If it doesn't answer
+    /// within the same time, the connection counts as lost. Defaults to 30 seconds.
+    pub keepalive: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Wait forever, at every step
+    pub const NONE: Self = Self {
+        rendezvous_connect: None,
+        peer_wait: None,
+        transit_negotiation: None,
+        transit_hint_connect: None,
+        transfer_inactivity: None,
+        close: None,
+        keepalive: None,
+    };
+
+    /// Use the same timeout for every step
+    pub const fn uniform(timeout: Duration) -> Self {
+        Self {
+            rendezvous_connect: Some(timeout),
+            peer_wait: Some(timeout),
+            transit_negotiation: Some(timeout),
+            transit_hint_connect: Some(timeout),
+            transfer_inactivity: Some(timeout),
+            close: Some(timeout),
+            keepalive: Some(timeout),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            rendezvous_connect: Some(Duration::from_secs(60)),
+            peer_wait: None,
+            transit_negotiation: Some(Duration::from_secs(60)),
+            transit_hint_connect: Some(Duration::from_secs(15)),
+            transfer_inactivity: Some(Duration::from_secs(120)),
+            close: Some(Duration::from_secs(10)),
+            keepalive: Some(Duration::from_secs(30)),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use super::WormholeError;
+use serde_derive::{Deserialize, Serialize};
+
+/**
+ * The version of the envelope used by [`Wormhole::send_typed`](super::Wormhole::send_typed)
+ *
+ * Bump this when changing the encoding, so that peers fail loudly instead of misinterpreting
+ * each other's messages.
+ */
+pub(crate) const ENCODING_VERSION: u32 = crate::protocol::v1::TYPED_ENCODING_VERSION;
+
+/** The encoding version is sent next to every payload */
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Envelope<T> {
+    encoding_version: u32,
+    payload: T,
+}
+
+pub(crate) fn encode<T: serde::Serialize>(payload: &T) -> Result<Vec<u8>, WormholeError> {
+    Ok(serde_json::to_vec(&Envelope {
+        encoding_version: ENCODING_VERSION,
+        payload,
+    })?)
+}
+
+pub(crate) fn decode<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T, WormholeError> {
+    /* Check the version first, so that we can give a helpful error message */
+    #[derive(Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Version {
+        encoding_version: u32,
+    }
+
+    let Version { encoding_version } = serde_json::from_slice(data)?;
+    ensure!(
+        encoding_version == ENCODING_VERSION,
+        WormholeError::Protocol(
+            format!(
+                "Unsupported message encoding version {} (expected {})",
+                encoding_version, ENCODING_VERSION
+            )
+            .into()
+        )
+    );
+    let envelope: Envelope<T> = serde_json::from_slice(data)?;
+    Ok(envelope.payload)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Ping {
+        count: u32,
+    }
+
+    #[test]
+    fn test_typed_roundtrip() {
+        let data = encode(&Ping { count: 3 }).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&data).unwrap(),
+            json!({"encoding-version": 1, "payload": {"count": 3}})
+        );
+        assert_eq!(decode::<Ping>(&data).unwrap(), Ping { count: 3 });
+    }
+
+    #[test]
+    fn test_typed_rejects_unknown_version() {
+        let data =
+            serde_json::to_vec(&json!({"encoding-version": 2, "payload": {"count": 3}})).unwrap();
+        assert!(matches!(
+            decode::<Ping>(&data),
+            Err(WormholeError::Protocol(_))
+        ));
+
+        /* Untyped messages are no typed messages */
+        let data = serde_json::to_vec(&json!({"count": 3})).unwrap();
+        assert!(matches!(
+            decode::<Ping>(&data),
+            Err(WormholeError::ProtocolJson(_))
+        ));
+    }
+}
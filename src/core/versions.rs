@@ -0,0 +1,144 @@
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/**
+ * A typed view on the application version information both sides exchange during the key exchange
+ *
+ * The fields known to this crate are parsed, everything else ends up in the
+ * [`extensions`](Self::extensions). This can be used as the `app_version` of an
+ * [`AppConfig`](super::AppConfig), and to parse the information of the peer with
+ * [`Wormhole::peer_app_versions`](super::Wormhole::peer_app_versions).
+ *
+ * ```
+ * use magic_wormhole::AppVersions;
+ *
+ * let ours = AppVersions::new()
+ *     .with_ability("transfer-v1")
+ *     .with_ability("transfer-v2");
+ * let theirs = AppVersions::new().with_ability("transfer-v1");
+ * assert_eq!(ours.common_abilities(&theirs), ["transfer-v1"]);
+ * ```
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AppVersions {
+    /// The protocols and features supported by the application, like `transfer-v1`
+    #[serde(default)]
+    pub abilities: Vec<String>,
+    /// The supported versions of the dilation protocol
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub can_dilate: Vec<String>,
+    /// The transports supported for dilation, in the same format as the transit abilities
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dilation_abilities: Vec<serde_json::Value>,
+    /// Everything else
+    #[serde(flatten)]
+    pub extensions: HashMap<String, serde_json::Value>,
+}
+
+impl AppVersions {
+    /// No abilities, no extensions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an ability
+    pub fn with_ability(mut self, ability: impl Into<String>) -> Self {
+        self.abilities.push(ability.into());
+        self
+    }
+
+    /// Set an extension field, replacing any previous value
+    pub fn with_extension(
+        mut self,
+        name: impl Into<String>,
+        value: impl serde::Serialize,
+    ) -> Result<Self, serde_json::Error> {
+        self.extensions
+            .insert(name.into(), serde_json::to_value(value)?);
+        Ok(self)
+    }
+
+    /// Whether an ability is supported
+    pub fn supports(&self, ability: &str) -> bool {
+        self.abilities.iter().any(|ours| ours == ability)
+    }
+
+    /// The abilities supported by both sides, in our order of preference
+    pub fn common_abilities(&self, peer: &Self) -> Vec<String> {
+        intersect(&self.abilities, &peer.abilities)
+    }
+
+    /// The dilation versions supported by both sides, in our order of preference
+    pub fn common_dilation_versions(&self, peer: &Self) -> Vec<String> {
+        intersect(&self.can_dilate, &peer.can_dilate)
+    }
+
+    /// Parse an extension field. Returns `None` if it is not present
+    pub fn extension<T: serde::de::DeserializeOwned>(
+        &self,
+        name: &str,
+    ) -> Option<Result<T, serde_json::Error>> {
+        self.extensions.get(name).map(T::deserialize)
+    }
+}
+
+fn intersect(ours: &[String], theirs: &[String]) -> Vec<String> {
+    ours.iter()
+        .filter(|ours| theirs.contains(ours))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_app_versions_encoding() {
+        let value = json!({
+            "abilities": ["transfer-v1", "transfer-v2"],
+            "can-dilate": ["1"],
+            "transfer-v2": {"supported-formats": ["plain", "tar"]},
+        });
+        let versions: AppVersions = serde_json::from_value(value.clone()).unwrap();
+        assert!(versions.supports("transfer-v2"));
+        assert_eq!(versions.can_dilate, ["1"]);
+        assert!(versions.dilation_abilities.is_empty());
+        assert_eq!(
+            versions
+                .extension::<serde_json::Value>("transfer-v2")
+                .unwrap()
+                .unwrap(),
+            json!({"supported-formats": ["plain", "tar"]})
+        );
+        assert_eq!(serde_json::to_value(&versions).unwrap(), value);
+
+        /* Older clients send nothing at all */
+        assert_eq!(
+            serde_json::from_value::<AppVersions>(json!({})).unwrap(),
+            AppVersions::new()
+        );
+    }
+
+    #[test]
+    fn test_app_versions_negotiation() {
+        let mut ours = AppVersions::new()
+            .with_ability("transfer-v2")
+            .with_ability("transfer-v1");
+        ours.can_dilate = vec!["2".into(), "1".into()];
+        let mut theirs = AppVersions::new()
+            .with_ability("transfer-v1")
+            .with_ability("transfer-v2")
+            .with_ability("something-else");
+        theirs.can_dilate = vec!["1".into()];
+
+        assert_eq!(
+            ours.common_abilities(&theirs),
+            ["transfer-v2", "transfer-v1"]
+        );
+        assert_eq!(ours.common_dilation_versions(&theirs), ["1"]);
+        assert!(AppVersions::new().common_abilities(&theirs).is_empty());
+    }
+}
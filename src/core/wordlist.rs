@@ -1,9 +1,102 @@
-use rand::{rngs::OsRng, seq::SliceRandom};
+//! Wordlists to generate codes from
+//!
+//! By default, codes use the [PGP word list](https://en.wikipedia.org/wiki/PGP_word_list), see
+//! [`default_wordlist`]. Applications can bring their own words with [`Wordlist::from_json`] and
+//! use them with [`WormholeBuilder::wordlist`](crate::WormholeBuilder::wordlist). Both sides don't
+//! need to agree on a wordlist, only on the resulting code.
+//!
+//! For users who don't speak English, [`default_wordlist_with_language`] has lists of 256 common
+//! words in other languages, which give the same entropy per word as the PGP list. They only use
+//! the letters a to z, so that the codes are easy to type on any keyboard.
+
+use rand::seq::SliceRandom;
 use serde_json::{self, Value};
 use std::fmt;
 
-#[derive(PartialEq)]
+/// A custom wordlist could not be loaded
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum WordlistError {
+    /// The wordlist could not be read
+    #[error("Failed to read the wordlist")]
+    Io(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+    /// The wordlist is not in the expected JSON format
+    #[error("The wordlist is not a JSON list of words, or a list of such lists")]
+    Json(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    /// The wordlist, or one of its lists, has no words
+    #[error("The wordlist has no words")]
+    Empty,
+    /// A word is empty, contains a dash or whitespace, or is not lowercase
+    #[error("Invalid word '{}' in the wordlist", _0)]
+    InvalidWord(String),
+    /// There is no wordlist for a language
+    #[error("No wordlist for the language '{}'", _0)]
+    UnknownLanguage(String),
+}
+
+/**
+ * How hard a code should be to guess, in bits of entropy
+ *
+ * An attacker guessing a code has a chance of one in 2^bits per try, and only one try per
+ * connection. The default of two PGP words gives 16 bits. The nameplate is not counted.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CodeStrength(u32);
+
+impl CodeStrength {
+    /// At least this many bits of entropy
+    pub const fn bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// The requested bits of entropy
+    pub const fn as_bits(self) -> u32 {
+        self.0
+    }
+}
+
+/// How a [`Completion`] matches what was typed
+#[cfg(feature = "completion")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompletionKind {
+    /// The last word is complete
+    Exact,
+    /// The last word is the start of this one
+    Prefix,
+    /// The last word is probably a typo of this one
+    Fuzzy,
+}
+
+/// A candidate of [`Wordlist::get_completions_ranked`]
+#[cfg(feature = "completion")]
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Completion {
+    /// The completed code, without the nameplate
+    pub text: String,
+    /// How well the candidate matches, from 0 to 1. Higher is better.
+    pub score: f64,
+    /// How the candidate matches
+    pub kind: CompletionKind,
+}
+
+/**
+ * The words to generate codes from
+ *
+ * The words of a code are taken from a number of lists in turn. The PGP wordlist for example
+ * alternates between two lists, to detect swapped words. Most custom wordlists have a single list.
+ */
+#[derive(PartialEq, Clone)]
 pub struct Wordlist {
+    /// The number of words of the codes generated with this list
     pub num_words: usize,
     words: Vec<Vec<String>>,
 }
@@ -14,13 +107,169 @@ impl fmt::Debug for Wordlist {
     }
 }
 
+/// The JSON representation of custom wordlists
+#[derive(serde_derive::Deserialize)]
+#[serde(untagged)]
+enum WordlistJson {
+    Single(Vec<String>),
+    Alternating(Vec<Vec<String>>),
+}
+
 impl Wordlist {
-    #[cfg(test)]
+    /**
+     * Create a wordlist generating codes of `num_words` words
+     *
+     * The n-th word of a code is taken from the list `n % words.len()`.
+     *
+     * # Panics
+     *
+     * If there are no lists, or one of them is empty.
+     */
     pub fn new(num_words: usize, words: Vec<Vec<String>>) -> Wordlist {
+        assert!(
+            !words.is_empty() && words.iter().all(|words| !words.is_empty()),
+            "A wordlist needs words"
+        );
         Wordlist { num_words, words }
     }
 
-    #[allow(dead_code)] // TODO make this API public one day
+    /**
+     * Parse a wordlist from JSON
+     *
+     * The JSON is either a list of words, like `["purple", "sausages", …]`, or a list of such lists to
+     * take the words from in turn. Words must be lowercase, without dashes or whitespace.
+     */
+    pub fn from_json(num_words: usize, json: &str) -> Result<Wordlist, WordlistError> {
+        Self::from_parsed(num_words, serde_json::from_str(json)?)
+    }
+
+    /// Like [`from_json`](Self::from_json), reading the JSON from a reader
+    pub fn from_reader(
+        num_words: usize,
+        reader: impl std::io::Read,
+    ) -> Result<Wordlist, WordlistError> {
+        Self::from_parsed(num_words, serde_json::from_reader(reader)?)
+    }
+
+    fn from_parsed(num_words: usize, parsed: WordlistJson) -> Result<Wordlist, WordlistError> {
+        let words = match parsed {
+            WordlistJson::Single(words) => vec![words],
+            WordlistJson::Alternating(words) => words,
+        };
+        if words.is_empty() || words.iter().any(Vec::is_empty) {
+            return Err(WordlistError::Empty);
+        }
+        if let Some(word) = words.iter().flatten().find(|word| {
+            word.is_empty()
+                || word.contains(|c: char| c == '-' || c.is_whitespace() || c.is_uppercase())
+        }) {
+            return Err(WordlistError::InvalidWord(word.clone()));
+        }
+        Ok(Wordlist { num_words, words })
+    }
+
+    /**
+     * The English [BIP39](https://github.com/bitcoin/bips/blob/master/bip-0039/english.txt)
+     * wordlist, generating codes of `num_words` words
+     *
+     * Each of its 2048 words carries 11 bits of entropy, compared to 8 bits of the PGP wordlist,
+     * and all of them are unique in their first four letters. The codes are not BIP39 mnemonics
+     * though, since they have no checksum.
+     */
+    #[cfg(feature = "bip39-wordlist")]
+    pub fn bip39(num_words: usize) -> Wordlist {
+        let words = bip39::Language::English
+            .word_list()
+            .iter()
+            .map(|word| word.to_string())
+            .collect();
+        Wordlist::new(num_words, vec![words])
+    }
+
+    /// Whether `word` may be the word at `position` of a code, counting from zero
+    pub fn contains(&self, position: usize, word: &str) -> bool {
+        self.words[position % self.words.len()]
+            .iter()
+            .any(|candidate| candidate == word)
+    }
+
+    /**
+     * The word most similar to `word` that may be at `position` of a code
+     *
+     * Returns `None` if no word is close enough for `word` to be a typo of it.
+     */
+    pub fn closest_word(&self, position: usize, word: &str) -> Option<&str> {
+        self.words[position % self.words.len()]
+            .iter()
+            .map(|candidate| (edit_distance(word, candidate), candidate))
+            .filter(|(distance, _)| *distance <= MAX_TYPO_DISTANCE)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate.as_str())
+    }
+
+    /// The bits of entropy of the codes generated with this list
+    pub fn entropy_bits(&self) -> f64 {
+        self.word_entropies().take(self.num_words).sum()
+    }
+
+    /**
+     * The number of words codes from this list need to have at least `strength`
+     *
+     * This is always at least one word.
+     *
+     * # Panics
+     *
+     * If more than zero bits are requested, but all lists consist of a single word.
+     */
+    pub fn num_words_for(&self, strength: CodeStrength) -> usize {
+        assert!(
+            strength.as_bits() == 0
+                || self.word_entropies().take(self.words.len()).sum::<f64>() > 0.0,
+            "A wordlist with a single word per list can't provide any entropy"
+        );
+        let mut bits = 0.0;
+        let mut num_words = 0;
+        for entropy in self.word_entropies() {
+            if num_words > 0 && bits >= f64::from(strength.as_bits()) {
+                break;
+            }
+            bits += entropy;
+            num_words += 1;
+        }
+        num_words
+    }
+
+    /// The same words, but generating codes with at least `strength`
+    pub fn with_strength(self, strength: CodeStrength) -> Self {
+        let num_words = self.num_words_for(strength);
+        self.with_num_words(num_words)
+    }
+
+    /// The entropy of each word of a code, repeating forever
+    fn word_entropies(&self) -> impl Iterator<Item = f64> + '_ {
+        self.words
+            .iter()
+            .map(|words| {
+                let unique: std::collections::HashSet<&String> = words.iter().collect();
+                (unique.len() as f64).log2()
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .cycle()
+    }
+
+    /// The same words, but generating codes of `num_words` words
+    pub fn with_num_words(mut self, num_words: usize) -> Self {
+        self.num_words = num_words;
+        self
+    }
+
+    /**
+     * All codes, or their next word, starting with a prefix
+     *
+     * Used for tab completion when entering a code. The nameplate is not part of the prefix.
+     */
+    #[cfg(feature = "completion")]
     pub fn get_completions(&self, prefix: &str) -> Vec<String> {
         let count_dashes = prefix.matches('-').count();
         let mut completions = Vec::new();
@@ -55,14 +304,69 @@ impl Wordlist {
         completions
     }
 
+    /**
+     * Like [`get_completions`](Self::get_completions), but ranked and including likely typos
+     *
+     * The best matches come first. Exact and prefix matches always rank above fuzzy ones, which
+     * are words that the last partial word is a typo of.
+     */
+    #[cfg(feature = "completion")]
+    pub fn get_completions_ranked(&self, prefix: &str) -> Vec<Completion> {
+        let count_dashes = prefix.matches('-').count();
+        let words = &self.words[count_dashes % self.words.len()];
+        let (start, partial) = match prefix.rfind('-') {
+            Some(index) => prefix.split_at(index + 1),
+            None => ("", prefix),
+        };
+
+        let mut completions: Vec<Completion> = words
+            .iter()
+            .filter_map(|word| {
+                let (kind, score) = if word == partial {
+                    (CompletionKind::Exact, 1.0)
+                } else if word.starts_with(partial) {
+                    let typed = partial.len() as f64 / word.len() as f64;
+                    (CompletionKind::Prefix, 0.5 + 0.5 * typed)
+                } else if partial.chars().count() >= MIN_FUZZY_LENGTH {
+                    let word_start: String = word.chars().take(partial.chars().count()).collect();
+                    let distance =
+                        edit_distance(partial, word).min(edit_distance(partial, &word_start));
+                    if distance > MAX_TYPO_DISTANCE {
+                        return None;
+                    }
+                    (CompletionKind::Fuzzy, 0.5 / (1 + distance) as f64)
+                } else {
+                    return None;
+                };
+
+                let mut text = format!("{}{}", start, word);
+                if count_dashes + 1 < self.num_words {
+                    text.push('-');
+                }
+                Some(Completion { text, score, kind })
+            })
+            .collect();
+        completions.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.text.cmp(&b.text))
+        });
+        completions
+    }
+
+    /// Generate a random code from the wordlist, without the nameplate
     pub fn choose_words(&self) -> String {
-        let mut rng = OsRng;
+        self.choose_words_with(&mut crate::util::rng())
+    }
+
+    /// Like [`choose_words`](Self::choose_words), but with the given RNG
+    pub fn choose_words_with(&self, rng: &mut (impl rand::Rng + rand::CryptoRng)) -> String {
         let components: Vec<String> = self
             .words
             .iter()
             .cycle()
             .take(self.num_words)
-            .map(|words| words.choose(&mut rng).unwrap().to_string())
+            .map(|words| words.choose(rng).unwrap().to_string())
             .collect();
         components.join("-")
     }
@@ -96,6 +400,64 @@ fn load_pgpwords() -> Vec<Vec<String>> {
     vec![even_words, odd_words]
 }
 
+/// The language of the words of a [`default_wordlist_with_language`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Language {
+    /// The PGP wordlist
+    #[default]
+    En,
+    /// German
+    De,
+    /// Spanish
+    Es,
+    /// French
+    Fr,
+    /// Japanese, in Hepburn romanization
+    Ja,
+}
+
+impl Language {
+    /// All languages with a wordlist
+    pub const ALL: &'static [Language] = &[
+        Language::En,
+        Language::De,
+        Language::Es,
+        Language::Fr,
+        Language::Ja,
+    ];
+
+    /// The ISO 639-1 code of the language, like `de`
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::De => "de",
+            Language::Es => "es",
+            Language::Fr => "fr",
+            Language::Ja => "ja",
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl std::str::FromStr for Language {
+    type Err = WordlistError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Language::ALL
+            .iter()
+            .copied()
+            .find(|language| language.code().eq_ignore_ascii_case(code))
+            .ok_or_else(|| WordlistError::UnknownLanguage(code.to_owned()))
+    }
+}
+
+/// The PGP wordlist, generating codes of `num_words` words
 pub fn default_wordlist(num_words: usize) -> Wordlist {
     Wordlist {
         num_words,
@@ -103,6 +465,193 @@ pub fn default_wordlist(num_words: usize) -> Wordlist {
     }
 }
 
+/**
+ * The wordlist of a language, generating codes of `num_words` words
+ *
+ * [`Language::En`] is the PGP wordlist, the same as [`default_wordlist`].
+ */
+pub fn default_wordlist_with_language(num_words: usize, language: Language) -> Wordlist {
+    let json = match language {
+        Language::En => return default_wordlist(num_words),
+        Language::De => include_str!("wordlists/de.json"),
+        Language::Es => include_str!("wordlists/es.json"),
+        Language::Fr => include_str!("wordlists/fr.json"),
+        Language::Ja => include_str!("wordlists/ja.json"),
+    };
+    Wordlist::from_json(num_words, json).expect("The embedded wordlists are valid")
+}
+
+/// The alphabet of [`CodeFormat::Base32`], Crockford's base32 in lowercase
+const BASE32_ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+
+/// The most edits a word may be away from a word of the list to be suggested as a typo
+const MAX_TYPO_DISTANCE: usize = 2;
+
+/// Partial words shorter than this are not completed to words they might be a typo of
+#[cfg(feature = "completion")]
+const MIN_FUZZY_LENGTH: usize = 3;
+
+/// Characters per group of [`CodeFormat::Digits`] and [`CodeFormat::Base32`] codes
+const GROUP_LENGTH: usize = 4;
+
+/// A code entered by the user does not match the [`CodeFormat`]
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum CodeFormatError {
+    /// The code has a character the format doesn't use
+    #[error("Invalid character '{}' in the code", _0)]
+    InvalidCharacter(char),
+    /// The code has no password after the nameplate
+    #[error("The code is missing the part after the nameplate")]
+    Empty,
+}
+
+/**
+ * What the codes look like, after the nameplate
+ *
+ * Words are easier to remember, but characters are easier to read out over the phone to somebody who
+ * doesn't speak English. Set the format on both sides with
+ * [`WormholeBuilder::code_format`](crate::WormholeBuilder::code_format): the side allocating the
+ * code generates it in that format, the side claiming it uses the format to
+ * [`canonicalize`](Self::canonicalize) what the user entered.
+ */
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum CodeFormat {
+    /// Words from a wordlist, like `purple-sausages`. Codes are used as entered.
+    Words(Wordlist),
+    /// That many decimal digits in groups of four, like `8392-0175`
+    Digits(usize),
+    /**
+     * That many characters of Crockford's base32 in groups of four, like `k3m9-x2p7`
+     *
+     * This alphabet leaves out `i`, `l`, `o` and `u`. When claiming a code, `i` and `l` are read
+     * as `1`, `o` as `0` and upper case as lower case.
+     */
+    Base32(usize),
+}
+
+impl Default for CodeFormat {
+    fn default() -> Self {
+        CodeFormat::Words(default_wordlist(2))
+    }
+}
+
+impl CodeFormat {
+    /// The bits of entropy of the codes generated in this format
+    pub fn entropy_bits(&self) -> f64 {
+        match self {
+            CodeFormat::Words(wordlist) => wordlist.entropy_bits(),
+            CodeFormat::Digits(length) => *length as f64 * 10f64.log2(),
+            CodeFormat::Base32(length) => *length as f64 * 5.0,
+        }
+    }
+
+    /// The same format, with codes of that many words or characters
+    pub fn with_length(self, length: usize) -> Self {
+        match self {
+            CodeFormat::Words(wordlist) => CodeFormat::Words(wordlist.with_num_words(length)),
+            CodeFormat::Digits(_) => CodeFormat::Digits(length),
+            CodeFormat::Base32(_) => CodeFormat::Base32(length),
+        }
+    }
+
+    /// The same format, with codes at least as hard to guess as `strength`
+    pub fn with_strength(self, strength: CodeStrength) -> Self {
+        let bits = f64::from(strength.as_bits());
+        match self {
+            CodeFormat::Words(wordlist) => CodeFormat::Words(wordlist.with_strength(strength)),
+            CodeFormat::Digits(_) => {
+                CodeFormat::Digits((bits / 10f64.log2()).ceil().max(1.0) as usize)
+            },
+            CodeFormat::Base32(_) => CodeFormat::Base32((bits / 5.0).ceil().max(1.0) as usize),
+        }
+    }
+
+    /// Generate a random code, without the nameplate
+    pub fn generate(&self) -> String {
+        self.generate_with(&mut crate::util::rng())
+    }
+
+    /// Like [`generate`](Self::generate), but with the given RNG
+    pub fn generate_with(&self, rng: &mut (impl rand::Rng + rand::CryptoRng)) -> String {
+        let (alphabet, length): (&[u8], usize) = match self {
+            CodeFormat::Words(wordlist) => return wordlist.choose_words_with(rng),
+            CodeFormat::Digits(length) => (&BASE32_ALPHABET[..10], *length),
+            CodeFormat::Base32(length) => (BASE32_ALPHABET, *length),
+        };
+        let characters: Vec<char> = (0..length)
+            .map(|_| *alphabet.choose(rng).unwrap() as char)
+            .collect();
+        group(&characters)
+    }
+
+    /**
+     * Bring the code entered by a user, without the nameplate, into the form it was generated in
+     *
+     * Separators and whitespace are ignored, and the characters are regrouped. Both sides need the
+     * canonical form for the key exchange to succeed. Any length is accepted, since the claiming
+     * side doesn't know the length of the code.
+     */
+    pub fn canonicalize(&self, password: &str) -> Result<String, CodeFormatError> {
+        if let CodeFormat::Words(_) = self {
+            return Ok(password.to_owned());
+        }
+        let characters = password
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .map(|c| {
+                self.canonical_char(c)
+                    .ok_or(CodeFormatError::InvalidCharacter(c))
+            })
+            .collect::<Result<Vec<char>, _>>()?;
+        if characters.is_empty() {
+            return Err(CodeFormatError::Empty);
+        }
+        Ok(group(&characters))
+    }
+
+    fn canonical_char(&self, c: char) -> Option<char> {
+        let c = match (self, c.to_ascii_lowercase()) {
+            (CodeFormat::Base32(_), 'i' | 'l') => '1',
+            (CodeFormat::Base32(_), 'o') => '0',
+            (_, c) => c,
+        };
+        let alphabet: &[u8] = match self {
+            CodeFormat::Digits(_) => &BASE32_ALPHABET[..10],
+            _ => BASE32_ALPHABET,
+        };
+        c.is_ascii()
+            .then_some(c)
+            .filter(|c| alphabet.contains(&(*c as u8)))
+    }
+}
+
+/// The Levenshtein distance between two words
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(ca != *cb))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Join the characters in groups of [`GROUP_LENGTH`], separated by dashes
+fn group(characters: &[char]) -> String {
+    characters
+        .chunks(GROUP_LENGTH)
+        .map(|chunk| chunk.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -127,6 +676,115 @@ mod test {
         assert_eq!(d.words[1][255], "zulu");
     }
 
+    #[test]
+    fn test_code_strength() {
+        let w = default_wordlist(2);
+        assert_eq!(w.entropy_bits(), 16.0);
+        assert_eq!(w.num_words_for(CodeStrength::bits(0)), 1);
+        assert_eq!(w.num_words_for(CodeStrength::bits(16)), 2);
+        assert_eq!(w.num_words_for(CodeStrength::bits(17)), 3);
+        let w = w.with_strength(CodeStrength::bits(40));
+        assert_eq!(w.num_words, 5);
+        assert_eq!(w.choose_words().split('-').count(), 5);
+        assert!(w.entropy_bits() >= 40.0);
+
+        /* Duplicates don't count */
+        let w = Wordlist::new(2, vec![vecstrings("purple purple green yellow")]);
+        assert!((w.entropy_bits() - 2.0 * 3f64.log2()).abs() < 1e-9);
+        assert_eq!(w.num_words_for(CodeStrength::bits(4)), 3);
+    }
+
+    #[test]
+    fn test_code_formats() {
+        use rand::SeedableRng;
+
+        let digits = CodeFormat::Digits(10);
+        let code = digits.generate_with(&mut rand::rngs::StdRng::seed_from_u64(1));
+        assert_eq!(code.len(), 12);
+        assert!(code.split('-').map(str::len).eq([4, 4, 2]));
+        assert!(code.chars().all(|c| c == '-' || c.is_ascii_digit()));
+        assert_eq!(digits.canonicalize(&code).unwrap(), code);
+        assert_eq!(digits.canonicalize(" 0815 4711 ").unwrap(), "0815-4711");
+        assert!(matches!(
+            digits.canonicalize("0815-47a1"),
+            Err(CodeFormatError::InvalidCharacter('a'))
+        ));
+        assert!(matches!(
+            digits.canonicalize(" - "),
+            Err(CodeFormatError::Empty)
+        ));
+
+        let base32 = CodeFormat::Base32(8);
+        let code = base32.generate();
+        assert_eq!(code.len(), 9);
+        assert!(code
+            .chars()
+            .all(|c| c == '-' || BASE32_ALPHABET.contains(&(c as u8))));
+        assert_eq!(base32.canonicalize(&code.to_uppercase()).unwrap(), code);
+        assert_eq!(base32.canonicalize("K3M9 XILO").unwrap(), "k3m9-x110");
+        assert!(matches!(
+            base32.canonicalize("k3m9-x2pu"),
+            Err(CodeFormatError::InvalidCharacter('u'))
+        ));
+
+        let words = CodeFormat::default();
+        assert_eq!(
+            words.canonicalize("Purple sausages").unwrap(),
+            "Purple sausages"
+        );
+
+        assert_eq!(words.entropy_bits(), 16.0);
+        assert_eq!(base32.entropy_bits(), 40.0);
+        let strength = CodeStrength::bits(40);
+        assert_eq!(
+            CodeFormat::Digits(1).with_strength(strength),
+            CodeFormat::Digits(13)
+        );
+        assert_eq!(CodeFormat::Base32(1).with_strength(strength), base32);
+        assert_eq!(
+            CodeFormat::default().with_strength(strength),
+            CodeFormat::Words(default_wordlist(5))
+        );
+        assert_eq!(base32.with_length(4), CodeFormat::Base32(4));
+    }
+
+    #[test]
+    fn test_closest_word() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("purple", "purple"), 0);
+
+        let w = default_wordlist(2);
+        assert!(w.contains(0, "adroitness"));
+        assert!(!w.contains(1, "adroitness"));
+        assert!(w.contains(1, "aardvark"));
+        assert_eq!(w.closest_word(1, "ardvark"), Some("aardvark"));
+        assert_eq!(w.closest_word(0, "armistise"), Some("armistice"));
+        assert_eq!(w.closest_word(0, "xylophonist"), None);
+    }
+
+    #[test]
+    fn test_languages() {
+        assert_eq!(
+            default_wordlist_with_language(2, Language::En),
+            default_wordlist(2)
+        );
+        for &language in Language::ALL {
+            let w = default_wordlist_with_language(3, language);
+            let words: Vec<&String> = w.words.iter().flatten().collect();
+            assert_eq!(words.len(), 256 * w.words.len(), "{language}");
+            let unique: std::collections::HashSet<_> = words.iter().collect();
+            assert_eq!(unique.len(), words.len(), "{language} has duplicate words");
+            assert!(words
+                .iter()
+                .all(|word| word.bytes().all(|b| b.is_ascii_lowercase())));
+            assert_eq!(w.choose_words().split('-').count(), 3);
+
+            assert_eq!(language.to_string().parse::<Language>().unwrap(), language);
+        }
+        assert!("xx".parse::<Language>().is_err());
+    }
+
     fn vecstrings(all: &str) -> Vec<String> {
         all.split_whitespace()
             .map(|s| {
@@ -139,6 +797,7 @@ mod test {
             .collect()
     }
 
+    #[cfg(feature = "completion")]
     #[test]
     fn test_completion() {
         let words: Vec<Vec<String>> = vec![
@@ -153,6 +812,33 @@ mod test {
         assert_eq!(w.get_completions("purple-sa"), vec!["purple-sausages"]);
     }
 
+    #[test]
+    fn test_from_json() {
+        let w = Wordlist::from_json(3, r#"["purple", "sausages"]"#).unwrap();
+        assert_eq!(w, Wordlist::new(3, vec![vecstrings("purple sausages")]));
+        let w = Wordlist::from_reader(2, &br#"[["purple"], ["sausages"]]"#[..]).unwrap();
+        assert_eq!(w.choose_words(), "purple-sausages");
+
+        assert!(matches!(
+            Wordlist::from_json(2, "[]"),
+            Err(WordlistError::Empty)
+        ));
+        assert!(matches!(
+            Wordlist::from_json(2, r#"[["purple"], []]"#),
+            Err(WordlistError::Empty)
+        ));
+        assert!(matches!(
+            Wordlist::from_json(2, r#"{"purple": "sausages"}"#),
+            Err(WordlistError::Json(_))
+        ));
+        for word in ["", "ice-cream", "two words", "Purple"] {
+            assert!(matches!(
+                Wordlist::from_json(2, &serde_json::json!([word]).to_string()),
+                Err(WordlistError::InvalidWord(invalid)) if invalid == word
+            ));
+        }
+    }
+
     #[test]
     fn test_choose_words() {
         let few_words: Vec<Vec<String>> = vec![vecstrings("purple"), vecstrings("sausages")];
@@ -165,6 +851,19 @@ mod test {
         assert_eq!(w.choose_words(), "purple-sausages-purple-sausages");
     }
 
+    #[test]
+    fn test_choose_words_with_rng() {
+        use rand::SeedableRng;
+
+        let w = default_wordlist(3);
+        let code = w.choose_words_with(&mut rand::rngs::StdRng::seed_from_u64(42));
+        assert_eq!(
+            code,
+            w.choose_words_with(&mut rand::rngs::StdRng::seed_from_u64(42))
+        );
+        assert_eq!(code.split('-').count(), 3);
+    }
+
     #[test]
     fn test_choose_more_words() {
         let more_words: Vec<Vec<String>> =
@@ -192,6 +891,7 @@ mod test {
         }
     }
 
+    #[cfg(feature = "completion")]
     #[test]
     fn test_default_completions() {
         let w = default_wordlist(2);
@@ -234,4 +934,70 @@ mod test {
         let c = w.get_completions("armistice-baboon");
         assert_eq!(c, vec!["armistice-baboon-"]);
     }
+
+    #[cfg(feature = "bip39-wordlist")]
+    #[test]
+    fn test_bip39() {
+        let w = Wordlist::bip39(4);
+        assert_eq!(w.words.len(), 1);
+        assert_eq!(w.words[0].len(), 2048);
+        assert_eq!(w.words[0][0], "abandon");
+        assert_eq!(w.words[0][2047], "zoo");
+        assert_eq!(w.choose_words().split('-').count(), 4);
+        #[cfg(feature = "completion")]
+        {
+            assert_eq!(w.get_completions("aban"), vec!["abandon-"]);
+            assert_eq!(w.get_completions("zo"), vec!["zone-", "zoo-"]);
+        }
+    }
+
+    #[cfg(feature = "completion")]
+    #[test]
+    fn test_ranked_completions() {
+        let w = default_wordlist(2);
+        let c = w.get_completions_ranked("armis");
+        assert_eq!(c[0].text, "armistice-");
+        assert_eq!(c[0].kind, CompletionKind::Prefix);
+        assert!(c[1..].iter().all(|c| c.kind == CompletionKind::Fuzzy));
+        assert!(c.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        let c = w.get_completions_ranked("armistice-baboon");
+        assert_eq!(c[0].text, "armistice-baboon");
+        assert_eq!(c[0].kind, CompletionKind::Exact);
+        assert_eq!(c[0].score, 1.0);
+
+        /* A typo in the last word */
+        let c = w.get_completions_ranked("armistice-babon");
+        assert_eq!(c[0].text, "armistice-baboon");
+        assert_eq!(c[0].kind, CompletionKind::Fuzzy);
+
+        /* Prefix matches of the ranked and the plain completions agree */
+        let mut prefix: Vec<String> = w
+            .get_completions_ranked("armistice-ba")
+            .into_iter()
+            .filter(|c| c.kind != CompletionKind::Fuzzy)
+            .map(|c| c.text)
+            .collect();
+        prefix.sort();
+        assert_eq!(prefix, w.get_completions("armistice-ba"));
+        assert!(w
+            .get_completions_ranked("ba")
+            .iter()
+            .all(|c| c.kind == CompletionKind::Prefix));
+    }
+
+    #[cfg(feature = "completion")]
+    #[test]
+    fn test_language_completions() {
+        let w = default_wordlist_with_language(2, Language::De);
+        assert_eq!(w.get_completions("schne"), vec!["schnecke-", "schnee-"]);
+        assert_eq!(
+            w.get_completions("apfel-bir"),
+            vec!["apfel-birke", "apfel-birne"]
+        );
+        assert_eq!(w.get_completions("apfel-").len(), 256);
+
+        let w = default_wordlist_with_language(2, Language::Ja);
+        assert_eq!(w.get_completions("saku"), vec!["sakura-"]);
+    }
 }
@@ -27,7 +27,7 @@ use std::{
 };
 use transit::{TransitConnectError, TransitError};
 
-const APPID_RAW: &str = "piegames.de/wormhole/port-forwarding";
+const APPID_RAW: &str = crate::protocol::v1::FORWARDING_APPID;
 
 /// The App ID associated with this protocol.
 pub const APPID: AppID = AppID(Cow::Borrowed(APPID_RAW));
@@ -121,6 +121,9 @@ pub enum ForwardingError {
     ),
 }
 
+#[cfg(feature = "serde-types")]
+impl_serialize_error!(ForwardingError);
+
 impl ForwardingError {
     fn protocol(message: impl Into<Box<str>>) -> Self {
         Self::Protocol(message.into())
@@ -134,6 +137,18 @@ impl ForwardingError {
     }
 }
 
+/**
+ * The timeouts of the wormhole, except for the inactivity timeout of the transit connection
+ *
+ * The tunnel lives as long as the forwarded connections, which may stay silent for hours.
+ */
+fn tunnel_timeouts(wormhole: &Wormhole) -> crate::Timeouts {
+    crate::Timeouts {
+        transfer_inactivity: None,
+        ..*wormhole.timeouts()
+    }
+}
+
 /// Offer to forward some ports
 ///
 /// `targets` is a mapping of (host, port) pairs. If no target host is provided, then
@@ -144,6 +159,7 @@ impl ForwardingError {
 /// or `cancel` resolves. The last one can be used to provide timeouts or to inject CTRL-C
 /// handling. If you want the forward to never (successfully) stop, pass [`futures::future::pending()`]
 /// as the value.
+#[tracing::instrument(name = "forwarding", skip_all, fields(side = %wormhole.side()))]
 pub async fn serve(
     mut wormhole: Wormhole,
     transit_handler: impl FnOnce(transit::TransitInfo),
@@ -151,6 +167,7 @@ pub async fn serve(
     targets: Vec<(Option<url::Host>, u16)>,
     cancel: impl Future<Output = ()>,
 ) -> Result<(), ForwardingError> {
+    let transit_handler = wormhole.event_sender().transit_handler(transit_handler);
     assert!(
         !targets.is_empty(),
         "The list of target ports must not be empty"
@@ -162,11 +179,14 @@ pub async fn serve(
         .expect("You may only use a Wormhole instance with the correct AppVersion type!");
     let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version().clone())?;
     let connector = transit::init(
-        our_version.transit_abilities,
+        wormhole.transit_abilities(our_version.transit_abilities),
         Some(peer_version.transit_abilities),
         relay_hints,
     )
-    .await?;
+    .await?
+    .with_timeouts(&tunnel_timeouts(&wormhole))
+    .with_proxy(wormhole.proxy())
+    .with_rate_limit(wormhole.rate_limit());
 
     /* Send our transit hints */
     wormhole
@@ -275,16 +295,43 @@ pub async fn serve(
     }
 }
 
+/**
+ * A background task that forwards data from one local connection to the transit
+ *
+ * The task is aborted when this gets dropped. This way, dropping a [`serve`] or [`connect`]
+ * future does not leave the connections (and their sockets) open in the background.
+ */
+struct Worker {
+    handle: async_std::task::JoinHandle<()>,
+    abort: futures::future::AbortHandle,
+}
+
+impl Worker {
+    fn spawn(future: impl Future<Output = ()> + 'static) -> Self {
+        let (abort, registration) = futures::future::AbortHandle::new_pair();
+        let handle = async_std::task::spawn_local(async move {
+            let _ = futures::future::Abortable::new(future, registration).await;
+        });
+        Self { handle, abort }
+    }
+
+    /** Abort the task and wait for it to stop */
+    async fn cancel(mut self) {
+        self.abort.abort();
+        (&mut self.handle).await;
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
+}
+
 struct ForwardingServe {
     targets: HashMap<String, (Option<url::Host>, u16)>,
     /* self => remote */
-    connections: HashMap<
-        u64,
-        (
-            async_std::task::JoinHandle<()>,
-            futures::io::WriteHalf<TcpStream>,
-        ),
-    >,
+    connections: HashMap<u64, (Worker, futures::io::WriteHalf<TcpStream>)>,
     /* Track old connection IDs that won't be reused again. This is to distinguish race hazards where
      * one side closes a connection while the other one accesses it simultaneously. Despite the name, the
      * set also includes connections that are currently live.
@@ -398,7 +445,7 @@ impl ForwardingServe {
         };
         let (mut connection_rd, connection_wr) = stream.split();
         let mut backchannel_tx = self.backchannel_tx.clone();
-        let worker = async_std::task::spawn_local(async move {
+        let worker = Worker::spawn(async move {
             let mut buffer = vec![0; 4096];
             /* Ignore errors */
             macro_rules! break_on_err {
@@ -532,6 +579,7 @@ impl ForwardingServe {
 ///
 /// This method already binds to all the necessary ports up-front. To limit abuse potential
 /// no more than 1024 ports may be forwarded at once.
+#[tracing::instrument(name = "forwarding", skip_all, fields(side = %wormhole.side()))]
 pub async fn connect(
     mut wormhole: Wormhole,
     transit_handler: impl FnOnce(transit::TransitInfo),
@@ -539,17 +587,21 @@ pub async fn connect(
     bind_address: Option<std::net::IpAddr>,
     custom_ports: &[u16],
 ) -> Result<ConnectOffer, ForwardingError> {
+    let transit_handler = wormhole.event_sender().transit_handler(transit_handler);
     let our_version: &AppVersion = wormhole
         .our_version()
         .downcast_ref()
         .expect("You may only use a Wormhole instance with the correct AppVersion type!");
     let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version().clone())?;
     let connector = transit::init(
-        our_version.transit_abilities,
+        wormhole.transit_abilities(our_version.transit_abilities),
         Some(peer_version.transit_abilities),
         relay_hints,
     )
-    .await?;
+    .await?
+    .with_timeouts(&tunnel_timeouts(&wormhole))
+    .with_proxy(wormhole.proxy())
+    .with_rate_limit(wormhole.rate_limit());
     let bind_address = bind_address.unwrap_or_else(|| std::net::IpAddr::V6("::".parse().unwrap()));
 
     /* Send our transit hints */
@@ -751,13 +803,7 @@ struct ForwardConnect {
     >,
     /* Our next unique connection_id */
     connection_counter: u64,
-    connections: HashMap<
-        u64,
-        (
-            async_std::task::JoinHandle<()>,
-            futures::io::WriteHalf<TcpStream>,
-        ),
-    >,
+    connections: HashMap<u64, (Worker, futures::io::WriteHalf<TcpStream>)>,
     /* application => self. (connection_id, Some=payload or None=close) */
     backchannel_tx: futures::channel::mpsc::Sender<(u64, Option<Vec<u8>>)>,
     backchannel_rx: futures::channel::mpsc::Receiver<(u64, Option<Vec<u8>>)>,
@@ -845,7 +891,7 @@ impl ForwardConnect {
             )
             .await?;
 
-        let worker = async_std::task::spawn_local(async move {
+        let worker = Worker::spawn(async move {
             let mut buffer = vec![0; 4096];
             /* Ignore errors */
             macro_rules! break_on_err {
@@ -0,0 +1,60 @@
+//! Entry points for the fuzz targets in `fuzz/`
+//!
+//! The parsers are private, so this module exposes one function per target. Each of them takes
+//! arbitrary input and must never panic, whatever the input is. Errors are expected and ignored.
+//!
+//! Not part of the public API, only available with the `fuzzing` feature.
+
+#![allow(deprecated)]
+
+use crate::core::{key, rendezvous, Code};
+
+/// A message from the rendezvous server, as received over the websocket
+pub fn rendezvous_message(data: &[u8]) {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = rendezvous::parse_message(text);
+    }
+}
+
+/// The PAKE message and an encrypted message of the other side
+pub fn peer_crypto(data: &[u8]) {
+    let _ = key::extract_pake_msg(data);
+    let _ = key::decrypt_data(&Default::default(), data);
+}
+
+/// A code as entered by the user, including the completion of partial codes
+pub fn code(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let code = Code::from(text.to_owned());
+    let _ = code.split();
+    let _ = code.nameplate();
+    #[cfg(feature = "completion")]
+    let _ = crate::core::wordlist::default_wordlist(2).get_completions(text);
+    #[cfg(feature = "transfer")]
+    let _ = text.parse::<crate::uri::WormholeTransferUri>();
+}
+
+/// A message of the file transfer protocol, both the JSON (v1) and msgpack (v2) encoding
+#[cfg(feature = "transfer")]
+pub fn transfer_message(data: &[u8]) {
+    let _ = serde_json::from_slice::<crate::transfer::PeerMessage>(data);
+    #[cfg(feature = "experimental-transfer-v2")]
+    let _ = crate::transfer::v2::PeerMessageV2::de_msgpack(data);
+}
+
+/// The transit hints and abilities of the other side
+#[cfg(feature = "transit")]
+pub fn transit_hints(data: &[u8]) {
+    let _ = serde_json::from_slice::<crate::transit::Hints>(data);
+    let _ = serde_json::from_slice::<crate::transit::Abilities>(data);
+}
+
+/// The first line a client sends to the transit relay server
+#[cfg(all(feature = "server", not(target_family = "wasm")))]
+pub fn relay_handshake(data: &[u8]) {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = crate::server::relay::parse_handshake(text);
+    }
+}
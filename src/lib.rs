@@ -14,10 +14,59 @@
 //!
 //! As an alternative to file transfer, there is the [`forwarding`] module, which allows to forward arbitrary TCP connections over the Wormhole/Transit tunnel.
 //!
+//! The `ssh` module exchanges SSH public keys to set up logins, like `wormhole ssh invite` and `wormhole ssh accept`. It requires the `transfer` feature.
+//!
 //! Transferring large amounts of data should not be done over the rendezvous server. Instead, you have to set up a [`transit`]
 //! connection. A transit is little more than an encrypted TcpConnection. If a direct connection between both clients is not possible,
 //! a relay server will transparently connect them together. Transit is used by the file transfer for example, but any other AppID protocol
 //! might make use of it as well.
+//!
+//! ## Features
+//!
+//! Without any features, this crate only connects to the rendezvous server and does the key exchange, which keeps the dependency
+//! tree small for applications bringing their own protocol. Everything else is optional:
+//!
+//! - `transit`: encrypted direct or relayed connections between both sides, enabled by default
+//! - `transfer`: the file transfer protocol, including the archive support for sending folders. Enabled by default, implies `transit`
+//! - `forwarding`: TCP port forwarding, implies `transit`
+//! - `compression`: compress file transfers with zstd if the other side supports it too. Implies `transfer`, not available on wasm
+//! - `completion`: tab completion of codes using the wordlist
+//! - `rustyline`: a [`rustyline`](https://docs.rs/rustyline) helper completing codes in interactive prompts, see the [`completer`] module. Implies `completion`
+//! - `serde-types`: `Serialize` and `Deserialize` for public types that are not part of the wire protocol
+//! - `server`: a rendezvous server and a transit relay for self-hosting, see the [`server`] module
+//! - `testing`: run both servers locally for integration tests, see the [`testing`] module
+//! - `tor`: connect through a local Tor daemon and only use transit relays, see [`proxy::ProxyConfig::tor`]
+//! - `prometheus`: export the [`metrics`] in the Prometheus format
+//! - `bip39-wordlist`: generate codes from the BIP39 wordlist, see [`wordlist::Wordlist::bip39`]
+//! - `tls` or `native-tls`: connect to rendezvous servers using `wss://`
+//!
+//! The command line interface lives in its own crate, `magic-wormhole-cli`.
+//!
+//! ## Errors
+//!
+//! All errors are typed enums that implement [`std::error::Error`] and are `Send + Sync + 'static`, so you can match on the cause of a
+//! failure or wrap them into your own error type. They follow the layering of the protocols: [`WormholeError`] covers the connection to the
+//! rendezvous server (see [`rendezvous::RendezvousError`]) and the key exchange with the peer. `transit::TransitConnectError` and
+//! `transit::TransitError` cover transit connections. Finally, `transfer::TransferError` and `forwarding::ForwardingError` wrap all of
+//! the above together with the errors specific to their protocol. Use [`std::error::Error::source`] to walk down the layers.
+//!
+//! ## WebAssembly
+//!
+//! The crate builds for `wasm32-unknown-unknown` to run in browsers. There, the rendezvous connection uses the browser's `WebSocket`
+//! and timers are based on `setTimeout`, so no `async-std` is involved. Transit only works over relays reachable with WebSockets
+//! (`ws://` or `wss://` relay hints), browsers can't do direct connections. Code generation, the key exchange and the file transfer
+//! work the same as natively, except for the parts touching the file system.
+//!
+//! ## Diagnostics
+//!
+//! This crate logs using [`tracing`](https://docs.rs/tracing). The connection to the rendezvous server, claiming the nameplate, the
+//! key exchange, the transit negotiation and the transfers each run in their own span. These carry the `side` (a random identifier of
+//! this client, also sent to the server) and where known the `nameplate`, so that the events of one session can be correlated.
+//!
+//! For bug reports, there is an opt-in protocol trace at the `TRACE` level with the target `magic_wormhole::protocol`. It records every
+//! transition of the mailbox and key exchange state machines, every WebSocket frame from and to the rendezvous server, and the start and
+//! outcome of every transit connection attempt, as structured events. Message bodies and hashcash stamps are replaced by their length,
+//! so the trace contains no key material and no payload. Enable it with a filter like `magic_wormhole::protocol=trace`.
 
 #![forbid(unsafe_code)]
 #![allow(clippy::upper_case_acronyms)]
@@ -27,9 +76,29 @@
 
 #[macro_use]
 mod util;
+#[cfg(feature = "benchmarks")]
+#[doc(hidden)]
+pub mod benchmarks;
+#[cfg(all(feature = "transfer", not(target_family = "wasm")))]
+pub mod blocking;
+#[cfg(feature = "rustyline")]
+pub mod completer;
 mod core;
 #[cfg(feature = "forwarding")]
 pub mod forwarding;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+pub mod metrics;
+pub mod protocol;
+pub mod proxy;
+pub mod seeds;
+#[cfg(all(feature = "server", not(target_family = "wasm")))]
+pub mod server;
+#[cfg(all(feature = "transfer", not(target_family = "wasm")))]
+pub mod ssh;
+#[cfg(all(feature = "testing", not(target_family = "wasm")))]
+pub mod testing;
 #[cfg(feature = "transfer")]
 pub mod transfer;
 #[cfg(feature = "transit")]
@@ -39,7 +108,9 @@ pub mod uri;
 
 #[allow(deprecated)]
 pub use crate::core::{
-    key::{GenericKey, Key, KeyPurpose, WormholeKey},
-    rendezvous, AppConfig, AppID, Code, MailboxConnection, Mood, Nameplate, Wormhole,
-    WormholeError, WormholeWelcome,
+    key::{GenericKey, Key, KeyLengthError, KeyPurpose, WormholeKey, MAX_DERIVED_KEY_LENGTH},
+    rendezvous, wordlist, AppConfig, AppID, AppMessageSender, AppVersions, CloseReason, Code,
+    CodeParseError, ConfirmationHandler, MailboxConnection, Mood, Nameplate, PendingWormhole,
+    ServerConnection, Timeouts, Wormhole, WormholeBuilder, WormholeError, WormholeEvent,
+    WormholeWelcome,
 };
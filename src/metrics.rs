@@ -0,0 +1,236 @@
+//! Operational metrics
+//!
+//! Daemons running many transfers want to know how they're doing. Implement [`Metrics`] and
+//! pass it to [`WormholeBuilder::metrics`](crate::WormholeBuilder::metrics) to get called at
+//! well-defined points of every connection: connecting to the rendezvous server, failures,
+//! every [`WormholeEvent`], bytes transferred and finished transfers.
+//!
+//! With the `prometheus` feature, [`PrometheusMetrics`] exports them in the Prometheus format.
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::{metrics::Metrics, transfer::APP_CONFIG, Wormhole};
+//! use std::sync::{
+//!     atomic::{AtomicU64, Ordering},
+//!     Arc,
+//! };
+//!
+//! #[derive(Debug, Default)]
+//! struct Failures(AtomicU64);
+//!
+//! impl Metrics for Failures {
+//!     fn connection_failed(&self, _reason: &'static str) {
+//!         self.0.fetch_add(1, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let metrics = Arc::new(Failures::default());
+//! let pending = Wormhole::builder(APP_CONFIG)
+//!     .metrics(metrics.clone())
+//!     .allocate_code()
+//!     .await?;
+//! # Ok(()) })}
+//! ```
+
+use crate::WormholeEvent;
+#[cfg(feature = "transfer")]
+use std::time::Duration;
+
+/// Whether we sent or received a transfer
+#[cfg(feature = "transfer")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, derive_more::Display)]
+#[non_exhaustive]
+pub enum Direction {
+    /// We sent the data
+    #[display("send")]
+    Send,
+    /// We received the data
+    #[display("receive")]
+    Receive,
+}
+
+/**
+ * Gets called at well-defined points of every connection
+ *
+ * All methods do nothing by default, implement the ones you're interested in. They are called
+ * from async code, so they must not block.
+ *
+ * Failure reasons are short static strings like `"timeout"` or `"pake_failed"`, suitable as
+ * metric labels.
+ */
+pub trait Metrics: std::fmt::Debug + Send + Sync {
+    /// Connected to the rendezvous server. This counts reconnects too
+    fn rendezvous_connected(&self) {}
+
+    /// Connecting to the rendezvous server or to the other side failed
+    fn connection_failed(&self, _reason: &'static str) {}
+
+    /// Something happened during the lifetime of a wormhole, like [`WormholeEvent::KeyConfirmed`]
+    fn event(&self, _event: &WormholeEvent) {}
+
+    /// Some more bytes of a transfer have been sent or received
+    #[cfg(feature = "transfer")]
+    fn bytes_transferred(&self, _direction: Direction, _bytes: u64) {}
+
+    /// A file transfer finished, successfully if there is no failure reason
+    #[cfg(feature = "transfer")]
+    fn transfer_finished(
+        &self,
+        _direction: Direction,
+        _duration: Duration,
+        _failure: Option<&'static str>,
+    ) {
+    }
+}
+
+/// Metrics that go nowhere. This is the default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus::PrometheusMetrics;
+
+#[cfg(feature = "prometheus")]
+mod prometheus {
+    use super::*;
+    use prometheus_client::{
+        metrics::{counter::Counter, family::Family, histogram::Histogram},
+        registry::Registry,
+    };
+
+    type Labels = Vec<(&'static str, String)>;
+
+    /**
+     * Export the metrics in the Prometheus format, using `prometheus-client`
+     *
+     * Registers these metrics, all prefixed with `wormhole_`:
+     *
+     * - `rendezvous_connections_total`
+     * - `connection_failures_total`, by `reason`
+     * - `events_total`, by `type`. Includes `key_confirmed`, `transit_connected` and `closed`
+     * - `transferred_bytes_total`, by `direction`
+     * - `transfers_total`, by `direction` and `result`
+     * - `transfer_duration_seconds`, a histogram of all finished transfers
+     *
+     * ```no_run
+     * use magic_wormhole::metrics::PrometheusMetrics;
+     * use prometheus_client::registry::Registry;
+     *
+     * let mut registry = Registry::default();
+     * let metrics = std::sync::Arc::new(PrometheusMetrics::new(&mut registry));
+     * // Use `metrics` with `WormholeBuilder::metrics`, serve `registry` with `prometheus_client::encoding::text::encode`
+     * ```
+     */
+    #[derive(Debug)]
+    pub struct PrometheusMetrics {
+        rendezvous_connections: Counter,
+        connection_failures: Family<Labels, Counter>,
+        events: Family<Labels, Counter>,
+        transferred_bytes: Family<Labels, Counter>,
+        transfers: Family<Labels, Counter>,
+        transfer_duration: Histogram,
+    }
+
+    impl PrometheusMetrics {
+        /// Create the metrics and register them
+        pub fn new(registry: &mut Registry) -> Self {
+            let metrics = Self {
+                rendezvous_connections: Counter::default(),
+                connection_failures: Family::default(),
+                events: Family::default(),
+                transferred_bytes: Family::default(),
+                transfers: Family::default(),
+                /* From 100ms to about 15h */
+                transfer_duration: Histogram::new(
+                    prometheus_client::metrics::histogram::exponential_buckets(0.1, 4.0, 10),
+                ),
+            };
+            let registry = registry.sub_registry_with_prefix("wormhole");
+            registry.register(
+                "rendezvous_connections",
+                "Connections to the rendezvous server",
+                metrics.rendezvous_connections.clone(),
+            );
+            registry.register(
+                "connection_failures",
+                "Failures to connect to the server or the other side",
+                metrics.connection_failures.clone(),
+            );
+            registry.register(
+                "events",
+                "Events during the lifetime of wormholes",
+                metrics.events.clone(),
+            );
+            registry.register(
+                "transferred_bytes",
+                "Bytes sent or received by file transfers",
+                metrics.transferred_bytes.clone(),
+            );
+            registry.register(
+                "transfers",
+                "Finished file transfers",
+                metrics.transfers.clone(),
+            );
+            registry.register(
+                "transfer_duration_seconds",
+                "Duration of finished file transfers",
+                metrics.transfer_duration.clone(),
+            );
+            metrics
+        }
+    }
+
+    impl Metrics for PrometheusMetrics {
+        fn rendezvous_connected(&self) {
+            self.rendezvous_connections.inc();
+        }
+
+        fn connection_failed(&self, reason: &'static str) {
+            self.connection_failures
+                .get_or_create(&vec![("reason", reason.to_owned())])
+                .inc();
+        }
+
+        fn event(&self, event: &WormholeEvent) {
+            let kind = match event {
+                WormholeEvent::PeerConnected => "peer_connected",
+                WormholeEvent::KeyConfirmed { .. } => "key_confirmed",
+                WormholeEvent::NameplateReleased => "nameplate_released",
+                #[cfg(feature = "transit")]
+                WormholeEvent::TransitConnected(_) => "transit_connected",
+                /* Counted as bytes instead */
+                #[cfg(feature = "transfer")]
+                WormholeEvent::TransferProgress { .. } => return,
+                WormholeEvent::Closed { .. } => "closed",
+            };
+            self.events
+                .get_or_create(&vec![("type", kind.to_owned())])
+                .inc();
+        }
+
+        #[cfg(feature = "transfer")]
+        fn bytes_transferred(&self, direction: Direction, bytes: u64) {
+            self.transferred_bytes
+                .get_or_create(&vec![("direction", direction.to_string())])
+                .inc_by(bytes);
+        }
+
+        #[cfg(feature = "transfer")]
+        fn transfer_finished(
+            &self,
+            direction: Direction,
+            duration: Duration,
+            failure: Option<&'static str>,
+        ) {
+            self.transfers
+                .get_or_create(&vec![
+                    ("direction", direction.to_string()),
+                    ("result", failure.unwrap_or("success").to_owned()),
+                ])
+                .inc();
+            self.transfer_duration.observe(duration.as_secs_f64());
+        }
+    }
+}
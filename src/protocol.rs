@@ -0,0 +1,113 @@
+//! Constants of the wire protocols, by protocol version
+//!
+//! Everything in here ends up on the wire, so it must match the other implementations of Magic
+//! Wormhole. The values are grouped by the protocol revision introducing them, so that a future
+//! revision can be added as a new module next to the existing ones without changing the values
+//! older clients depend on. [`latest`] always points to the newest revision.
+//!
+//! Most applications never need these directly: the [`AppConfig`](crate::AppConfig)s of the
+//! [`transfer`](crate::transfer) and [`forwarding`](crate::forwarding) modules already use them.
+
+use std::fmt;
+
+/** The first revision of the protocols, as implemented by all clients */
+pub mod v1 {
+    /// The public rendezvous server, using the websocket protocol version 1
+    pub const RENDEZVOUS_SERVER: &str = "ws://relay.magic-wormhole.io:4000/v1";
+    /// The public transit relay server
+    pub const RELAY_SERVER: &str = "tcp://transit.magic-wormhole.io:4001";
+
+    /// The AppID of the file transfer protocol
+    pub const TRANSFER_APPID: &str = "lothar.com/wormhole/text-or-file-xfer";
+    /// The AppID of the port forwarding protocol
+    pub const FORWARDING_APPID: &str = "piegames.de/wormhole/port-forwarding";
+    /// The AppID of the SSH key exchange, see the `ssh` module
+    pub const SSH_APPID: &str = "lothar.com/wormhole/ssh-add";
+
+    /// The phase of the key exchange message
+    pub const PHASE_PAKE: &str = "pake";
+    /// The phase of the message carrying the application version information
+    pub const PHASE_VERSION: &str = "version";
+    /// The phase of the final message sent by [`Wormhole::close_with`](crate::Wormhole::close_with)
+    pub const PHASE_CLOSE: &str = "close";
+    /// Application messages use this prefix followed by a counter for their phase
+    pub const APP_PHASE_PREFIX: &str = "app-";
+
+    /// The encoding version of [`Wormhole::send_typed`](crate::Wormhole::send_typed)
+    pub const TYPED_ENCODING_VERSION: u32 = 1;
+
+    /// The ability advertising the file transfer protocol version 1
+    pub const ABILITY_TRANSFER_V1: &str = "transfer-v1";
+    /// The ability advertising the file transfer protocol version 2
+    pub const ABILITY_TRANSFER_V2: &str = "transfer-v2";
+    /// The ability advertising that zstd compressed file transfers can be received. Other
+    /// implementations don't know it, so transfers with them are never compressed
+    pub const ABILITY_COMPRESSION_ZSTD: &str = "compression-zstd-v1";
+}
+
+pub use self::v1 as latest;
+
+/**
+ * The versions of the file transfer protocol
+ *
+ * Converts from and to the abilities advertised in the application version information, see
+ * [`AppVersions::supports`](crate::AppVersions::supports).
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum TransferVersion {
+    /// The original file transfer protocol, understood by all clients
+    V1,
+    /// The experimental successor, based on dilation
+    V2,
+}
+
+impl TransferVersion {
+    /// The ability advertising support for this version
+    pub const fn ability(self) -> &'static str {
+        match self {
+            Self::V1 => v1::ABILITY_TRANSFER_V1,
+            Self::V2 => v1::ABILITY_TRANSFER_V2,
+        }
+    }
+}
+
+impl TryFrom<&str> for TransferVersion {
+    type Error = UnknownVersion;
+
+    fn try_from(ability: &str) -> Result<Self, Self::Error> {
+        match ability {
+            v1::ABILITY_TRANSFER_V1 => Ok(Self::V1),
+            v1::ABILITY_TRANSFER_V2 => Ok(Self::V2),
+            other => Err(UnknownVersion(other.into())),
+        }
+    }
+}
+
+impl fmt::Display for TransferVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.ability())
+    }
+}
+
+/// A protocol version unknown to this version of the crate
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Unknown protocol version: {}", _0)]
+pub struct UnknownVersion(pub Box<str>);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transfer_version_abilities() {
+        for version in [TransferVersion::V1, TransferVersion::V2] {
+            assert_eq!(TransferVersion::try_from(version.ability()), Ok(version));
+        }
+        assert_eq!(TransferVersion::V2.to_string(), "transfer-v2");
+        assert_eq!(
+            TransferVersion::try_from("experimental-transfer-v2"),
+            Err(UnknownVersion("experimental-transfer-v2".into()))
+        );
+    }
+}
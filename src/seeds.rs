@@ -0,0 +1,248 @@
+//! Reconnect to known peers without exchanging a new code
+//!
+//! After a normal connection with a code, both sides can remember each other with a [`Seed`], from
+//! [`Wormhole::seed`]. This is a long-term secret derived from the session key, which both sides store
+//! under a name of their choice, for example in a [`SeedStore`]. Later on,
+//! [`Wormhole::connect_with_seed`] or
+//! [`WormholeBuilder::connect_with_seed`](crate::WormholeBuilder::connect_with_seed) brings both
+//! sides together again, without a code. Both of them use the same method, in any order.
+//!
+//! The seed determines the mailbox on the rendezvous server as well as the password of the key
+//! exchange. Nobody without the seed can find the mailbox, let alone join the connection. Seeds are
+//! derived with the app ID of the connection they came from, and only work with the same app ID.
+//!
+//! ## Rotation and revocation
+//!
+//! Both sides should [`rotate`](Seed::rotate) the seed once they are connected with it. The new
+//! seed is derived from the key of that connection, which moves the next connection to a fresh
+//! mailbox and limits what a leaked copy of an old seed is good for. Both sides have to rotate and
+//! save their seed, or they won't find each other anymore. In that case, exchange a code again.
+//!
+//! To revoke a peer, delete its seed on either side.
+//!
+//! ## Stale mailboxes
+//!
+//! A side that waits for the other one and gets cancelled closes the mailbox, which deletes it on the
+//! server. If it crashes instead, the server keeps the mailbox with its messages until it expires,
+//! and connecting with the same seed fails until then.
+
+#![allow(deprecated)]
+
+use crate::{
+    core::{key::Key, Code, Mailbox, Nameplate, Wormhole},
+    KeyPurpose,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(not(target_family = "wasm"))]
+use std::{io, path::Path};
+
+impl Wormhole {
+    /**
+     * Derive a [`Seed`] to reconnect to this peer later, see the [module documentation](self)
+     *
+     * Shorthand for [`Seed::new`].
+     */
+    pub fn seed(&self) -> Seed {
+        Seed::new(self)
+    }
+}
+
+/// The purpose of the long-term secret of a [`Seed`]
+#[derive(Clone, Debug)]
+pub struct SeedKey;
+impl KeyPurpose for SeedKey {}
+
+/**
+ * A long-term secret shared with one peer, see the [module documentation](self)
+ *
+ * Keep it as secret as the files you transfer with it. Its `Debug` output is redacted.
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Seed {
+    #[serde(with = "hex_secret")]
+    secret: Key<SeedKey>,
+    generation: u64,
+}
+
+impl Seed {
+    /**
+     * Derive a new seed from an established connection
+     *
+     * Both sides must call this on the same connection to get the same seed.
+     */
+    pub fn new(wormhole: &Wormhole) -> Self {
+        Self {
+            secret: Self::derive_secret(wormhole),
+            generation: 0,
+        }
+    }
+
+    /**
+     * Replace the secret with one derived from an established connection
+     *
+     * Typically called on the connection made with this seed. Both sides must do this, and then
+     * save the seed again.
+     */
+    pub fn rotate(&mut self, wormhole: &Wormhole) {
+        self.secret = Self::derive_secret(wormhole);
+        self.generation += 1;
+    }
+
+    /// How many times the seed has been rotated
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn derive_secret(wormhole: &Wormhole) -> Key<SeedKey> {
+        let secret = wormhole
+            .derive_key("seed", 32)
+            .expect("32 bytes are below the limit");
+        Key::new(Box::new(crypto_secretbox::Key::clone_from_slice(&secret)))
+    }
+
+    /// The mailbox on the rendezvous server both sides open
+    pub(crate) fn mailbox(&self) -> Mailbox {
+        let key: Key<SeedKey> = self
+            .secret
+            .derive_subkey_from_purpose(&format!("wormhole:seed:mailbox:{}", self.generation));
+        Mailbox(hex::encode(&key[..16]))
+    }
+
+    /**
+     * The code both sides use for the key exchange
+     *
+     * It is never shown to the user. The nameplate is only there so that logs show where a code
+     * came from, the password is derived from the secret.
+     */
+    pub(crate) fn code(&self) -> Code {
+        let key: Key<SeedKey> = self
+            .secret
+            .derive_subkey_from_purpose(&format!("wormhole:seed:password:{}", self.generation));
+        Code::new(&Nameplate::new("seed"), &key.to_hex())
+    }
+}
+
+mod hex_secret {
+    use super::{Key, SeedKey};
+
+    pub fn serialize<S: serde::Serializer>(key: &Key<SeedKey>, ser: S) -> Result<S::Ok, S::Error> {
+        hex::serde::serialize(&**key, ser)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Key<SeedKey>, D::Error> {
+        let key: zeroize::Zeroizing<[u8; 32]> =
+            zeroize::Zeroizing::new(hex::serde::deserialize(de)?);
+        Ok(Key::new(Box::new((*key).into())))
+    }
+}
+
+/**
+ * Seeds by the name of their peer, stored as a JSON file
+ *
+ * The file contains the secrets in plain text. On Unix, it is only readable by its owner.
+ */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SeedStore {
+    seeds: BTreeMap<String, Seed>,
+}
+
+impl SeedStore {
+    /// Read the seeds from a file, starting with no seeds if it doesn't exist yet
+    #[cfg(not(target_family = "wasm"))]
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Write the seeds to a file, replacing it atomically
+    #[cfg(not(target_family = "wasm"))]
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        use std::io::Write;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp = path.with_extension("tmp");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temp)?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(temp, path)
+    }
+
+    /// The seed shared with `name`
+    pub fn get(&self, name: &str) -> Option<&Seed> {
+        self.seeds.get(name)
+    }
+
+    /// Remember a seed under `name`, replacing any previous one
+    pub fn insert(&mut self, name: impl Into<String>, seed: Seed) {
+        self.seeds.insert(name.into(), seed);
+    }
+
+    /// Forget the seed of `name`, revoking that peer
+    pub fn remove(&mut self, name: &str) -> Option<Seed> {
+        self.seeds.remove(name)
+    }
+
+    /// The names of all peers with a seed, in alphabetical order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.seeds.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn seed(byte: u8) -> Seed {
+        Seed {
+            secret: Key::new(Box::new([byte; 32].into())),
+            generation: 0,
+        }
+    }
+
+    #[test]
+    fn test_seed_derivation() {
+        let mut seed = seed(1);
+        let (mailbox, code) = (seed.mailbox(), seed.code());
+        assert_eq!(mailbox.0.len(), 32);
+        assert_eq!(code.nameplate(), Nameplate::new("seed"));
+        assert_ne!(mailbox.0, code.as_ref()[5..37]);
+
+        seed.generation = 1;
+        assert_ne!(seed.mailbox(), mailbox);
+        assert_ne!(seed.code().as_ref(), code.as_ref());
+        assert!(!format!("{seed:?}").contains(&hex::encode([1; 32])));
+    }
+
+    #[test]
+    fn test_seed_store() -> std::io::Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("wormhole-seeds-{}", std::process::id()))
+            .join("seeds.json");
+        assert!(SeedStore::load(&path)?.names().next().is_none());
+
+        let mut store = SeedStore::default();
+        store.insert("bob", seed(2));
+        store.insert("alice", seed(1));
+        store.save(&path)?;
+
+        let mut store = SeedStore::load(&path)?;
+        assert_eq!(store.names().collect::<Vec<_>>(), ["alice", "bob"]);
+        assert_eq!(store.get("alice").unwrap().mailbox(), seed(1).mailbox());
+        assert!(store.remove("bob").is_some());
+        store.save(&path)?;
+        assert!(SeedStore::load(&path)?.get("bob").is_none());
+
+        std::fs::remove_dir_all(path.parent().unwrap())
+    }
+}
@@ -0,0 +1,22 @@
+//! Servers for self-hosting the Magic Wormhole infrastructure
+//!
+//! Clients only talk to each other through a rendezvous server, which is why two clients must
+//! use the same one. The public servers are operated by volunteers. If you'd rather not depend
+//! on them, or need a server inside of a closed network, you can run your own with the modules
+//! in here: [`rendezvous`] connects clients, [`relay`] forwards the data of those that can't
+//! reach each other directly. They speak the same protocol as the reference implementation written in Python, so
+//! every client can use them.
+//!
+//! Only available with the `server` feature. The `wormhole-rs serve` command of the CLI wraps
+//! them into a runnable binary.
+
+pub mod relay;
+pub mod rendezvous;
+
+/// The current time, in the format used on the wire
+pub(crate) fn unix_time() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_secs_f64())
+        .unwrap_or_default()
+}
@@ -0,0 +1,457 @@
+//! The transit relay server
+//!
+//! If two clients can't reach each other directly, both connect to a relay server instead.
+//! Each of them sends a handshake with a token derived from their shared key, and the relay
+//! pairs up the two connections carrying the same token. From then on, it copies the bytes
+//! from one connection to the other. The traffic is end-to-end encrypted, the relay never
+//! learns what is being transferred.
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::server::relay::{Config, RelayServer};
+//!
+//! let server = RelayServer::new(Config::default());
+//! let listener = async_std::net::TcpListener::bind("0.0.0.0:4001").await?;
+//! server.serve(listener).await?;
+//! # Ok(()) })}
+//! ```
+//!
+//! Clients then use `tcp://<host>:4001` as relay hint. It can run next to a
+//! [`MailboxServer`](super::rendezvous::MailboxServer) in the same process.
+
+use async_std::net::{Shutdown, TcpListener, TcpStream};
+use futures::{channel::oneshot, future::Either, AsyncReadExt, AsyncWriteExt};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/** Longer handshake lines can't be valid */
+const MAX_HANDSHAKE_LEN: usize = 200;
+
+/**
+ * How to run a [`RelayServer`]
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// How long to wait for the handshake, and then for the other side to show up. Defaults to one minute.
+    pub pairing_timeout: Duration,
+    /// Close both connections of a session once one of them sent more than this many bytes.
+    /// Defaults to no limit.
+    pub max_bytes_per_connection: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pairing_timeout: Duration::from_secs(60),
+            max_bytes_per_connection: None,
+        }
+    }
+}
+
+/// Usage statistics of a [`RelayServer`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Currently open connections, including those waiting for the other side
+    pub connections: usize,
+    /// Sessions currently relaying data
+    pub sessions: usize,
+    /// Sessions that got paired since the server started
+    pub sessions_total: u64,
+    /// Bytes relayed by finished sessions, in both directions
+    pub bytes_total: u64,
+    /// Connections that sent an invalid handshake
+    pub bad_handshakes: u64,
+    /// Connections whose other side never showed up
+    pub lonely: u64,
+    /// Sessions that got closed because they exceeded [`Config::max_bytes_per_connection`]
+    pub limited: u64,
+}
+
+/** A connection waiting for the other side */
+struct Pending {
+    id: u64,
+    side: Option<String>,
+    partner: oneshot::Sender<TcpStream>,
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashMap<String, Vec<Pending>>,
+    stats: Stats,
+    next_connection: u64,
+}
+
+/** A parsed handshake line */
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Handshake {
+    token: String,
+    side: Option<String>,
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/** Parse `please relay TOKEN for side SIDE`, or the older `please relay TOKEN` */
+pub(crate) fn parse_handshake(line: &str) -> Option<Handshake> {
+    let rest = line.strip_prefix("please relay ")?;
+    let (token, side) = match rest.split_once(" for side ") {
+        Some((token, side)) => (token, Some(side)),
+        None => (rest, None),
+    };
+    if !is_hex(token, 64) || !side.map_or(true, |side| is_hex(side, 16)) {
+        return None;
+    }
+    Some(Handshake {
+        token: token.into(),
+        side: side.map(Into::into),
+    })
+}
+
+/**
+ * A transit relay server
+ *
+ * Cheap to clone, all clones share the same state.
+ */
+#[derive(Clone)]
+pub struct RelayServer {
+    config: Arc<Config>,
+    state: Arc<Mutex<State>>,
+}
+
+impl std::fmt::Debug for RelayServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RelayServer")
+            .field("config", &self.config)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl RelayServer {
+    /// Create a new server
+    pub fn new(config: Config) -> Self {
+        Self {
+            config: Arc::new(config),
+            state: Default::default(),
+        }
+    }
+
+    /// The current usage statistics
+    pub fn stats(&self) -> Stats {
+        self.state.lock().unwrap().stats.clone()
+    }
+
+    /**
+     * Accept clients until the listener fails
+     *
+     * Each connection is handled on its own task.
+     */
+    pub async fn serve(&self, listener: TcpListener) -> std::io::Result<()> {
+        tracing::info!("Transit relay listening on {}", listener.local_addr()?);
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let server = self.clone();
+            async_std::task::spawn(async move {
+                server.state.lock().unwrap().stats.connections += 1;
+                if let Err(error) = server.handle_connection(stream).await {
+                    tracing::debug!("Connection with {} failed: {}", peer, error);
+                }
+                server.state.lock().unwrap().stats.connections -= 1;
+            });
+        }
+    }
+
+    async fn handle_connection(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let handshake =
+            match crate::util::timeout(self.config.pairing_timeout, read_handshake(&mut stream))
+                .await
+            {
+                Ok(Ok(Some(handshake))) => handshake,
+                Ok(Ok(None)) => {
+                    self.state.lock().unwrap().stats.bad_handshakes += 1;
+                    stream.write_all(b"bad handshake\n").await?;
+                    return Ok(());
+                },
+                Ok(Err(error)) => return Err(error),
+                Err(_) => {
+                    self.state.lock().unwrap().stats.lonely += 1;
+                    return Ok(());
+                },
+            };
+        tracing::trace!("Handshake {:?}", handshake);
+
+        /* Pair up with someone already waiting, or wait ourselves */
+        let (id, partner) = {
+            let mut guard = self.state.lock().unwrap();
+            let state = &mut *guard;
+            let waiting = state.pending.entry(handshake.token.clone()).or_default();
+            /* Both sides may connect multiple times, never pair a side with itself */
+            let found = waiting.iter().position(|pending| {
+                pending.side.is_none() || handshake.side.is_none() || pending.side != handshake.side
+            });
+            if let Some(index) = found {
+                /* The waiting task does the relaying. This fails if it gave up in the meantime,
+                 * then we're the one waiting */
+                match waiting.remove(index).partner.send(stream) {
+                    Ok(()) => {
+                        if waiting.is_empty() {
+                            state.pending.remove(&handshake.token);
+                        }
+                        return Ok(());
+                    },
+                    Err(returned) => stream = returned,
+                }
+            }
+            let (tx, rx) = oneshot::channel();
+            state.next_connection += 1;
+            let id = state.next_connection;
+            waiting.push(Pending {
+                id,
+                side: handshake.side.clone(),
+                partner: tx,
+            });
+            (id, rx)
+        };
+
+        /* Clients don't send anything before the "ok", so reading returns only once they are gone */
+        let mut buf = [0; 1];
+        let waiting = futures::future::select(partner, stream.read(&mut buf));
+        let partner = match crate::util::timeout(self.config.pairing_timeout, waiting).await {
+            Ok(Either::Left((Ok(partner), _))) => Some(partner),
+            Ok(Either::Left((Err(oneshot::Canceled), _))) => None,
+            Ok(Either::Right(_)) => None,
+            Err(_) => {
+                self.state.lock().unwrap().stats.lonely += 1;
+                None
+            },
+        };
+        let Some(partner) = partner else {
+            let mut state = self.state.lock().unwrap();
+            if let Some(waiting) = state.pending.get_mut(&handshake.token) {
+                waiting.retain(|pending| pending.id != id);
+                if waiting.is_empty() {
+                    state.pending.remove(&handshake.token);
+                }
+            }
+            return Ok(());
+        };
+
+        self.relay(stream, partner).await
+    }
+
+    /** Tell both sides we're ready, then copy everything over until one of them closes */
+    async fn relay(&self, mut a: TcpStream, mut b: TcpStream) -> std::io::Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.stats.sessions += 1;
+            state.stats.sessions_total += 1;
+        }
+        let result = async {
+            a.write_all(b"ok\n").await?;
+            b.write_all(b"ok\n").await?;
+            let limit = self.config.max_bytes_per_connection;
+            /* Each direction closes both connections when done, which ends the other one too */
+            Ok::<_, std::io::Error>(
+                futures::future::join(
+                    pump(a.clone(), b.clone(), limit),
+                    pump(b.clone(), a.clone(), limit),
+                )
+                .await,
+            )
+        }
+        .await;
+        let _ = a.shutdown(Shutdown::Both);
+        let _ = b.shutdown(Shutdown::Both);
+
+        let mut state = self.state.lock().unwrap();
+        state.stats.sessions -= 1;
+        let (first, second) = result?;
+        for transferred in [first, second] {
+            match transferred {
+                Transferred::Done(bytes) => state.stats.bytes_total += bytes,
+                Transferred::Limited(bytes) => {
+                    state.stats.bytes_total += bytes;
+                    state.stats.limited += 1;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/** How a [`pump`] ended, with the number of bytes copied */
+enum Transferred {
+    Done(u64),
+    Limited(u64),
+}
+
+/**
+ * Copy from one connection to the other until either of them closes
+ *
+ * Both connections get closed afterwards, so that the other direction ends too.
+ */
+async fn pump(mut from: TcpStream, mut to: TcpStream, limit: Option<u64>) -> Transferred {
+    let mut buf = vec![0; 16 * 1024];
+    let mut total = 0u64;
+    let result = loop {
+        let read = match from.read(&mut buf).await {
+            Ok(0) | Err(_) => break Transferred::Done(total),
+            Ok(read) => read,
+        };
+        total += read as u64;
+        if limit.is_some_and(|limit| total > limit) {
+            break Transferred::Limited(total);
+        }
+        if to.write_all(&buf[..read]).await.is_err() {
+            break Transferred::Done(total);
+        }
+    };
+    let _ = to.shutdown(Shutdown::Both);
+    let _ = from.shutdown(Shutdown::Both);
+    result
+}
+
+/** Read a single handshake line, byte by byte to not consume any data after it */
+async fn read_handshake(stream: &mut TcpStream) -> std::io::Result<Option<Handshake>> {
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    while line.len() < MAX_HANDSHAKE_LEN {
+        if stream.read(&mut byte).await? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'\n' {
+            return Ok(std::str::from_utf8(&line).ok().and_then(parse_handshake));
+        }
+        line.push(byte[0]);
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_log::test;
+
+    const TOKEN: &str = "a6e50e1d8c1f4ac1c9fa0c0f2b4a84a8d6f3cd0c2e1a1b6df44a79ff34a3b4aa";
+
+    async fn start(config: Config) -> std::io::Result<(RelayServer, std::net::SocketAddr)> {
+        let server = RelayServer::new(config);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let serving = server.clone();
+        async_std::task::spawn(async move { serving.serve(listener).await });
+        Ok((server, addr))
+    }
+
+    async fn connect(addr: std::net::SocketAddr, side: &str) -> std::io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(format!("please relay {} for side {}\n", TOKEN, side).as_bytes())
+            .await?;
+        Ok(stream)
+    }
+
+    #[test]
+    fn test_parse_handshake() {
+        assert_eq!(
+            parse_handshake(&format!("please relay {} for side 0123456789abcdef", TOKEN)),
+            Some(Handshake {
+                token: TOKEN.into(),
+                side: Some("0123456789abcdef".into()),
+            })
+        );
+        assert_eq!(
+            parse_handshake(&format!("please relay {}", TOKEN)),
+            Some(Handshake {
+                token: TOKEN.into(),
+                side: None,
+            })
+        );
+        /* What the clients send, see `tests/golden` */
+        let golden: serde_json::Value =
+            serde_json::from_str(include_str!("../../tests/golden/keys.json")).unwrap();
+        assert_eq!(
+            parse_handshake(
+                golden["relay_handshake"]
+                    .as_str()
+                    .unwrap()
+                    .trim_end_matches('\n')
+            ),
+            Some(Handshake {
+                token: golden["transit_relay_token"].as_str().unwrap().into(),
+                side: Some(golden["transit_side"].as_str().unwrap().into()),
+            })
+        );
+        assert_eq!(parse_handshake("please relay abc"), None);
+        assert_eq!(parse_handshake("GET / HTTP/1.1"), None);
+    }
+
+    #[test(async_std::test)]
+    async fn test_relay() -> eyre::Result<()> {
+        let (server, addr) = start(Config::default()).await?;
+
+        let mut a = connect(addr, "0123456789abcdef").await?;
+        crate::util::sleep(Duration::from_millis(50)).await;
+        /* The same side connecting twice must not get paired with itself */
+        let _a2 = connect(addr, "0123456789abcdef").await?;
+        crate::util::sleep(Duration::from_millis(50)).await;
+        let mut b = connect(addr, "fedcba9876543210").await?;
+
+        let mut ok = [0; 3];
+        a.read_exact(&mut ok).await?;
+        assert_eq!(&ok, b"ok\n");
+        b.read_exact(&mut ok).await?;
+        assert_eq!(&ok, b"ok\n");
+
+        a.write_all(b"hello").await?;
+        let mut hello = [0; 5];
+        b.read_exact(&mut hello).await?;
+        assert_eq!(&hello, b"hello");
+        b.write_all(b"world").await?;
+        a.read_exact(&mut hello).await?;
+        assert_eq!(&hello, b"world");
+
+        drop(a);
+        /* Closing one side closes the other one too */
+        assert_eq!(b.read(&mut hello).await?, 0);
+        crate::util::sleep(Duration::from_millis(100)).await;
+        let stats = server.stats();
+        assert_eq!(stats.sessions_total, 1);
+        assert_eq!(stats.bytes_total, 10);
+        Ok(())
+    }
+
+    #[test(async_std::test)]
+    async fn test_relay_limits() -> eyre::Result<()> {
+        let (server, addr) = start(Config {
+            max_bytes_per_connection: Some(4),
+            ..Config::default()
+        })
+        .await?;
+
+        let mut bad = TcpStream::connect(addr).await?;
+        bad.write_all(b"please relay nothing\n").await?;
+        let mut response = Vec::new();
+        bad.read_to_end(&mut response).await?;
+        assert_eq!(response, b"bad handshake\n");
+
+        let mut a = connect(addr, "0123456789abcdef").await?;
+        let mut b = connect(addr, "fedcba9876543210").await?;
+        let mut ok = [0; 3];
+        a.read_exact(&mut ok).await?;
+        b.read_exact(&mut ok).await?;
+        a.write_all(b"too much").await?;
+        let mut received = Vec::new();
+        b.read_to_end(&mut received).await?;
+        assert!(received.len() <= 4);
+
+        crate::util::sleep(Duration::from_millis(100)).await;
+        let stats = server.stats();
+        assert_eq!((stats.bad_handshakes, stats.limited), (1, 1));
+        Ok(())
+    }
+}
@@ -0,0 +1,892 @@
+//! The rendezvous server, also known as mailbox server
+//!
+//! Clients connect to it over a websocket and bind to an [`AppID`](crate::AppID). They then
+//! allocate or claim a *nameplate*, the number at the start of a code, which points to a
+//! *mailbox*. Both sides put their messages into the mailbox, and the server forwards them to
+//! everybody who opened it. The server never sees any plaintext apart from the key exchange
+//! messages, which are useless without the code.
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::server::rendezvous::{Config, MailboxServer};
+//!
+//! let server = MailboxServer::new(Config {
+//!     motd: Some("Welcome to my server".into()),
+//!     ..Config::default()
+//! })?;
+//! let listener = async_std::net::TcpListener::bind("0.0.0.0:4000").await?;
+//! server.serve(listener).await?;
+//! # Ok(()) })}
+//! ```
+//!
+//! Clients then use `ws://<host>:4000/v1` as rendezvous URL.
+
+use super::unix_time;
+use async_std::net::{TcpListener, TcpStream};
+use async_tungstenite::tungstenite as ws2;
+use futures::{channel::mpsc, future::Either, SinkExt, StreamExt};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// An error of the rendezvous server
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ServerError {
+    /// Reading or writing the state file failed, or the network failed
+    #[error("I/O error")]
+    IO(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+    /// The state file is corrupt
+    #[error("Corrupt state file")]
+    State(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+}
+
+#[cfg(feature = "serde-types")]
+impl_serialize_error!(ServerError);
+
+/**
+ * How to run a [`MailboxServer`]
+ *
+ * The defaults match the ones of the reference implementation.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Message of the day, shown to users by most clients
+    pub motd: Option<String>,
+    /// Advertise this as the newest version of the Python CLI, so that older clients suggest upgrading
+    pub advertise_version: Option<String>,
+    /// Send this error in the welcome message, which makes clients refuse to continue. Useful to
+    /// tell users that a server is shutting down
+    pub signal_error: Option<String>,
+    /// Nameplates and mailboxes nobody is connected to are deleted after this time of inactivity.
+    /// Defaults to eleven hours.
+    pub expiry: Duration,
+    /// How often to look for expired mailboxes and to write the state file. Defaults to one minute.
+    pub maintenance_interval: Duration,
+    /// Keep the nameplates, mailboxes and statistics in this file, so that they survive restarts
+    pub state_file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            motd: None,
+            advertise_version: None,
+            signal_error: None,
+            expiry: Duration::from_secs(11 * 60 * 60),
+            maintenance_interval: Duration::from_secs(60),
+            state_file: None,
+        }
+    }
+}
+
+/// Usage statistics of a [`MailboxServer`]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Stats {
+    /// Currently connected clients
+    #[serde(skip)]
+    pub connections: usize,
+    /// Clients connected since the statistics were started
+    pub connections_total: u64,
+    /// Currently claimed nameplates
+    #[serde(skip)]
+    pub nameplates: usize,
+    /// Currently existing mailboxes
+    #[serde(skip)]
+    pub mailboxes: usize,
+    /// Messages added to mailboxes since the statistics were started
+    pub messages_total: u64,
+    /// How many mailboxes got closed with which mood, as reported by the last side closing it
+    pub moods: BTreeMap<String, u64>,
+    /// Mailboxes that got deleted because of inactivity
+    pub mailboxes_expired: u64,
+}
+
+/// A message in a mailbox, as sent to the clients
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StoredMessage {
+    side: String,
+    phase: String,
+    /// Hex encoded, passed on as-is
+    body: String,
+    server_rx: f64,
+    id: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct NameplateEntry {
+    mailbox: String,
+    sides: Vec<String>,
+    updated: f64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct MailboxEntry {
+    sides: Vec<String>,
+    messages: Vec<StoredMessage>,
+    updated: f64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct App {
+    nameplates: BTreeMap<String, NameplateEntry>,
+    mailboxes: HashMap<String, MailboxEntry>,
+}
+
+/** Everything that survives a restart */
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Persistent {
+    apps: HashMap<String, App>,
+    stats: Stats,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    persistent: Persistent,
+    /** The connections that opened a mailbox, by app and mailbox */
+    listeners: HashMap<(String, String), HashMap<u64, mpsc::UnboundedSender<String>>>,
+    next_connection: u64,
+}
+
+/** What we know about a single client connection */
+struct Connection {
+    id: u64,
+    tx: mpsc::UnboundedSender<String>,
+    appid: Option<String>,
+    side: Option<String>,
+    nameplate: Option<String>,
+    mailbox: Option<String>,
+}
+
+/** The messages a client may send */
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+enum ClientMessage {
+    /* Older versions of this crate sent it without the "s" */
+    #[serde(rename = "submit-permissions", alias = "submit-permission")]
+    SubmitPermission {},
+    Bind {
+        appid: String,
+        side: String,
+    },
+    List,
+    Allocate,
+    Claim {
+        nameplate: String,
+    },
+    Release {
+        #[serde(default)]
+        nameplate: Option<String>,
+    },
+    Open {
+        mailbox: String,
+    },
+    Add {
+        phase: String,
+        body: String,
+    },
+    Close {
+        #[serde(default)]
+        mailbox: Option<String>,
+        #[serde(default)]
+        mood: Option<String>,
+    },
+    Ping {
+        ping: Value,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+/** Sent back to the client as `error` message */
+type ClientError = String;
+
+fn send(tx: &mpsc::UnboundedSender<String>, mut message: Value) {
+    message["server_tx"] = json!(unix_time());
+    /* Fails only if the connection is gone, in which case nobody cares anymore */
+    let _ = tx.unbounded_send(message.to_string());
+}
+
+/**
+ * A rendezvous server
+ *
+ * Cheap to clone, all clones share the same state.
+ */
+#[derive(Clone, Debug)]
+pub struct MailboxServer {
+    config: Arc<Config>,
+    state: Arc<Mutex<State>>,
+}
+
+impl MailboxServer {
+    /**
+     * Create a new server
+     *
+     * Loads the state file if there is one.
+     */
+    pub fn new(config: Config) -> Result<Self, ServerError> {
+        let persistent = match &config.state_file {
+            Some(path) if path.exists() => {
+                let persistent: Persistent = serde_json::from_slice(&std::fs::read(path)?)?;
+                tracing::info!(
+                    "Loaded {} apps from {}",
+                    persistent.apps.len(),
+                    path.display()
+                );
+                persistent
+            },
+            _ => Persistent::default(),
+        };
+        let server = Self {
+            config: Arc::new(config),
+            state: Arc::new(Mutex::new(State {
+                persistent,
+                ..State::default()
+            })),
+        };
+        server.state.lock().unwrap().update_counts();
+        Ok(server)
+    }
+
+    /// The current usage statistics
+    pub fn stats(&self) -> Stats {
+        self.state.lock().unwrap().persistent.stats.clone()
+    }
+
+    /**
+     * Accept clients until the listener fails
+     *
+     * Each connection is handled on its own task. Expired mailboxes are cleaned up and the state
+     * file is written in the background.
+     */
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), ServerError> {
+        tracing::info!("Rendezvous server listening on {}", listener.local_addr()?);
+        let maintenance = async_std::task::spawn(self.clone().maintenance());
+        let result = loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(error) => break Err(error.into()),
+            };
+            let server = self.clone();
+            async_std::task::spawn(async move {
+                let peer = stream.peer_addr().ok();
+                if let Err(error) = server.handle_connection(stream).await {
+                    tracing::debug!("Connection with {:?} failed: {}", peer, error);
+                }
+            });
+        };
+        maintenance.cancel().await;
+        result
+    }
+
+    async fn maintenance(self) {
+        loop {
+            crate::util::sleep(self.config.maintenance_interval).await;
+            self.expire();
+            if let Err(error) = self.save() {
+                tracing::warn!("Failed to write the state file: {}", error);
+            }
+        }
+    }
+
+    /**
+     * Write the state file, if there is one configured
+     *
+     * This happens regularly while serving. Call it before shutting down to not lose anything.
+     */
+    pub fn save(&self) -> Result<(), ServerError> {
+        let Some(path) = &self.config.state_file else {
+            return Ok(());
+        };
+        let data = serde_json::to_vec(&self.state.lock().unwrap().persistent)?;
+        /* Write atomically, a crash while writing must not lose everything */
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, data)?;
+        std::fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    /** Delete everything that nobody used for longer than the expiry time */
+    fn expire(&self) {
+        let deadline = unix_time() - self.config.expiry.as_secs_f64();
+        let mut state = self.state.lock().unwrap();
+        let State {
+            persistent,
+            listeners,
+            ..
+        } = &mut *state;
+        for (appid, app) in &mut persistent.apps {
+            let before = app.mailboxes.len();
+            app.mailboxes.retain(|mailbox, entry| {
+                entry.updated >= deadline
+                    || listeners.contains_key(&(appid.clone(), mailbox.clone()))
+            });
+            persistent.stats.mailboxes_expired += (before - app.mailboxes.len()) as u64;
+            let mailboxes = &app.mailboxes;
+            app.nameplates.retain(|_, entry| {
+                entry.updated >= deadline || mailboxes.contains_key(&entry.mailbox)
+            });
+        }
+        persistent
+            .apps
+            .retain(|_, app| !app.nameplates.is_empty() || !app.mailboxes.is_empty());
+        state.update_counts();
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> Result<(), ws2::Error> {
+        let (mut sink, mut source) = async_tungstenite::accept_async(stream).await?.split();
+        let (tx, mut rx) = mpsc::unbounded();
+        let mut connection = {
+            let mut state = self.state.lock().unwrap();
+            state.next_connection += 1;
+            state.persistent.stats.connections += 1;
+            state.persistent.stats.connections_total += 1;
+            Connection {
+                id: state.next_connection,
+                tx,
+                appid: None,
+                side: None,
+                nameplate: None,
+                mailbox: None,
+            }
+        };
+        let mut welcome = json!({});
+        if let Some(motd) = &self.config.motd {
+            welcome["motd"] = json!(motd);
+        }
+        if let Some(version) = &self.config.advertise_version {
+            welcome["current_cli_version"] = json!(version);
+        }
+        if let Some(error) = &self.config.signal_error {
+            welcome["error"] = json!(error);
+        }
+        send(
+            &connection.tx,
+            json!({"type": "welcome", "welcome": welcome}),
+        );
+
+        let result = loop {
+            let next = futures::future::select(source.next(), rx.next()).await;
+            let result = match next {
+                Either::Left((Some(Ok(ws2::Message::Text(text))), _)) => {
+                    self.handle_message(&mut connection, &text);
+                    Ok(())
+                },
+                Either::Left((Some(Ok(ws2::Message::Ping(data))), _)) => {
+                    sink.send(ws2::Message::Pong(data)).await
+                },
+                Either::Left((Some(Ok(ws2::Message::Close(_))) | None, _)) => break Ok(()),
+                Either::Left((Some(Ok(_)), _)) => Ok(()),
+                Either::Left((Some(Err(error)), _)) => Err(error),
+                Either::Right((Some(message), _)) => sink.send(ws2::Message::Text(message)).await,
+                Either::Right((None, _)) => unreachable!("We hold a sender ourselves"),
+            };
+            if let Err(error) = result {
+                break Err(error);
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.persistent.stats.connections -= 1;
+        if let (Some(appid), Some(mailbox)) = (connection.appid, connection.mailbox) {
+            state.unsubscribe(&appid, &mailbox, connection.id);
+        }
+        result
+    }
+
+    fn handle_message(&self, connection: &mut Connection, text: &str) {
+        let orig: Value = match serde_json::from_str(text) {
+            Ok(orig) => orig,
+            Err(_) => {
+                send(
+                    &connection.tx,
+                    json!({"type": "error", "error": "Invalid JSON", "orig": text}),
+                );
+                return;
+            },
+        };
+        let message = serde_json::from_value::<ClientMessage>(orig.clone());
+        tracing::trace!("Received {:?}", message);
+        /* Everything gets acknowledged right away, before it gets processed, pings too */
+        send(
+            &connection.tx,
+            json!({"type": "ack", "id": orig.get("id").cloned()}),
+        );
+        let result = message
+            .map_err(|error| format!("Invalid message: {}", error))
+            .and_then(|message| self.process(connection, message, &orig));
+        if let Err(error) = result {
+            tracing::debug!("Client error: {}", error);
+            send(
+                &connection.tx,
+                json!({"type": "error", "error": error, "orig": orig}),
+            );
+        }
+    }
+
+    fn process(
+        &self,
+        connection: &mut Connection,
+        message: ClientMessage,
+        orig: &Value,
+    ) -> Result<(), ClientError> {
+        let tx = connection.tx.clone();
+        let (appid, side) = match (&message, &connection.appid, &connection.side) {
+            (ClientMessage::Ping { ping }, _, _) => {
+                send(&tx, json!({"type": "pong", "pong": ping}));
+                return Ok(());
+            },
+            (ClientMessage::SubmitPermission {}, _, _) => return Ok(()),
+            (ClientMessage::Unknown, _, _) => return Err("Unknown message type".into()),
+            (ClientMessage::Bind { appid, side }, None, None) => {
+                connection.appid = Some(appid.clone());
+                connection.side = Some(side.clone());
+                return Ok(());
+            },
+            (ClientMessage::Bind { .. }, _, _) => return Err("Already bound".into()),
+            (_, Some(appid), Some(side)) => (appid.clone(), side.clone()),
+            (_, _, _) => return Err("Must bind first".into()),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let now = unix_time();
+        match message {
+            ClientMessage::List => {
+                let nameplates: Vec<Value> = state
+                    .app(&appid)
+                    .nameplates
+                    .keys()
+                    .map(|id| json!({ "id": id }))
+                    .collect();
+                send(&tx, json!({"type": "nameplates", "nameplates": nameplates}));
+            },
+            ClientMessage::Allocate => {
+                if connection.nameplate.is_some() {
+                    return Err("You already allocated one nameplate, don't be greedy".into());
+                }
+                let nameplate = free_nameplate(&state.app(&appid).nameplates)
+                    .ok_or_else(|| ClientError::from("Unable to find a free nameplate"))?;
+                state.claim(&appid, &side, &nameplate, now)?;
+                connection.nameplate = Some(nameplate.clone());
+                send(&tx, json!({"type": "allocated", "nameplate": nameplate}));
+            },
+            ClientMessage::Claim { nameplate } => {
+                if connection
+                    .nameplate
+                    .as_ref()
+                    .is_some_and(|ours| ours != &nameplate)
+                {
+                    return Err("Only one claim per connection".into());
+                }
+                let mailbox = state.claim(&appid, &side, &nameplate, now)?;
+                connection.nameplate = Some(nameplate);
+                send(&tx, json!({"type": "claimed", "mailbox": mailbox}));
+            },
+            ClientMessage::Release { nameplate } => {
+                let nameplate = match (nameplate, &connection.nameplate) {
+                    (Some(theirs), Some(ours)) if &theirs != ours => {
+                        return Err("Release and claim must use the same nameplate".into())
+                    },
+                    (_, Some(ours)) => ours.clone(),
+                    (_, None) => return Err("Release without claim".into()),
+                };
+                let app = state.app(&appid);
+                if let Some(entry) = app.nameplates.get_mut(&nameplate) {
+                    entry.sides.retain(|s| s != &side);
+                    if entry.sides.is_empty() {
+                        app.nameplates.remove(&nameplate);
+                    }
+                }
+                connection.nameplate = None;
+                state.update_counts();
+                send(&tx, json!({"type": "released"}));
+            },
+            ClientMessage::Open { mailbox } => {
+                if connection.mailbox.is_some() {
+                    return Err("Only one open per connection".into());
+                }
+                let entry = state
+                    .app(&appid)
+                    .mailboxes
+                    .entry(mailbox.clone())
+                    .or_default();
+                if !entry.sides.contains(&side) {
+                    if entry.sides.len() >= 2 {
+                        return Err("crowded".into());
+                    }
+                    entry.sides.push(side.clone());
+                }
+                entry.updated = now;
+                for message in &entry.messages {
+                    send(&tx, message_json(message));
+                }
+                state
+                    .listeners
+                    .entry((appid.clone(), mailbox.clone()))
+                    .or_default()
+                    .insert(connection.id, tx.clone());
+                connection.mailbox = Some(mailbox);
+                state.update_counts();
+            },
+            ClientMessage::Add { phase, body } => {
+                let Some(mailbox) = &connection.mailbox else {
+                    return Err("Must open mailbox before adding".into());
+                };
+                let message = StoredMessage {
+                    side,
+                    phase,
+                    body,
+                    server_rx: now,
+                    id: orig.get("id").cloned(),
+                };
+                state.persistent.stats.messages_total += 1;
+                let entry = state
+                    .app(&appid)
+                    .mailboxes
+                    .entry(mailbox.clone())
+                    .or_default();
+                entry.updated = now;
+                entry.messages.push(message.clone());
+                for listener in state
+                    .listeners
+                    .get(&(appid.clone(), mailbox.clone()))
+                    .into_iter()
+                    .flat_map(HashMap::values)
+                {
+                    send(listener, message_json(&message));
+                }
+            },
+            ClientMessage::Close { mailbox, mood } => {
+                let mailbox = match (mailbox, &connection.mailbox) {
+                    (Some(theirs), Some(ours)) if &theirs != ours => {
+                        return Err("Open and close must use the same mailbox".into())
+                    },
+                    (_, Some(ours)) => ours.clone(),
+                    (Some(theirs), None) => theirs,
+                    (None, None) => return Err("Close without mailbox".into()),
+                };
+                state.unsubscribe(&appid, &mailbox, connection.id);
+                let app = state.app(&appid);
+                let mut deleted = false;
+                if let Some(entry) = app.mailboxes.get_mut(&mailbox) {
+                    entry.sides.retain(|s| s != &side);
+                    if entry.sides.is_empty() {
+                        app.mailboxes.remove(&mailbox);
+                        app.nameplates.retain(|_, entry| entry.mailbox != mailbox);
+                        deleted = true;
+                    }
+                }
+                if deleted {
+                    *state
+                        .persistent
+                        .stats
+                        .moods
+                        .entry(mood.unwrap_or_else(|| "unknown".into()))
+                        .or_default() += 1;
+                }
+                connection.mailbox = None;
+                state.update_counts();
+                send(&tx, json!({"type": "closed"}));
+            },
+            ClientMessage::Ping { .. }
+            | ClientMessage::SubmitPermission {}
+            | ClientMessage::Bind { .. }
+            | ClientMessage::Unknown => unreachable!("Handled above"),
+        }
+        Ok(())
+    }
+}
+
+fn message_json(message: &StoredMessage) -> Value {
+    json!({
+        "type": "message",
+        "side": message.side,
+        "phase": message.phase,
+        "body": message.body,
+        "server_rx": message.server_rx,
+        "id": message.id,
+    })
+}
+
+impl State {
+    fn app(&mut self, appid: &str) -> &mut App {
+        self.persistent.apps.entry(appid.into()).or_default()
+    }
+
+    /** Claim a nameplate for a side, creating it if needed. Returns the mailbox */
+    fn claim(
+        &mut self,
+        appid: &str,
+        side: &str,
+        nameplate: &str,
+        now: f64,
+    ) -> Result<String, ClientError> {
+        let app = self.app(appid);
+        let entry = app
+            .nameplates
+            .entry(nameplate.into())
+            .or_insert_with(|| NameplateEntry {
+                mailbox: random_mailbox_id(),
+                ..NameplateEntry::default()
+            });
+        if !entry.sides.iter().any(|s| s == side) {
+            if entry.sides.len() >= 2 {
+                return Err("crowded".into());
+            }
+            entry.sides.push(side.into());
+        }
+        entry.updated = now;
+        let mailbox = entry.mailbox.clone();
+        let mailbox_entry = app.mailboxes.entry(mailbox.clone()).or_default();
+        mailbox_entry.updated = now;
+        self.update_counts();
+        Ok(mailbox)
+    }
+
+    fn unsubscribe(&mut self, appid: &str, mailbox: &str, connection: u64) {
+        let key = (appid.to_owned(), mailbox.to_owned());
+        if let Some(listeners) = self.listeners.get_mut(&key) {
+            listeners.remove(&connection);
+            if listeners.is_empty() {
+                self.listeners.remove(&key);
+            }
+        }
+    }
+
+    fn update_counts(&mut self) {
+        let stats = &mut self.persistent.stats;
+        stats.nameplates = self
+            .persistent
+            .apps
+            .values()
+            .map(|app| app.nameplates.len())
+            .sum();
+        stats.mailboxes = self
+            .persistent
+            .apps
+            .values()
+            .map(|app| app.mailboxes.len())
+            .sum();
+    }
+}
+
+/**
+ * Pick a random nameplate among the free ones with the fewest digits, like the Python server
+ *
+ * Short codes are easier to type, and picking them randomly makes them harder to guess. Once all
+ * nameplates up to 999 are taken, try random ones up to a million for a while.
+ */
+fn free_nameplate<T>(claimed: &BTreeMap<String, T>) -> Option<String> {
+    use rand::{seq::IteratorRandom, Rng};
+
+    let mut rng = crate::util::rng();
+    for digits in 1..=3 {
+        let free = (10u32.pow(digits - 1)..10u32.pow(digits))
+            .map(|id| id.to_string())
+            .filter(|id| !claimed.contains_key(id))
+            .choose(&mut rng);
+        if free.is_some() {
+            return free;
+        }
+    }
+    (0..1000)
+        .map(|_| rng.gen_range(1000..1_000_000u32).to_string())
+        .find(|id| !claimed.contains_key(id))
+}
+
+fn random_mailbox_id() -> String {
+    use rand::RngCore;
+    let mut bytes = [0; 8];
+    crate::util::rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{rendezvous::RendezvousError, AppConfig, AppID, Wormhole, WormholeError};
+    use test_log::test;
+
+    async fn start(config: Config) -> eyre::Result<(MailboxServer, String)> {
+        let server = MailboxServer::new(config)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}/v1", listener.local_addr()?);
+        let serving = server.clone();
+        async_std::task::spawn(async move { serving.serve(listener).await });
+        Ok((server, url))
+    }
+
+    #[test(async_std::test)]
+    async fn test_rendezvous_server() -> eyre::Result<()> {
+        let (server, url) = start(Config {
+            motd: Some("Hello".into()),
+            ..Config::default()
+        })
+        .await?;
+        let config = AppConfig {
+            id: AppID::new("piegames.de/wormhole/test/server"),
+            rendezvous_url: url.into(),
+            app_version: (),
+        };
+
+        let pending = Wormhole::builder(config.clone()).allocate_code().await?;
+        assert_eq!(pending.welcome(), Some("Hello"));
+        /* The only free nameplates are the single digit ones */
+        let nameplate = pending.code().nameplate().to_string().parse::<u32>()?;
+        assert!((1..=9).contains(&nameplate), "{nameplate}");
+        let code = pending.code().clone();
+        let (mut sender, mut receiver) = futures::try_join!(
+            pending.wait_for_peer(),
+            Wormhole::builder(config).connect_with_code(code),
+        )?;
+        assert_ne!(sender.side(), receiver.side());
+        assert_eq!(server.stats().nameplates, 0);
+        assert_eq!(server.stats().mailboxes, 1);
+
+        sender.send(b"hello".to_vec()).await?;
+        assert_eq!(receiver.receive().await?, b"hello");
+        futures::try_join!(sender.close(), receiver.close())?;
+
+        let stats = server.stats();
+        assert_eq!(stats.mailboxes, 0);
+        assert_eq!(stats.connections_total, 2);
+        assert_eq!(stats.moods.get("happy"), Some(&1));
+        Ok(())
+    }
+
+    /** Pings get acknowledged and answered, even before binding */
+    #[test(async_std::test)]
+    async fn test_rendezvous_server_ping() -> eyre::Result<()> {
+        let (_server, url) = start(Config::default()).await?;
+        let (mut socket, _) = async_tungstenite::async_std::connect_async(url).await?;
+        async fn receive(
+            socket: &mut (impl futures::Stream<Item = Result<ws2::Message, ws2::Error>> + Unpin),
+        ) -> eyre::Result<Value> {
+            match socket.next().await {
+                Some(Ok(ws2::Message::Text(text))) => Ok(serde_json::from_str(&text)?),
+                other => eyre::bail!("Expected a text message, got {:?}", other),
+            }
+        }
+        assert_eq!(receive(&mut socket).await?["type"], "welcome");
+
+        socket
+            .send(ws2::Message::Text(
+                json!({"type": "ping", "ping": 7, "id": "ping-1"}).to_string(),
+            ))
+            .await?;
+        let ack = receive(&mut socket).await?;
+        assert_eq!(ack["type"], "ack");
+        assert_eq!(ack["id"], "ping-1");
+        let pong = receive(&mut socket).await?;
+        assert_eq!(pong["type"], "pong");
+        assert_eq!(pong["pong"], 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_free_nameplate() {
+        let mut claimed = BTreeMap::new();
+        for id in 1..=8 {
+            claimed.insert(id.to_string(), ());
+        }
+        assert_eq!(free_nameplate(&claimed).as_deref(), Some("9"));
+        claimed.insert("9".into(), ());
+        let nameplate = free_nameplate(&claimed).unwrap().parse::<u32>().unwrap();
+        assert!((10..=99).contains(&nameplate), "{nameplate}");
+        for id in 10..=999 {
+            claimed.insert(id.to_string(), ());
+        }
+        let nameplate = free_nameplate(&claimed).unwrap().parse::<u32>().unwrap();
+        assert!((1000..1_000_000).contains(&nameplate), "{nameplate}");
+    }
+
+    #[test(async_std::test)]
+    async fn test_rendezvous_server_welcome() -> eyre::Result<()> {
+        let config = |url: String| AppConfig {
+            id: AppID::new("piegames.de/wormhole/test/server"),
+            rendezvous_url: url.into(),
+            app_version: (),
+        };
+
+        let (_server, url) = start(Config {
+            motd: Some("Hello".into()),
+            advertise_version: Some("0.17.0".into()),
+            ..Config::default()
+        })
+        .await?;
+        let server = Wormhole::builder(config(url)).connect_to_server().await?;
+        assert_eq!(server.server_welcome().motd.as_deref(), Some("Hello"));
+        assert_eq!(
+            server.server_welcome().current_cli_version.as_deref(),
+            Some("0.17.0")
+        );
+        server.cancel().await?;
+
+        let (_server, url) = start(Config {
+            signal_error: Some("This server is shutting down".into()),
+            ..Config::default()
+        })
+        .await?;
+        match Wormhole::builder(config(url)).connect_to_server().await {
+            Err(WormholeError::ServerError(RendezvousError::Welcome(error))) => {
+                assert_eq!(&*error, "This server is shutting down")
+            },
+            other => panic!("Expected a welcome error, got {:?}", other.map(|_| ())),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rendezvous_server_persistence() -> eyre::Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("wormhole-state-{}.json", random_mailbox_id()));
+        let config = Config {
+            state_file: Some(path.clone()),
+            expiry: Duration::from_secs(60),
+            ..Config::default()
+        };
+
+        let server = MailboxServer::new(config.clone())?;
+        let mailbox = server
+            .state
+            .lock()
+            .unwrap()
+            .claim("appid", "side", "4", unix_time())
+            .unwrap();
+        server.save()?;
+
+        let server = MailboxServer::new(config)?;
+        std::fs::remove_file(&path)?;
+        {
+            let mut state = server.state.lock().unwrap();
+            assert_eq!(state.app("appid").nameplates["4"].mailbox, mailbox);
+            /* Pretend nothing happened in a while */
+            state
+                .app("appid")
+                .mailboxes
+                .get_mut(&mailbox)
+                .unwrap()
+                .updated = 0.0;
+            state.app("appid").nameplates.get_mut("4").unwrap().updated = 0.0;
+        }
+        server.expire();
+        let stats = server.stats();
+        assert_eq!((stats.nameplates, stats.mailboxes), (0, 0));
+        assert_eq!(stats.mailboxes_expired, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+//! Client-to-Client protocol to set up SSH access, like `wormhole ssh invite` and `wormhole ssh accept`
+//!
+//! The side that wants to grant access ("invite") allocates a code and waits for a public key, which it
+//! appends to its `authorized_keys`. The side that wants to log in ("accept") enters the code and sends one of
+//! its public keys. This way, nobody has to copy keys around by hand.
+//!
+//! On the wire, the key is a text message of the file transfer protocol, sent under its own [`APPID`]. This
+//! is compatible with the Python implementation. As the wormhole is authenticated by the code, only the
+//! person the code was told to can get their key added.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), magic_wormhole::ssh::SshError> {
+//! use magic_wormhole::{ssh, MailboxConnection, Wormhole};
+//!
+//! let mailbox = MailboxConnection::create(ssh::APP_CONFIG, 2).await?;
+//! println!("wormhole ssh accept {}", mailbox.code());
+//! let wormhole = Wormhole::connect(mailbox).await?;
+//! if let Some(key) = ssh::invite(wormhole, futures::future::pending()).await? {
+//!     let ssh_dir = std::path::Path::new("/home/alice/.ssh");
+//!     ssh::append_authorized_key(ssh_dir, &key)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{transfer::TransferError, AppID, Wormhole, WormholeError};
+use base64::Engine;
+use futures::Future;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const APPID_RAW: &str = crate::protocol::v1::SSH_APPID;
+
+/// The App ID associated with this protocol.
+pub const APPID: AppID = AppID(Cow::Borrowed(APPID_RAW));
+
+/// An [`crate::AppConfig`] with sane defaults for this protocol.
+///
+/// You **must not** change `id` and `rendezvous_url` to be interoperable.
+pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::<AppVersion> {
+    id: AppID(Cow::Borrowed(APPID_RAW)),
+    rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
+    app_version: AppVersion {},
+};
+
+/**
+ * The application specific version information for this protocol.
+ *
+ * There is nothing to negotiate, so this is always empty.
+ */
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AppVersion {}
+
+/// The file in the SSH directory that lists the keys allowed to log in
+pub const AUTHORIZED_KEYS: &str = "authorized_keys";
+
+/* The key types OpenSSH accepts in `authorized_keys`, without certificates */
+const KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ssh-dss",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+/// An error occurred while exchanging or installing an SSH key
+pub enum SshError {
+    /// The public key is malformed or of an unknown type
+    #[error("Invalid SSH public key: {}", _0)]
+    InvalidKey(Box<str>),
+    /// Reading the public keys or writing `authorized_keys` failed
+    #[error("IO error")]
+    IO(
+        #[from]
+        #[source]
+        io::Error,
+    ),
+    /// Wormhole connection error
+    #[error("Wormhole connection error")]
+    Wormhole(
+        #[from]
+        #[source]
+        WormholeError,
+    ),
+    /// Sending or receiving the key failed
+    #[error("Error while exchanging the key")]
+    Transfer(
+        #[from]
+        #[source]
+        TransferError,
+    ),
+}
+
+/**
+ * An SSH public key, in the format of `id_*.pub` files and `authorized_keys` lines
+ *
+ * Parsing only accepts a single line of the form `<type> <base64 key> [comment]`. In particular, the
+ * options `authorized_keys` supports in front of the type (like `command="…"`) are rejected, so that
+ * the other side can't restrict or extend what it is allowed to do.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicKey {
+    kind: String,
+    key: String,
+    comment: Option<String>,
+}
+
+impl PublicKey {
+    /// The key type, for example `ssh-ed25519`
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// The comment after the key, usually `user@host`
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /* Two keys are the same if type and key match, the comment doesn't matter */
+    fn same_key(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.key == other.key
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = SshError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        if line.contains(['\n', '\r', '\0']) {
+            return Err(SshError::InvalidKey("more than one line".into()));
+        }
+        let mut parts = line.splitn(3, [' ', '\t']);
+        let kind = parts.next().unwrap_or_default();
+        if !KEY_TYPES.contains(&kind) {
+            return Err(SshError::InvalidKey(
+                format!("unsupported key type '{kind}'").into(),
+            ));
+        }
+        let key = parts.next().unwrap_or_default();
+        let blob = base64::engine::general_purpose::STANDARD
+            .decode(key)
+            .map_err(|_| SshError::InvalidKey("the key is not valid base64".into()))?;
+        /* The blob starts with the key type as length prefixed string */
+        let name = blob
+            .get(..4)
+            .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+            .and_then(|len| blob.get(4..)?.get(..len));
+        if name != Some(kind.as_bytes()) {
+            return Err(SshError::InvalidKey(
+                format!("the key does not match its type '{kind}'").into(),
+            ));
+        }
+        let comment = parts
+            .next()
+            .map(str::trim)
+            .filter(|comment| !comment.is_empty());
+        if comment.is_some_and(|comment| comment.contains(char::is_control)) {
+            return Err(SshError::InvalidKey(
+                "the comment contains control characters".into(),
+            ));
+        }
+        Ok(PublicKey {
+            kind: kind.into(),
+            key: key.into(),
+            comment: comment.map(Into::into),
+        })
+    }
+}
+
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind, self.key)?;
+        if let Some(comment) = &self.comment {
+            write!(f, " {comment}")?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Find the public keys in an SSH directory, usually `~/.ssh`
+ *
+ * These are all `*.pub` files that contain a valid key, sorted by path. Other files are skipped. If there is
+ * more than one, let the user choose which one to send.
+ */
+pub fn find_public_keys(ssh_dir: &Path) -> Result<Vec<(PathBuf, PublicKey)>, SshError> {
+    let mut keys = Vec::new();
+    for entry in fs::read_dir(ssh_dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "pub")
+            || !path.is_file()
+        {
+            continue;
+        }
+        match fs::read_to_string(&path)?.parse() {
+            Ok(key) => keys.push((path, key)),
+            Err(err) => tracing::debug!("Skipping {}: {}", path.display(), err),
+        }
+    }
+    keys.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(keys)
+}
+
+/**
+ * Append a key to `authorized_keys` in `ssh_dir`, usually `~/.ssh`
+ *
+ * The directory and the file are created if they don't exist, readable only by the owner. Returns `false`
+ * without changing anything if the key is already authorized.
+ */
+pub fn append_authorized_key(ssh_dir: &Path, key: &PublicKey) -> Result<bool, SshError> {
+    let path = ssh_dir.join(AUTHORIZED_KEYS);
+    let existing = match fs::read_to_string(&path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    if existing
+        .lines()
+        .filter_map(|line| line.parse::<PublicKey>().ok())
+        .any(|authorized| authorized.same_key(key))
+    {
+        return Ok(false);
+    }
+
+    let mut dir = fs::DirBuilder::new();
+    dir.recursive(true);
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+        dir.mode(0o700);
+        options.mode(0o600);
+    }
+    dir.create(ssh_dir)?;
+    let mut file = options.open(&path)?;
+    /* Don't glue the key to the last line if it lacks its newline */
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{key}")?;
+    Ok(true)
+}
+
+/**
+ * Receive a public key from the other side, like `wormhole ssh invite`
+ *
+ * The key is validated, but not installed. Show it to the user and pass it to [`append_authorized_key`].
+ *
+ * Returns `None` if the task got cancelled.
+ */
+pub async fn invite(
+    wormhole: Wormhole,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<PublicKey>, SshError> {
+    match crate::transfer::receive_text(wormhole, cancel).await? {
+        Some(key) => Ok(Some(key.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/**
+ * Send a public key to the other side, like `wormhole ssh accept`
+ *
+ * Use [`find_public_keys`] to get the keys of the user.
+ */
+pub async fn accept(
+    wormhole: Wormhole,
+    key: &PublicKey,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), SshError> {
+    crate::transfer::send_text(wormhole, key.to_string(), cancel).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /* A real key, generated with `ssh-keygen -t ed25519` */
+    const KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIIbUbK7DMXGWn4U1QJz4yGTuwb7fc9sD7NqMEEYdU2Lu";
+
+    fn key(comment: &str) -> PublicKey {
+        format!("{KEY} {comment}").parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_public_key() {
+        let key = key("alice@laptop");
+        assert_eq!(key.kind(), "ssh-ed25519");
+        assert_eq!(key.comment(), Some("alice@laptop"));
+        assert_eq!(key.to_string(), format!("{KEY} alice@laptop"));
+        assert_eq!(KEY.parse::<PublicKey>().unwrap().comment(), None);
+
+        for invalid in [
+            "",
+            "ssh-ed25519",
+            "ssh-ed25519 not-base64!",
+            /* The type in the blob doesn't match */
+            &KEY.replace("ssh-ed25519", "ssh-rsa"),
+            &format!("command=\"rm -rf /\" {KEY}"),
+            &format!("{KEY} alice\n{KEY} mallory"),
+            &format!("{KEY} alice\u{1b}[2J"),
+        ] {
+            assert!(invalid.parse::<PublicKey>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_append_authorized_key() -> Result<(), SshError> {
+        let ssh_dir =
+            std::env::temp_dir().join(format!("wormhole-ssh-test-{}", std::process::id()));
+        assert!(append_authorized_key(&ssh_dir, &key("alice"))?);
+        /* Same key with another comment */
+        assert!(!append_authorized_key(&ssh_dir, &key("bob"))?);
+
+        let path = ssh_dir.join(AUTHORIZED_KEYS);
+        assert_eq!(fs::read_to_string(&path)?, format!("{KEY} alice\n"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&ssh_dir)?.permissions().mode() & 0o777, 0o700);
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::write(ssh_dir.join("id_ed25519.pub"), format!("{KEY} alice\n"))?;
+        fs::write(ssh_dir.join("id_ed25519"), "private")?;
+        let keys = find_public_keys(&ssh_dir)?;
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].1, key("alice"));
+
+        fs::remove_dir_all(&ssh_dir)?;
+        Ok(())
+    }
+}
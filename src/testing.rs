@@ -0,0 +1,197 @@
+//! Run the whole protocol locally, for tests
+//!
+//! A [`TestServer`] runs a rendezvous server and a transit relay in the background, listening on
+//! random ports on the loopback interface. Both speak the real wire protocol, so clients connect
+//! to them like to the public servers. Integration tests can then do full transfers in
+//! `cargo test`, without network access or a server written in Python.
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::{testing::TestServer, transfer, Wormhole};
+//!
+//! let server = TestServer::start().await?;
+//! let config = server.app_config(transfer::APP_CONFIG);
+//! let pending = Wormhole::builder(config.clone()).allocate_code().await?;
+//! let code = pending.code().clone();
+//! let (sender, receiver) = futures::try_join!(
+//!     pending.wait_for_peer(),
+//!     Wormhole::builder(config).connect_with_code(code),
+//! )?;
+//! # Ok(()) })}
+//! ```
+//!
+//! The servers run on the executor of `async-std`, which starts on its own. So a [`TestServer`]
+//! works the same from `#[async_std::test]`, `#[tokio::test]` or a plain `block_on`.
+//!
+//! To get the same code and side on every run, seed the connections with
+//! [`WormholeBuilder::rng`](crate::WormholeBuilder::rng) and
+//! [`WormholeBuilder::clock`](crate::WormholeBuilder::clock).
+//!
+//! Only available with the `testing` feature.
+
+pub mod faults;
+
+use crate::{
+    server::{relay, rendezvous},
+    AppConfig,
+};
+use async_std::net::TcpListener;
+use futures::future::{AbortHandle, Abortable};
+use std::net::SocketAddr;
+
+/**
+ * A local rendezvous server and transit relay
+ *
+ * Both get shut down when this is dropped.
+ */
+#[derive(Debug)]
+pub struct TestServer {
+    mailbox_server: rendezvous::MailboxServer,
+    relay_server: relay::RelayServer,
+    rendezvous_addr: SocketAddr,
+    relay_addr: SocketAddr,
+    abort: Vec<AbortHandle>,
+    faults: Vec<faults::FaultyProxy>,
+}
+
+impl TestServer {
+    /// Start both servers with their default configuration
+    pub async fn start() -> std::io::Result<Self> {
+        Self::start_with(rendezvous::Config::default(), relay::Config::default()).await
+    }
+
+    /**
+     * Start both servers with a custom configuration
+     *
+     * The state file of the rendezvous configuration is ignored, nothing is written to disk.
+     */
+    pub async fn start_with(
+        rendezvous_config: rendezvous::Config,
+        relay_config: relay::Config,
+    ) -> std::io::Result<Self> {
+        let mailbox_server = rendezvous::MailboxServer::new(rendezvous::Config {
+            state_file: None,
+            ..rendezvous_config
+        })
+        .map_err(std::io::Error::other)?;
+        let relay_server = relay::RelayServer::new(relay_config);
+
+        let rendezvous_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let relay_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let rendezvous_addr = rendezvous_listener.local_addr()?;
+        let relay_addr = relay_listener.local_addr()?;
+
+        let (rendezvous_abort, registration) = AbortHandle::new_pair();
+        let server = mailbox_server.clone();
+        async_std::task::spawn(Abortable::new(
+            async move {
+                if let Err(error) = server.serve(rendezvous_listener).await {
+                    tracing::warn!("Test rendezvous server failed: {}", error);
+                }
+            },
+            registration,
+        ));
+        let (relay_abort, registration) = AbortHandle::new_pair();
+        let server = relay_server.clone();
+        async_std::task::spawn(Abortable::new(
+            async move {
+                if let Err(error) = server.serve(relay_listener).await {
+                    tracing::warn!("Test relay server failed: {}", error);
+                }
+            },
+            registration,
+        ));
+
+        Ok(Self {
+            mailbox_server,
+            relay_server,
+            rendezvous_addr,
+            relay_addr,
+            abort: vec![rendezvous_abort, relay_abort],
+            faults: Vec::new(),
+        })
+    }
+
+    /**
+     * Start both servers behind proxies that inject the faults of a scenario
+     *
+     * The URLs point to the proxies. Both servers get their own proxy, with different seeds
+     * derived from the one of the scenario, and a shared [`VirtualTimer`](faults::VirtualTimer).
+     * See the [`faults`] module.
+     */
+    pub async fn start_faulty(scenario: faults::Scenario) -> std::io::Result<Self> {
+        let mut server = Self::start().await?;
+        let timer = faults::VirtualTimer::default();
+        let rendezvous_proxy =
+            faults::FaultyProxy::start(server.rendezvous_addr, scenario.clone(), timer.clone())
+                .await?;
+        let relay_proxy = faults::FaultyProxy::start(
+            server.relay_addr,
+            faults::Scenario {
+                seed: scenario.seed.wrapping_add(1),
+                ..scenario
+            },
+            timer,
+        )
+        .await?;
+        server.rendezvous_addr = rendezvous_proxy.addr();
+        server.relay_addr = relay_proxy.addr();
+        server.faults = vec![rendezvous_proxy, relay_proxy];
+        Ok(server)
+    }
+
+    /// The URL of the rendezvous server, like `ws://127.0.0.1:12345/v1`
+    pub fn rendezvous_url(&self) -> String {
+        format!("ws://{}/v1", self.rendezvous_addr)
+    }
+
+    /// The address of the rendezvous server, to put a proxy in front of it
+    #[cfg(test)]
+    pub(crate) fn rendezvous_addr(&self) -> SocketAddr {
+        self.rendezvous_addr
+    }
+
+    /// The URL of the transit relay, like `tcp://127.0.0.1:12345`
+    pub fn relay_url(&self) -> String {
+        format!("tcp://{}", self.relay_addr)
+    }
+
+    /// Point an [`AppConfig`] at this rendezvous server
+    pub fn app_config<V>(&self, config: AppConfig<V>) -> AppConfig<V> {
+        config.rendezvous_url(self.rendezvous_url().into())
+    }
+
+    /// Relay hints pointing at this transit relay, to be used instead of the default ones
+    #[cfg(feature = "transit")]
+    pub fn relay_hints(&self) -> Vec<crate::transit::RelayHint> {
+        let url = self.relay_url().parse().expect("Relay URL must be valid");
+        vec![crate::transit::RelayHint::from_urls(None, [url]).expect("Relay URL must be valid")]
+    }
+
+    /// The rendezvous server, to inspect its statistics
+    pub fn mailbox_server(&self) -> &rendezvous::MailboxServer {
+        &self.mailbox_server
+    }
+
+    /// The transit relay, to inspect its statistics
+    pub fn relay_server(&self) -> &relay::RelayServer {
+        &self.relay_server
+    }
+
+    /// The faults injected so far, when started with [`start_faulty`](Self::start_faulty)
+    pub fn fault_stats(&self) -> faults::Stats {
+        self.faults
+            .iter()
+            .fold(faults::Stats::default(), |stats, proxy| {
+                stats + proxy.stats()
+            })
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        for abort in &self.abort {
+            abort.abort();
+        }
+    }
+}
@@ -0,0 +1,536 @@
+//! Inject network faults between the clients and a [`TestServer`](super::TestServer)
+//!
+//! A [`FaultyProxy`] forwards TCP connections to a server and disturbs them according to a
+//! [`Scenario`]: it delays the data, refuses connections and cuts them in the middle of a
+//! transfer. All decisions are drawn from a random generator seeded by the scenario, so a failing
+//! seed can be replayed. The delays run on a [`VirtualTimer`], so they cost no real time.
+//!
+//! TCP delivers the data of one connection in order, so the jitter only reorders messages
+//! relative to other connections, like the ones of the other side. Dropped packets are modelled
+//! as cut connections, since TCP would otherwise retransmit them.
+//!
+//! Use [`TestServer::start_faulty`](super::TestServer::start_faulty) to put proxies in front of
+//! both servers:
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::testing::{faults::Scenario, TestServer};
+//!
+//! for seed in 0..10 {
+//!     let server = TestServer::start_faulty(Scenario::random(seed)).await?;
+//!     // Run a transfer, it must either succeed or fail with a network error
+//! }
+//! # Ok(()) })}
+//! ```
+
+use async_std::net::{Shutdown, TcpListener, TcpStream};
+use futures::{
+    future::{AbortHandle, Abortable},
+    AsyncReadExt, AsyncWriteExt,
+};
+use rand::{Rng, SeedableRng};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+/// Which faults to inject, and how often
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Scenario {
+    /// Seed of all random decisions
+    pub seed: u64,
+    /// Delay of every chunk of data, in both directions
+    pub latency: Duration,
+    /// Additional random delay of every chunk, up to this much
+    pub jitter: Duration,
+    /// Probability to close a new connection right away
+    pub refuse_probability: f64,
+    /// Probability to cut a connection after some bytes
+    pub disconnect_probability: f64,
+    /// Connections are cut after a random number of bytes below this, counting both directions
+    pub disconnect_within: u64,
+}
+
+impl Default for Scenario {
+    /** No faults at all */
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            refuse_probability: 0.0,
+            disconnect_probability: 0.0,
+            disconnect_within: 64 * 1024,
+        }
+    }
+}
+
+impl Scenario {
+    /// A mix of faults, derived from the seed
+    pub fn random(seed: u64) -> Self {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        Self {
+            seed,
+            latency: Duration::from_millis(rng.gen_range(0..20)),
+            jitter: Duration::from_millis(rng.gen_range(0..20)),
+            refuse_probability: rng.gen_range(0.0..0.2),
+            disconnect_probability: rng.gen_range(0.0..0.3),
+            ..Self::default()
+        }
+    }
+
+    /// Only delays, the protocol must always complete
+    pub fn delays(seed: u64, latency: Duration, jitter: Duration) -> Self {
+        Self {
+            seed,
+            latency,
+            jitter,
+            ..Self::default()
+        }
+    }
+}
+
+/// What a [`FaultyProxy`] did so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// Connections accepted, including refused ones
+    pub connections: u64,
+    /// Connections closed right away
+    pub refused: u64,
+    /// Connections cut after some bytes
+    pub disconnected: u64,
+}
+
+impl Stats {
+    /// Whether any connection was refused or cut
+    pub fn any_faults(&self) -> bool {
+        self.refused > 0 || self.disconnected > 0
+    }
+}
+
+impl std::ops::Add for Stats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            connections: self.connections + other.connections,
+            refused: self.refused + other.refused,
+            disconnected: self.disconnected + other.disconnected,
+        }
+    }
+}
+
+/**
+ * A mock timer for the delays of the proxies
+ *
+ * Sleeping on it doesn't wait in real time. Pending sleeps complete one after the other in the
+ * order of their deadlines, advancing the virtual time to each deadline. So the delays still
+ * reorder the data of different connections like on a real network, but take no time.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct VirtualTimer(Arc<Mutex<TimerState>>);
+
+#[derive(Debug, Default)]
+struct TimerState {
+    now: Duration,
+    /* The pending sleeps by deadline, numbered to keep the ones with the same deadline apart */
+    sleeps: BTreeMap<(Duration, u64), Option<Waker>>,
+    next_id: u64,
+}
+
+impl TimerState {
+    /* Let the earliest sleep check whether it is due */
+    fn wake_first(&mut self) {
+        if let Some(waker) = self.sleeps.values_mut().next().and_then(Option::take) {
+            waker.wake();
+        }
+    }
+}
+
+impl VirtualTimer {
+    /// The virtual time elapsed since the timer got created
+    pub fn now(&self) -> Duration {
+        self.0.lock().unwrap().now
+    }
+
+    /// Complete after all sleeps with an earlier deadline, advancing the virtual time
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        let mut state = self.0.lock().unwrap();
+        let key = (state.now + duration, state.next_id);
+        state.next_id += 1;
+        state.sleeps.insert(key, None);
+        Sleep {
+            timer: self.clone(),
+            key,
+            polled: false,
+            done: false,
+        }
+    }
+}
+
+/// Returned by [`VirtualTimer::sleep`]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Sleep {
+    timer: VirtualTimer,
+    key: (Duration, u64),
+    /* Whether the other tasks had a chance to start their sleeps yet */
+    polled: bool,
+    done: bool,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(());
+        }
+        let mut state = this.timer.0.lock().unwrap();
+        let is_first = state.sleeps.keys().next() == Some(&this.key);
+        if is_first && this.polled {
+            state.sleeps.remove(&this.key);
+            state.now = state.now.max(this.key.0);
+            state.wake_first();
+            this.done = true;
+            return Poll::Ready(());
+        }
+        state.sleeps.insert(this.key, Some(cx.waker().clone()));
+        if is_first {
+            /* Yield once, so that sleeps with an earlier deadline can still come in */
+            this.polled = true;
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if !self.done {
+            let mut state = self.timer.0.lock().unwrap();
+            state.sleeps.remove(&self.key);
+            state.wake_first();
+        }
+    }
+}
+
+/**
+ * Forwards TCP connections to a server, injecting the faults of a [`Scenario`]
+ *
+ * Stops accepting connections when dropped.
+ */
+#[derive(Debug)]
+pub struct FaultyProxy {
+    addr: SocketAddr,
+    stats: Arc<Mutex<Stats>>,
+    abort: AbortHandle,
+}
+
+impl FaultyProxy {
+    /**
+     * Listen on a random port on the loopback interface, forwarding to `target`
+     *
+     * The delays run on `timer`. Share it between proxies to order their delays against each other.
+     */
+    pub async fn start(
+        target: SocketAddr,
+        scenario: Scenario,
+        timer: VirtualTimer,
+    ) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let stats = Arc::new(Mutex::new(Stats::default()));
+
+        let (abort, registration) = AbortHandle::new_pair();
+        let accept_stats = stats.clone();
+        async_std::task::spawn(Abortable::new(
+            async move {
+                let mut rng = rand::rngs::StdRng::seed_from_u64(scenario.seed);
+                loop {
+                    let client = match listener.accept().await {
+                        Ok((client, _)) => client,
+                        Err(error) => {
+                            tracing::warn!("Faulty proxy failed to accept: {}", error);
+                            continue;
+                        },
+                    };
+                    /* Each connection gets its own generator, so that concurrent connections
+                     * don't change each other's decisions */
+                    let connection_rng = rand::rngs::StdRng::seed_from_u64(rng.gen());
+                    let stats = accept_stats.clone();
+                    let scenario = scenario.clone();
+                    let timer = timer.clone();
+                    async_std::task::spawn(async move {
+                        if let Err(error) =
+                            forward(client, target, scenario, timer, connection_rng, stats).await
+                        {
+                            tracing::debug!("Faulty proxy connection failed: {}", error);
+                        }
+                    });
+                }
+            },
+            registration,
+        ));
+
+        Ok(Self { addr, stats, abort })
+    }
+
+    /// The address clients should connect to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// What the proxy did so far
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+impl Drop for FaultyProxy {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
+}
+
+async fn forward(
+    client: TcpStream,
+    target: SocketAddr,
+    scenario: Scenario,
+    timer: VirtualTimer,
+    mut rng: rand::rngs::StdRng,
+    stats: Arc<Mutex<Stats>>,
+) -> std::io::Result<()> {
+    stats.lock().unwrap().connections += 1;
+    if rng.gen_bool(scenario.refuse_probability) {
+        stats.lock().unwrap().refused += 1;
+        return client.shutdown(Shutdown::Both);
+    }
+    let disconnect_at = rng
+        .gen_bool(scenario.disconnect_probability)
+        .then(|| rng.gen_range(0..scenario.disconnect_within.max(1)));
+
+    let server = TcpStream::connect(target).await?;
+    /* The bytes forwarded in both directions, and whether the connection got cut */
+    let state = Arc::new(Mutex::new((0u64, false)));
+    let pump = |mut from: TcpStream, mut to: TcpStream, mut rng: rand::rngs::StdRng| {
+        let state = state.clone();
+        let stats = stats.clone();
+        let scenario = scenario.clone();
+        let timer = timer.clone();
+        async move {
+            let mut buffer = vec![0; 16 * 1024];
+            loop {
+                let read = from.read(&mut buffer).await?;
+                if read == 0 {
+                    break;
+                }
+                let jitter = scenario.jitter.mul_f64(rng.gen());
+                timer.sleep(scenario.latency + jitter).await;
+
+                let keep = {
+                    let (transferred, cut) = &mut *state.lock().unwrap();
+                    if *cut {
+                        return Ok(());
+                    }
+                    let before = *transferred;
+                    *transferred += read as u64;
+                    match disconnect_at {
+                        Some(at) if *transferred > at => {
+                            *cut = true;
+                            Some(at.saturating_sub(before) as usize)
+                        },
+                        _ => None,
+                    }
+                };
+                if let Some(keep) = keep {
+                    /* Forward the bytes up to the cut, then close both connections */
+                    to.write_all(&buffer[..keep]).await?;
+                    stats.lock().unwrap().disconnected += 1;
+                    let _ = from.shutdown(Shutdown::Both);
+                    let _ = to.shutdown(Shutdown::Both);
+                    return Ok(());
+                }
+                to.write_all(&buffer[..read]).await?;
+            }
+            let _ = to.shutdown(Shutdown::Write);
+            std::io::Result::Ok(())
+        }
+    };
+    let upstream = pump(
+        client.clone(),
+        server.clone(),
+        rand::rngs::StdRng::seed_from_u64(rng.gen()),
+    );
+    let downstream = pump(server, client, rand::rngs::StdRng::seed_from_u64(rng.gen()));
+    futures::try_join!(upstream, downstream)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        testing::TestServer,
+        transfer::{self, TransferError},
+        transit, Timeouts, Wormhole,
+    };
+    use test_log::test;
+
+    /** Short timeouts, so that a stuck side gives up instead of hanging the test */
+    const TIMEOUTS: Timeouts = Timeouts {
+        rendezvous_connect: Some(Duration::from_secs(5)),
+        peer_wait: Some(Duration::from_secs(10)),
+        transit_negotiation: Some(Duration::from_secs(10)),
+        transit_hint_connect: Some(Duration::from_secs(5)),
+        transfer_inactivity: Some(Duration::from_secs(5)),
+        close: Some(Duration::from_secs(2)),
+        keepalive: Some(Duration::from_secs(5)),
+    };
+
+    /** Send a file over the relay, returning the errors of both sides and the received data */
+    async fn transfer(server: &TestServer, data: &[u8]) -> (Vec<TransferError>, Option<Vec<u8>>) {
+        let config = server.app_config(transfer::APP_CONFIG);
+        let pending = match Wormhole::builder(config.clone())
+            .timeouts(TIMEOUTS)
+            .allocate_code()
+            .await
+        {
+            Ok(pending) => pending,
+            Err(error) => return (vec![error.into()], None),
+        };
+        let code = pending.code().clone();
+
+        let send = async {
+            let wormhole = pending.wait_for_peer().await?;
+            #[allow(deprecated)]
+            transfer::send_file(
+                wormhole,
+                server.relay_hints(),
+                &mut &data[..],
+                "data.bin",
+                data.len() as u64,
+                transit::Abilities::FORCE_RELAY,
+                |_info| {},
+                |_sent, _total| {},
+                futures::future::pending(),
+            )
+            .await
+        };
+        let receive = async {
+            let wormhole = Wormhole::builder(config)
+                .timeouts(TIMEOUTS)
+                .connect_with_code(code)
+                .await?;
+            let request = transfer::request_file(
+                wormhole,
+                server.relay_hints(),
+                transit::Abilities::FORCE_RELAY,
+                futures::future::pending(),
+            )
+            .await?
+            .ok_or(TransferError::Protocol("Cancelled".into()))?;
+            let mut received = Vec::new();
+            request
+                .accept(
+                    |_info| {},
+                    |_received, _total| {},
+                    &mut received,
+                    futures::future::pending(),
+                )
+                .await?;
+            Ok(received)
+        };
+        match futures::join!(send, receive) {
+            (Ok(()), Ok(received)) => (vec![], Some(received)),
+            (Err(error), Ok(received)) => (vec![error], Some(received)),
+            (Ok(()), Err(error)) => (vec![error], None),
+            (Err(sent), Err(received)) => (vec![sent, received], None),
+        }
+    }
+
+    #[test(async_std::test)]
+    async fn test_latency_completes() -> eyre::Result<()> {
+        let data = b"Hello, wormhole!".repeat(4000);
+        for seed in 0..3 {
+            let scenario =
+                Scenario::delays(seed, Duration::from_millis(5), Duration::from_millis(20));
+            let server = TestServer::start_faulty(scenario).await?;
+            let (errors, received) = transfer(&server, &data).await;
+            assert!(errors.is_empty(), "seed {}: {:?}", seed, errors);
+            assert_eq!(received.as_deref(), Some(&data[..]), "seed {}", seed);
+            assert!(!server.fault_stats().any_faults());
+        }
+        Ok(())
+    }
+
+    /**
+     * Whatever goes wrong on the network, a transfer either completes with the right data or
+     * fails with a network error. It never hangs, panics or reports corrupt data.
+     */
+    #[test(async_std::test)]
+    async fn test_faults_fail_cleanly() -> eyre::Result<()> {
+        let data = b"Hello, wormhole!".repeat(4000);
+        for seed in 0..8 {
+            let server = TestServer::start_faulty(Scenario::random(seed)).await?;
+            let (errors, received) =
+                crate::util::timeout(Duration::from_secs(60), transfer(&server, &data))
+                    .await
+                    .unwrap_or_else(|_| panic!("seed {} hangs", seed));
+            let stats = server.fault_stats();
+            tracing::info!("Seed {}: {:?}, {:?}", seed, stats, errors);
+
+            if let Some(received) = received {
+                assert_eq!(received, data, "seed {}", seed);
+            }
+            if !stats.any_faults() {
+                assert!(errors.is_empty(), "seed {}: {:?}", seed, errors);
+            }
+            for error in errors {
+                assert!(
+                    !matches!(
+                        error.reason(),
+                        "integrity" | "crypto" | "pake_failed" | "unsupported_offer"
+                    ),
+                    "seed {}: {}",
+                    seed,
+                    error
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test(async_std::test)]
+    async fn test_virtual_timer() {
+        let timer = VirtualTimer::default();
+        let woken = Mutex::new(Vec::new());
+        let start = std::time::Instant::now();
+        let sleep = |secs: u64| {
+            let timer = &timer;
+            let woken = &woken;
+            async move {
+                timer.sleep(Duration::from_secs(secs)).await;
+                woken.lock().unwrap().push(secs);
+            }
+        };
+        futures::join!(sleep(30), sleep(10), sleep(20));
+        assert_eq!(*woken.lock().unwrap(), [10, 20, 30]);
+        assert_eq!(timer.now(), Duration::from_secs(30));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_scenario_is_deterministic() {
+        assert_eq!(Scenario::random(7), Scenario::random(7));
+        assert_ne!(Scenario::random(7), Scenario::random(8));
+    }
+}
@@ -28,12 +28,15 @@ use transit::{
 };
 
 mod cancel;
+mod compression;
 #[doc(hidden)]
 pub mod offer;
+mod progress;
 mod v1;
 #[cfg(feature = "experimental-transfer-v2")]
 #[allow(missing_docs)]
-mod v2;
+pub(crate) mod v2;
+mod zip;
 
 #[doc(hidden)]
 pub use v1::ReceiveRequest as ReceiveRequestV1;
@@ -41,10 +44,13 @@ pub use v1::ReceiveRequest as ReceiveRequestV1;
 #[cfg(not(feature = "experimental-transfer-v2"))]
 pub use v1::ReceiveRequest;
 
+pub use progress::{Progress, ProgressTracker};
+pub use v1::TextOrFileRequest;
+
 #[cfg(feature = "experimental-transfer-v2")]
 pub use v2::ReceiveRequest as ReceiveRequestV2;
 
-const APPID_RAW: &str = "lothar.com/wormhole/text-or-file-xfer";
+const APPID_RAW: &str = crate::protocol::v1::TRANSFER_APPID;
 
 /// The App ID associated with this protocol.
 pub const APPID: AppID = AppID(Cow::Borrowed(APPID_RAW));
@@ -157,7 +163,43 @@ pub enum TransferError {
     ),
 }
 
+#[cfg(feature = "serde-types")]
+impl_serialize_error!(TransferError);
+
 impl TransferError {
+    /**
+     * Whether trying again might succeed
+     *
+     * This is the case for network failures and timeouts, see [`WormholeError::is_retryable`].
+     * Errors reading or writing the files are not retryable.
+     */
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Wormhole(error) => error.is_retryable(),
+            Self::TransitConnect(_) => true,
+            Self::Transit(error) => matches!(error, TransitError::IO(_) | TransitError::Timeout),
+            _ => false,
+        }
+    }
+
+    /** A short label for the [`Metrics`](crate::metrics::Metrics) */
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            Self::AckError => "rejected",
+            Self::Checksum | Self::FileSize { .. } | Self::FilesystemSkew => "integrity",
+            Self::UnsupportedOffer => "unsupported_offer",
+            Self::PeerError(_) => "peer_error",
+            Self::ProtocolJson(_)
+            | Self::ProtocolMsgpack(_)
+            | Self::Protocol(_)
+            | Self::ProtocolUnexpectedMessage(..) => "protocol",
+            Self::Wormhole(error) => error.reason(),
+            Self::TransitConnect(_) => "transit_connect",
+            Self::Transit(_) => "transit",
+            Self::IO(_) => "io",
+        }
+    }
+
     pub(self) fn unexpected_message(
         expected: impl Into<Box<str>>,
         got: impl std::fmt::Display,
@@ -185,7 +227,9 @@ impl AppVersion {
         Self {
             // Dont advertize v2 for now
             abilities: Cow::Borrowed(&[
-                Cow::Borrowed("transfer-v1"), /* Cow::Borrowed("experimental-transfer-v2") */
+                Cow::Borrowed(crate::protocol::v1::ABILITY_TRANSFER_V1), /* Cow::Borrowed("experimental-transfer-v2") */
+                #[cfg(feature = "compression")]
+                Cow::Borrowed(crate::protocol::v1::ABILITY_COMPRESSION_ZSTD),
             ]),
             #[cfg(feature = "experimental-transfer-v2")]
             transfer_v2: Some(AppVersionTransferV2Hint::new()),
@@ -194,7 +238,13 @@ impl AppVersion {
 
     #[allow(dead_code)]
     fn supports_v2(&self) -> bool {
-        self.abilities.contains(&"transfer-v2".into())
+        self.abilities
+            .contains(&crate::protocol::TransferVersion::V2.ability().into())
+    }
+
+    fn supports_compression(&self) -> bool {
+        self.abilities
+            .contains(&crate::protocol::v1::ABILITY_COMPRESSION_ZSTD.into())
     }
 }
 
@@ -270,15 +320,21 @@ pub enum PeerMessage {
 }
 
 impl PeerMessage {
-    #[allow(unused)]
     fn offer_message_v1(msg: impl Into<String>) -> Self {
         PeerMessage::Offer(v1::OfferMessage::Message(msg.into()))
     }
 
-    fn offer_file_v1(name: impl Into<String>, size: u64) -> Self {
+    fn offer_file_v1(
+        name: impl Into<String>,
+        size: u64,
+        sha256: Option<[u8; 32]>,
+        compression: Option<compression::Compression>,
+    ) -> Self {
         PeerMessage::Offer(v1::OfferMessage::File {
             filename: name.into(),
             filesize: size,
+            sha256: sha256.map(hex::encode),
+            compression,
         })
     }
 
@@ -299,7 +355,6 @@ impl PeerMessage {
         })
     }
 
-    #[allow(dead_code)]
     fn message_ack_v1(msg: impl Into<String>) -> Self {
         PeerMessage::Answer(v1::AnswerMessage::MessageAck(msg.into()))
     }
@@ -443,6 +498,139 @@ pub async fn request_file(
     v1::request(wormhole, relay_hints, transit_abilities, cancel).await
 }
 
+/// Send a short text message to the other side
+///
+/// The message is sent over the wormhole itself, no transit connection is made.
+/// Returns once the other side acknowledged the message.
+pub async fn send_text(
+    wormhole: Wormhole,
+    text: impl Into<String>,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    v1::send_text(wormhole, text.into(), cancel).await
+}
+
+/// Wait for a short text message from the other side, like `wormhole receive --only-text`
+///
+/// No transit connection is made, and file offers are rejected with [`TransferError::UnsupportedOffer`].
+/// Use [`request_text_or_file`] to accept both.
+///
+/// Returns None if the task got cancelled.
+pub async fn receive_text(
+    wormhole: Wormhole,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<String>, TransferError> {
+    v1::receive_text(wormhole, cancel).await
+}
+
+/// Reduce an untrusted file name from an offer to a name that is safe to create in a directory
+///
+/// Only the last component of the path is kept, with both `/` and `\` as separators. Returns
+/// `None` if nothing usable remains, for example for `..` or a name ending with a separator.
+pub fn sanitize_file_name(file_name: &str) -> Option<&str> {
+    let name = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+    match name {
+        "" | "." | ".." => None,
+        name if name.contains('\0') => None,
+        name => Some(name),
+    }
+}
+
+/// What to do with a file offer, decided by the policy of [`receive_file`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AcceptDecision {
+    /// Receive the file under the name the sender proposed
+    Accept,
+    /// Tell the sender that we don't want the file
+    Reject,
+    /// Receive the file under another name. Only the last component of the path is used
+    RenameTo(String),
+}
+
+/// The information about a file offer that the policy of [`receive_file`] gets
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct OfferInfo {
+    /// The name the sender proposed
+    ///
+    /// **Security warning:** this is untrusted and unverified input
+    pub file_name: String,
+    /// The size of the file, or of the zip archive for directories
+    pub file_size: u64,
+    /// Whether this is a directory, which gets unpacked into a new directory named like the file without the `.zip`
+    pub is_directory: bool,
+    /// Same as [`Wormhole::verifier`], to compare it with the sender
+    pub verifier: Box<crypto_secretbox::Key>,
+    /// The SHA-256 of the file, if the sender announced it. The received file gets checked against it
+    pub sha256: Option<[u8; 32]>,
+}
+
+/// What got received, returned by [`receive_file`] and [`ReceiveRequestV1::accept_to_path`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TransferReceipt {
+    /// The SHA-256 of the received bytes, which matched the one the sender announced, if any
+    pub hash: [u8; 32],
+    /// The number of received bytes, the size of the zip archive for directories
+    pub bytes: u64,
+    /// How long the transfer took, from accepting the offer until the last byte arrived
+    pub duration: std::time::Duration,
+}
+
+/// Wait for a file offer, and receive it into `target_dir` if the `policy` accepts it
+///
+/// The policy can implement size limits, blocklists of file extensions or ask the user about the
+/// file, in which case it should also check whether a file of that name exists already. Files
+/// are never overwritten: if one exists, the transfer fails. The offered name is reduced with
+/// [`sanitize_file_name`], and files are received atomically, see
+/// [`ReceiveRequestV1::accept_to_path`]. Directories get unpacked.
+///
+/// Returns the path of the received file or directory with a [`TransferReceipt`], or `None` if the
+/// policy rejected the offer or the task got cancelled. Transfer protocol version 2 is not supported.
+#[cfg(not(target_family = "wasm"))]
+pub async fn receive_file<P, Fut>(
+    wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    target_dir: &Path,
+    policy: P,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    progress_handler: impl FnMut(u64, u64) + 'static,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<(PathBuf, TransferReceipt)>, TransferError>
+where
+    P: FnOnce(OfferInfo) -> Fut,
+    Fut: Future<Output = AcceptDecision>,
+{
+    v1::receive_file(
+        wormhole,
+        relay_hints,
+        transit_abilities,
+        target_dir,
+        policy,
+        transit_handler,
+        progress_handler,
+        cancel,
+    )
+    .await
+}
+
+/// Wait for a text message or a file offer from the other side
+///
+/// Text messages are returned directly, file offers are wrapped in a [`ReceiveRequestV1`] like
+/// [`request_file`] does. Transfer protocol version 2 does not support text messages.
+///
+/// Returns None if the task got cancelled.
+pub async fn request_text_or_file(
+    wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<TextOrFileRequest>, TransferError> {
+    v1::request_text_or_file(wormhole, relay_hints, transit_abilities, cancel).await
+}
+
 /// Send a file to the other side
 ///
 /// You must ensure that the Reader contains exactly as many bytes as advertized in file_size.
@@ -476,6 +664,7 @@ where
         file,
         file_name,
         file_size,
+        None,
         transit_abilities,
         transit_handler,
         progress_handler,
@@ -547,8 +736,10 @@ where
 }
 
 /// Send a folder to the other side
-/// This isn’t a proper folder transfer as per the Wormhole protocol because it sends it in a way so
-/// that the receiver still has to manually unpack it. But it’s better than nothing
+///
+/// Like the Python implementation, this sends a zip archive that is generated while sending it.
+/// The receiver unpacks it with [`ReceiveRequestV1::accept_directory`]. Folders of 4 GiB or more
+/// are sent as tar file instead, which the receiver has to unpack manually.
 #[cfg_attr(
     feature = "experimental-transfer-v2",
     deprecated(
@@ -595,6 +786,7 @@ where
  */
 #[must_use]
 #[cfg(feature = "experimental-transfer-v2")]
+#[non_exhaustive]
 pub enum ReceiveRequest {
     /// A protocol version 1 receive request
     V1(ReceiveRequestV1),
@@ -713,19 +905,40 @@ mod test {
 
     #[test]
     fn test_offer_file() {
-        let f1 = PeerMessage::offer_file_v1("somefile.txt", 34556);
+        let f1 = PeerMessage::offer_file_v1("somefile.txt", 34556, None, None);
         assert_eq!(
             serde_json::json!(f1).to_string(),
             "{\"offer\":{\"file\":{\"filename\":\"somefile.txt\",\"filesize\":34556}}}"
         );
+
+        let f2 = PeerMessage::offer_file_v1(
+            "somefile.txt",
+            34556,
+            Some([0xab; 32]),
+            Some(compression::Compression::Zstd),
+        );
+        assert_eq!(
+            serde_json::json!(f2),
+            serde_json::json!({"offer": {"file": {
+                "filename": "somefile.txt",
+                "filesize": 34556,
+                "sha256": "ab".repeat(32),
+                "compression": "zstd",
+            }}})
+        );
+        /* Offers of other implementations don't have it */
+        assert!(matches!(
+            serde_json::from_value(serde_json::json!(f1)).unwrap(),
+            PeerMessage::Offer(v1::OfferMessage::File { sha256: None, .. })
+        ));
     }
 
     #[test]
     fn test_offer_directory() {
-        let d1 = PeerMessage::offer_directory_v1("somedirectory", "zipped", 45, 1234, 10);
+        let d1 = PeerMessage::offer_directory_v1("somedirectory", "zipfile/deflated", 45, 1234, 10);
         assert_eq!(
             serde_json::json!(d1).to_string(),
-            "{\"offer\":{\"directory\":{\"dirname\":\"somedirectory\",\"mode\":\"zipped\",\"numbytes\":1234,\"numfiles\":10,\"zipsize\":45}}}"
+            "{\"offer\":{\"directory\":{\"dirname\":\"somedirectory\",\"mode\":\"zipfile/deflated\",\"numbytes\":1234,\"numfiles\":10,\"zipsize\":45}}}"
         );
     }
 
@@ -746,4 +959,16 @@ mod test {
             "{\"answer\":{\"file_ack\":\"ok\"}}"
         );
     }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("file.txt"), Some("file.txt"));
+        assert_eq!(sanitize_file_name("../../.bashrc"), Some(".bashrc"));
+        assert_eq!(sanitize_file_name("/etc/passwd"), Some("passwd"));
+        assert_eq!(sanitize_file_name("C:\\Windows\\win.ini"), Some("win.ini"));
+        assert_eq!(sanitize_file_name(".."), None);
+        assert_eq!(sanitize_file_name("dir/.."), None);
+        assert_eq!(sanitize_file_name("dir/"), None);
+        assert_eq!(sanitize_file_name(""), None);
+    }
 }
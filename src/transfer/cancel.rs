@@ -1,6 +1,6 @@
 /// Various helpers to deal with closing connections and cancellation
 use super::*;
-use crate::util;
+use crate::{util, Mood};
 
 /// A weird mixture of [`futures::future::Abortable`], [`async_std::sync::Condvar`] and [`futures::future::Select`] tailored to our Ctrl+C handling.
 ///
@@ -180,7 +180,10 @@ pub async fn handle_run_result_noclose<T, C: Future<Output = ()>>(
                             .await,
                         "notify peer about the error",
                     );
-                    debug_err(wormhole.close().await, "close Wormhole");
+                    debug_err(
+                        wormhole.close_with(Mood::Errory, error.to_string()).await,
+                        "close Wormhole",
+                    );
                 },
                 cancel,
             )
@@ -199,7 +202,12 @@ pub async fn handle_run_result_noclose<T, C: Future<Output = ()>>(
                             .await,
                         "notify peer about our cancellation",
                     );
-                    debug_err(wormhole.close().await, "close Wormhole");
+                    debug_err(
+                        wormhole
+                            .close_with(Mood::Errory, cancelled.to_string())
+                            .await,
+                        "close Wormhole",
+                    );
                 },
                 futures::future::pending(),
             )
@@ -0,0 +1,56 @@
+//! Compression of the records of a file transfer, see the `compression` feature
+//!
+//! Each record is compressed on its own, so that the receiver can check and write it right away.
+//! Without the feature, we don't advertise the ability, and the peer must not send compressed
+//! records.
+
+use serde_derive::{Deserialize, Serialize};
+use std::io;
+
+/** How the records of a file transfer are compressed, announced in the file offer */
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    Zstd,
+    #[serde(other)]
+    Unknown,
+}
+
+/** Whether we can receive compressed transfers */
+pub(super) const SUPPORTED: bool = cfg!(feature = "compression");
+
+#[cfg(feature = "compression")]
+pub(super) fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)
+}
+
+/** Decompress a record, which must not be larger than `capacity` afterwards */
+#[cfg(feature = "compression")]
+pub(super) fn decompress(record: &[u8], capacity: usize) -> io::Result<Vec<u8>> {
+    zstd::bulk::decompress(record, capacity)
+}
+
+#[cfg(not(feature = "compression"))]
+pub(super) fn compress(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(feature = "compression"))]
+pub(super) fn decompress(_record: &[u8], _capacity: usize) -> io::Result<Vec<u8>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let data = b"log line\n".repeat(1000);
+        let record = compress(&data).unwrap();
+        assert!(record.len() < data.len() / 10);
+        assert_eq!(decompress(&record, data.len()).unwrap(), data);
+        /* The receiver refuses records that decompress to more than a record */
+        assert!(decompress(&record, data.len() - 1).is_err());
+    }
+}
@@ -111,23 +111,52 @@ impl<T> Offer<T> {
 
     #[cfg(not(target_family = "wasm"))]
     pub fn accept_all(&self, target_dir: &Path) -> OfferAccept {
-        self.set_content(|path| {
-            let full_path: PathBuf = target_dir.join(path.join("/"));
-            let content = new_accept_content(move |append| {
-                let full_path = full_path.clone();
-                async_std::fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .append(append)
-                    .truncate(!append)
-                    .open(full_path)
-            });
-            AcceptInner {
-                content: Box::new(content) as _,
-                offset: 0,
-                sha256: None,
+        self.set_content(|path| accept_file(target_dir, path))
+    }
+
+    /**
+     * Like [`accept_all`](Self::accept_all), but continue the files that were partially received
+     * into `target_dir` before
+     *
+     * The sender checks the SHA-256 of what is already there, and sends the whole file again if it
+     * doesn't match. Nothing but the files themselves is needed for this, so the transfer can be
+     * resumed over a new wormhole with new keys.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn resume_all(&self, target_dir: &Path) -> std::io::Result<OfferAccept> {
+        use futures::AsyncReadExt;
+        use sha2::{digest::FixedOutput, Digest, Sha256};
+
+        let mut partial = std::collections::HashMap::new();
+        for (path, _, size) in self.iter_files() {
+            let mut file = match async_std::fs::File::open(target_dir.join(path.join("/"))).await {
+                Ok(file) => file,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            /* Larger files can't be a prefix, receive them again */
+            let offset = file.metadata().await?.len();
+            if offset == 0 || offset > size {
+                continue;
             }
-        })
+            let mut hasher = Sha256::default();
+            futures::io::copy(
+                (&mut file).take(offset),
+                &mut futures::io::AllowStdIo::new(&mut hasher),
+            )
+            .await?;
+            tracing::debug!("Resuming {} after {offset} bytes", path.join("/"));
+            partial.insert(path, (offset, <[u8; 32]>::from(hasher.finalize_fixed())));
+        }
+
+        Ok(self.set_content(|path| {
+            let mut inner = accept_file(target_dir, path);
+            if let Some(&(offset, sha256)) = partial.get(path) {
+                inner.offset = offset;
+                inner.sha256 = Some(sha256);
+            }
+            inner
+        }))
     }
 
     #[cfg(not(target_family = "wasm"))]
@@ -175,6 +204,22 @@ impl<T> Offer<T> {
                 .any(|f| matches!(f, OfferEntry::Directory { .. }))
     }
 
+    /**
+     * Only keep the files for which `keep` returns `true`, given their path and size
+     *
+     * Use this on an answer to only receive some of the offered files. Directories stay, even if
+     * they become empty.
+     */
+    pub fn retain_files(&mut self, mut keep: impl FnMut(&[String], u64) -> bool) {
+        self.content.retain(|name, entry| match entry {
+            OfferEntry::RegularFile { size, .. } => keep(&[name.clone()], *size),
+            OfferEntry::Directory { .. } => {
+                entry.retain_files(&mut vec![name.clone()], &mut keep);
+                true
+            },
+        });
+    }
+
     pub fn set_content<U>(&self, mut f: impl FnMut(&[String]) -> U) -> Offer<U> {
         Offer {
             content: self
@@ -242,6 +287,7 @@ pub type OfferSendEntry = OfferEntry<OfferContent>;
 #[serde(rename_all = "kebab-case")]
 #[serde(tag = "type")]
 #[serde(bound(deserialize = "T: Default"))]
+#[non_exhaustive]
 pub enum OfferEntry<T = ()> {
     RegularFile {
         size: u64,
@@ -422,6 +468,28 @@ impl<T> OfferEntry<T> {
     //     }
     // }
 
+    /** Only affects the content of directories, the caller decides about files */
+    fn retain_files(
+        &mut self,
+        base_path: &mut Vec<String>,
+        keep: &mut impl FnMut(&[String], u64) -> bool,
+    ) {
+        if let Self::Directory { content } = self {
+            content.retain(|name, entry| {
+                base_path.push(name.clone());
+                let retain = match entry {
+                    Self::RegularFile { size, .. } => keep(base_path, *size),
+                    Self::Directory { .. } => {
+                        entry.retain_files(base_path, keep);
+                        true
+                    },
+                };
+                base_path.pop();
+                retain
+            });
+        }
+    }
+
     fn set_content<U>(
         &self,
         base_path: &mut Vec<String>,
@@ -514,8 +582,100 @@ where
 
 pub type OfferAccept = Offer<AcceptInner>;
 
+/* Write the file at `path` below `target_dir`, appending if the sender continues it */
+#[cfg(not(target_family = "wasm"))]
+fn accept_file(target_dir: &Path, path: &[String]) -> AcceptInner {
+    let full_path: PathBuf = target_dir.join(path.join("/"));
+    let content = new_accept_content(move |append| {
+        let full_path = full_path.clone();
+        async_std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(full_path)
+    });
+    AcceptInner {
+        content: Box::new(content) as _,
+        offset: 0,
+        sha256: None,
+    }
+}
+
 pub struct AcceptInner {
     pub offset: u64,
     pub sha256: Option<[u8; 32]>,
     pub content: AcceptContent,
 }
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod test {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    #[async_std::test]
+    async fn test_resume_all() {
+        let file = |size| OfferEntry::RegularFile { size, content: () };
+        let offer = Offer {
+            content: [
+                ("partial.txt".to_owned(), file(10)),
+                ("missing.txt".to_owned(), file(10)),
+                ("larger.txt".to_owned(), file(2)),
+            ]
+            .into(),
+        };
+        let target = std::env::temp_dir().join(format!(
+            "wormhole-resume-test-{:06}",
+            rand::random::<u32>() % 1_000_000
+        ));
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("partial.txt"), b"Hello").unwrap();
+        std::fs::write(target.join("larger.txt"), b"Hello").unwrap();
+
+        let answer = offer.resume_all(&target).await.unwrap();
+        let get = |name: &str| answer.get_file(&[name.to_owned()]).unwrap().0;
+        assert_eq!(get("partial.txt").offset, 5);
+        assert_eq!(
+            get("partial.txt").sha256,
+            Some(Sha256::digest(b"Hello").into())
+        );
+        for name in ["missing.txt", "larger.txt"] {
+            assert_eq!(get(name).offset, 0);
+            assert_eq!(get(name).sha256, None);
+        }
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn test_retain_files() {
+        let file = |size| OfferEntry::RegularFile { size, content: () };
+        let mut offer = Offer {
+            content: [
+                ("small.txt".to_owned(), file(1)),
+                ("large.txt".to_owned(), file(1000)),
+                (
+                    "folder".to_owned(),
+                    OfferEntry::Directory {
+                        content: [
+                            ("small.txt".to_owned(), file(2)),
+                            ("large.txt".to_owned(), file(2000)),
+                        ]
+                        .into(),
+                    },
+                ),
+            ]
+            .into(),
+        };
+
+        offer.retain_files(|_path, size| size < 100);
+        assert_eq!(
+            offer.iter_file_paths().collect::<Vec<_>>(),
+            [vec!["folder", "small.txt"], vec!["small.txt"]]
+        );
+
+        offer.retain_files(|path, _size| path.len() == 1);
+        assert_eq!(offer.iter_file_paths().collect::<Vec<_>>(), [["small.txt"]]);
+        assert!(offer.get(&["folder".to_owned()]).is_some());
+    }
+}
@@ -0,0 +1,191 @@
+//! Throughput and ETA estimation on top of the plain `FnMut(u64, u64)` progress handlers
+
+use crate::util::Instant;
+use std::time::Duration;
+
+/**
+ * How long it takes until older throughput measurements lose most of their weight.
+ * Long enough to hide short stalls, short enough to follow a changing connection.
+ */
+const SMOOTHING_TIME: Duration = Duration::from_secs(5);
+
+/**
+ * The state of a running transfer, as reported by [`ProgressTracker`]
+ */
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Progress {
+    /// The number of bytes transferred so far
+    pub transferred: u64,
+    /// The total number of bytes of the transfer
+    pub total: u64,
+    /// The time since the first report
+    pub elapsed: Duration,
+    /// Bytes per second since the previous report
+    pub throughput: f64,
+    /// Bytes per second, smoothed over the last few seconds
+    pub smoothed_throughput: f64,
+    /// The estimated time until the transfer completes, based on the smoothed throughput.
+    /// `None` as long as nothing has been transferred yet.
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    /// The fraction of the transfer that is done, between `0.0` and `1.0`
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            (self.transferred as f64 / self.total as f64).min(1.0)
+        }
+    }
+}
+
+/**
+ * Turns the raw `(transferred, total)` updates of a transfer into [`Progress`] reports
+ *
+ * The transfer functions call their progress handler after every chunk, which is far too
+ * often to redraw a user interface. The tracker only reports once per `interval`, except for
+ * the first and the final update, which are always reported.
+ *
+ * ```no_run
+ * use magic_wormhole::transfer::ProgressTracker;
+ * use std::time::Duration;
+ *
+ * let _progress_handler =
+ *     ProgressTracker::new(Duration::from_millis(250)).into_handler(|progress| {
+ *         println!(
+ *             "{} of {} bytes, {:.0} B/s, ETA {:?}",
+ *             progress.transferred, progress.total, progress.smoothed_throughput, progress.eta
+ *         );
+ *     });
+ * ```
+ */
+#[derive(Clone, Debug)]
+pub struct ProgressTracker {
+    interval: Duration,
+    started: Option<Instant>,
+    /// Time and byte count of the previous report
+    last: Option<(Instant, u64)>,
+    smoothed_throughput: Option<f64>,
+}
+
+impl ProgressTracker {
+    /// Report at most once per `interval`
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            started: None,
+            last: None,
+            smoothed_throughput: None,
+        }
+    }
+
+    /**
+     * Feed an update of the transfer into the tracker
+     *
+     * Returns `None` if the update came in less than `interval` after the previous report.
+     */
+    pub fn update(&mut self, transferred: u64, total: u64) -> Option<Progress> {
+        self.update_at(Instant::now(), transferred, total)
+    }
+
+    fn update_at(&mut self, now: Instant, transferred: u64, total: u64) -> Option<Progress> {
+        let started = *self.started.get_or_insert(now);
+        let finished = transferred >= total;
+
+        let throughput = match self.last {
+            None => 0.0,
+            Some((last_time, last_transferred)) => {
+                let since_last = now.duration_since(last_time);
+                if since_last < self.interval && !finished {
+                    return None;
+                }
+                if since_last.is_zero() {
+                    0.0
+                } else {
+                    transferred.saturating_sub(last_transferred) as f64 / since_last.as_secs_f64()
+                }
+            },
+        };
+
+        if let Some((last_time, _)) = self.last {
+            let since_last = now.duration_since(last_time).as_secs_f64();
+            let weight = 1.0 - (-since_last / SMOOTHING_TIME.as_secs_f64()).exp();
+            self.smoothed_throughput = Some(match self.smoothed_throughput {
+                None => throughput,
+                Some(smoothed) => smoothed + weight * (throughput - smoothed),
+            });
+        }
+        self.last = Some((now, transferred));
+
+        let smoothed_throughput = self.smoothed_throughput.unwrap_or(0.0);
+        let eta = if finished {
+            Some(Duration::ZERO)
+        } else if smoothed_throughput > 0.0 {
+            Some(Duration::from_secs_f64(
+                (total - transferred) as f64 / smoothed_throughput,
+            ))
+        } else {
+            None
+        };
+
+        Some(Progress {
+            transferred,
+            total,
+            elapsed: now.duration_since(started),
+            throughput,
+            smoothed_throughput,
+            eta,
+        })
+    }
+
+    /**
+     * Create a progress handler for the transfer functions, which calls `handler` with the reports
+     */
+    pub fn into_handler(
+        mut self,
+        mut handler: impl FnMut(Progress) + 'static,
+    ) -> impl FnMut(u64, u64) + 'static {
+        move |transferred, total| {
+            if let Some(progress) = self.update(transferred, total) {
+                handler(progress)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_progress_tracker() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut tracker = ProgressTracker::new(Duration::from_millis(500));
+
+        let first = tracker.update_at(at(0), 0, 10_000).unwrap();
+        assert_eq!(first.throughput, 0.0);
+        assert_eq!(first.eta, None);
+
+        /* Too early */
+        assert_eq!(tracker.update_at(at(100), 100, 10_000), None);
+
+        let second = tracker.update_at(at(1000), 1000, 10_000).unwrap();
+        assert_eq!(second.elapsed, Duration::from_secs(1));
+        assert_eq!(second.throughput, 1000.0);
+        assert_eq!(second.smoothed_throughput, 1000.0);
+        assert_eq!(second.eta, Some(Duration::from_secs(9)));
+
+        /* A faster second only partially moves the smoothed value */
+        let third = tracker.update_at(at(2000), 4000, 10_000).unwrap();
+        assert_eq!(third.throughput, 3000.0);
+        assert!(third.smoothed_throughput > 1000.0 && third.smoothed_throughput < 3000.0);
+
+        /* The final update is always reported */
+        let last = tracker.update_at(at(2100), 10_000, 10_000).unwrap();
+        assert_eq!(last.eta, Some(Duration::ZERO));
+        assert_eq!(last.fraction(), 1.0);
+    }
+}
@@ -4,7 +4,8 @@ use futures::{
 };
 use sha2::{digest::FixedOutput, Digest, Sha256};
 
-use super::{offer::*, *};
+use super::{compression::Compression, offer::*, *};
+use crate::metrics::Direction;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "kebab-case")]
@@ -13,6 +14,12 @@ pub enum OfferMessage {
     File {
         filename: String,
         filesize: u64,
+        /// Hex encoded, not sent by other implementations
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+        /// Only used if the receiver advertised support for it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<Compression>,
     },
     Directory {
         dirname: String,
@@ -67,6 +74,13 @@ impl TransitAck {
     }
 }
 
+/** Compress the transfer if both sides support it */
+fn negotiate_compression(wormhole: &Wormhole) -> Option<Compression> {
+    let peer_version = serde_json::from_value::<AppVersion>(wormhole.peer_version().clone());
+    (compression::SUPPORTED && peer_version.is_ok_and(|version| version.supports_compression()))
+        .then_some(Compression::Zstd)
+}
+
 pub(crate) async fn send(
     wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
@@ -107,12 +121,23 @@ pub(crate) async fn send(
         .await
     } else {
         let (file_name, file) = offer.content.into_iter().next().unwrap();
-        let (mut file, file_size) = match file {
+        let (mut file, file_size, sha256) = match file {
             OfferSendEntry::RegularFile { content, size } => {
+                let sha256 = if wormhole.offer_digests() {
+                    let mut hasher = Sha256::default();
+                    futures::io::copy(
+                        content().await?,
+                        &mut futures::io::AllowStdIo::new(&mut hasher),
+                    )
+                    .await?;
+                    Some(hasher.finalize_fixed().into())
+                } else {
+                    None
+                };
                 /* This must be split into two statements to appease the borrow checker (unfortunate side effect of borrow-through) */
                 let content = content();
                 let content = content.await?;
-                (content, size)
+                (content, size, sha256)
             },
             _ => unreachable!(),
         };
@@ -122,6 +147,7 @@ pub(crate) async fn send(
             &mut file,
             file_name,
             file_size,
+            sha256,
             transit_abilities,
             transit_handler,
             progress_handler,
@@ -131,12 +157,14 @@ pub(crate) async fn send(
     }
 }
 
+#[tracing::instrument(skip_all, fields(version = 1, side = %wormhole.side()))]
 pub(crate) async fn send_file<F, G, H>(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     file: &mut F,
     file_name: impl Into<String>,
     file_size: u64,
+    sha256: Option<[u8; 32]>,
     transit_abilities: transit::Abilities,
     transit_handler: G,
     progress_handler: H,
@@ -147,8 +175,21 @@ where
     G: FnOnce(transit::TransitInfo),
     H: FnMut(u64, u64) + 'static,
 {
+    let compression = negotiate_compression(&wormhole);
+    let events = wormhole.event_sender().clone();
+    let started = crate::util::Instant::now();
+    let transit_handler = events.transit_handler(transit_handler);
+    let progress_handler = events.progress_handler(Direction::Send, progress_handler);
     let run = Box::pin(async {
-        let connector = transit::init(transit_abilities, None, relay_hints).await?;
+        let connector = transit::init(
+            wormhole.transit_abilities(transit_abilities),
+            None,
+            relay_hints,
+        )
+        .await?
+        .with_timeouts(wormhole.timeouts())
+        .with_proxy(wormhole.proxy())
+        .with_rate_limit(wormhole.rate_limit());
 
         // We want to do some transit
         tracing::debug!("Sending transit message '{:?}", connector.our_hints());
@@ -162,7 +203,12 @@ where
         // Send file offer message.
         tracing::debug!("Sending file offer");
         wormhole
-            .send_json(&PeerMessage::offer_file_v1(file_name, file_size))
+            .send_json(&PeerMessage::offer_file_v1(
+                file_name,
+                file_size,
+                sha256,
+                compression,
+            ))
             .await?;
 
         // Wait for their transit response
@@ -210,11 +256,21 @@ where
         let file = futures::stream::once(futures::future::ready(std::io::Result::Ok(
             Box::new(file) as Box<dyn AsyncRead + Unpin + Send>,
         )));
-        let checksum = v1::send_records(&mut transit, file, file_size, progress_handler).await?;
+        let checksum = wormhole
+            .drive_app_messages(v1::send_records(
+                &mut transit,
+                file,
+                file_size,
+                compression.is_some(),
+                progress_handler,
+            ))
+            .await?;
 
         // 13. wait for the transit ack with sha256 sum from the peer.
         tracing::debug!("sent file. Waiting for ack");
-        let transit_ack = transit.receive_record().await?;
+        let transit_ack = wormhole
+            .drive_app_messages(transit.receive_record())
+            .await?;
         let transit_ack_msg = serde_json::from_slice::<TransitAck>(&transit_ack)?;
         ensure!(
             transit_ack_msg.sha256 == hex::encode(checksum),
@@ -227,9 +283,14 @@ where
 
     futures::pin_mut!(cancel);
     let result = cancel::cancellable_2(run, cancel).await;
-    cancel::handle_run_result(wormhole, result).await
+    events.transfer_finished(
+        Direction::Send,
+        started,
+        cancel::handle_run_result(wormhole, result).await,
+    )
 }
 
+#[tracing::instrument(skip_all, fields(version = 1, side = %wormhole.side()))]
 pub(crate) async fn send_folder(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
@@ -240,8 +301,21 @@ pub(crate) async fn send_folder(
     progress_handler: impl FnMut(u64, u64) + 'static,
     cancel: impl Future<Output = ()>,
 ) -> Result<(), TransferError> {
+    let compression = negotiate_compression(&wormhole);
+    let events = wormhole.event_sender().clone();
+    let started = crate::util::Instant::now();
+    let transit_handler = events.transit_handler(transit_handler);
+    let progress_handler = events.progress_handler(Direction::Send, progress_handler);
     let run = Box::pin(async {
-        let connector = transit::init(transit_abilities, None, relay_hints).await?;
+        let connector = transit::init(
+            wormhole.transit_abilities(transit_abilities),
+            None,
+            relay_hints,
+        )
+        .await?
+        .with_timeouts(wormhole.timeouts())
+        .with_proxy(wormhole.proxy())
+        .with_rate_limit(wormhole.rate_limit());
 
         // We want to do some transit
         tracing::debug!("Sending transit message '{:?}", connector.our_hints());
@@ -252,92 +326,112 @@ pub(crate) async fn send_folder(
             ))
             .await?;
 
-        /* We need to know the length of what we are going to send in advance. So we already build
-         * all the headers of our file now but without the contents. We know that a file is
-         * header + contents + padding
+        /* Send the directory like the Python implementation does, as zip archive that the receiver
+         * unpacks. Directories that would need ZIP64 get sent as tar file instead.
          */
-        tracing::debug!("Estimating the file size");
-
-        // TODO try again but without pinning
-        use futures::{
-            future::{ready, BoxFuture},
-            io::Cursor,
-        };
-        use std::io::Result as IoResult;
-
-        type WrappedDataFut = BoxFuture<'static, IoResult<Box<dyn AsyncRead + Unpin + Send>>>;
+        let (content, total_size) = if let Some(archive) = zip::ZipArchive::new(&folder) {
+            tracing::debug!("Sending directory offer ({} bytes)", archive.size);
+            wormhole
+                .send_json(&PeerMessage::offer_directory_v1(
+                    folder_name,
+                    DIRECTORY_MODE,
+                    archive.size,
+                    archive.num_bytes,
+                    archive.num_files,
+                ))
+                .await?;
+            (archive.content, archive.size)
+        } else {
+            /* We need to know the length of what we are going to send in advance. So we already build
+             * all the headers of our file now but without the contents. We know that a file is
+             * header + contents + padding
+             */
+            tracing::debug!("Estimating the file size");
+
+            // TODO try again but without pinning
+            use futures::{
+                future::{ready, BoxFuture},
+                io::Cursor,
+            };
+            use std::io::Result as IoResult;
 
-        /* Type tetris :) */
-        fn wrap(buffer: impl AsRef<[u8]> + Unpin + Send + 'static) -> WrappedDataFut {
-            Box::pin(ready(IoResult::Ok(
-                Box::new(Cursor::new(buffer)) as Box<dyn AsyncRead + Unpin + Send>
-            ))) as _
-        }
+            type WrappedDataFut = BoxFuture<'static, IoResult<Box<dyn AsyncRead + Unpin + Send>>>;
 
-        /* Walk our offer recursively, concatenate all our readers into a stream that will build the tar file */
-        fn create_offer(
-            mut total_content: Vec<WrappedDataFut>,
-            total_size: &mut u64,
-            offer: OfferSendEntry,
-            path: &mut Vec<String>,
-        ) -> IoResult<Vec<WrappedDataFut>> {
-            match offer {
-                OfferSendEntry::Directory { content } => {
-                    tracing::debug!("Adding directory {path:?}");
-                    let header = tar_helper::create_header_directory(path)?;
-                    *total_size += header.len() as u64;
-                    total_content.push(wrap(header));
-
-                    for (name, file) in content {
-                        path.push(name);
-                        total_content = create_offer(total_content, total_size, file, path)?;
-                        path.pop();
-                    }
-                },
-                OfferSendEntry::RegularFile { size, content } => {
-                    tracing::debug!("Adding file {path:?}; {size} bytes");
-                    let header = tar_helper::create_header_file(path, size)?;
-                    let padding = tar_helper::padding(size);
-                    *total_size += header.len() as u64;
-                    *total_size += padding.len() as u64;
-                    *total_size += size;
-
-                    total_content.push(wrap(header));
-                    let content = content().map_ok(
-                        /* Re-box because we can't upcast trait objects */
-                        |read| Box::new(read) as Box<dyn AsyncRead + Unpin + Send>,
-                    );
-                    total_content.push(Box::pin(content) as _);
-                    total_content.push(wrap(padding));
-                },
-                // OfferSendEntry::Symlink { .. } => todo!(),
+            /* Type tetris :) */
+            fn wrap(buffer: impl AsRef<[u8]> + Unpin + Send + 'static) -> WrappedDataFut {
+                Box::pin(ready(IoResult::Ok(
+                    Box::new(Cursor::new(buffer)) as Box<dyn AsyncRead + Unpin + Send>
+                ))) as _
             }
-            Ok(total_content)
-        }
 
-        let mut total_size = 0;
-        let mut content = create_offer(
-            Vec::new(),
-            &mut total_size,
-            folder,
-            &mut vec![folder_name.clone()],
-        )?;
-
-        /* Finish tar file */
-        total_size += 1024;
-        content.push(wrap([0; 1024]));
+            /* Walk our offer recursively, concatenate all our readers into a stream that will build the tar file */
+            fn create_offer(
+                mut total_content: Vec<WrappedDataFut>,
+                total_size: &mut u64,
+                offer: OfferSendEntry,
+                path: &mut Vec<String>,
+            ) -> IoResult<Vec<WrappedDataFut>> {
+                match offer {
+                    OfferSendEntry::Directory { content } => {
+                        tracing::debug!("Adding directory {path:?}");
+                        let header = tar_helper::create_header_directory(path)?;
+                        *total_size += header.len() as u64;
+                        total_content.push(wrap(header));
+
+                        for (name, file) in content {
+                            path.push(name);
+                            total_content = create_offer(total_content, total_size, file, path)?;
+                            path.pop();
+                        }
+                    },
+                    OfferSendEntry::RegularFile { size, content } => {
+                        tracing::debug!("Adding file {path:?}; {size} bytes");
+                        let header = tar_helper::create_header_file(path, size)?;
+                        let padding = tar_helper::padding(size);
+                        *total_size += header.len() as u64;
+                        *total_size += padding.len() as u64;
+                        *total_size += size;
+
+                        total_content.push(wrap(header));
+                        let content = content().map_ok(
+                            /* Re-box because we can't upcast trait objects */
+                            |read| Box::new(read) as Box<dyn AsyncRead + Unpin + Send>,
+                        );
+                        total_content.push(Box::pin(content) as _);
+                        total_content.push(wrap(padding));
+                    },
+                    // OfferSendEntry::Symlink { .. } => todo!(),
+                }
+                Ok(total_content)
+            }
 
+            let mut total_size = 0;
+            let mut content = create_offer(
+                Vec::new(),
+                &mut total_size,
+                folder,
+                &mut vec![folder_name.clone()],
+            )?;
+
+            /* Finish tar file */
+            total_size += 1024;
+            content.push(wrap([0; 1024]));
+
+            // Send file offer message.
+            tracing::debug!("Sending file offer ({total_size} bytes)");
+            folder_name.push_str(".tar");
+            wormhole
+                .send_json(&PeerMessage::offer_file_v1(
+                    folder_name,
+                    total_size,
+                    None,
+                    compression,
+                ))
+                .await?;
+            (content, total_size)
+        };
         let content = futures::stream::iter(content).then(|content| content);
 
-        /* Convert to stream */
-
-        // Send file offer message.
-        tracing::debug!("Sending file offer ({total_size} bytes)");
-        folder_name.push_str(".tar");
-        wormhole
-            .send_json(&PeerMessage::offer_file_v1(folder_name, total_size))
-            .await?;
-
         // Wait for their transit response
         let (their_abilities, their_hints): (transit::Abilities, transit::Hints) =
             match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
@@ -372,12 +466,21 @@ pub(crate) async fn send_folder(
         tracing::debug!("Beginning file transfer");
 
         // 11. send the file as encrypted records.
-        let checksum =
-            v1::send_records(&mut transit, content, total_size, progress_handler).await?;
+        let checksum = wormhole
+            .drive_app_messages(v1::send_records(
+                &mut transit,
+                content,
+                total_size,
+                compression.is_some(),
+                progress_handler,
+            ))
+            .await?;
 
         // 13. wait for the transit ack with sha256 sum from the peer.
         tracing::debug!("sent file. Waiting for ack");
-        let transit_ack = transit.receive_record().await?;
+        let transit_ack = wormhole
+            .drive_app_messages(transit.receive_record())
+            .await?;
         let transit_ack_msg = serde_json::from_slice::<TransitAck>(&transit_ack)?;
         ensure!(
             transit_ack_msg.sha256 == hex::encode(checksum),
@@ -388,11 +491,182 @@ pub(crate) async fn send_folder(
         Ok(())
     });
 
+    futures::pin_mut!(cancel);
+    let result = cancel::cancellable_2(run, cancel).await;
+    events.transfer_finished(
+        Direction::Send,
+        started,
+        cancel::handle_run_result(wormhole, result).await,
+    )
+}
+
+/**
+ * Send a short text message to the other side
+ *
+ * The message is sent directly over the wormhole, no transit connection is established.
+ */
+#[tracing::instrument(skip_all, fields(version = 1, side = %wormhole.side()))]
+pub(crate) async fn send_text(
+    mut wormhole: Wormhole,
+    text: String,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    let run = Box::pin(async {
+        tracing::debug!("Sending text offer");
+        wormhole
+            .send_json(&PeerMessage::offer_message_v1(text))
+            .await?;
+
+        loop {
+            match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
+                /* The receiver might send its transit hints before it knows that we are sending text */
+                PeerMessage::Transit(transit) => {
+                    tracing::debug!("Ignoring transit message: {:?}", transit);
+                },
+                PeerMessage::Answer(AnswerMessage::MessageAck(msg)) => {
+                    ensure!(msg == "ok", TransferError::AckError);
+                    break;
+                },
+                other => {
+                    bail!(TransferError::unexpected_message(
+                        "answer/message_ack",
+                        other
+                    ));
+                },
+            }
+        }
+        tracing::debug!("Text message acknowledged");
+
+        Ok(())
+    });
+
     futures::pin_mut!(cancel);
     let result = cancel::cancellable_2(run, cancel).await;
     cancel::handle_run_result(wormhole, result).await
 }
 
+/**
+ * Wait for a short text message from the other side
+ *
+ * No transit connection is made. File offers are rejected with [`TransferError::UnsupportedOffer`].
+ *
+ * Returns `None` if the task got cancelled.
+ */
+#[tracing::instrument(skip_all, fields(version = 1, side = %wormhole.side()))]
+pub(crate) async fn receive_text(
+    mut wormhole: Wormhole,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<String>, TransferError> {
+    let run = Box::pin(async {
+        loop {
+            match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
+                /* Senders of files send their transit hints first */
+                PeerMessage::Transit(transit) => {
+                    tracing::debug!("Ignoring transit message: {:?}", transit);
+                },
+                PeerMessage::Offer(v1::OfferMessage::Message(text)) => {
+                    tracing::debug!("Received text message, sending ack");
+                    wormhole
+                        .send_json(&PeerMessage::message_ack_v1("ok"))
+                        .await?;
+                    break Ok(text);
+                },
+                /* Errors get sent to the other side, which rejects the offer */
+                PeerMessage::Offer(_) => bail!(TransferError::UnsupportedOffer),
+                other => {
+                    bail!(TransferError::unexpected_message("offer", other));
+                },
+            }
+        }
+    });
+
+    futures::pin_mut!(cancel);
+    let result = cancel::cancellable_2(run, cancel).await;
+    match cancel::handle_run_result_noclose(wormhole, result).await? {
+        Some((text, wormhole, _)) => {
+            wormhole.close().await?;
+            Ok(Some(text))
+        },
+        None => Ok(None),
+    }
+}
+
+/**
+ * Wait for a file offer, ask `policy` about it and save the file into `target_dir`
+ *
+ * Returns the path of the received file or directory with the receipt, or `None` if the offer got
+ * rejected or the task got cancelled.
+ */
+#[cfg(not(target_family = "wasm"))]
+pub(crate) async fn receive_file<P, Fut>(
+    wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    target_dir: &Path,
+    policy: P,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    progress_handler: impl FnMut(u64, u64) + 'static,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<(PathBuf, TransferReceipt)>, TransferError>
+where
+    P: FnOnce(OfferInfo) -> Fut,
+    Fut: Future<Output = AcceptDecision>,
+{
+    let verifier = Box::new(*wormhole.verifier());
+    futures::pin_mut!(cancel);
+    let Some(request) = request(wormhole, relay_hints, transit_abilities, cancel.as_mut()).await?
+    else {
+        return Ok(None);
+    };
+
+    let info = OfferInfo {
+        file_name: request.file_name(),
+        file_size: request.file_size(),
+        is_directory: request.is_directory(),
+        verifier,
+        sha256: request.sha256(),
+    };
+    let file_name = match policy(info).await {
+        AcceptDecision::Accept => request.file_name(),
+        AcceptDecision::RenameTo(file_name) => file_name,
+        AcceptDecision::Reject => {
+            request.reject().await?;
+            return Ok(None);
+        },
+    };
+
+    /* Directories get unpacked into a new directory, named without the `.zip` */
+    let name = if request.is_directory() {
+        file_name.strip_suffix(".zip").unwrap_or(&file_name)
+    } else {
+        &file_name
+    };
+    /* Never write outside of `target_dir`, whatever the offer says */
+    let Some(name) = sanitize_file_name(name) else {
+        request.reject().await?;
+        bail!(TransferError::Protocol(
+            format!("Invalid file name {file_name:?}").into_boxed_str()
+        ));
+    };
+    let path = target_dir.join(name);
+
+    if request.is_directory() {
+        if let Err(err) = async_std::fs::create_dir(&path).await {
+            request.reject().await?;
+            bail!(err);
+        }
+        let receipt = request
+            .unpack_directory(transit_handler, progress_handler, &path, cancel)
+            .await?;
+        Ok(receipt.map(|receipt| (path, receipt)))
+    } else {
+        let receipt = request
+            .accept_to_path(transit_handler, progress_handler, &path, false, cancel)
+            .await?;
+        Ok(receipt.map(|receipt| (path, receipt)))
+    }
+}
+
 /**
  * Wait for a file offer from the other side
  *
@@ -402,14 +676,72 @@ pub(crate) async fn send_folder(
  * Returns `None` if the task got cancelled.
  */
 pub async fn request(
-    mut wormhole: Wormhole,
+    wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     transit_abilities: transit::Abilities,
     cancel: impl Future<Output = ()>,
 ) -> Result<Option<ReceiveRequest>, TransferError> {
+    request_inner(wormhole, relay_hints, transit_abilities, false, cancel)
+        .await
+        .map(|req| {
+            req.map(|req| match req {
+                TextOrFileRequest::File(req) => req,
+                TextOrFileRequest::Text(_) => unreachable!("Text offers are rejected"),
+            })
+        })
+}
+
+/**
+ * Wait for a text message or a file offer from the other side
+ *
+ * Text messages are acknowledged right away and the wormhole gets closed. For file offers,
+ * this behaves like [`request`].
+ *
+ * Returns `None` if the task got cancelled.
+ */
+pub async fn request_text_or_file(
+    wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<TextOrFileRequest>, TransferError> {
+    request_inner(wormhole, relay_hints, transit_abilities, true, cancel).await
+}
+
+/** The offer received by [`request_inner`], before building a [`ReceiveRequest`] out of it */
+enum ReceivedOffer {
+    Text(String),
+    File {
+        filename: String,
+        filesize: u64,
+        unpacked_size: Option<u64>,
+        sha256: Option<[u8; 32]>,
+        compressed: bool,
+        connector: TransitConnector,
+        their_abilities: transit::Abilities,
+        their_hints: transit::Hints,
+    },
+}
+
+#[tracing::instrument(name = "request", skip_all, fields(version = 1, side = %wormhole.side()))]
+async fn request_inner(
+    mut wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    accept_text: bool,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<TextOrFileRequest>, TransferError> {
     // Error handling
     let run = Box::pin(async {
-        let connector = transit::init(transit_abilities, None, relay_hints).await?;
+        let connector = transit::init(
+            wormhole.transit_abilities(transit_abilities),
+            None,
+            relay_hints,
+        )
+        .await?
+        .with_timeouts(wormhole.timeouts())
+        .with_proxy(wormhole.proxy())
+        .with_rate_limit(wormhole.rate_limit());
 
         // send the transit message
         tracing::debug!("Sending transit message '{:?}", connector.our_hints());
@@ -420,59 +752,135 @@ pub async fn request(
             ))
             .await?;
 
-        // receive transit message
-        let (their_abilities, their_hints): (transit::Abilities, transit::Hints) =
+        /* Receive the transit message and the offer. Senders of text messages don't send
+         * any transit message, so the offer might come first.
+         */
+        let mut their_transit = None;
+        let offer = loop {
             match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
-                PeerMessage::Transit(transit) => {
+                PeerMessage::Transit(transit) if their_transit.is_none() => {
                     tracing::debug!("received transit message: {:?}", transit);
-                    (transit.abilities_v1, transit.hints_v1)
+                    their_transit = Some((transit.abilities_v1, transit.hints_v1));
                 },
+                PeerMessage::Offer(offer_type) => break offer_type,
                 other => {
                     bail!(TransferError::unexpected_message("transit", other));
                 },
-            };
+            }
+        };
 
-        // 3. receive file offer message from peer
-        let (filename, filesize) =
-            match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
-                PeerMessage::Offer(offer_type) => match offer_type {
-                    v1::OfferMessage::File { filename, filesize } => (filename, filesize),
-                    v1::OfferMessage::Directory {
-                        mut dirname,
-                        zipsize,
-                        ..
-                    } => {
-                        dirname.push_str(".zip");
-                        (dirname, zipsize)
-                    },
-                    _ => bail!(TransferError::UnsupportedOffer),
-                },
-                other => {
-                    bail!(TransferError::unexpected_message("offer", other));
-                },
-            };
+        // 3. handle the offer message from the peer
+        let (filename, filesize, unpacked_size, sha256, compressed) = match offer {
+            v1::OfferMessage::Message(text) if accept_text => {
+                tracing::debug!("Received text message, sending ack");
+                wormhole
+                    .send_json(&PeerMessage::message_ack_v1("ok"))
+                    .await?;
+                return Ok(ReceivedOffer::Text(text));
+            },
+            v1::OfferMessage::File {
+                filename,
+                filesize,
+                sha256,
+                compression,
+            } => {
+                let sha256 = sha256.as_deref().map(parse_sha256).transpose()?;
+                let compressed = match compression {
+                    None => false,
+                    Some(Compression::Zstd) if compression::SUPPORTED => true,
+                    Some(_) => bail!(TransferError::UnsupportedOffer),
+                };
+                (filename, filesize, None, sha256, compressed)
+            },
+            v1::OfferMessage::Directory {
+                mut dirname,
+                mode,
+                zipsize,
+                numbytes,
+                ..
+            } => {
+                /* Like the Python implementation, we don't know any other mode */
+                ensure!(mode == DIRECTORY_MODE, TransferError::UnsupportedOffer);
+                dirname.push_str(".zip");
+                (dirname, zipsize, Some(numbytes), None, false)
+            },
+            _ => bail!(TransferError::UnsupportedOffer),
+        };
+
+        let Some((their_abilities, their_hints)) = their_transit else {
+            bail!(TransferError::unexpected_message("transit", "offer"));
+        };
 
-        Ok((filename, filesize, connector, their_abilities, their_hints))
+        Ok(ReceivedOffer::File {
+            filename,
+            filesize,
+            unpacked_size,
+            sha256,
+            compressed,
+            connector,
+            their_abilities,
+            their_hints,
+        })
     });
 
     futures::pin_mut!(cancel);
     let result = cancel::cancellable_2(run, cancel).await;
-    cancel::handle_run_result_noclose(wormhole, result)
-        .await
-        .map(|inner: Option<_>| {
-            inner.map(
-                |((filename, filesize, connector, their_abilities, their_hints), wormhole, _)| {
-                    ReceiveRequest::new(
-                        filename,
-                        filesize,
-                        connector,
-                        their_abilities,
-                        their_hints,
-                        wormhole,
-                    )
-                },
-            )
-        })
+    match cancel::handle_run_result_noclose(wormhole, result).await? {
+        Some((ReceivedOffer::Text(text), wormhole, _)) => {
+            wormhole.close().await?;
+            Ok(Some(TextOrFileRequest::Text(text)))
+        },
+        Some((
+            ReceivedOffer::File {
+                filename,
+                filesize,
+                unpacked_size,
+                sha256,
+                compressed,
+                connector,
+                their_abilities,
+                their_hints,
+            },
+            wormhole,
+            _,
+        )) => Ok(Some(TextOrFileRequest::File(ReceiveRequest::new(
+            filename,
+            filesize,
+            unpacked_size,
+            sha256,
+            compressed,
+            connector,
+            their_abilities,
+            their_hints,
+            wormhole,
+        )))),
+        None => Ok(None),
+    }
+}
+
+/** Parse the hex encoded SHA-256 of a file offer */
+fn parse_sha256(sha256: &str) -> Result<[u8; 32], TransferError> {
+    let mut hash = [0; 32];
+    hex::decode_to_slice(sha256, &mut hash).map_err(|_| {
+        TransferError::Protocol(format!("Invalid SHA-256 {sha256:?} in the offer").into())
+    })?;
+    Ok(hash)
+}
+
+/**
+ * What the other side sent us: either a short text message or a file offer
+ *
+ * Returned by [`request_text_or_file`].
+ */
+#[must_use]
+#[non_exhaustive]
+pub enum TextOrFileRequest {
+    /// A text message. It has already been acknowledged, and the wormhole is closed.
+    ///
+    /// **Security warning:** this is untrusted and unverified input
+    Text(String),
+    /// A file offer, which you *should* either accept or reject
+    File(ReceiveRequest),
 }
 
 /**
@@ -494,6 +902,11 @@ pub struct ReceiveRequest {
     /// The expected size of the file
     #[deprecated(since = "0.7.0", note = "use ReceiveRequest::file_size(..) instead")]
     pub filesize: u64,
+    /** The total size of the files, for directories */
+    unpacked_size: Option<u64>,
+    sha256: Option<[u8; 32]>,
+    /// The records are compressed, see [`Compression`]
+    compressed: bool,
 
     #[allow(dead_code)]
     offer: Arc<Offer>,
@@ -506,6 +919,9 @@ impl ReceiveRequest {
     fn new(
         file_name: String,
         filesize: u64,
+        unpacked_size: Option<u64>,
+        sha256: Option<[u8; 32]>,
+        compressed: bool,
         connector: TransitConnector,
         their_abilities: transit::Abilities,
         their_hints: transit::Hints,
@@ -533,6 +949,9 @@ impl ReceiveRequest {
             filename: PathBuf::from(file_name.clone()),
             file_name,
             filesize,
+            unpacked_size,
+            sha256,
+            compressed,
             offer,
             their_abilities,
             their_hints,
@@ -545,7 +964,7 @@ impl ReceiveRequest {
      * This will transfer the file and save it on disk.
      */
     pub async fn accept<F, G, W>(
-        mut self,
+        self,
         transit_handler: G,
         progress_handler: F,
         content_handler: &mut W,
@@ -556,6 +975,29 @@ impl ReceiveRequest {
         G: FnOnce(transit::TransitInfo),
         W: AsyncWrite + Unpin,
     {
+        self.accept_inner(transit_handler, progress_handler, content_handler, cancel)
+            .await
+            .map(|_| ())
+    }
+
+    /** Like [`accept`](Self::accept), returning the receipt or `None` if the task got cancelled */
+    async fn accept_inner<F, G, W>(
+        mut self,
+        transit_handler: G,
+        progress_handler: F,
+        content_handler: &mut W,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<TransferReceipt>, TransferError>
+    where
+        F: FnMut(u64, u64) + 'static,
+        G: FnOnce(transit::TransitInfo),
+        W: AsyncWrite + Unpin,
+    {
+        let mut receipt = None;
+        let events = self.wormhole.event_sender().clone();
+        let started = crate::util::Instant::now();
+        let transit_handler = events.transit_handler(transit_handler);
+        let progress_handler = events.progress_handler(Direction::Receive, progress_handler);
         let run = Box::pin(async {
             // send file ack.
             tracing::debug!("Sending ack");
@@ -576,19 +1018,176 @@ impl ReceiveRequest {
             transit_handler(info);
 
             tracing::debug!("Beginning file transfer");
-            tcp_file_receive(
-                &mut transit,
-                self.filesize,
-                progress_handler,
-                content_handler,
-            )
-            .await?;
+            let hash = self
+                .wormhole
+                .drive_app_messages(tcp_file_receive(
+                    &mut transit,
+                    self.filesize,
+                    self.sha256,
+                    self.compressed,
+                    progress_handler,
+                    content_handler,
+                ))
+                .await?;
+            receipt = Some(TransferReceipt {
+                hash,
+                bytes: self.filesize,
+                duration: started.elapsed(),
+            });
             Ok(())
         });
 
         futures::pin_mut!(cancel);
         let result = cancel::cancellable_2(run, cancel).await;
-        cancel::handle_run_result(self.wormhole, result).await
+        events.transfer_finished(
+            Direction::Receive,
+            started,
+            cancel::handle_run_result(self.wormhole, result)
+                .await
+                .map(|()| receipt),
+        )
+    }
+
+    /**
+     * Accept the directory offer and unpack it into `target_dir`
+     *
+     * Only use this if [`is_directory`](ReceiveRequest::is_directory). The archive gets unpacked
+     * while it is received, without storing it. `target_dir` should be a new, empty directory.
+     * Existing files don't get overwritten, and the transfer fails if the archive contains paths
+     * leading outside of `target_dir`. The progress refers to the size of the archive.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn accept_directory<F, G>(
+        self,
+        transit_handler: G,
+        progress_handler: F,
+        target_dir: &std::path::Path,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<(), TransferError>
+    where
+        F: FnMut(u64, u64) + 'static,
+        G: FnOnce(transit::TransitInfo),
+    {
+        self.unpack_directory(transit_handler, progress_handler, target_dir, cancel)
+            .await
+            .map(|_| ())
+    }
+
+    /** Like [`accept_directory`](Self::accept_directory), returning the receipt of the archive */
+    #[cfg(not(target_family = "wasm"))]
+    async fn unpack_directory<F, G>(
+        self,
+        transit_handler: G,
+        progress_handler: F,
+        target_dir: &std::path::Path,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<TransferReceipt>, TransferError>
+    where
+        F: FnMut(u64, u64) + 'static,
+        G: FnOnce(transit::TransitInfo),
+    {
+        let Some(unpacked_size) = self.unpacked_size else {
+            self.reject().await?;
+            bail!(TransferError::UnsupportedOffer);
+        };
+
+        let (mut writer, reader) = zip::pipe();
+        let receive = async {
+            let result = self
+                .accept_inner(transit_handler, progress_handler, &mut writer, cancel)
+                .await;
+            /* Let the unpacking know that there is nothing more to come */
+            drop(writer);
+            result
+        };
+        match futures::join!(receive, zip::unpack(reader, target_dir, unpacked_size)) {
+            /* Unpacking stops early when the transfer fails, so the latter is the cause */
+            (Err(err), Err(unpack_err))
+                if unpack_err.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                Err(err)
+            },
+            (_, Err(err)) => Err(err.into()),
+            (result, Ok(())) => result,
+        }
+    }
+
+    /**
+     * Accept the file offer and save it at `path`
+     *
+     * The file is received into a temporary file next to `path`, with `.part` appended to its name.
+     * Only once the transfer is complete it gets synced to disk and renamed to `path`, so that an
+     * interrupted transfer never leaves a truncated file under the final name. A temporary file left
+     * over by an earlier attempt gets overwritten, and the temporary file is removed if the transfer
+     * fails. Unless `overwrite` is set, the transfer fails if `path`
+     * exists. If the sender announced the [`sha256`](Self::sha256) of the file, the transfer fails
+     * unless the received file matches it.
+     *
+     * Returns `None` if the task got cancelled.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn accept_to_path<F, G>(
+        self,
+        transit_handler: G,
+        progress_handler: F,
+        path: &std::path::Path,
+        overwrite: bool,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<TransferReceipt>, TransferError>
+    where
+        F: FnMut(u64, u64) + 'static,
+        G: FnOnce(transit::TransitInfo),
+    {
+        let already_exists = || {
+            std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            )
+        };
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = std::path::PathBuf::from(part_path);
+
+        let file = if !overwrite && path.exists() {
+            Err(already_exists())
+        } else {
+            async_std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&part_path)
+                .await
+        };
+        let mut file = match file {
+            Ok(file) => file,
+            Err(err) => {
+                self.reject().await?;
+                bail!(err);
+            },
+        };
+
+        let result = async {
+            let Some(receipt) = self
+                .accept_inner(transit_handler, progress_handler, &mut file, cancel)
+                .await?
+            else {
+                return Ok(None);
+            };
+            file.sync_all().await?;
+            drop(file);
+            if !overwrite && path.exists() {
+                bail!(already_exists());
+            }
+            async_std::fs::rename(&part_path, path).await?;
+            Ok::<_, TransferError>(Some(receipt))
+        }
+        .await;
+        if !matches!(result, Ok(Some(_))) {
+            if let Err(err) = async_std::fs::remove_file(&part_path).await {
+                tracing::warn!("Failed to remove {}: {}", part_path.display(), err);
+            }
+        }
+        result
     }
 
     /**
@@ -618,18 +1217,37 @@ impl ReceiveRequest {
         self.file_name.clone()
     }
 
+    /// The SHA-256 of the file, if the sender announced it. The transfer fails if the received file
+    /// doesn't match it.
+    pub fn sha256(&self) -> Option<[u8; 32]> {
+        self.sha256
+    }
+
     /// The expected file size
     pub fn file_size(&self) -> u64 {
         self.filesize
     }
+
+    /// Whether the other side offers a directory, as zip archive. The [`file_name`](ReceiveRequest::file_name)
+    /// then ends with `.zip`, and [`accept_directory`](ReceiveRequest::accept_directory) unpacks it.
+    pub fn is_directory(&self) -> bool {
+        self.unpacked_size.is_some()
+    }
 }
 
+/** The directory mode of the Python implementation, the only one it accepts */
+const DIRECTORY_MODE: &str = "zipfile/deflated";
+
+/** The size of the records, before compression */
+const RECORD_SIZE: usize = 16 * 1024;
+
 // encrypt and send the file to tcp stream and return the sha256 sum
 // of the file before encryption.
 pub(crate) async fn send_records<'a>(
     transit: &mut Transit,
     files: impl futures::Stream<Item = std::io::Result<Box<dyn AsyncRead + Unpin + Send + 'a>>>,
     file_size: u64,
+    compress: bool,
     mut progress_handler: impl FnMut(u64, u64) + 'static,
 ) -> Result<Vec<u8>, TransferError> {
     // rough plan:
@@ -646,7 +1264,7 @@ pub(crate) async fn send_records<'a>(
 
     let mut hasher = Sha256::default();
 
-    let mut plaintext = vec![0u8; 16 * 1024].into_boxed_slice();
+    let mut plaintext = vec![0u8; RECORD_SIZE].into_boxed_slice();
     let mut sent_size = 0;
     futures::pin_mut!(files);
     while let Some(mut file) = files.next().await.transpose()? {
@@ -660,7 +1278,13 @@ pub(crate) async fn send_records<'a>(
             }
 
             // send the encrypted record
-            transit.send_record(&plaintext[0..n]).await?;
+            if compress {
+                transit
+                    .send_record(&compression::compress(&plaintext[0..n])?)
+                    .await?;
+            } else {
+                transit.send_record(&plaintext[0..n]).await?;
+            }
             sent_size += n as u64;
             progress_handler(sent_size, file_size);
 
@@ -688,10 +1312,11 @@ pub(crate) async fn send_records<'a>(
 
 pub(crate) async fn receive_records<F, W>(
     filesize: u64,
+    compressed: bool,
     transit: &mut Transit,
     mut progress_handler: F,
     mut content_handler: W,
-) -> Result<Vec<u8>, TransferError>
+) -> Result<[u8; 32], TransferError>
 where
     F: FnMut(u64, u64) + 'static,
     W: AsyncWrite + Unpin,
@@ -707,7 +1332,10 @@ where
 
     while remaining_size > 0 {
         // 3. decrypt the vector 'enc_packet' with the key.
-        let plaintext = transit.receive_record().await?;
+        let mut plaintext = transit.receive_record().await?;
+        if compressed {
+            plaintext = compression::decompress(&plaintext, RECORD_SIZE)?.into_boxed_slice();
+        }
 
         content_handler.write_all(&plaintext).await?;
 
@@ -723,15 +1351,17 @@ where
 
     tracing::debug!("done");
     // TODO: 5. write the buffer into a file.
-    Ok(hasher.finalize_fixed().to_vec())
+    Ok(hasher.finalize_fixed().into())
 }
 
 pub(crate) async fn tcp_file_receive<F, W>(
     transit: &mut Transit,
     filesize: u64,
+    expected_sha256: Option<[u8; 32]>,
+    compressed: bool,
     progress_handler: F,
     content_handler: &mut W,
-) -> Result<(), TransferError>
+) -> Result<[u8; 32], TransferError>
 where
     F: FnMut(u64, u64) + 'static,
     W: AsyncWrite + Unpin,
@@ -739,10 +1369,20 @@ where
     // 5. receive encrypted records
     // now skey and rkey can be used. skey is used by the tx side, rkey is used
     // by the rx side for symmetric encryption.
-    let checksum = receive_records(filesize, transit, progress_handler, content_handler).await?;
+    let checksum = receive_records(
+        filesize,
+        compressed,
+        transit,
+        progress_handler,
+        content_handler,
+    )
+    .await?;
 
     let sha256sum = hex::encode(checksum.as_slice());
     tracing::debug!("sha256 sum: {:?}", sha256sum);
+    if let Some(expected_sha256) = expected_sha256 {
+        ensure!(checksum == expected_sha256, TransferError::Checksum);
+    }
 
     // 6. verify sha256 sum by sending an ack message to peer along with checksum.
     transit
@@ -752,7 +1392,7 @@ where
     // 7. close socket.
     // well, no need, it gets dropped when it goes out of scope.
     tracing::debug!("Transfer complete");
-    Ok(())
+    Ok(checksum)
 }
 
 /// Custom functions from the `tar` crate to access internals
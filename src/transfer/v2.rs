@@ -3,6 +3,7 @@ use serde_derive::{Deserialize, Serialize};
 use sha2::{digest::FixedOutput, Sha256};
 
 use super::{offer::*, *};
+use crate::{core::EventSender, metrics::Direction};
 
 /**
  * A set of hints for both sides to find each other
@@ -104,7 +105,15 @@ async fn make_transit(
     transit_abilities: transit::Abilities,
     peer_abilities: transit::Abilities,
 ) -> Result<(transit::Transit, transit::TransitInfo), TransferError> {
-    let connector = transit::init(transit_abilities, Some(peer_abilities), relay_hints).await?;
+    let connector = transit::init(
+        wormhole.transit_abilities(transit_abilities),
+        Some(peer_abilities),
+        relay_hints,
+    )
+    .await?
+    .with_timeouts(wormhole.timeouts())
+    .with_proxy(wormhole.proxy())
+    .with_rate_limit(wormhole.rate_limit());
 
     /* Send our transit hints */
     wormhole
@@ -150,6 +159,7 @@ async fn make_transit(
     Ok((transit, info))
 }
 
+#[tracing::instrument(skip_all, fields(version = 2, side = %wormhole.side()))]
 pub async fn send(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
@@ -160,44 +170,51 @@ pub async fn send(
     cancel: impl Future<Output = ()>,
 ) -> Result<(), TransferError> {
     let peer_abilities = peer_version.transfer_v2.unwrap();
-    futures::pin_mut!(cancel);
+    let events = wormhole.event_sender().clone();
+    let started = crate::util::Instant::now();
+    let progress_handler = events.progress_handler(Direction::Send, progress_handler);
+    let result = async {
+        futures::pin_mut!(cancel);
 
-    /* Establish transit connection, close the Wormhole and switch to using the transit connection (msgpack instead of json) */
-    let (mut transit, wormhole, cancel) = cancel::with_cancel_wormhole!(
-        wormhole,
-        run = async {
-            Ok(make_transit(
-                &mut wormhole,
-                true,
-                relay_hints,
-                transit_abilities,
-                peer_abilities.transit_abilities,
-            )
-            .await?
-            .0)
-        },
-        cancel,
-        ret_cancel = (),
-    );
+        /* Establish transit connection, close the Wormhole and switch to using the transit connection (msgpack instead of json) */
+        let (mut transit, wormhole, cancel) = cancel::with_cancel_wormhole!(
+            wormhole,
+            run = async {
+                Ok(make_transit(
+                    &mut wormhole,
+                    true,
+                    relay_hints,
+                    transit_abilities,
+                    peer_abilities.transit_abilities,
+                )
+                .await?
+                .0)
+            },
+            cancel,
+            ret_cancel = (),
+        );
 
-    cancel::with_cancel_transit!(
-        transit,
-        run = async {
-            /* Close the wormhole only here so that the operation may be cancelled */
-            wormhole.close().await?;
+        cancel::with_cancel_transit!(
+            transit,
+            run = async {
+                /* Close the wormhole only here so that the operation may be cancelled */
+                wormhole.close().await?;
 
-            send_inner(&mut transit, offer, progress_handler).await
-        },
-        cancel,
-        |err| PeerMessageV2::Error(err.to_string()).ser_msgpack(),
-        |msg| match PeerMessageV2::de_msgpack(msg)? {
-            PeerMessageV2::Error(err) => Ok(Some(err)),
-            _ => Ok(None),
-        },
-        ret_cancel = (),
-    );
+                send_inner(&mut transit, offer, progress_handler).await
+            },
+            cancel,
+            |err| PeerMessageV2::Error(err.to_string()).ser_msgpack(),
+            |msg| match PeerMessageV2::de_msgpack(msg)? {
+                PeerMessageV2::Error(err) => Ok(Some(err)),
+                _ => Ok(None),
+            },
+            ret_cancel = (),
+        );
 
-    Ok(())
+        Ok(())
+    }
+    .await;
+    events.transfer_finished(Direction::Send, started, result)
 }
 
 /** We've established the transit connection and closed the Wormhole */
@@ -247,7 +264,7 @@ async fn send_inner(
 
         /* If they specified a hash, check our local file's contents */
         if let Some(sha256) = sha256 {
-            content.seek(std::io::SeekFrom::Start(offset)).await?;
+            content.seek(std::io::SeekFrom::Start(0)).await?;
             let mut hasher = Sha256::default();
             futures::io::copy(
                 (&mut content).take(offset),
@@ -258,6 +275,8 @@ async fn send_inner(
 
             /* If it doesn't match, start at 0 instead of the originally requested offset */
             if *our_hash == sha256[..] {
+                /* The hashing left us right at the offset */
+                total_sent += offset;
                 transit
                     .send_record(
                         &PeerMessageV2::FileStart(FileStart {
@@ -278,10 +297,10 @@ async fn send_inner(
                     )
                     .await?;
                 content.seek(std::io::SeekFrom::Start(0)).await?;
-                // offset = 0; TODO
             }
         } else {
             content.seek(std::io::SeekFrom::Start(offset)).await?;
+            total_sent += offset;
             transit
                 .send_record(
                     &PeerMessageV2::FileStart(FileStart {
@@ -330,6 +349,7 @@ async fn send_inner(
     Ok(())
 }
 
+#[tracing::instrument(skip_all, fields(version = 2, side = %wormhole.side()))]
 pub async fn request(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
@@ -338,6 +358,8 @@ pub async fn request(
     cancel: impl Future<Output = ()>,
 ) -> Result<Option<ReceiveRequest>, TransferError> {
     let peer_abilities = peer_version.transfer_v2.unwrap();
+    /* Keep reporting events after the wormhole got closed */
+    let events = wormhole.event_sender().clone();
     futures::pin_mut!(cancel);
 
     /* Establish transit connection, close the Wormhole and switch to using the transit connection (msgpack instead of json) */
@@ -382,7 +404,7 @@ pub async fn request(
         ret_cancel = None,
     );
 
-    Ok(Some(ReceiveRequest::new(transit, offer, info)))
+    Ok(Some(ReceiveRequest::new(transit, offer, info, events)))
 }
 
 /**
@@ -395,14 +417,21 @@ pub struct ReceiveRequest {
     transit: Transit,
     offer: Arc<Offer>,
     info: transit::TransitInfo,
+    events: EventSender,
 }
 
 impl ReceiveRequest {
-    pub fn new(transit: Transit, offer: Offer, info: transit::TransitInfo) -> Self {
+    pub(crate) fn new(
+        transit: Transit,
+        offer: Offer,
+        info: transit::TransitInfo,
+        events: EventSender,
+    ) -> Self {
         Self {
             transit,
             offer: Arc::new(offer),
             info,
+            events,
         }
     }
 
@@ -423,38 +452,45 @@ impl ReceiveRequest {
         progress_handler: impl FnMut(u64, u64) + 'static,
         cancel: impl Future<Output = ()>,
     ) -> Result<(), TransferError> {
-        transit_handler(self.info);
-        futures::pin_mut!(cancel);
-
-        let mut transit = self.transit;
-        cancel::with_cancel_transit!(
-            transit,
-            run = async {
-                transit.send_record(&{
-                    /* This must be split into two statements to appease the borrow checker (unfortunate side effect of borrow-through) */
-                    let msg = PeerMessageV2::Answer(AnswerMessage {
-                    files: answer.iter_files()
-                        .map(|(path, inner, _size)| AnswerMessageInner {
-                            file: path,
-                            offset: inner.offset,
-                            sha256: inner.sha256,
-                        })
-                        .collect(),
-                    }).ser_msgpack();
-                    msg
-                }).await?;
-
-                receive_inner(&mut transit, &self.offer, answer, progress_handler).await
-            },
-            cancel,
-            |err| PeerMessageV2::Error(err.to_string()).ser_msgpack(),
-            |msg| match PeerMessageV2::de_msgpack(msg)? {
-                PeerMessageV2::Error(err) => Ok(Some(err)),
-                _ => Ok(None),
-            },
-            ret_cancel = (),
-        );
-        Ok(())
+        let events = self.events.clone();
+        let started = crate::util::Instant::now();
+        events.transit_handler(transit_handler)(self.info);
+        let progress_handler = events.progress_handler(Direction::Receive, progress_handler);
+        let result = async {
+            futures::pin_mut!(cancel);
+
+            let mut transit = self.transit;
+            cancel::with_cancel_transit!(
+                transit,
+                run = async {
+                    transit.send_record(&{
+                        /* This must be split into two statements to appease the borrow checker (unfortunate side effect of borrow-through) */
+                        let msg = PeerMessageV2::Answer(AnswerMessage {
+                        files: answer.iter_files()
+                            .map(|(path, inner, _size)| AnswerMessageInner {
+                                file: path,
+                                offset: inner.offset,
+                                sha256: inner.sha256,
+                            })
+                            .collect(),
+                        }).ser_msgpack();
+                        msg
+                    }).await?;
+
+                    receive_inner(&mut transit, &self.offer, answer, progress_handler).await
+                },
+                cancel,
+                |err| PeerMessageV2::Error(err.to_string()).ser_msgpack(),
+                |msg| match PeerMessageV2::de_msgpack(msg)? {
+                    PeerMessageV2::Error(err) => Ok(Some(err)),
+                    _ => Ok(None),
+                },
+                ret_cancel = (),
+            );
+            Ok(())
+        }
+        .await;
+        events.transfer_finished(Direction::Receive, started, result)
     }
 
     /**
@@ -526,12 +562,14 @@ async fn receive_inner(
             content = (answer.content)(true).await?;
             let offset = answer.offset;
             received_size = offset;
+            total_received += offset;
         } else {
             content = (answer.content)(false).await?;
         }
 
         progress_handler(total_received, total_size);
-        loop {
+        /* Resumed files might be complete already */
+        while received_size < size {
             let payload =
                 match PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err()? {
                     PeerMessageV2::Payload(payload) => payload.payload,
@@ -554,16 +592,14 @@ async fn receive_inner(
             total_received += payload.len() as u64;
             progress_handler(total_received, total_size);
 
-            if received_size == size {
-                break;
-            } else if received_size >= size {
+            if received_size > size {
                 /* `received_size` must never become greater than `size` or we might panic on an integer underflow in the next iteration
                  * (only on an unhappy path, but still). Also, the progress bar might not appreciate.
                  */
                 bail!(TransferError::Protocol(
                     format!(
                         "File too large: expected only {size} bytes, got at least {} more",
-                        size - received_size
+                        received_size - size
                     )
                     .into_boxed_str()
                 ))
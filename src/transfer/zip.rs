@@ -0,0 +1,661 @@
+//! Directories in transfer v1, as zip archives
+//!
+//! The Python implementation sends directories as zip archives. We generate them on the fly while
+//! sending, without compression, and unpack them while receiving. Neither side keeps the archive in
+//! memory or on disk. ZIP64 is not supported, larger directories get sent as tar file instead.
+
+use super::offer::OfferSendEntry;
+use futures::{
+    future::{ready, BoxFuture},
+    io::{AsyncRead, AsyncReadExt, Cursor},
+    TryFutureExt,
+};
+use std::{
+    collections::BTreeMap,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/** Part of the archive. They get read one after another, and only created when needed. */
+pub(super) type ArchivePart = BoxFuture<'static, io::Result<Box<dyn AsyncRead + Unpin + Send>>>;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+
+const LOCAL_HEADER_SIZE: u64 = 30;
+const DATA_DESCRIPTOR_SIZE: u64 = 16;
+const CENTRAL_HEADER_SIZE: u64 = 46;
+const END_OF_CENTRAL_DIRECTORY_SIZE: u64 = 22;
+
+/* Version 2.0 covers directories and deflate */
+const VERSION: u16 = 20;
+/* The CRC-32 follows the data, in a data descriptor */
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+const FLAG_UTF8: u16 = 0x0800;
+const METHOD_STORED: u16 = 0;
+/* 1980-01-01 00:00, the earliest time zip can express. The receivers don't use it anyways. */
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+const ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+fn wrap(buffer: Vec<u8>) -> ArchivePart {
+    Box::pin(ready(io::Result::Ok(
+        Box::new(Cursor::new(buffer)) as Box<dyn AsyncRead + Unpin + Send>
+    ))) as _
+}
+
+/** Little endian encoding of the archive structures */
+trait PutLe {
+    fn put_u16(&mut self, value: u16);
+    fn put_u32(&mut self, value: u32);
+}
+
+impl PutLe for Vec<u8> {
+    fn put_u16(&mut self, value: u16) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/** A file or directory in the archive */
+struct Entry<'a> {
+    /** Relative to the directory, with `/` as separator. Directories end with a `/`. */
+    name: String,
+    /** `None` for directories */
+    file: Option<(u64, &'a super::offer::OfferContent)>,
+}
+
+/* Visit all entries in archive order, i.e. every directory before its content */
+fn walk<'a>(
+    content: &'a BTreeMap<String, OfferSendEntry>,
+    prefix: &str,
+    entries: &mut Vec<Entry<'a>>,
+) {
+    for (name, entry) in content {
+        match entry {
+            OfferSendEntry::Directory { content } => {
+                let name = format!("{prefix}{name}/");
+                entries.push(Entry {
+                    name: name.clone(),
+                    file: None,
+                });
+                walk(content, &name, entries);
+            },
+            OfferSendEntry::RegularFile { size, content } => entries.push(Entry {
+                name: format!("{prefix}{name}"),
+                file: Some((*size, content)),
+            }),
+        }
+    }
+}
+
+/** Feeds the content of a file into its CRC-32 while it gets sent */
+struct CrcReader<R> {
+    inner: R,
+    hasher: Arc<Mutex<crc32fast::Hasher>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CrcReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.hasher.lock().unwrap().update(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+}
+
+/**
+ * An uncompressed zip archive of a directory, generated while it gets sent
+ *
+ * All sizes are known in advance, only the checksums get calculated on the fly. They go into data
+ * descriptors after each file and into the central directory at the end.
+ */
+pub(super) struct ZipArchive {
+    /// Size of the whole archive
+    pub size: u64,
+    /// Size of all files in it
+    pub num_bytes: u64,
+    /// Number of files in it, not counting directories
+    pub num_files: u64,
+    pub content: Vec<ArchivePart>,
+}
+
+impl ZipArchive {
+    /**
+     * Build the archive for `folder`. Its own name is not part of the archive.
+     *
+     * Returns `None` if `folder` is not a directory or if the archive would need ZIP64.
+     */
+    pub fn new(folder: &OfferSendEntry) -> Option<Self> {
+        let OfferSendEntry::Directory { content } = folder else {
+            return None;
+        };
+        let mut entries = Vec::new();
+        walk(content, "", &mut entries);
+
+        let size = entries
+            .iter()
+            .map(|entry| {
+                let name = entry.name.len() as u64;
+                let data = entry
+                    .file
+                    .map_or(0, |(size, _)| size + DATA_DESCRIPTOR_SIZE);
+                LOCAL_HEADER_SIZE + CENTRAL_HEADER_SIZE + 2 * name + data
+            })
+            .sum::<u64>()
+            + END_OF_CENTRAL_DIRECTORY_SIZE;
+        /* With the whole archive fitting, all offsets and sizes in it fit as well */
+        if size > u32::MAX as u64 || entries.len() > u16::MAX as usize {
+            return None;
+        }
+
+        let mut this = Self {
+            size,
+            num_bytes: 0,
+            num_files: 0,
+            content: Vec::new(),
+        };
+        let mut central_directory = Vec::new();
+        let mut offset = 0u64;
+        for Entry { name, file } in entries {
+            let (flags, size) = match file {
+                Some((size, _)) => (FLAG_UTF8 | FLAG_DATA_DESCRIPTOR, size as u32),
+                None => (FLAG_UTF8, 0),
+            };
+            let mut header = Vec::with_capacity(LOCAL_HEADER_SIZE as usize + name.len());
+            header.put_u32(LOCAL_HEADER_SIGNATURE);
+            header.put_u16(VERSION);
+            header.put_u16(flags);
+            header.put_u16(METHOD_STORED);
+            header.put_u16(DOS_TIME);
+            header.put_u16(DOS_DATE);
+            /* The CRC-32 follows in the data descriptor. The sizes are known, and the receivers
+             * need them to find the end of uncompressed data.
+             */
+            header.put_u32(0);
+            header.put_u32(size);
+            header.put_u32(size);
+            header.put_u16(name.len() as u16);
+            header.put_u16(0);
+            header.extend_from_slice(name.as_bytes());
+            this.content.push(wrap(header));
+
+            let hasher = Arc::new(Mutex::new(crc32fast::Hasher::new()));
+            if let Some((file_size, content)) = file {
+                this.num_files += 1;
+                this.num_bytes += file_size;
+
+                let content = content().map_ok({
+                    let hasher = hasher.clone();
+                    move |read| {
+                        Box::new(CrcReader {
+                            inner: read.take(file_size),
+                            hasher,
+                        }) as Box<dyn AsyncRead + Unpin + Send>
+                    }
+                });
+                this.content.push(Box::pin(content));
+
+                let hasher = hasher.clone();
+                this.content.push(Box::pin(async move {
+                    let crc = hasher.lock().unwrap().clone().finalize();
+                    let mut descriptor = Vec::with_capacity(DATA_DESCRIPTOR_SIZE as usize);
+                    descriptor.put_u32(DATA_DESCRIPTOR_SIGNATURE);
+                    descriptor.put_u32(crc);
+                    descriptor.put_u32(size);
+                    descriptor.put_u32(size);
+                    io::Result::Ok(
+                        Box::new(Cursor::new(descriptor)) as Box<dyn AsyncRead + Unpin + Send>
+                    )
+                }));
+            }
+
+            let header_offset = offset as u32;
+            offset += LOCAL_HEADER_SIZE + name.len() as u64;
+            if file.is_some() {
+                offset += size as u64 + DATA_DESCRIPTOR_SIZE;
+            }
+            central_directory.push((name, flags, size, header_offset, file.is_some(), hasher));
+        }
+
+        let num_entries = central_directory.len() as u16;
+        this.content.push(Box::pin(async move {
+            let mut buffer = Vec::new();
+            for (name, flags, size, header_offset, is_file, hasher) in central_directory {
+                buffer.put_u32(CENTRAL_HEADER_SIGNATURE);
+                buffer.put_u16(VERSION);
+                buffer.put_u16(VERSION);
+                buffer.put_u16(flags);
+                buffer.put_u16(METHOD_STORED);
+                buffer.put_u16(DOS_TIME);
+                buffer.put_u16(DOS_DATE);
+                buffer.put_u32(if is_file {
+                    hasher.lock().unwrap().clone().finalize()
+                } else {
+                    0
+                });
+                buffer.put_u32(size);
+                buffer.put_u32(size);
+                buffer.put_u16(name.len() as u16);
+                /* Extra field, comment, disk number and internal attributes */
+                buffer.put_u16(0);
+                buffer.put_u16(0);
+                buffer.put_u16(0);
+                buffer.put_u16(0);
+                buffer.put_u32(if is_file { 0 } else { ATTRIBUTE_DIRECTORY });
+                buffer.put_u32(header_offset);
+                buffer.extend_from_slice(name.as_bytes());
+            }
+
+            let central_directory_size = buffer.len() as u32;
+            buffer.put_u32(END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+            buffer.put_u16(0);
+            buffer.put_u16(0);
+            buffer.put_u16(num_entries);
+            buffer.put_u16(num_entries);
+            buffer.put_u32(central_directory_size);
+            buffer.put_u32(offset as u32);
+            buffer.put_u16(0);
+            io::Result::Ok(Box::new(Cursor::new(buffer)) as Box<dyn AsyncRead + Unpin + Send>)
+        }));
+
+        Some(this)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(super) use unpacking::{pipe, unpack};
+
+#[cfg(not(target_family = "wasm"))]
+mod unpacking {
+    use super::*;
+    use futures::{
+        channel::mpsc,
+        io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+        StreamExt, TryStreamExt,
+    };
+    use std::path::{Component, Path, PathBuf};
+
+    const FLAG_ENCRYPTED: u16 = 0x0001;
+    const METHOD_DEFLATED: u16 = 8;
+
+    fn invalid_data(message: impl Into<String>) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message.into())
+    }
+
+    async fn read_u32(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<u32> {
+        let mut buffer = [0; 4];
+        reader.read_exact(&mut buffer).await?;
+        Ok(u32::from_le_bytes(buffer))
+    }
+
+    /**
+     * Where to put an entry, or an error if it would end up outside of `target_dir`
+     *
+     * Leading slashes are ignored, like Python's `zipfile` does.
+     */
+    pub(super) fn entry_path(target_dir: &Path, name: &str) -> io::Result<PathBuf> {
+        let mut path = target_dir.to_path_buf();
+        let mut empty = true;
+        for component in name.split('/') {
+            if component.is_empty() || component == "." {
+                continue;
+            }
+            /* Each component must be a plain name on this platform. This rejects `..`, as well
+             * as drive letters and backslashes on Windows.
+             */
+            let mut components = Path::new(component).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => path.push(component),
+                _ => return Err(invalid_data(format!("Unsafe path in archive: {name:?}"))),
+            }
+            empty = false;
+        }
+        if empty {
+            return Err(invalid_data(format!("Empty path in archive: {name:?}")));
+        }
+        Ok(path)
+    }
+
+    /**
+     * Unpack a zip archive into `target_dir`, while reading it
+     *
+     * `target_dir` should be a new, empty directory. Existing files don't get overwritten, and
+     * entries leaving `target_dir` are an error. Only stored and deflated entries are supported.
+     * Stored entries must have their size in the local header, which everybody but streaming
+     * archivers does.
+     *
+     * Unpacking fails as soon as the files get larger than `max_size` in total, so that a small
+     * archive can't fill up the disk. Pass the size the sender announced.
+     */
+    pub async fn unpack(
+        reader: impl AsyncRead + Unpin,
+        target_dir: &Path,
+        max_size: u64,
+    ) -> io::Result<()> {
+        let mut reader = BufReader::new(reader);
+        let mut remaining = max_size;
+        loop {
+            match read_u32(&mut reader).await? {
+                LOCAL_HEADER_SIGNATURE => {
+                    unpack_entry(&mut reader, target_dir, &mut remaining).await?
+                },
+                /* The central directory only repeats what we already know */
+                CENTRAL_HEADER_SIGNATURE | END_OF_CENTRAL_DIRECTORY_SIGNATURE => break,
+                signature => {
+                    return Err(invalid_data(format!(
+                        "Unexpected signature {signature:#010x} in archive"
+                    )))
+                },
+            }
+        }
+        /* Consume the rest, so that the sender doesn't get stuck */
+        futures::io::copy(reader, &mut futures::io::sink()).await?;
+        Ok(())
+    }
+
+    async fn unpack_entry(
+        reader: &mut (impl AsyncBufRead + Unpin),
+        target_dir: &Path,
+        remaining: &mut u64,
+    ) -> io::Result<()> {
+        let mut header = [0; LOCAL_HEADER_SIZE as usize - 4];
+        reader.read_exact(&mut header).await?;
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let flags = u16_at(2);
+        let method = u16_at(4);
+        let mut crc = u32_at(10);
+        let compressed_size = u32_at(14);
+        let mut size = u32_at(18);
+
+        let mut name = vec![0; u16_at(22) as usize];
+        reader.read_exact(&mut name).await?;
+        let mut extra = vec![0; u16_at(24) as usize];
+        reader.read_exact(&mut extra).await?;
+        let name = String::from_utf8_lossy(&name);
+
+        if flags & FLAG_ENCRYPTED != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Encrypted archive entry {name:?}"),
+            ));
+        }
+        if compressed_size == u32::MAX || size == u32::MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("ZIP64 archive entry {name:?}"),
+            ));
+        }
+
+        let path = entry_path(target_dir, &name)?;
+        tracing::debug!("Unpacking {name:?}");
+        let mut file = if name.ends_with('/') {
+            async_std::fs::create_dir_all(&path).await?;
+            None
+        } else {
+            if let Some(parent) = path.parent() {
+                async_std::fs::create_dir_all(parent).await?;
+            }
+            Some(
+                async_std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .await?,
+            )
+        };
+
+        let mut output = Output {
+            file: &mut file,
+            hasher: crc32fast::Hasher::new(),
+            written: 0,
+            remaining,
+        };
+
+        match method {
+            METHOD_STORED => {
+                let mut buffer = vec![0; 16 * 1024];
+                let mut remaining = compressed_size as usize;
+                while remaining > 0 {
+                    let n = reader
+                        .read(&mut buffer[..remaining.min(buffer.len())])
+                        .await?;
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    output.write(&buffer[..n]).await?;
+                    remaining -= n;
+                }
+            },
+            METHOD_DEFLATED => {
+                let mut decompress = flate2::Decompress::new(false);
+                let mut buffer = vec![0; 16 * 1024];
+                loop {
+                    let input = reader.fill_buf().await?;
+                    let eof = input.is_empty();
+                    let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+                    let status = decompress
+                        .decompress(input, &mut buffer, flate2::FlushDecompress::None)
+                        .map_err(|err| invalid_data(err.to_string()))?;
+                    let consumed = (decompress.total_in() - total_in) as usize;
+                    let produced = (decompress.total_out() - total_out) as usize;
+                    reader.consume_unpin(consumed);
+                    output.write(&buffer[..produced]).await?;
+
+                    if status == flate2::Status::StreamEnd {
+                        break;
+                    }
+                    if consumed == 0 && produced == 0 {
+                        return Err(if eof {
+                            io::ErrorKind::UnexpectedEof.into()
+                        } else {
+                            invalid_data(format!("Corrupt compressed data for {name:?}"))
+                        });
+                    }
+                }
+                if flags & FLAG_DATA_DESCRIPTOR == 0
+                    && decompress.total_in() != compressed_size as u64
+                {
+                    return Err(invalid_data(format!("Wrong compressed size for {name:?}")));
+                }
+            },
+            method => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("Compression method {method} of {name:?}"),
+                ))
+            },
+        }
+        let Output {
+            hasher, written, ..
+        } = output;
+
+        if flags & FLAG_DATA_DESCRIPTOR != 0 {
+            /* The signature is optional */
+            crc = read_u32(reader).await?;
+            if crc == DATA_DESCRIPTOR_SIGNATURE {
+                crc = read_u32(reader).await?;
+            }
+            let _compressed_size = read_u32(reader).await?;
+            size = read_u32(reader).await?;
+        }
+
+        if written != size as u64 {
+            return Err(invalid_data(format!("Wrong size for {name:?}")));
+        }
+        if hasher.finalize() != crc {
+            return Err(invalid_data(format!("Wrong checksum for {name:?}")));
+        }
+        if let Some(mut file) = file {
+            file.close().await?;
+        }
+        Ok(())
+    }
+
+    /** Where the content of an entry goes */
+    struct Output<'a> {
+        /** `None` for directories, which must not have any content */
+        file: &'a mut Option<async_std::fs::File>,
+        hasher: crc32fast::Hasher,
+        written: u64,
+        /** How much more all entries together may unpack to */
+        remaining: &'a mut u64,
+    }
+
+    impl Output<'_> {
+        async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+            *self.remaining = self
+                .remaining
+                .checked_sub(data.len() as u64)
+                .ok_or_else(|| invalid_data("Archive unpacks to more than announced"))?;
+            self.hasher.update(data);
+            self.written += data.len() as u64;
+            match &mut self.file {
+                Some(file) => file.write_all(data).await,
+                None if data.is_empty() => Ok(()),
+                None => Err(invalid_data("Directory with content in archive")),
+            }
+        }
+    }
+
+    /** The writing end of a [`pipe`] */
+    pub struct PipeWriter(mpsc::Sender<Vec<u8>>);
+
+    impl AsyncWrite for PipeWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let sender = &mut self.get_mut().0;
+            futures::ready!(sender.poll_ready(cx))
+                .and_then(|()| sender.start_send(buf.to_vec()))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.get_mut().0.close_channel();
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /** Connects receiving the archive with unpacking it, without buffering it as a whole */
+    pub fn pipe() -> (PipeWriter, impl AsyncRead + Unpin) {
+        let (sender, receiver) = mpsc::channel(8);
+        (
+            PipeWriter(sender),
+            receiver.map(io::Result::Ok).into_async_read(),
+        )
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod test {
+    use super::*;
+    use crate::transfer::offer::new_offer_content;
+
+    fn file(data: &'static [u8]) -> OfferSendEntry {
+        OfferSendEntry::RegularFile {
+            size: data.len() as u64,
+            content: new_offer_content(move || ready(Ok(Cursor::new(data)))),
+        }
+    }
+
+    async fn archive(folder: &OfferSendEntry) -> (ZipArchive, Vec<u8>) {
+        let mut archive = ZipArchive::new(folder).unwrap();
+        let mut buffer = Vec::new();
+        for part in std::mem::take(&mut archive.content) {
+            part.await.unwrap().read_to_end(&mut buffer).await.unwrap();
+        }
+        (archive, buffer)
+    }
+
+    #[async_std::test]
+    async fn test_roundtrip() {
+        let folder = OfferSendEntry::Directory {
+            content: [
+                ("a.txt".to_owned(), file(b"Hello")),
+                (
+                    "sub".to_owned(),
+                    OfferSendEntry::Directory {
+                        content: [
+                            ("b.bin".to_owned(), file(&[0xff; 100])),
+                            ("empty".to_owned(), file(b"")),
+                        ]
+                        .into(),
+                    },
+                ),
+            ]
+            .into(),
+        };
+        let (archive, buffer) = archive(&folder).await;
+        assert_eq!(archive.size, buffer.len() as u64);
+        assert_eq!(archive.num_files, 3);
+        assert_eq!(archive.num_bytes, 105);
+        assert_eq!(&buffer[..4], b"PK\x03\x04");
+
+        let target = std::env::temp_dir().join(format!(
+            "wormhole-zip-test-{:06}",
+            rand::random::<u32>() % 1_000_000
+        ));
+        unpack(&buffer[..], &target, archive.num_bytes)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(target.join("a.txt")).unwrap(), b"Hello");
+        assert_eq!(
+            std::fs::read(target.join("sub/b.bin")).unwrap(),
+            [0xff; 100]
+        );
+        assert_eq!(std::fs::read(target.join("sub/empty")).unwrap(), b"");
+
+        /* Unpacking again must not overwrite anything */
+        assert!(unpack(&buffer[..], &target, archive.num_bytes)
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&target).unwrap();
+
+        /* Nor unpack to more than announced */
+        let error = unpack(&buffer[..], &target, archive.num_bytes - 1)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn test_entry_path() {
+        let target = std::path::Path::new("target");
+        assert_eq!(
+            unpacking::entry_path(target, "a/b.txt").unwrap(),
+            target.join("a").join("b.txt")
+        );
+        assert_eq!(
+            unpacking::entry_path(target, "/a/./b/").unwrap(),
+            target.join("a").join("b")
+        );
+        assert!(unpacking::entry_path(target, "../evil").is_err());
+        assert!(unpacking::entry_path(target, "a/../../evil").is_err());
+        assert!(unpacking::entry_path(target, "/").is_err());
+        #[cfg(windows)]
+        {
+            assert!(unpacking::entry_path(target, "C:/evil").is_err());
+            assert!(unpacking::entry_path(target, "a\\..\\..\\evil").is_err());
+        }
+    }
+}
@@ -30,21 +30,44 @@ use std::{
     collections::HashSet,
     net::{IpAddr, SocketAddr},
     sync::Arc,
-    time::Instant,
 };
 
 mod crypto;
+#[cfg(not(target_family = "wasm"))]
+mod stream;
+mod throttle;
 mod transport;
 use crypto::TransitHandshakeError;
+#[cfg(not(target_family = "wasm"))]
+pub use stream::TransitStream;
+pub use throttle::RateLimit;
+use throttle::{throttle, TokenBucket};
 use transport::{TransitTransport, TransitTransportRx, TransitTransportTx};
 
 /// ULR to a default hosted relay server. Please don't abuse or DOS.
-pub const DEFAULT_RELAY_SERVER: &str = "tcp://transit.magic-wormhole.io:4001";
+pub const DEFAULT_RELAY_SERVER: &str = crate::protocol::v1::RELAY_SERVER;
+
+/// The relay hints for [`DEFAULT_RELAY_SERVER`], which wormholes use unless configured otherwise
+pub fn default_relay_hints() -> Vec<RelayHint> {
+    vec![RelayHint::from_urls(None, [DEFAULT_RELAY_SERVER.parse().unwrap()]).unwrap()]
+}
 // No need to make public, it's hard-coded anyways (:
 // Open an issue if you want an API for this
 // Use <stun.stunprotocol.org:3478> for non-production testing
 #[cfg(not(target_family = "wasm"))]
 const PUBLIC_STUN_SERVER: &str = "stun.piegames.de:3478";
+/// Priority of our direct hints in private networks, so that peers in the same LAN try them first
+#[cfg(not(target_family = "wasm"))]
+const LAN_HINT_PRIORITY: f32 = 1.0;
+/// Priority of our link-local direct hints. They only work on the same link, but don't need any configuration
+#[cfg(not(target_family = "wasm"))]
+const LINK_LOCAL_HINT_PRIORITY: f32 = 0.5;
+/// Head start of the direct connections over the relays, if the peer has a hint in one of our local networks
+#[cfg(not(target_family = "wasm"))]
+const LAN_RELAY_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+/// How many ports after a public direct hint of the peer we try too, see [`DirectHint::predicted_ports`]
+#[cfg(not(target_family = "wasm"))]
+const PREDICTED_PORTS: u16 = 3;
 
 /// Deprecated: This will be a private type in the future. Open an issue if you require access to protocol intrinsics in the future
 #[deprecated(
@@ -127,6 +150,10 @@ pub enum TransitError {
         std::io::Error,
     ),
 
+    /// Nothing was sent or received within [`Timeouts::transfer_inactivity`](crate::Timeouts::transfer_inactivity)
+    #[error("The connection has been inactive for too long")]
+    Timeout,
+
     /// WASM error
     #[cfg(target_family = "wasm")]
     #[error("WASM error")]
@@ -137,6 +164,9 @@ pub enum TransitError {
     ),
 }
 
+#[cfg(feature = "serde-types")]
+impl_serialize_error!(TransitConnectError, TransitError);
+
 impl From<()> for TransitError {
     fn from(_: ()) -> Self {
         Self::Crypto
@@ -154,8 +184,14 @@ pub struct Abilities {
     pub direct_tcp_v1: bool,
     /** Connection over a relay */
     pub relay_v1: bool,
-    #[cfg(any())]
-    /** **Experimental** Use the [noise protocol](https://noiseprotocol.org) for the encryption. */
+    /**
+     * **Experimental** Use the [noise protocol](https://noiseprotocol.org) for the encryption
+     *
+     * Each connection then does its own ephemeral key exchange, so recorded traffic stays secret
+     * even if the wormhole key leaks later. It is only used if both sides support it, otherwise
+     * the connection falls back to secretbox.
+     */
+    #[cfg(feature = "experimental-transit-noise")]
     pub noise_v1: bool,
 }
 
@@ -164,8 +200,8 @@ impl Abilities {
     pub const ALL: Self = Self {
         direct_tcp_v1: true,
         relay_v1: true,
-        #[cfg(any())]
-        noise_v1: false,
+        #[cfg(feature = "experimental-transit-noise")]
+        noise_v1: true,
     };
 
     /// The abilities preset that contains all abilities
@@ -181,8 +217,8 @@ impl Abilities {
     pub const FORCE_DIRECT: Self = Self {
         direct_tcp_v1: true,
         relay_v1: false,
-        #[cfg(any())]
-        noise_v1: false,
+        #[cfg(feature = "experimental-transit-noise")]
+        noise_v1: true,
     };
 
     /**
@@ -190,14 +226,14 @@ impl Abilities {
      *
      * If the other side forces a the usage of a direct connection the attempt will fail.
      * Note that the other side might control the relay server being used, if you really
-     * don't want your IP to potentially be disclosed use TOR instead (not supported by
-     * the Rust implementation yet).
+     * don't want your IP to potentially be disclosed use Tor instead, see the `tor` feature
+     * of [`ProxyConfig`](crate::proxy::ProxyConfig).
      */
     pub const FORCE_RELAY: Self = Self {
         direct_tcp_v1: false,
         relay_v1: true,
-        #[cfg(any())]
-        noise_v1: false,
+        #[cfg(feature = "experimental-transit-noise")]
+        noise_v1: true,
     };
 
     /// Whether direct transfer is allowed
@@ -210,12 +246,14 @@ impl Abilities {
         self.relay_v1
     }
 
-    #[cfg(any())]
+    /// Whether noise cryptography is supported
+    #[cfg(feature = "experimental-transit-noise")]
     pub fn can_noise_crypto(&self) -> bool {
         self.noise_v1
     }
 
     /// Whether noise cryptography is supported
+    #[cfg(not(feature = "experimental-transit-noise"))]
     #[deprecated(since = "0.7.0", note = "Noise cryptography is not standardized")]
     pub fn can_noise_crypto(&self) -> bool {
         false
@@ -225,7 +263,7 @@ impl Abilities {
     pub fn intersect(mut self, other: &Self) -> Self {
         self.direct_tcp_v1 &= other.direct_tcp_v1;
         self.relay_v1 &= other.relay_v1;
-        #[cfg(any())]
+        #[cfg(feature = "experimental-transit-noise")]
         {
             self.noise_v1 &= other.noise_v1;
         }
@@ -249,7 +287,7 @@ impl serde::Serialize for Abilities {
                 "type": "relay-v1",
             }));
         }
-        #[cfg(any())]
+        #[cfg(feature = "experimental-transit-noise")]
         if self.noise_v1 {
             hints.push(serde_json::json!({
                 "type": "noise-crypto-v1",
@@ -270,7 +308,7 @@ impl<'de> serde::Deserialize<'de> for Abilities {
             DirectTcpV1,
             RelayV1,
             RelayV2,
-            #[cfg(any())]
+            #[cfg(feature = "experimental-transit-noise")]
             NoiseCryptoV1,
             #[serde(other)]
             Other,
@@ -286,7 +324,7 @@ impl<'de> serde::Deserialize<'de> for Abilities {
                 Ability::RelayV1 => {
                     abilities.relay_v1 = true;
                 },
-                #[cfg(any())]
+                #[cfg(feature = "experimental-transit-noise")]
                 Ability::NoiseCryptoV1 => {
                     abilities.noise_v1 = true;
                 },
@@ -374,27 +412,94 @@ impl serde::Serialize for Hints {
     }
 }
 
-/** hostname and port for direct connection */
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash, derive_more::Display)]
+/**
+ * hostname and port for direct connection
+ *
+ * Two hints are equal if they point to the same endpoint, regardless of their priority.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, derive_more::Display)]
 #[display("tcp://{}:{}", hostname, port)]
 pub struct DirectHint {
-    // DirectHint also contains a `priority` field, but it is underspecified
-    // and we won't use it
-    // pub priority: f32,
+    /// Hints with a higher priority are tried first. Defaults to `0.0`, like in the Python implementation.
+    #[serde(default, skip_serializing_if = "is_default_priority")]
+    pub priority: f32,
     /// The hostname via which to reach this peer
     pub hostname: String,
     /// The port to use
     pub port: u16,
 }
 
+fn is_default_priority(priority: &f32) -> bool {
+    *priority == 0.0
+}
+
 impl DirectHint {
     /// Create a new direct hint
     pub fn new(hostname: impl Into<String>, port: u16) -> Self {
         Self {
+            priority: 0.0,
             hostname: hostname.into(),
             port,
         }
     }
+
+    /// Set the priority of this hint. Higher values get tried first.
+    pub fn with_priority(mut self, priority: f32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /**
+     * Guess the next few ports a NAT will hand out after the one in this hint
+     *
+     * Many NATs allocate their external ports sequentially. If the peer advertised a public
+     * address learned via STUN, its NAT might have assigned a new port by the time it connects
+     * to us, most likely one of the following ones. Private addresses are not NATted, so we
+     * don't guess anything for them.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    fn predicted_ports(&self) -> impl Iterator<Item = Self> + '_ {
+        let is_public = match IpAddr::try_from(self) {
+            Ok(IpAddr::V4(ip)) => {
+                !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified())
+            },
+            Ok(IpAddr::V6(ip)) => {
+                /* Unique local (fc00::/7) and link local (fe80::/10) addresses */
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (ip.segments()[0] & 0xfe00) == 0xfc00
+                    || (ip.segments()[0] & 0xffc0) == 0xfe80)
+            },
+            Err(_) => false,
+        };
+        (1..=PREDICTED_PORTS)
+            .filter(move |_| is_public)
+            .filter_map(move |offset| self.port.checked_add(offset))
+            .map(move |port| {
+                Self::new(self.hostname.clone(), port).with_priority(self.priority - 1.0)
+            })
+    }
+
+    /* Highest priority first. `sort_by` is stable, so equal priorities keep their order. */
+    #[cfg(not(target_family = "wasm"))]
+    fn sort_by_priority(hints: &mut [Self]) {
+        hints.sort_by(|a, b| b.priority.total_cmp(&a.priority));
+    }
+}
+
+impl PartialEq for DirectHint {
+    fn eq(&self, other: &Self) -> bool {
+        self.hostname == other.hostname && self.port == other.port
+    }
+}
+
+impl Eq for DirectHint {}
+
+impl std::hash::Hash for DirectHint {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.hostname.hash(state);
+        self.port.hash(state);
+    }
 }
 
 /* Wire representation of a single relay hint (Helper struct for serialization) */
@@ -514,12 +619,13 @@ impl RelayHint {
             );
             match url.scheme() {
                 "tcp" => {
-                    /* Using match */
-                    let (hostname, port) = match (url.host_str(), url.port()) {
-                        (Some(hostname), Some(port)) => (hostname.into(), port),
+                    /* IPv6 literals are bracketed in URLs, but not in hints */
+                    let (hostname, port) = match (url.host(), url.port()) {
+                        (Some(url::Host::Ipv6(ip)), Some(port)) => (ip.to_string(), port),
+                        (Some(hostname), Some(port)) => (hostname.to_string(), port),
                         _ => bail!(RelayHintParseError::InvalidTcp(url)),
                     };
-                    this.tcp.insert(DirectHint { hostname, port });
+                    this.tcp.insert(DirectHint::new(hostname, port));
                 },
                 "ws" | "wss" => {
                     this.ws.insert(url);
@@ -634,8 +740,14 @@ impl<'de> serde::Deserialize<'de> for RelayHint {
 
 impl TryFrom<&DirectHint> for IpAddr {
     type Error = std::net::AddrParseError;
+    /** Also accepts bracketed IPv6 literals, like `[::1]` */
     fn try_from(hint: &DirectHint) -> Result<IpAddr, std::net::AddrParseError> {
-        hint.hostname.parse()
+        let hostname = hint.hostname.as_str();
+        hostname
+            .strip_prefix('[')
+            .and_then(|hostname| hostname.strip_suffix(']'))
+            .unwrap_or(hostname)
+            .parse()
     }
 }
 
@@ -652,8 +764,60 @@ impl TryFrom<&DirectHint> for SocketAddr {
     }
 }
 
+/** Priority of a direct hint for one of our interface addresses, see [`LAN_HINT_PRIORITY`] */
+#[cfg(not(target_family = "wasm"))]
+fn local_hint_priority(ip: &IpAddr) -> f32 {
+    match ip {
+        IpAddr::V4(ip) if ip.is_private() => LAN_HINT_PRIORITY,
+        IpAddr::V4(ip) if ip.is_link_local() => LINK_LOCAL_HINT_PRIORITY,
+        /* Unique local addresses, fc00::/7 */
+        IpAddr::V6(ip) if (ip.segments()[0] & 0xfe00) == 0xfc00 => LAN_HINT_PRIORITY,
+        /* Link-local addresses, fe80::/10 */
+        IpAddr::V6(ip) if (ip.segments()[0] & 0xffc0) == 0xfe80 => LINK_LOCAL_HINT_PRIORITY,
+        _ => 0.0,
+    }
+}
+
+/** Whether both addresses are in the same subnet, given by the netmask of one of our interfaces */
+#[cfg(not(target_family = "wasm"))]
+fn is_same_subnet(ours: IpAddr, theirs: IpAddr, netmask: IpAddr) -> bool {
+    match (ours, theirs, netmask) {
+        (IpAddr::V4(ours), IpAddr::V4(theirs), IpAddr::V4(netmask)) => {
+            u32::from(ours) & u32::from(netmask) == u32::from(theirs) & u32::from(netmask)
+        },
+        (IpAddr::V6(ours), IpAddr::V6(theirs), IpAddr::V6(netmask)) => {
+            u128::from(ours) & u128::from(netmask) == u128::from(theirs) & u128::from(netmask)
+        },
+        _ => false,
+    }
+}
+
+/** Whether a hint of the peer points into one of the networks we are directly connected to */
+#[cfg(not(target_family = "wasm"))]
+fn is_on_local_network(hint: &DirectHint, interfaces: &[if_addrs::Interface]) -> bool {
+    let Ok(theirs) = IpAddr::try_from(hint) else {
+        return false;
+    };
+    interfaces
+        .iter()
+        .filter(|iface| !iface.is_loopback())
+        .any(|iface| match &iface.addr {
+            if_addrs::IfAddr::V4(addr) => {
+                is_same_subnet(addr.ip.into(), theirs, addr.netmask.into())
+            },
+            if_addrs::IfAddr::V6(addr) => {
+                is_same_subnet(addr.ip.into(), theirs, addr.netmask.into())
+            },
+        })
+}
+
 /// Direct or relay
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde-types", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde-types",
+    serde(rename_all = "kebab-case", tag = "type")
+)]
 #[non_exhaustive]
 pub enum ConnectionType {
     /// We are directly connected to our peer
@@ -667,6 +831,7 @@ pub enum ConnectionType {
 
 /// Metadata for the established transit connection
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde-types", derive(Serialize, Deserialize))]
 #[non_exhaustive]
 pub struct TransitInfo {
     /// Whether we are connected directly or via a relay server
@@ -776,6 +941,7 @@ pub fn log_transit_connection(
     note = "This will be a private type in the future. Open an issue if you require access to protocol intrinsics in the future"
 )]
 #[allow(deprecated)]
+#[tracing::instrument(name = "transit_init", skip_all)]
 pub async fn init(
     mut abilities: Abilities,
     peer_abilities: Option<Abilities>,
@@ -788,6 +954,11 @@ pub async fn init(
     if let Some(peer_abilities) = peer_abilities {
         abilities = abilities.intersect(&peer_abilities);
     }
+    /* Browsers can neither accept nor open TCP connections, so don't make the peer wait for them */
+    #[cfg(target_family = "wasm")]
+    {
+        abilities = abilities.intersect(&Abilities::FORCE_RELAY);
+    }
 
     /* Detect our IP addresses if the ability is enabled */
     #[cfg(not(target_family = "wasm"))]
@@ -806,10 +977,10 @@ pub async fn init(
             {
                 Ok(Ok((external_ip, stream))) => {
                     tracing::debug!("Our external IP address is {}", external_ip);
-                    our_hints.direct_tcp.insert(DirectHint {
-                        hostname: external_ip.ip().to_string(),
-                        port: external_ip.port(),
-                    });
+                    our_hints.direct_tcp.insert(DirectHint::new(
+                        external_ip.ip().to_string(),
+                        external_ip.port(),
+                    ));
                     tracing::debug!(
                         "Our socket for connecting is bound to {} and connected to {}",
                         stream.local_addr()?,
@@ -841,7 +1012,8 @@ pub async fn init(
              * the port. In theory, we could, but it really confused the kernel to the point
              * of `accept` calls never returning again.
              */
-            let listener = TcpListener::bind("[::]:0").await?;
+            let listener =
+                transport::tcp_listen_custom(&"[::]:0".parse::<SocketAddr>().unwrap().into())?;
 
             /* Find our ports, iterate all our local addresses, combine them with the ports and that's our hints */
             let port = socket.local_addr()?.as_socket().unwrap().port();
@@ -850,16 +1022,12 @@ pub async fn init(
                 if_addrs::get_if_addrs()?
                     .iter()
                     .filter(|iface| !iface.is_loopback())
-                    .flat_map(|ip| {
+                    .flat_map(|iface| {
+                        let ip = iface.ip();
+                        let priority = local_hint_priority(&ip);
                         [
-                            DirectHint {
-                                hostname: ip.ip().to_string(),
-                                port,
-                            },
-                            DirectHint {
-                                hostname: ip.ip().to_string(),
-                                port: port2,
-                            },
+                            DirectHint::new(ip.to_string(), port).with_priority(priority),
+                            DirectHint::new(ip.to_string(), port2).with_priority(priority),
                         ]
                         .into_iter()
                     }),
@@ -883,11 +1051,18 @@ pub async fn init(
         our_hints.relay.extend(relay_hints);
     }
 
+    let timeouts = crate::Timeouts::default();
     Ok(TransitConnector {
         #[cfg(not(target_family = "wasm"))]
         sockets,
+        #[cfg(not(target_family = "wasm"))]
+        proxy: None,
         our_abilities: abilities,
         our_hints: Arc::new(our_hints),
+        negotiation_timeout: timeouts.transit_negotiation,
+        hint_connect_timeout: timeouts.transit_hint_connect,
+        inactivity_timeout: timeouts.transfer_inactivity,
+        rate_limit: RateLimit::default(),
     })
 }
 
@@ -929,12 +1104,48 @@ pub struct TransitConnector {
      */
     #[cfg(not(target_family = "wasm"))]
     sockets: Option<(MaybeConnectedSocket, TcpListener)>,
+    /* Outgoing connections go through this one, if set */
+    #[cfg(not(target_family = "wasm"))]
+    proxy: Option<crate::proxy::ProxyConfig>,
     our_abilities: Abilities,
     our_hints: Arc<Hints>,
+    negotiation_timeout: Option<std::time::Duration>,
+    hint_connect_timeout: Option<std::time::Duration>,
+    inactivity_timeout: Option<std::time::Duration>,
+    rate_limit: RateLimit,
 }
 
 #[allow(deprecated)]
 impl TransitConnector {
+    /// Apply the transit related timeouts, usually the ones of the [`Wormhole`](crate::Wormhole)
+    pub(crate) fn with_timeouts(mut self, timeouts: &crate::Timeouts) -> Self {
+        self.negotiation_timeout = timeouts.transit_negotiation;
+        self.hint_connect_timeout = timeouts.transit_hint_connect;
+        self.inactivity_timeout = timeouts.transfer_inactivity;
+        self
+    }
+
+    /**
+     * Make the outgoing connections through a proxy, usually the one of the [`Wormhole`](crate::Wormhole)
+     *
+     * The [`TransitInfo::peer_addr`] of these connections is the address of the proxy.
+     */
+    pub(crate) fn with_proxy(mut self, proxy: Option<&crate::proxy::ProxyConfig>) -> Self {
+        #[cfg(not(target_family = "wasm"))]
+        {
+            self.proxy = proxy.cloned();
+        }
+        #[cfg(target_family = "wasm")]
+        let _ = proxy;
+        self
+    }
+
+    /// Limit the speed of the established connection, usually to the one of the [`Wormhole`](crate::Wormhole)
+    pub(crate) fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
     /// The abilities that we've sent to the other side
     pub fn our_abilities(&self) -> &Abilities {
         &self.our_abilities
@@ -971,6 +1182,7 @@ impl TransitConnector {
     /**
      * Connect to the other side, as sender.
      */
+    #[tracing::instrument(name = "transit", skip_all, fields(leader = true))]
     pub async fn leader_connect(
         self,
         transit_key: Key<TransitKey>,
@@ -980,12 +1192,18 @@ impl TransitConnector {
         let Self {
             #[cfg(not(target_family = "wasm"))]
             sockets,
+            #[cfg(not(target_family = "wasm"))]
+            proxy,
             our_abilities,
             our_hints,
+            negotiation_timeout,
+            hint_connect_timeout,
+            inactivity_timeout,
+            rate_limit,
         } = self;
         let transit_key = Arc::new(transit_key);
 
-        let start = Instant::now();
+        let start = crate::util::Instant::now();
         let mut connection_stream = Box::pin(
             Self::connect_inner(
                 true,
@@ -994,8 +1212,11 @@ impl TransitConnector {
                 our_hints,
                 their_abilities,
                 their_hints,
+                hint_connect_timeout,
                 #[cfg(not(target_family = "wasm"))]
                 sockets,
+                #[cfg(not(target_family = "wasm"))]
+                proxy,
             )
             .filter_map(|result| async {
                 match result {
@@ -1009,9 +1230,9 @@ impl TransitConnector {
         );
 
         let (mut transit, mut finalizer, mut conn_info) =
-            util::timeout(std::time::Duration::from_secs(60), connection_stream.next())
+            util::timeout_opt(negotiation_timeout, connection_stream.next())
                 .await
-                .map_err(|_| {
+                .map_err(|()| {
                     tracing::debug!("`leader_connect` timed out");
                     TransitConnectError::Handshake
                 })?
@@ -1069,6 +1290,9 @@ impl TransitConnector {
                 socket: transit,
                 tx,
                 rx,
+                inactivity_timeout,
+                upload: rate_limit.upload.map(TokenBucket::new),
+                download: rate_limit.download.map(TokenBucket::new),
             },
             conn_info,
         ))
@@ -1077,6 +1301,7 @@ impl TransitConnector {
     /**
      * Connect to the other side, as receiver
      */
+    #[tracing::instrument(name = "transit", skip_all, fields(leader = false))]
     pub async fn follower_connect(
         self,
         transit_key: Key<TransitKey>,
@@ -1086,8 +1311,14 @@ impl TransitConnector {
         let Self {
             #[cfg(not(target_family = "wasm"))]
             sockets,
+            #[cfg(not(target_family = "wasm"))]
+            proxy,
             our_abilities,
             our_hints,
+            negotiation_timeout,
+            hint_connect_timeout,
+            inactivity_timeout,
+            rate_limit,
         } = self;
         let transit_key = Arc::new(transit_key);
 
@@ -1099,8 +1330,11 @@ impl TransitConnector {
                 our_hints,
                 their_abilities,
                 their_hints,
+                hint_connect_timeout,
                 #[cfg(not(target_family = "wasm"))]
                 sockets,
+                #[cfg(not(target_family = "wasm"))]
+                proxy,
             )
             .filter_map(|result| async {
                 match result {
@@ -1113,11 +1347,8 @@ impl TransitConnector {
             }),
         );
 
-        let transit = match util::timeout(
-            std::time::Duration::from_secs(60),
-            &mut connection_stream.next(),
-        )
-        .await
+        let transit = match util::timeout_opt(negotiation_timeout, &mut connection_stream.next())
+            .await
         {
             Ok(Some((mut socket, finalizer, conn_info))) => {
                 let (tx, rx) = finalizer
@@ -1128,7 +1359,17 @@ impl TransitConnector {
                         TransitConnectError::Handshake
                     })?;
 
-                Ok((Transit { socket, tx, rx }, conn_info))
+                Ok((
+                    Transit {
+                        socket,
+                        tx,
+                        rx,
+                        inactivity_timeout,
+                        upload: rate_limit.upload.map(TokenBucket::new),
+                        download: rate_limit.download.map(TokenBucket::new),
+                    },
+                    conn_info,
+                ))
             },
             Ok(None) | Err(_) => {
                 tracing::debug!("`follower_connect` timed out");
@@ -1160,7 +1401,9 @@ impl TransitConnector {
         our_hints: Arc<Hints>,
         their_abilities: Abilities,
         their_hints: Arc<Hints>,
+        hint_connect_timeout: Option<std::time::Duration>,
         #[cfg(not(target_family = "wasm"))] sockets: Option<(MaybeConnectedSocket, TcpListener)>,
+        #[cfg(not(target_family = "wasm"))] proxy: Option<crate::proxy::ProxyConfig>,
     ) -> impl Stream<Item = Result<HandshakeResult, TransitHandshakeError>> + 'static {
         /* Have Some(sockets) → Can direct */
         #[cfg(not(target_family = "wasm"))]
@@ -1192,9 +1435,33 @@ impl TransitConnector {
         type ConnectorFuture = BoxFuture<'static, Result<TransitConnection, TransitHandshakeError>>;
         let mut connectors: BoxIterator<ConnectorFuture> = Box::new(std::iter::empty());
 
+        /* Give up on a single hint after a while. The other ones are tried concurrently anyways. */
+        async fn connect_timeout(
+            timeout: Option<std::time::Duration>,
+            attempt: String,
+            connect: impl std::future::Future<Output = Result<TransitConnection, TransitHandshakeError>>,
+        ) -> Result<TransitConnection, TransitHandshakeError> {
+            tracing::trace!(target: util::PROTOCOL_TRACE, %attempt, "transit attempt");
+            let result = util::timeout_opt(timeout, connect)
+                .await
+                .map_err(|()| TransitHandshakeError::Timeout)
+                .and_then(|result| result);
+            match &result {
+                Ok(_) => {
+                    tracing::trace!(target: util::PROTOCOL_TRACE, %attempt, "transit connected")
+                },
+                Err(error) => {
+                    tracing::trace!(target: util::PROTOCOL_TRACE, %attempt, %error, "transit failed")
+                },
+            }
+            result
+        }
+
         #[cfg(not(target_family = "wasm"))]
         let (socket, listener) = sockets.unzip();
         #[cfg(not(target_family = "wasm"))]
+        let mut relay_delay = std::time::Duration::ZERO;
+        #[cfg(not(target_family = "wasm"))]
         if our_abilities.can_direct() && their_abilities.can_direct() {
             let local_addr = socket.map(|socket| {
                 Arc::new(
@@ -1203,16 +1470,57 @@ impl TransitConnector {
                         .expect("This is guaranteed to be an IP socket"),
                 )
             });
+            let mut direct_hints = their_hints.direct_tcp.iter().cloned().collect::<Vec<_>>();
+            /* Hints in one of our own networks are the fastest way to the peer, whatever it prefers.
+             * Give them a head start over the relays too, so that we don't hairpin through them.
+             */
+            let interfaces = if_addrs::get_if_addrs().unwrap_or_default();
+            for hint in &mut direct_hints {
+                if is_on_local_network(hint, &interfaces) {
+                    tracing::debug!("{hint} is in our local network");
+                    hint.priority = hint.priority.max(0.0) + LAN_HINT_PRIORITY;
+                    relay_delay = LAN_RELAY_DELAY;
+                }
+            }
+            DirectHint::sort_by_priority(&mut direct_hints);
+            /* Nobody should have that many IP addresses, even with NATing */
+            direct_hints.truncate(50);
+            /* The guessed ports go last, after all the hints the peer actually gave us. Through a proxy,
+             * our port does not matter and the peer's NAT can't be opened, so don't bother.
+             */
+            if proxy.is_none() {
+                let predicted = direct_hints
+                    .iter()
+                    .flat_map(DirectHint::predicted_ports)
+                    .filter(|hint| !their_hints.direct_tcp.contains(hint))
+                    .collect::<HashSet<_>>();
+                direct_hints.extend(predicted);
+            }
             /* Connect to each hint of the peer */
             connectors = Box::new(
                 connectors.chain(
-                    their_hints
-                        .direct_tcp
-                        .clone()
+                    direct_hints
                         .into_iter()
-                        /* Nobody should have that many IP addresses, even with NATing */
-                        .take(50)
-                        .map(move |hint| transport::connect_tcp_direct(local_addr.clone(), hint))
+                        .map({
+                            let proxy = proxy.clone();
+                            move |hint| {
+                                let attempt = format!("direct {hint}");
+                                let connect = match (&local_addr, &proxy) {
+                                    /* From our bound port, so that both sides can open their NATs simultaneously */
+                                    (Some(local_addr), None) => {
+                                        transport::connect_tcp_punching(local_addr.clone(), hint)
+                                            .boxed()
+                                    },
+                                    _ => transport::connect_tcp_direct(
+                                        local_addr.clone(),
+                                        hint,
+                                        proxy.clone(),
+                                    )
+                                    .boxed(),
+                                };
+                                connect_timeout(hint_connect_timeout, attempt, connect)
+                            }
+                        })
                         .map(|fut| Box::pin(fut) as ConnectorFuture),
                 ),
             ) as BoxIterator<ConnectorFuture>;
@@ -1220,9 +1528,11 @@ impl TransitConnector {
 
         /* Relay hints. Make sure that both sides advertise it, since it is fine to support it without providing own hints. */
         if our_abilities.can_relay() && their_abilities.can_relay() {
-            /* Collect intermediate into HashSet for deduplication */
+            /* Collect intermediate into HashSet for deduplication. Race all of our relays, since the user
+             * configured them, but only some of the peer's.
+             */
             let mut relay_hints = Vec::<RelayHint>::new();
-            relay_hints.extend(our_hints.relay.iter().take(2).cloned());
+            relay_hints.extend(our_hints.relay.iter().cloned());
             for hint in their_hints.relay.iter().take(2).cloned() {
                 hint.merge_into(&mut relay_hints);
             }
@@ -1253,18 +1563,28 @@ impl TransitConnector {
                                         })
                                         .next()
                                     });
-                            hint.tcp
-                                .into_iter()
+                            let mut tcp = hint.tcp.into_iter().collect::<Vec<_>>();
+                            DirectHint::sort_by_priority(&mut tcp);
+                            tcp.into_iter()
                                 .take(3)
                                 .enumerate()
                                 .map(move |(i, h)| (i, h, name.clone()))
                             })
-                            .map(|(index, host, name)| async move {
-                                util::sleep(std::time::Duration::from_secs(
-                                    index as u64 * 5,
-                                ))
-                                .await;
-                                transport::connect_tcp_relay(host, name).await
+                            .map(move |(index, host, name)| {
+                                let proxy = proxy.clone();
+                                async move {
+                                    util::sleep(
+                                        relay_delay
+                                            + std::time::Duration::from_secs(index as u64 * 5),
+                                    )
+                                    .await;
+                                    connect_timeout(
+                                        hint_connect_timeout,
+                                        format!("relay {host}"),
+                                        transport::connect_tcp_relay(host, name, proxy),
+                                    )
+                                    .await
+                                }
                             })
                             .map(|fut| Box::pin(fut) as ConnectorFuture),
                     ),
@@ -1308,7 +1628,12 @@ impl TransitConnector {
                                     index as u64 * 5,
                                 ))
                                 .await;
-                                transport::connect_ws_relay(url, name).await
+                                connect_timeout(
+                                    hint_connect_timeout,
+                                    format!("relay {url}"),
+                                    transport::connect_ws_relay(url, name),
+                                )
+                                .await
                             })
                             .map(|fut| Box::pin(fut) as ConnectorFuture),
                     ),
@@ -1407,18 +1732,49 @@ pub struct Transit {
     socket: Box<dyn TransitTransport>,
     tx: Box<dyn crypto::TransitCryptoEncrypt>,
     rx: Box<dyn crypto::TransitCryptoDecrypt>,
+    inactivity_timeout: Option<std::time::Duration>,
+    upload: Option<TokenBucket>,
+    download: Option<TokenBucket>,
+}
+
+/** Fail with [`TransitError::Timeout`] if a single send or receive takes too long */
+async fn inactivity_timeout<T>(
+    timeout: Option<std::time::Duration>,
+    future: impl futures::Future<Output = Result<T, TransitError>>,
+) -> Result<T, TransitError> {
+    util::timeout_opt(timeout, future)
+        .await
+        .map_err(|()| TransitError::Timeout)?
 }
 
 impl Transit {
     /** Receive and decrypt one message from the other side. */
     pub async fn receive_record(&mut self) -> Result<Box<[u8]>, TransitError> {
-        self.rx.decrypt(&mut self.socket).await
+        let record =
+            inactivity_timeout(self.inactivity_timeout, self.rx.decrypt(&mut self.socket)).await?;
+        throttle(&mut self.download, record.len()).await;
+        Ok(record)
     }
 
     /** Send an encrypted message to the other side */
     pub async fn send_record(&mut self, plaintext: &[u8]) -> Result<(), TransitError> {
         assert!(!plaintext.is_empty());
-        self.tx.encrypt(&mut self.socket, plaintext).await
+        throttle(&mut self.upload, plaintext.len()).await;
+        inactivity_timeout(
+            self.inactivity_timeout,
+            self.tx.encrypt(&mut self.socket, plaintext),
+        )
+        .await
+    }
+
+    /**
+     * Limit the speed of this connection, replacing the previous limit
+     *
+     * Waiting for the rate limit does not count towards the inactivity timeout.
+     */
+    pub fn set_rate_limit(&mut self, rate_limit: RateLimit) {
+        self.upload = rate_limit.upload.map(TokenBucket::new);
+        self.download = rate_limit.download.map(TokenBucket::new);
     }
 
     /// Flush the socket
@@ -1427,7 +1783,11 @@ impl Transit {
         self.socket.flush().await.map_err(Into::into)
     }
 
-    /** Convert the transit connection to a [`Stream`]/[`Sink`] pair */
+    /**
+     * Convert the transit connection to a [`Stream`]/[`Sink`] pair
+     *
+     * Use [`into_stream`](Self::into_stream) instead to get an [`AsyncRead`] + [`AsyncWrite`].
+     */
     #[cfg(not(target_family = "wasm"))]
     pub fn split(
         self,
@@ -1436,20 +1796,25 @@ impl Transit {
         impl futures::stream::Stream<Item = Result<Box<[u8]>, TransitError>>,
     ) {
         let (reader, writer) = self.socket.split();
+        let timeout = self.inactivity_timeout;
         (
             futures::sink::unfold(
-                (writer, self.tx),
-                |(mut writer, mut tx), plaintext: Box<[u8]>| async move {
-                    tx.encrypt(&mut writer, &plaintext)
+                (writer, self.tx, self.upload),
+                move |(mut writer, mut tx, mut upload), plaintext: Box<[u8]>| async move {
+                    throttle(&mut upload, plaintext.len()).await;
+                    inactivity_timeout(timeout, tx.encrypt(&mut writer, &plaintext))
                         .await
-                        .map(|()| (writer, tx))
+                        .map(|()| (writer, tx, upload))
+                },
+            ),
+            futures::stream::try_unfold(
+                (reader, self.rx, self.download),
+                move |(mut reader, mut rx, mut download)| async move {
+                    let record = inactivity_timeout(timeout, rx.decrypt(&mut reader)).await?;
+                    throttle(&mut download, record.len()).await;
+                    Ok::<_, TransitError>(Some((record, (reader, rx, download))))
                 },
             ),
-            futures::stream::try_unfold((reader, self.rx), |(mut reader, mut rx)| async move {
-                rx.decrypt(&mut reader)
-                    .await
-                    .map(|record| Some((record, (reader, rx))))
-            }),
         )
     }
 }
@@ -1487,9 +1852,8 @@ async fn handshake_exchange(
     if host_type != &ConnectionType::Direct {
         tracing::trace!("initiating relay handshake");
 
-        let sub_key = key.derive_subkey_from_purpose::<crate::GenericKey>("transit_relay_token");
         socket
-            .write_all(format!("please relay {} for side {}\n", sub_key.to_hex(), tside).as_bytes())
+            .write_all(relay_handshake(&key, &tside).as_bytes())
             .await?;
         let mut rx = [0u8; 3];
         socket.read_exact(&mut rx).await?;
@@ -1506,12 +1870,70 @@ async fn handshake_exchange(
     Ok((socket, finalizer))
 }
 
+/** The line asking the relay server to connect us with the other side */
+#[allow(deprecated)]
+fn relay_handshake(key: &Key<TransitKey>, tside: &str) -> String {
+    let sub_key = key.derive_subkey_from_purpose::<crate::GenericKey>("transit_relay_token");
+    format!("please relay {} for side {}\n", sub_key.to_hex(), tside)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use serde_json::json;
 
+    /** A mismatch must not stop reading early, wherever it is */
+    #[async_std::test]
+    async fn test_read_expect() {
+        use transport::TransitTransportRx;
+
+        let expected = b"transit receiver 0123456789abcdef ready\n\n";
+        let mut socket = futures::io::Cursor::new(expected.to_vec());
+        socket.read_expect(expected).await.unwrap();
+
+        for position in 0..expected.len() {
+            let mut received = expected.to_vec();
+            received[position] ^= 0x01;
+            received.extend_from_slice(b"go\n");
+            let mut socket = futures::io::Cursor::new(received);
+            assert!(matches!(
+                socket.read_expect(expected).await,
+                Err(TransitHandshakeError::HandshakeFailed)
+            ));
+            assert_eq!(socket.position(), expected.len() as u64);
+        }
+    }
+
+    /** The handshake lines of the reference implementation, see `tests/golden` */
+    #[test]
+    #[allow(deprecated)]
+    pub fn test_golden_handshakes() {
+        let golden: serde_json::Value =
+            serde_json::from_str(include_str!("../tests/golden/keys.json")).unwrap();
+        let key = Key::<TransitKey>::new(Box::new(
+            crypto_secretbox::Key::from_exact_iter(
+                hex::decode(golden["transit_key"].as_str().unwrap()).unwrap(),
+            )
+            .unwrap(),
+        ));
+
+        assert_eq!(
+            relay_handshake(&key, golden["transit_side"].as_str().unwrap()),
+            golden["relay_handshake"].as_str().unwrap()
+        );
+        let init = crypto::SecretboxInit { key: Arc::new(key) };
+        assert_eq!(
+            init.sender_handshake(),
+            golden["sender_handshake"].as_str().unwrap()
+        );
+        assert_eq!(
+            init.receiver_handshake(),
+            golden["receiver_handshake"].as_str().unwrap()
+        );
+    }
+
     #[test]
+    #[cfg(not(feature = "experimental-transit-noise"))]
     pub fn test_abilities_encoding() {
         assert_eq!(
             serde_json::to_value(Abilities::ALL).unwrap(),
@@ -1523,14 +1945,32 @@ mod test {
         );
     }
 
+    #[test]
+    #[cfg(feature = "experimental-transit-noise")]
+    pub fn test_abilities_encoding() {
+        assert_eq!(
+            serde_json::to_value(Abilities::ALL).unwrap(),
+            json!([{"type": "direct-tcp-v1"}, {"type": "relay-v1"}, {"type": "noise-crypto-v1"}])
+        );
+        assert_eq!(
+            serde_json::to_value(Abilities::FORCE_DIRECT).unwrap(),
+            json!([{"type": "direct-tcp-v1"}, {"type": "noise-crypto-v1"}])
+        );
+
+        /* Peers that don't know noise, like the Python implementation, get secretbox */
+        let python: Abilities =
+            serde_json::from_value(json!([{"type": "direct-tcp-v1"}, {"type": "relay-v1"}]))
+                .unwrap();
+        assert!(!Abilities::ALL.intersect(&python).can_noise_crypto());
+        let rust: Abilities = serde_json::from_value(json!([{"type": "noise-crypto-v1"}])).unwrap();
+        assert!(Abilities::ALL.intersect(&rust).can_noise_crypto());
+    }
+
     #[test]
     pub fn test_hints_encoding() {
         assert_eq!(
             serde_json::to_value(Hints::new(
-                [DirectHint {
-                    hostname: "localhost".into(),
-                    port: 1234
-                }],
+                [DirectHint::new("localhost", 1234)],
                 [RelayHint::new(
                     Some("default".into()),
                     [DirectHint::new("transit.magic-wormhole.io", 4001)],
@@ -1562,4 +2002,237 @@ mod test {
             ])
         )
     }
+
+    /** Two ends of a transit connection over localhost, skipping the hints */
+    #[cfg(not(target_family = "wasm"))]
+    #[allow(deprecated)]
+    async fn transit_pair() -> (Transit, Transit) {
+        let key = Arc::new(Key::<TransitKey>::new(Box::new(
+            crypto_secretbox::Key::from([7; 32]),
+        )));
+        transit_pair_with(&crypto::SecretboxInit { key }).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[allow(deprecated)]
+    async fn transit_pair_with(init: &dyn crypto::TransitCryptoInit) -> (Transit, Transit) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let transit = |socket: Box<dyn TransitTransport>, (tx, rx)| Transit {
+            socket,
+            tx,
+            rx,
+            inactivity_timeout: None,
+            upload: None,
+            download: None,
+        };
+        futures::join!(
+            async {
+                let mut socket: Box<dyn TransitTransport> =
+                    Box::new(TcpStream::connect(address).await.unwrap());
+                let finalizer = init.handshake_leader(&mut *socket).await.unwrap();
+                let ciphers = finalizer.handshake_finalize(&mut *socket).await.unwrap();
+                transit(socket, ciphers)
+            },
+            async {
+                let mut socket: Box<dyn TransitTransport> =
+                    Box::new(listener.accept().await.unwrap().0);
+                let finalizer = init.handshake_follower(&mut *socket).await.unwrap();
+                let ciphers = finalizer.handshake_finalize(&mut *socket).await.unwrap();
+                transit(socket, ciphers)
+            },
+        )
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[async_std::test]
+    async fn test_transit_stream() {
+        let (leader, follower) = transit_pair().await;
+        let mut leader = leader.into_stream();
+        let mut follower = follower.into_stream();
+
+        /* More than one record */
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let ((), received) = futures::join!(
+            async {
+                leader.write_all(&data).await.unwrap();
+                leader.close().await.unwrap();
+            },
+            async {
+                let mut received = Vec::new();
+                follower.read_to_end(&mut received).await.unwrap();
+                received
+            },
+        );
+        assert_eq!(received, data);
+
+        /* The other direction is still open */
+        follower.write_all(b"pong").await.unwrap();
+        let mut pong = [0; 4];
+        leader.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"pong");
+    }
+
+    #[cfg(all(feature = "experimental-transit-noise", not(target_family = "wasm")))]
+    #[async_std::test]
+    #[allow(deprecated)]
+    async fn test_noise_transit() {
+        let key = Arc::new(Key::<TransitKey>::new(Box::new(
+            crypto_secretbox::Key::from([7; 32]),
+        )));
+        let (mut leader, mut follower) = transit_pair_with(&crypto::NoiseInit { key }).await;
+
+        leader.send_record(b"ping").await.unwrap();
+        assert_eq!(&*follower.receive_record().await.unwrap(), b"ping");
+        follower.send_record(b"pong").await.unwrap();
+        assert_eq!(&*leader.receive_record().await.unwrap(), b"pong");
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    pub fn test_hint_priority() {
+        /* The Python implementation always sends a priority */
+        let hints: Hints = serde_json::from_value(json!([
+            {"type": "direct-tcp-v1", "priority": 0.0, "hostname": "192.168.1.8", "port": 1234},
+            {"type": "direct-tcp-v1", "priority": 1.5, "hostname": "10.0.0.1", "port": 1234},
+            {"type": "direct-tcp-v1", "hostname": "10.0.0.2", "port": 1234},
+        ]))
+        .unwrap();
+        let mut direct = hints.direct_tcp.into_iter().collect::<Vec<_>>();
+        DirectHint::sort_by_priority(&mut direct);
+        assert_eq!(direct[0], DirectHint::new("10.0.0.1", 1234));
+        assert_eq!(direct[0].priority, 1.5);
+        assert!(direct[1..].iter().all(|hint| hint.priority == 0.0));
+
+        /* Hints to the same endpoint are deduplicated, whatever their priority */
+        assert_eq!(
+            DirectHint::new("10.0.0.1", 1234).with_priority(2.0),
+            DirectHint::new("10.0.0.1", 1234)
+        );
+
+        assert_eq!(
+            serde_json::to_value(DirectHint::new("10.0.0.1", 1234).with_priority(-1.0)).unwrap(),
+            json!({"priority": -1.0, "hostname": "10.0.0.1", "port": 1234})
+        );
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    pub fn test_predicted_ports() {
+        let hint = DirectHint::new("203.0.113.7", 4000).with_priority(1.0);
+        let predicted = hint.predicted_ports().collect::<Vec<_>>();
+        assert_eq!(
+            predicted,
+            vec![
+                DirectHint::new("203.0.113.7", 4001),
+                DirectHint::new("203.0.113.7", 4002),
+                DirectHint::new("203.0.113.7", 4003),
+            ]
+        );
+        assert!(predicted.iter().all(|hint| hint.priority == 0.0));
+
+        /* Not behind a NAT, or not an IP address at all */
+        for hostname in [
+            "192.168.1.8",
+            "10.0.0.1",
+            "fd00::1",
+            "fe80::1",
+            "example.org",
+        ] {
+            assert_eq!(
+                DirectHint::new(hostname, 4000).predicted_ports().count(),
+                0,
+                "{hostname}"
+            );
+        }
+        assert_eq!(
+            DirectHint::new("2001:db8::1", u16::MAX)
+                .predicted_ports()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    pub fn test_ipv6_hints() {
+        let hint =
+            RelayHint::from_urls(None, ["tcp://[2001:db8::1]:4001".parse().unwrap()]).unwrap();
+        assert_eq!(
+            hint.tcp.into_iter().collect::<Vec<_>>(),
+            vec![DirectHint::new("2001:db8::1", 4001)]
+        );
+
+        /* Bracketed literals from other implementations */
+        assert_eq!(
+            SocketAddr::try_from(&DirectHint::new("[2001:db8::1]", 4001)).unwrap(),
+            "[2001:db8::1]:4001".parse().unwrap()
+        );
+        assert_eq!(
+            SocketAddr::try_from(&DirectHint::new("192.168.1.8", 4001)).unwrap(),
+            "[::ffff:192.168.1.8]:4001".parse().unwrap()
+        );
+        assert!(IpAddr::try_from(&DirectHint::new("[192.168.1.8", 4001)).is_err());
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    pub fn test_local_network_hints() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        assert_eq!(local_hint_priority(&ip("192.168.1.8")), LAN_HINT_PRIORITY);
+        assert_eq!(local_hint_priority(&ip("fd12:3456::1")), LAN_HINT_PRIORITY);
+        assert_eq!(
+            local_hint_priority(&ip("169.254.3.4")),
+            LINK_LOCAL_HINT_PRIORITY
+        );
+        assert_eq!(
+            local_hint_priority(&ip("fe80::1")),
+            LINK_LOCAL_HINT_PRIORITY
+        );
+        assert_eq!(local_hint_priority(&ip("203.0.113.7")), 0.0);
+        assert_eq!(local_hint_priority(&ip("2001:db8::1")), 0.0);
+
+        let mask = ip("255.255.255.0");
+        assert!(is_same_subnet(ip("192.168.1.8"), ip("192.168.1.200"), mask));
+        assert!(!is_same_subnet(ip("192.168.1.8"), ip("192.168.2.8"), mask));
+        let mask = ip("ffff:ffff:ffff:ffff::");
+        assert!(is_same_subnet(ip("fe80::1"), ip("fe80::abcd:1"), mask));
+        assert!(!is_same_subnet(
+            ip("2001:db8:0:1::1"),
+            ip("2001:db8:0:2::1"),
+            mask
+        ));
+        /* Different families never match */
+        assert!(!is_same_subnet(ip("10.0.0.1"), ip("::ffff:10.0.0.1"), mask));
+
+        assert!(!is_on_local_network(
+            &DirectHint::new("example.org", 1234),
+            &[]
+        ));
+    }
+
+    #[cfg(all(feature = "serde-types", not(target_family = "wasm")))]
+    #[test]
+    pub fn test_transit_info_serde() {
+        let info = TransitInfo {
+            conn_type: ConnectionType::Relay {
+                name: Some("default".into()),
+            },
+            peer_addr: "127.0.0.1:4001".parse().unwrap(),
+        };
+        let value = serde_json::to_value(&info).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "conn_type": {"type": "relay", "name": "default"},
+                "peer_addr": "127.0.0.1:4001",
+            })
+        );
+        assert_eq!(serde_json::from_value::<TransitInfo>(value).unwrap(), info);
+
+        assert_eq!(
+            serde_json::to_value(TransitError::from(std::io::Error::other("boom"))).unwrap(),
+            json!({"message": "I/O error", "causes": ["boom"]})
+        );
+    }
 }
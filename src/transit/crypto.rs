@@ -25,6 +25,8 @@ pub(super) enum TransitHandshakeError {
     HandshakeFailed,
     #[error("Relay handshake failed")]
     RelayHandshakeFailed,
+    #[error("Timed out connecting")]
+    Timeout,
     #[error("Malformed peer address")]
     BadAddress(
         #[from]
@@ -106,6 +108,28 @@ pub struct SecretboxInit {
     pub key: Arc<Key<TransitKey>>,
 }
 
+impl SecretboxInit {
+    /// The first line the leader sends
+    pub(super) fn sender_handshake(&self) -> String {
+        format!(
+            "transit sender {} ready\n\n",
+            self.key
+                .derive_subkey_from_purpose::<crate::GenericKey>("transit_sender")
+                .to_hex()
+        )
+    }
+
+    /// The first line the follower sends
+    pub(super) fn receiver_handshake(&self) -> String {
+        format!(
+            "transit receiver {} ready\n\n",
+            self.key
+                .derive_subkey_from_purpose::<crate::GenericKey>("transit_receiver")
+                .to_hex()
+        )
+    }
+}
+
 #[async_trait]
 impl TransitCryptoInit for SecretboxInit {
     async fn handshake_leader(
@@ -122,24 +146,9 @@ impl TransitCryptoInit for SecretboxInit {
 
         // for transmit mode, send send_handshake_msg and compare.
         // the received message with send_handshake_msg
-        socket
-            .write_all(
-                format!(
-                    "transit sender {} ready\n\n",
-                    self.key
-                        .derive_subkey_from_purpose::<crate::GenericKey>("transit_sender")
-                        .to_hex()
-                )
-                .as_bytes(),
-            )
-            .await?;
+        socket.write_all(self.sender_handshake().as_bytes()).await?;
 
-        let expected_rx_handshake = format!(
-            "transit receiver {} ready\n\n",
-            self.key
-                .derive_subkey_from_purpose::<crate::GenericKey>("transit_receiver")
-                .to_hex()
-        );
+        let expected_rx_handshake = self.receiver_handshake();
         assert_eq!(expected_rx_handshake.len(), 89);
         socket.read_expect(expected_rx_handshake.as_bytes()).await?;
 
@@ -192,23 +201,10 @@ impl TransitCryptoInit for SecretboxInit {
         // for receive mode, send receive_handshake_msg and compare.
         // the received message with send_handshake_msg
         socket
-            .write_all(
-                format!(
-                    "transit receiver {} ready\n\n",
-                    self.key
-                        .derive_subkey_from_purpose::<crate::GenericKey>("transit_receiver")
-                        .to_hex(),
-                )
-                .as_bytes(),
-            )
+            .write_all(self.receiver_handshake().as_bytes())
             .await?;
 
-        let expected_tx_handshake = format!(
-            "transit sender {} ready\n\ngo\n",
-            self.key
-                .derive_subkey_from_purpose::<crate::GenericKey>("transit_sender")
-                .to_hex(),
-        );
+        let expected_tx_handshake = self.sender_handshake() + "go\n";
         assert_eq!(expected_tx_handshake.len(), 90);
         socket.read_expect(expected_tx_handshake.as_bytes()).await?;
 
@@ -400,6 +396,16 @@ struct SecretboxCryptoDecrypt {
     pub rnonce: secretbox::Nonce,
 }
 
+/** Increment a big-endian nonce in place, like `sodium_increment` but in network byte order. */
+fn sodium_increment_be(n: &mut [u8]) {
+    let mut c = 1u16;
+    for b in n.iter_mut().rev() {
+        c += *b as u16;
+        *b = c as u8;
+        c >>= 8;
+    }
+}
+
 #[async_trait]
 impl TransitCryptoEncrypt for SecretboxCryptoEncrypt {
     async fn encrypt(
@@ -427,7 +433,7 @@ impl TransitCryptoEncrypt for SecretboxCryptoEncrypt {
         socket.write_all(nonce).await?;
         socket.write_all(&ciphertext).await?;
 
-        crate::util::sodium_increment_be(nonce);
+        sodium_increment_be(nonce);
 
         Ok(())
     }
@@ -463,7 +469,7 @@ impl TransitCryptoDecrypt for SecretboxCryptoDecrypt {
                     TransitError::Nonce(received_nonce.into(), nonce.as_slice().into()),
                 );
 
-                crate::util::sodium_increment_be(nonce);
+                sodium_increment_be(nonce);
             }
 
             let cipher = secretbox::XSalsa20Poly1305::new(secretbox::Key::from_slice(&self.rkey));
@@ -0,0 +1,154 @@
+use super::{Transit, TransitError};
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    ready,
+    task::{Context, Poll},
+    Sink, Stream,
+};
+use std::{io, pin::Pin};
+
+/**
+ * Larger writes are split into records of this size.
+ *
+ * Each record costs a few bytes for the length, nonce and authentication tag, and the other side
+ * has to hold a whole record in memory before it can check it.
+ */
+const MAX_RECORD_SIZE: usize = 64 * 1024;
+
+type RecordSink = Pin<Box<dyn Sink<Box<[u8]>, Error = TransitError> + Send>>;
+type RecordStream = Pin<Box<dyn Stream<Item = Result<Box<[u8]>, TransitError>> + Send>>;
+
+/**
+ * A [`Transit`] connection as a bidirectional byte stream, see [`Transit::into_stream`]
+ *
+ * Writes are sent as encrypted records of up to 64 KiB. Closing the stream sends an empty record,
+ * which the other side reads as end of file. Both sides must use a `TransitStream` for this to work.
+ * The connection itself is closed once the stream is dropped.
+ */
+pub struct TransitStream {
+    sink: RecordSink,
+    stream: RecordStream,
+    /** The part of the last received record that has not been read yet */
+    read_buffer: Box<[u8]>,
+    read_position: usize,
+    /** The other side sent the end of file */
+    read_eof: bool,
+    /** We sent our end of file */
+    write_eof: bool,
+}
+
+impl std::fmt::Debug for TransitStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransitStream")
+            .field("read_eof", &self.read_eof)
+            .field("write_eof", &self.write_eof)
+            .finish_non_exhaustive()
+    }
+}
+
+fn into_io_error(error: TransitError) -> io::Error {
+    match error {
+        TransitError::IO(error) => error,
+        TransitError::Timeout => io::Error::new(io::ErrorKind::TimedOut, error),
+        error => io::Error::new(io::ErrorKind::InvalidData, error),
+    }
+}
+
+impl Transit {
+    /**
+     * Convert the transit connection to a byte stream, to tunnel arbitrary protocols through it
+     *
+     * The encryption, the inactivity timeout and the rate limit still apply. Interactive protocols
+     * that may stay silent for a while should disable
+     * [`Timeouts::transfer_inactivity`](crate::Timeouts::transfer_inactivity).
+     */
+    pub fn into_stream(self) -> TransitStream {
+        let (sink, stream) = self.split();
+        TransitStream {
+            sink: Box::pin(sink),
+            stream: Box::pin(stream),
+            read_buffer: Box::new([]),
+            read_position: 0,
+            read_eof: false,
+            write_eof: false,
+        }
+    }
+}
+
+impl AsyncRead for TransitStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            let remaining = &this.read_buffer[this.read_position..];
+            if !remaining.is_empty() {
+                let length = remaining.len().min(buf.len());
+                buf[..length].copy_from_slice(&remaining[..length]);
+                this.read_position += length;
+                return Poll::Ready(Ok(length));
+            }
+            if this.read_eof {
+                return Poll::Ready(Ok(0));
+            }
+            match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(Ok(record)) if record.is_empty() => this.read_eof = true,
+                Some(Ok(record)) => {
+                    this.read_buffer = record;
+                    this.read_position = 0;
+                },
+                Some(Err(error)) => return Poll::Ready(Err(into_io_error(error))),
+                None => this.read_eof = true,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for TransitStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.write_eof {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(this.sink.as_mut().poll_ready(cx)).map_err(into_io_error)?;
+        let length = buf.len().min(MAX_RECORD_SIZE);
+        this.sink
+            .as_mut()
+            .start_send(buf[..length].into())
+            .map_err(into_io_error)?;
+        Poll::Ready(Ok(length))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .sink
+            .as_mut()
+            .poll_flush(cx)
+            .map_err(into_io_error)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.write_eof {
+            ready!(this.sink.as_mut().poll_ready(cx)).map_err(into_io_error)?;
+            this.sink
+                .as_mut()
+                .start_send(Box::new([]))
+                .map_err(into_io_error)?;
+            this.write_eof = true;
+        }
+        this.sink.as_mut().poll_close(cx).map_err(into_io_error)
+    }
+}
@@ -0,0 +1,106 @@
+use crate::util::{self, Instant};
+use std::{num::NonZeroU64, time::Duration};
+
+/**
+ * How much may be sent at once after the connection has been idle.
+ * Short enough that the rate limit still holds over a few seconds.
+ */
+const BURST: Duration = Duration::from_millis(250);
+
+/**
+ * Maximum speeds of a transit connection, in bytes per second
+ *
+ * `None` means unlimited, which is the default. Set it with
+ * [`Wormhole::set_rate_limit`](crate::Wormhole::set_rate_limit) before a transfer, or
+ * with [`Transit::set_rate_limit`](super::Transit::set_rate_limit) on a connection.
+ *
+ * ```
+ * use magic_wormhole::transit::RateLimit;
+ * use std::num::NonZeroU64;
+ *
+ * let rate_limit = RateLimit {
+ *     upload: NonZeroU64::new(1024 * 1024),
+ *     ..RateLimit::default()
+ * };
+ * ```
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Sending records to the other side
+    pub upload: Option<NonZeroU64>,
+    /// Receiving records from the other side
+    pub download: Option<NonZeroU64>,
+}
+
+/**
+ * A token bucket, filling up with `rate` bytes per second
+ *
+ * Records larger than the bucket may still pass; the bucket goes into debt and the next
+ * record waits until it is paid back.
+ */
+#[derive(Debug)]
+pub(super) struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(super) fn new(rate: NonZeroU64) -> Self {
+        let rate = rate.get() as f64;
+        Self {
+            rate,
+            tokens: rate * BURST.as_secs_f64(),
+            last: Instant::now(),
+        }
+    }
+
+    /** Take `bytes` out of the bucket, returning how long to wait before they may pass */
+    fn take_at(&mut self, now: Instant, bytes: usize) -> Duration {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate * BURST.as_secs_f64());
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    pub(super) async fn take(&mut self, bytes: usize) {
+        let delay = self.take_at(Instant::now(), bytes);
+        if !delay.is_zero() {
+            util::sleep(delay).await;
+        }
+    }
+}
+
+/** Wait until `bytes` may pass the bucket, if there is one */
+pub(super) async fn throttle(bucket: &mut Option<TokenBucket>, bytes: usize) {
+    if let Some(bucket) = bucket {
+        bucket.take(bytes).await;
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(NonZeroU64::new(1000).unwrap());
+        let start = bucket.last;
+        let at = |millis| start + Duration::from_millis(millis);
+
+        /* The burst passes right away */
+        assert_eq!(bucket.take_at(at(0), 250), Duration::ZERO);
+        /* Then we have to wait for the tokens */
+        assert_eq!(bucket.take_at(at(0), 500), Duration::from_millis(500));
+        /* Once paid back, the bucket refills at the rate */
+        assert_eq!(bucket.take_at(at(600), 100), Duration::ZERO);
+        /* But never beyond the burst */
+        assert_eq!(bucket.take_at(at(10_000), 250), Duration::ZERO);
+        assert_eq!(bucket.take_at(at(10_000), 100), Duration::from_millis(100));
+    }
+}
@@ -19,11 +19,21 @@ use std::{
 
 #[async_trait]
 pub(super) trait TransitTransportRx: AsyncRead + std::any::Any + Unpin + Send {
-    /// Helper method for handshake: read a fixed number of bytes and make sure they are as expected
+    /**
+     * Helper method for handshake: read a fixed number of bytes and make sure they are as expected
+     *
+     * The handshakes contain values derived from the transit key, so they are compared in constant
+     * time. Always reads the whole length, wherever the first mismatch is.
+     */
     async fn read_expect(&mut self, expected: &[u8]) -> Result<(), TransitHandshakeError> {
+        use subtle::ConstantTimeEq;
+
         let mut buffer = vec![0u8; expected.len()];
         self.read_exact(&mut buffer).await?;
-        ensure!(buffer == expected, TransitHandshakeError::HandshakeFailed);
+        ensure!(
+            bool::from(buffer.ct_eq(expected)),
+            TransitHandshakeError::HandshakeFailed
+        );
         Ok(())
     }
 
@@ -131,7 +141,7 @@ pub(super) async fn tcp_get_external_ip() -> Result<(SocketAddr, TcpStream), Stu
 
     fn get_binding_request() -> Result<Vec<u8>, bytecodec::Error> {
         use rand::Rng;
-        let random_bytes = rand::thread_rng().gen::<[u8; 12]>();
+        let random_bytes = crate::util::rng().gen::<[u8; 12]>();
 
         let mut message: Message<Attribute> = Message::new(
             MessageClass::Request,
@@ -225,16 +235,46 @@ async fn tcp_connect_custom(
     Ok(stream.into_inner()?.into())
 }
 
+/**
+ * Bind a listener with SO_REUSEADDR and SO_REUSEPORT, like the socket we connect from
+ *
+ * This lets the kernel match an incoming SYN to the listener even while our own connection
+ * attempts are still in flight, and allows binding again to a port we just used.
+ */
+#[cfg(not(target_family = "wasm"))]
+pub(super) fn tcp_listen_custom(
+    local_addr: &socket2::SockAddr,
+) -> std::io::Result<async_std::net::TcpListener> {
+    let socket = socket2::Socket::new(socket2::Domain::IPV6, socket2::Type::STREAM, None)?;
+    set_socket_opts(&socket)?;
+
+    socket.bind(local_addr)?;
+    socket.listen(128)?;
+
+    Ok(std::net::TcpListener::from(socket).into())
+}
+
 #[cfg(not(target_family = "wasm"))]
 pub(super) async fn connect_tcp_direct(
     local_addr: Option<Arc<socket2::SockAddr>>,
     hint: DirectHint,
+    proxy: Option<crate::proxy::ProxyConfig>,
 ) -> Result<TransitConnection, TransitHandshakeError> {
-    let dest_addr = SocketAddr::try_from(&hint)?;
+    let mut dest_addr = SocketAddr::try_from(&hint)?;
+    if let SocketAddr::V6(addr) = &mut dest_addr {
+        /* Link-local addresses (fe80::/10) are only unique per link, so the kernel needs to know which one */
+        if (addr.ip().segments()[0] & 0xffc0) == 0xfe80 && addr.scope_id() == 0 {
+            addr.set_scope_id(link_local_scope_id().unwrap_or(0));
+        }
+    }
     tracing::debug!("Connecting directly to {}", dest_addr);
     let socket;
 
-    if let Some(local_addr) = local_addr {
+    if let Some(proxy) = proxy {
+        /* The proxy connects from its own port, so there is no point in reusing ours */
+        socket = proxy.connect(&hint.hostname, hint.port).await?;
+        tracing::debug!("Connected to {} through the proxy!", dest_addr);
+    } else if let Some(local_addr) = local_addr {
         socket = tcp_connect_custom(&local_addr, &dest_addr.into()).await?;
         tracing::debug!("Connected to {}!", dest_addr);
     } else {
@@ -245,16 +285,122 @@ pub(super) async fn connect_tcp_direct(
     wrap_tcp_connection(socket, ConnectionType::Direct)
 }
 
+/**
+ * The index of the first interface with an IPv6 link-local address
+ *
+ * Hints don't carry a scope, and usually there is only one link anyways.
+ */
+#[cfg(not(target_family = "wasm"))]
+fn link_local_scope_id() -> Option<u32> {
+    if_addrs::get_if_addrs()
+        .ok()?
+        .into_iter()
+        .filter(|iface| !iface.is_loopback())
+        .find(|iface| matches!(&iface.addr, if_addrs::IfAddr::V6(addr) if addr.is_link_local()))
+        .and_then(|iface| iface.index)
+}
+
+/** How often [`connect_tcp_punching`] tries to connect, and the pause between the attempts */
+#[cfg(not(target_family = "wasm"))]
+const PUNCH_ATTEMPTS: u32 = 5;
+#[cfg(not(target_family = "wasm"))]
+const PUNCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/**
+ * Like [`connect_tcp_direct`] from our bound port, but try again a few times to punch a hole into the NATs
+ *
+ * If the peer is behind a NAT, our first SYN usually gets dropped or refused, since its NAT
+ * doesn't know us yet. But it creates the mapping in our NAT, so that the SYN of the peer, sent
+ * from the port it advertised to the port we advertised, gets through. If both SYNs cross, this
+ * is a TCP simultaneous open. The caller's timeout cancels the remaining attempts.
+ */
+#[cfg(not(target_family = "wasm"))]
+pub(super) async fn connect_tcp_punching(
+    local_addr: Arc<socket2::SockAddr>,
+    hint: DirectHint,
+) -> Result<TransitConnection, TransitHandshakeError> {
+    let mut attempt = 1;
+    loop {
+        match connect_tcp_direct(Some(local_addr.clone()), hint.clone(), None).await {
+            Err(TransitHandshakeError::IO(err)) if attempt < PUNCH_ATTEMPTS => {
+                tracing::trace!("Attempt {attempt} to connect to {hint} failed: {err}");
+                attempt += 1;
+                crate::util::sleep(PUNCH_INTERVAL).await;
+            },
+            result => return result,
+        }
+    }
+}
+
+/** How long to wait for a connection attempt before starting the next one, see [`tcp_connect_happy_eyeballs`] */
+#[cfg(not(target_family = "wasm"))]
+const CONNECTION_ATTEMPT_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+
+/**
+ * Connect to a host name via whichever of its addresses works first
+ *
+ * "Happy Eyeballs" as in RFC 8305: the IPv6 and IPv4 addresses are tried alternately, IPv6 first, and
+ * each attempt starts [`CONNECTION_ATTEMPT_DELAY`] after the previous one instead of waiting for it to
+ * fail. The first connection wins, the other attempts are cancelled.
+ */
+#[cfg(not(target_family = "wasm"))]
+async fn tcp_connect_happy_eyeballs(hostname: &str, port: u16) -> std::io::Result<TcpStream> {
+    use futures::stream::{FuturesUnordered, StreamExt};
+
+    let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        async_std::net::ToSocketAddrs::to_socket_addrs(&(hostname, port))
+            .await?
+            .partition(SocketAddr::is_ipv6);
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    let mut addrs = Vec::new();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => addrs.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    let mut attempts = addrs
+        .into_iter()
+        .enumerate()
+        .map(|(index, addr)| async move {
+            crate::util::sleep(CONNECTION_ATTEMPT_DELAY * index as u32).await;
+            tracing::trace!("Trying {addr}");
+            TcpStream::connect(addr).await
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut last_error = None;
+    while let Some(result) = attempts.next().await {
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{hostname} has no addresses"),
+        )
+    }))
+}
+
 /* Take a relay hint and try to connect to it */
 #[cfg(not(target_family = "wasm"))]
 pub(super) async fn connect_tcp_relay(
     host: DirectHint,
     name: Option<String>,
+    proxy: Option<crate::proxy::ProxyConfig>,
 ) -> Result<TransitConnection, TransitHandshakeError> {
     tracing::debug!("Connecting to relay {}", host);
-    let socket = TcpStream::connect((host.hostname.as_str(), host.port))
-        .err_into::<TransitHandshakeError>()
-        .await?;
+    let socket = match proxy {
+        Some(proxy) => proxy.connect(&host.hostname, host.port).await?,
+        None => {
+            tcp_connect_happy_eyeballs(&host.hostname, host.port)
+                .err_into::<TransitHandshakeError>()
+                .await?
+        },
+    };
     tracing::debug!("Connected to {}!", host);
 
     wrap_tcp_connection(socket, ConnectionType::Relay { name })
@@ -14,6 +14,41 @@ macro_rules! bail {
     }};
 }
 
+/** Implement [`serde::Serialize`] for error types using [`serialize_error`] */
+#[cfg(feature = "serde-types")]
+macro_rules! impl_serialize_error {
+    ($($error:ty),+ $(,)?) => {
+        $(
+            impl serde::Serialize for $error {
+                fn serialize<S: serde::Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+                    $crate::util::serialize_error(self, ser)
+                }
+            }
+        )+
+    };
+}
+
+/**
+ * Serialize an error as its message, together with the messages of all its sources
+ *
+ * Errors may wrap arbitrary other errors, thus they can only be serialized but not deserialized.
+ */
+#[cfg(feature = "serde-types")]
+pub(crate) fn serialize_error<S: serde::Serializer>(
+    error: &dyn std::error::Error,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+
+    let causes: Vec<String> = std::iter::successors(error.source(), |error| error.source())
+        .map(ToString::to_string)
+        .collect();
+    let mut state = ser.serialize_struct("Error", 2)?;
+    state.serialize_field("message", &error.to_string())?;
+    state.serialize_field("causes", &causes)?;
+    state.end()
+}
+
 /// A warpper around `&[u8]` that implements [`std::fmt::Display`] in a more intelligent+ way.
 pub struct DisplayBytes<'a>(pub &'a [u8]);
 
@@ -89,17 +124,141 @@ pub fn sodium_increment_le(n: &mut [u8]) {
     }
 }
 
-pub fn sodium_increment_be(n: &mut [u8]) {
-    let mut c = 1u16;
-    for b in n.iter_mut().rev() {
-        c += *b as u16;
-        *b = c as u8;
-        c >>= 8;
+/**
+ * The source of all randomness of this crate
+ *
+ * Backed by the operating system, or by `crypto.getRandomValues` in browsers. Code that wants to be
+ * testable with a deterministic RNG should take an `impl RngCore + CryptoRng` instead, and only
+ * fall back to this one.
+ */
+pub fn rng() -> rand::rngs::OsRng {
+    rand::rngs::OsRng
+}
+
+/**
+ * The RNG of one connection, shared by all its steps
+ *
+ * This is the [`rng`] of the operating system, unless a test replaced it with
+ * [`WormholeBuilder::rng`](crate::WormholeBuilder::rng).
+ */
+#[derive(Clone)]
+pub(crate) struct SharedRng(std::sync::Arc<std::sync::Mutex<dyn rand::RngCore + Send>>);
+
+impl SharedRng {
+    pub fn new(rng: impl rand::RngCore + rand::CryptoRng + Send + 'static) -> Self {
+        Self(std::sync::Arc::new(std::sync::Mutex::new(rng)))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, dyn rand::RngCore + Send + 'static> {
+        /* An RNG can't be left in an inconsistent state */
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl Default for SharedRng {
+    fn default() -> Self {
+        Self::new(rng())
     }
 }
 
-/** Mint a new hashcash token with a given difficulty and resource string. */
+impl std::fmt::Debug for SharedRng {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedRng")
+    }
+}
+
+impl rand::RngCore for SharedRng {
+    fn next_u32(&mut self) -> u32 {
+        self.lock().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.lock().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.lock().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.lock().try_fill_bytes(dest)
+    }
+}
+
+/* Only constructed from `CryptoRng`s */
+impl rand::CryptoRng for SharedRng {}
+
+/**
+ * The wall clock of one connection
+ *
+ * This is the system time, unless a test replaced it with [`WormholeBuilder::clock`](crate::WormholeBuilder::clock).
+ */
+#[derive(Clone)]
+pub(crate) struct Clock(std::sync::Arc<dyn Fn() -> time::OffsetDateTime + Send + Sync>);
+
+impl Clock {
+    pub fn new(now: impl Fn() -> time::OffsetDateTime + Send + Sync + 'static) -> Self {
+        Self(std::sync::Arc::new(now))
+    }
+
+    pub fn now(&self) -> time::OffsetDateTime {
+        (self.0)()
+    }
+}
+
+impl Default for Clock {
+    /* Not `SystemTime::now`, which panics on `wasm32-unknown-unknown` */
+    fn default() -> Self {
+        Self::new(time::OffsetDateTime::now_utc)
+    }
+}
+
+impl std::fmt::Debug for Clock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+/** Like [`std::time::Instant`], which panics on `wasm32-unknown-unknown` */
+#[cfg(not(target_family = "wasm"))]
+#[allow(unused_imports)]
+pub use std::time::Instant;
+#[cfg(target_family = "wasm")]
+#[allow(unused_imports)]
+pub use wasm_timer::Instant;
+
+/**
+ * Target of the protocol trace, see the "Diagnostics" section of the crate documentation
+ *
+ * Everything logged to it is at the `TRACE` level, and must not contain any secrets.
+ */
+pub(crate) const PROTOCOL_TRACE: &str = "magic_wormhole::protocol";
+
+/** Log a transition of one of the protocol's state machines to the protocol trace */
+pub(crate) fn trace_transition(machine: &str, from: &str, to: &str) {
+    tracing::trace!(target: PROTOCOL_TRACE, machine, from, to, "transition");
+}
+
+/**
+ * Mint a new hashcash token with a given difficulty and resource string.
+ *
+ * The stamps follow the `hashcash` module the Python server checks them with: the date is `YYMMDD`,
+ * the counter is hex encoded and the difficulty is counted in leading zero hex digits of the SHA-1,
+ * so it gets rounded up to a multiple of four bits.
+ */
 pub fn hashcash(resource: String, bits: u32) -> String {
+    hashcash_with(resource, bits, &mut rng(), time::OffsetDateTime::now_utc())
+}
+
+/** Like [`hashcash`], but with the given RNG and at the given time */
+pub(crate) fn hashcash_with(
+    resource: String,
+    bits: u32,
+    rng: &mut impl rand::RngCore,
+    now: time::OffsetDateTime,
+) -> String {
     use rand::{distributions::Standard, Rng};
     use sha1::{Digest, Sha1};
 
@@ -110,71 +269,42 @@ pub fn hashcash(resource: String, bits: u32) -> String {
         );
     }
 
-    /* This is the `[year][month][day]` format, but without activating the parser */
-    use time::format_description::{Component, FormatItem};
-    let format = [
-        FormatItem::Component(Component::Year(
-            time::format_description::modifier::Year::default(),
-        )),
-        FormatItem::Component(Component::Month(
-            time::format_description::modifier::Month::default(),
-        )),
-        FormatItem::Component(Component::Day(
-            time::format_description::modifier::Day::default(),
-        )),
-    ];
-
-    let base64_engine = base64::engine::general_purpose::STANDARD;
-
     /* I'm pretty sure HashCash should work with any time zone */
-    let date = base64_engine.encode(
-        time::OffsetDateTime::now_utc()
-            .date()
-            .format(&format[..])
-            .unwrap(),
+    let date = now.date();
+    let date = format!(
+        "{:02}{:02}{:02}",
+        date.year().rem_euclid(100),
+        u8::from(date.month()),
+        date.day()
     );
 
-    let rand: String = base64_engine.encode(
-        rand::thread_rng()
-            .sample_iter(&Standard)
-            .take(16)
-            .collect::<Vec<u8>>(),
-    );
+    let base64_engine = base64::engine::general_purpose::STANDARD;
+    let rand: String =
+        base64_engine.encode(rng.sample_iter(&Standard).take(16).collect::<Vec<u8>>());
 
-    /* 64 bit counter should suffice */
-    let mut counter = [0; 8];
+    let challenge = format!("1:{}:{}:{}::{}:", bits, date, resource, rand);
+    let zero_digits = bits.div_ceil(4) as usize;
     let mut hasher = Sha1::new();
 
-    loop {
-        sodium_increment_be(&mut counter);
-
-        let stamp = format!(
-            "1:{}:{}:{}::{}:{}",
-            bits,
-            date,
-            resource,
-            rand,
-            base64_engine.encode(counter)
-        );
+    /* 64 bit counter should suffice */
+    for counter in 0u64.. {
+        let stamp = format!("{}{:x}", challenge, counter);
 
         hasher.update(&stamp);
         let result = hasher.finalize_reset();
 
-        let mut leading_zeros = 0;
-        for byte in result {
-            let front_zeros = byte.leading_zeros();
-            leading_zeros += front_zeros;
-
-            if front_zeros < 8 {
-                break;
-            }
-        }
-
-        if leading_zeros >= bits {
+        if result
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0x0f])
+            .take(zero_digits)
+            .all(|digit| digit == 0)
+        {
             return stamp;
         }
     }
+    unreachable!("Exhausted the hashcash counter")
 }
+
 #[cfg(not(target_family = "wasm"))]
 #[allow(dead_code)]
 pub async fn sleep(duration: std::time::Duration) {
@@ -210,3 +340,96 @@ where
     use wasm_timer::TryFutureExt;
     future.map(Result::Ok).timeout(duration).await
 }
+
+/** Like [`timeout`], but waits forever if there is no duration */
+#[allow(dead_code)]
+pub async fn timeout_opt<F, T>(duration: Option<std::time::Duration>, future: F) -> Result<T, ()>
+where
+    F: futures::Future<Output = T>,
+{
+    match duration {
+        Some(duration) => timeout(duration, future).await.map_err(|_| ()),
+        None => Ok(future.await),
+    }
+}
+
+/**
+ * Check that a table of transitions matches the [`trace_transition`] calls in the source of a module
+ *
+ * Every traced transition must be in the table, and every transition in the table must be traced,
+ * so that the graph of [`Wormhole::machines_graphviz`](crate::Wormhole::machines_graphviz) can't
+ * drift from what the code does. Only calls with string literals are recognized.
+ */
+#[cfg(test)]
+pub(crate) fn assert_transitions_traced(source: &str, machine: &str, table: &[(&str, &str, &str)]) {
+    use std::collections::BTreeSet;
+
+    let literal = |argument: &str| {
+        let argument = argument.trim();
+        argument
+            .strip_prefix('"')
+            .and_then(|argument| argument.strip_suffix('"'))
+            .map(str::to_owned)
+    };
+    let traced = source
+        .split(concat!("trace_transition", "("))
+        .skip(1)
+        .filter_map(|call| {
+            let arguments = call.split(')').next()?;
+            match arguments.split(',').map(literal).collect::<Vec<_>>()[..] {
+                [Some(ref name), Some(ref from), Some(ref to), ..] if name == machine => {
+                    Some((from.clone(), to.clone()))
+                },
+                _ => None,
+            }
+        })
+        .collect::<BTreeSet<_>>();
+    let documented = table
+        .iter()
+        .map(|(from, to, _trigger)| (from.to_string(), to.to_string()))
+        .collect::<BTreeSet<_>>();
+    assert_eq!(
+        traced, documented,
+        "The {machine} transitions don't match the table"
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hashcash() {
+        use sha1::{Digest, Sha1};
+
+        /* 6 bits are rounded up to two hex digits, like the Python server checks them */
+        let stamp = hashcash("resource-string".into(), 6);
+        let fields = stamp.split(':').collect::<Vec<_>>();
+        assert_eq!(fields.len(), 7, "{stamp}");
+        assert_eq!(fields[..2], ["1", "6"]);
+        assert_eq!(fields[2].len(), 6);
+        assert!(fields[2].chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(fields[3..5], ["resource-string", ""]);
+        assert!(u64::from_str_radix(fields[6], 16).is_ok());
+        assert!(hex::encode(Sha1::digest(&stamp)).starts_with("00"));
+    }
+
+    #[test]
+    fn test_hashcash_deterministic() {
+        use rand::SeedableRng;
+
+        /* 2024-02-29 */
+        let now = time::OffsetDateTime::from_unix_timestamp(1_709_164_800).unwrap();
+        let mint = || {
+            hashcash_with(
+                "resource-string".into(),
+                8,
+                &mut rand::rngs::StdRng::seed_from_u64(42),
+                now,
+            )
+        };
+        let stamp = mint();
+        assert!(stamp.starts_with("1:8:240229:resource-string::"), "{stamp}");
+        assert_eq!(stamp, mint());
+    }
+}
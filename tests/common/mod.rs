@@ -0,0 +1,68 @@
+//! Helpers shared by the conformance tests against other implementations
+
+#![allow(dead_code)]
+
+use magic_wormhole::transit;
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+pub const TEXT: &str = "Hello from the conformance tests 🦀";
+
+/** Whether `executable` exists and runs successfully with `probe` as its only argument */
+pub fn available(executable: &str, probe: &str) -> bool {
+    let available = Command::new(executable)
+        .arg(probe)
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+    if !available {
+        tracing::warn!("Client `{executable}` not found, skipping the test");
+    }
+    available
+}
+
+/** Run a command to completion without blocking the executor, returning its standard output */
+pub async fn run(command: Command) -> eyre::Result<String> {
+    run_with_input(command, "").await
+}
+
+/** Like [`run`], but answer interactive questions of the command with `input` */
+pub async fn run_with_input(mut command: Command, input: &str) -> eyre::Result<String> {
+    let input = input.to_owned();
+    let output = async_std::task::spawn_blocking(move || {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        /* Dropping stdin closes it, so that the command doesn't wait for more */
+        child.stdin.take().unwrap().write_all(input.as_bytes())?;
+        child.wait_with_output()
+    })
+    .await?;
+    eyre::ensure!(
+        output.status.success(),
+        "Client failed with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+pub fn abilities(relay: bool) -> transit::Abilities {
+    if relay {
+        transit::Abilities::FORCE_RELAY
+    } else {
+        transit::Abilities::FORCE_DIRECT
+    }
+}
+
+pub fn temp_dir(name: &str) -> eyre::Result<PathBuf> {
+    let dir =
+        std::env::temp_dir().join(format!("wormhole-interop-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
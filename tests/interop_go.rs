@@ -0,0 +1,220 @@
+//! Conformance tests against wormhole-william, the implementation written in Go
+//!
+//! Like the tests in `interop_python.rs`, these spawn the `wormhole-william` command and transfer
+//! text and files with it, in both directions, both over a direct connection and over the relay.
+//! The servers are run locally with [`magic_wormhole::testing`].
+//!
+//! wormhole-william has no option to disable direct connections, so the relay tests only disable
+//! them on our side. Without the `direct-tcp-v1` ability, the other side has to use the relay too.
+//!
+//! They are ignored by default, since they need the Go client installed:
+//!
+//! ```sh
+//! go install github.com/psanford/wormhole-william@latest
+//! cargo test --features testing --test interop_go -- --ignored
+//! ```
+//!
+//! Set `WORMHOLE_GO` to use a different executable than `wormhole-william`. If it can't be found,
+//! the tests pass with a warning instead of failing.
+
+mod common;
+
+use common::{abilities, available, run, run_with_input, temp_dir, TEXT};
+use magic_wormhole::{testing::TestServer, transfer, transit, Code, Nameplate, Wormhole};
+use std::process::Command;
+use test_log::test;
+
+/** A `wormhole-william` command using the local servers, or None if the Go client is missing */
+fn go_wormhole(server: &TestServer) -> Option<Command> {
+    let executable = std::env::var("WORMHOLE_GO").unwrap_or_else(|_| "wormhole-william".into());
+    if !available(&executable, "version") {
+        return None;
+    }
+
+    let mut command = Command::new(executable);
+    command
+        .arg("--relay-url")
+        .arg(server.rendezvous_url())
+        .arg("--transit-helper")
+        .arg(server.relay_url().replacen("tcp://", "tcp:", 1));
+    Some(command)
+}
+
+async fn rust_sends_text() -> eyre::Result<()> {
+    let server = TestServer::start().await?;
+    let Some(mut go) = go_wormhole(&server) else {
+        return Ok(());
+    };
+
+    let pending = Wormhole::builder(server.app_config(transfer::APP_CONFIG))
+        .allocate_code()
+        .await?;
+    go.arg("receive").arg(pending.code().to_string());
+
+    let receiving = run(go);
+    let sending = async {
+        let wormhole = pending.wait_for_peer().await?;
+        transfer::send_text(wormhole, TEXT, futures::future::pending()).await?;
+        eyre::Result::<_>::Ok(())
+    };
+    let (received, ()) = futures::try_join!(receiving, sending)?;
+    assert_eq!(received.trim_end(), TEXT);
+    Ok(())
+}
+
+async fn go_sends_text() -> eyre::Result<()> {
+    let server = TestServer::start().await?;
+    let Some(mut go) = go_wormhole(&server) else {
+        return Ok(());
+    };
+
+    let code = Code::new(&Nameplate::new("7"), "guitarist-revenge");
+    go.arg("send")
+        .arg("--code")
+        .arg(code.to_string())
+        .arg("--text")
+        .arg(TEXT);
+
+    let sending = run(go);
+    let receiving = async {
+        let wormhole = Wormhole::builder(server.app_config(transfer::APP_CONFIG))
+            .connect_with_code(code)
+            .await?;
+        let request = transfer::request_text_or_file(
+            wormhole,
+            server.relay_hints(),
+            transit::Abilities::ALL_ABILITIES,
+            futures::future::pending(),
+        )
+        .await?;
+        match request {
+            Some(transfer::TextOrFileRequest::Text(text)) => Ok(text),
+            _ => eyre::bail!("Expected a text message"),
+        }
+    };
+    let (_, received) = futures::try_join!(sending, receiving)?;
+    assert_eq!(received, TEXT);
+    Ok(())
+}
+
+async fn rust_sends_file(relay: bool) -> eyre::Result<()> {
+    let server = TestServer::start().await?;
+    let Some(mut go) = go_wormhole(&server) else {
+        return Ok(());
+    };
+    let data = std::fs::read("tests/example-file.bin")?;
+    /* wormhole-william always receives into the current directory */
+    let dir = temp_dir(if relay { "go-relay" } else { "go-direct" })?;
+    let out = dir.join("example-file.bin");
+
+    let pending = Wormhole::builder(server.app_config(transfer::APP_CONFIG))
+        .allocate_code()
+        .await?;
+    go.current_dir(&dir)
+        .arg("receive")
+        .arg("--hide-progress")
+        .arg(pending.code().to_string());
+
+    /* Accept the file when asked */
+    let receiving = run_with_input(go, "y\n");
+    let sending = async {
+        let wormhole = pending.wait_for_peer().await?;
+        #[allow(deprecated)]
+        transfer::send_file(
+            wormhole,
+            server.relay_hints(),
+            &mut &data[..],
+            "example-file.bin",
+            data.len() as u64,
+            abilities(relay),
+            |_info| {},
+            |_sent, _total| {},
+            futures::future::pending(),
+        )
+        .await?;
+        eyre::Result::<_>::Ok(())
+    };
+    futures::try_join!(receiving, sending)?;
+    assert_eq!(std::fs::read(&out)?, data);
+    std::fs::remove_file(out)?;
+    Ok(())
+}
+
+async fn go_sends_file(relay: bool) -> eyre::Result<()> {
+    let server = TestServer::start().await?;
+    let Some(mut go) = go_wormhole(&server) else {
+        return Ok(());
+    };
+    let data = std::fs::read("tests/example-file.bin")?;
+
+    let code = Code::new(&Nameplate::new("8"), "sympathy-jamaica");
+    go.arg("send")
+        .arg("--hide-progress")
+        .arg("--code")
+        .arg(code.to_string())
+        .arg("tests/example-file.bin");
+
+    let sending = run(go);
+    let receiving = async {
+        let wormhole = Wormhole::builder(server.app_config(transfer::APP_CONFIG))
+            .connect_with_code(code)
+            .await?;
+        let request = transfer::request_file(
+            wormhole,
+            server.relay_hints(),
+            abilities(relay),
+            futures::future::pending(),
+        )
+        .await?
+        .ok_or_else(|| eyre::eyre!("Cancelled"))?;
+        let mut received = Vec::new();
+        request
+            .accept(
+                |_info| {},
+                |_received, _total| {},
+                &mut received,
+                futures::future::pending(),
+            )
+            .await?;
+        eyre::Result::<_>::Ok(received)
+    };
+    let (_, received) = futures::try_join!(sending, receiving)?;
+    assert_eq!(received, data);
+    Ok(())
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Go client"]
+async fn test_rust_sends_text() -> eyre::Result<()> {
+    rust_sends_text().await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Go client"]
+async fn test_go_sends_text() -> eyre::Result<()> {
+    go_sends_text().await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Go client"]
+async fn test_rust_sends_file_direct() -> eyre::Result<()> {
+    rust_sends_file(false).await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Go client"]
+async fn test_rust_sends_file_relay() -> eyre::Result<()> {
+    rust_sends_file(true).await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Go client"]
+async fn test_go_sends_file_direct() -> eyre::Result<()> {
+    go_sends_file(false).await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Go client"]
+async fn test_go_sends_file_relay() -> eyre::Result<()> {
+    go_sends_file(true).await
+}
@@ -0,0 +1,227 @@
+//! Conformance tests against the reference implementation written in Python
+//!
+//! These spawn the `wormhole` command of the Python package and transfer text and files with it,
+//! in both directions, both over a direct connection and over the relay. The servers are run
+//! locally with [`magic_wormhole::testing`], so no network access is needed.
+//!
+//! They are ignored by default, since they need the Python client installed:
+//!
+//! ```sh
+//! pip install magic-wormhole
+//! cargo test --features testing --test interop_python -- --ignored
+//! ```
+//!
+//! Set `WORMHOLE_PYTHON` to use a different executable than `wormhole`. If it can't be found,
+//! the tests pass with a warning instead of failing.
+
+mod common;
+
+use common::{abilities, available, run, temp_dir, TEXT};
+use magic_wormhole::{testing::TestServer, transfer, transit, Code, Nameplate, Wormhole};
+use std::process::Command;
+use test_log::test;
+
+/** A `wormhole` command using the local servers, or None if the Python client is missing */
+fn python_wormhole(server: &TestServer) -> Option<Command> {
+    let executable = std::env::var("WORMHOLE_PYTHON").unwrap_or_else(|_| "wormhole".into());
+    if !available(&executable, "--version") {
+        return None;
+    }
+
+    let mut command = Command::new(executable);
+    command
+        .arg("--relay-url")
+        .arg(server.rendezvous_url())
+        /* Python uses the Twisted endpoint syntax */
+        .arg("--transit-helper")
+        .arg(server.relay_url().replacen("tcp://", "tcp:", 1));
+    Some(command)
+}
+
+async fn rust_sends_text() -> eyre::Result<()> {
+    let server = TestServer::start().await?;
+    let Some(mut python) = python_wormhole(&server) else {
+        return Ok(());
+    };
+
+    let pending = Wormhole::builder(server.app_config(transfer::APP_CONFIG))
+        .allocate_code()
+        .await?;
+    python
+        .arg("receive")
+        .arg("--only-text")
+        .arg(pending.code().to_string());
+
+    let receiving = run(python);
+    let sending = async {
+        let wormhole = pending.wait_for_peer().await?;
+        transfer::send_text(wormhole, TEXT, futures::future::pending()).await?;
+        eyre::Result::<_>::Ok(())
+    };
+    let (received, ()) = futures::try_join!(receiving, sending)?;
+    assert_eq!(received.trim_end(), TEXT);
+    Ok(())
+}
+
+async fn python_sends_text() -> eyre::Result<()> {
+    let server = TestServer::start().await?;
+    let Some(mut python) = python_wormhole(&server) else {
+        return Ok(());
+    };
+
+    let code = Code::new(&Nameplate::new("7"), "guitarist-revenge");
+    python
+        .arg("send")
+        .arg("--code")
+        .arg(code.to_string())
+        .arg("--text")
+        .arg(TEXT);
+
+    let sending = run(python);
+    let receiving = async {
+        let wormhole = Wormhole::builder(server.app_config(transfer::APP_CONFIG))
+            .connect_with_code(code)
+            .await?;
+        let request = transfer::request_text_or_file(
+            wormhole,
+            server.relay_hints(),
+            transit::Abilities::ALL_ABILITIES,
+            futures::future::pending(),
+        )
+        .await?;
+        match request {
+            Some(transfer::TextOrFileRequest::Text(text)) => Ok(text),
+            _ => eyre::bail!("Expected a text message"),
+        }
+    };
+    let (_, received) = futures::try_join!(sending, receiving)?;
+    assert_eq!(received, TEXT);
+    Ok(())
+}
+
+async fn rust_sends_file(relay: bool) -> eyre::Result<()> {
+    let server = TestServer::start().await?;
+    let Some(mut python) = python_wormhole(&server) else {
+        return Ok(());
+    };
+    let data = std::fs::read("tests/example-file.bin")?;
+    let out = temp_dir("receive")?.join("example-file.bin");
+
+    let pending = Wormhole::builder(server.app_config(transfer::APP_CONFIG))
+        .allocate_code()
+        .await?;
+    python
+        .arg("receive")
+        .arg("--accept-file")
+        .arg("--output-file")
+        .arg(&out)
+        .arg(pending.code().to_string());
+    if relay {
+        python.arg("--no-listen");
+    }
+
+    let receiving = run(python);
+    let sending = async {
+        let wormhole = pending.wait_for_peer().await?;
+        #[allow(deprecated)]
+        transfer::send_file(
+            wormhole,
+            server.relay_hints(),
+            &mut &data[..],
+            "example-file.bin",
+            data.len() as u64,
+            abilities(relay),
+            |_info| {},
+            |_sent, _total| {},
+            futures::future::pending(),
+        )
+        .await?;
+        eyre::Result::<_>::Ok(())
+    };
+    futures::try_join!(receiving, sending)?;
+    assert_eq!(std::fs::read(&out)?, data);
+    std::fs::remove_file(out)?;
+    Ok(())
+}
+
+async fn python_sends_file(relay: bool) -> eyre::Result<()> {
+    let server = TestServer::start().await?;
+    let Some(mut python) = python_wormhole(&server) else {
+        return Ok(());
+    };
+    let data = std::fs::read("tests/example-file.bin")?;
+
+    let code = Code::new(&Nameplate::new("8"), "sympathy-jamaica");
+    python
+        .arg("send")
+        .arg("--code")
+        .arg(code.to_string())
+        .arg("tests/example-file.bin");
+    if relay {
+        python.arg("--no-listen");
+    }
+
+    let sending = run(python);
+    let receiving = async {
+        let wormhole = Wormhole::builder(server.app_config(transfer::APP_CONFIG))
+            .connect_with_code(code)
+            .await?;
+        let request = transfer::request_file(
+            wormhole,
+            server.relay_hints(),
+            abilities(relay),
+            futures::future::pending(),
+        )
+        .await?
+        .ok_or_else(|| eyre::eyre!("Cancelled"))?;
+        let mut received = Vec::new();
+        request
+            .accept(
+                |_info| {},
+                |_received, _total| {},
+                &mut received,
+                futures::future::pending(),
+            )
+            .await?;
+        eyre::Result::<_>::Ok(received)
+    };
+    let (_, received) = futures::try_join!(sending, receiving)?;
+    assert_eq!(received, data);
+    Ok(())
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Python client"]
+async fn test_rust_sends_text() -> eyre::Result<()> {
+    rust_sends_text().await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Python client"]
+async fn test_python_sends_text() -> eyre::Result<()> {
+    python_sends_text().await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Python client"]
+async fn test_rust_sends_file_direct() -> eyre::Result<()> {
+    rust_sends_file(false).await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Python client"]
+async fn test_rust_sends_file_relay() -> eyre::Result<()> {
+    rust_sends_file(true).await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Python client"]
+async fn test_python_sends_file_direct() -> eyre::Result<()> {
+    python_sends_file(false).await
+}
+
+#[test(async_std::test)]
+#[ignore = "needs the Python client"]
+async fn test_python_sends_file_relay() -> eyre::Result<()> {
+    python_sends_file(true).await
+}
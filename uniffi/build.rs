@@ -0,0 +1,3 @@
+fn main() {
+    uniffi::generate_scaffolding("src/magic_wormhole.udl").unwrap();
+}
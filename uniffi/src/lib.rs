@@ -0,0 +1,318 @@
+//! Kotlin and Swift bindings for the file transfer
+//!
+//! This exposes a small, blocking API on top of [`magic_wormhole::transfer`], for mobile apps that
+//! don't want to ship their own implementation of the protocol. The `_async` variants wrap it for
+//! coroutines. The interface is defined in
+//! `magic_wormhole.udl`, the bindings are generated with UniFFI (see the README).
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use futures::{
+    channel::oneshot,
+    future::{BoxFuture, Shared},
+    Future, FutureExt,
+};
+use magic_wormhole::{transfer, transit, Code, PendingWormhole, Wormhole, WormholeError};
+
+uniffi::include_scaffolding!("magic_wormhole");
+
+/// The errors reported to the foreign language
+#[derive(Debug, thiserror::Error)]
+pub enum TransferError {
+    /// The code does not exist (anymore), was already used, or is wrong
+    #[error("Invalid code: {0}")]
+    Code(String),
+    /// The other side rejected the transfer or reported an error
+    #[error("The other side rejected the transfer: {0}")]
+    Rejected(String),
+    /// The transfer got cancelled with a [`CancelHandle`]
+    #[error("The transfer was cancelled")]
+    Cancelled,
+    /// Everything else
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl From<WormholeError> for TransferError {
+    fn from(error: WormholeError) -> Self {
+        match error {
+            WormholeError::PakeFailed
+            | WormholeError::UnclaimedNameplate(_)
+            | WormholeError::InvalidCode(_) => Self::Code(error.to_string()),
+            error => Self::Failed(error.to_string()),
+        }
+    }
+}
+
+impl From<transfer::TransferError> for TransferError {
+    fn from(error: transfer::TransferError) -> Self {
+        match error {
+            transfer::TransferError::Wormhole(error) => error.into(),
+            transfer::TransferError::PeerError(error) => Self::Rejected(error),
+            error => Self::Failed(error.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for TransferError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Failed(error.to_string())
+    }
+}
+
+/// Implemented by the foreign language to follow a transfer
+pub trait TransferListener: Send + Sync {
+    /// The code to show to the user. Only called when sending
+    fn on_code(&self, code: String);
+    /// The other side connected. Compare the verifier for extra security
+    fn on_connected(&self, verifier: String);
+    /// The other side offers a file. Return whether to accept it. Only called when receiving
+    fn on_offer(&self, name: String, size: u64) -> bool;
+    /// Some more bytes have been sent or received
+    fn on_progress(&self, transferred: u64, total: u64);
+}
+
+/// Cancels a running transfer from another thread
+pub struct CancelHandle {
+    sender: Mutex<Option<oneshot::Sender<()>>>,
+    cancelled: Shared<BoxFuture<'static, ()>>,
+}
+
+impl CancelHandle {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let (sender, receiver) = oneshot::channel();
+        let cancelled = async move {
+            /* The sender only gets dropped together with us, don't treat that as cancellation */
+            if receiver.await.is_err() {
+                futures::future::pending::<()>().await
+            }
+        };
+        Self {
+            sender: Mutex::new(Some(sender)),
+            cancelled: cancelled.boxed().shared(),
+        }
+    }
+
+    /// Cancel the transfer. Calling this more than once has no effect
+    pub fn cancel(&self) {
+        if let Some(sender) = self.sender.lock().unwrap().take() {
+            let _ = sender.send(());
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.sender.lock().unwrap().is_none()
+    }
+
+    fn cancelled(&self) -> impl Future<Output = ()> {
+        self.cancelled.clone()
+    }
+}
+
+fn progress_handler(listener: &Arc<dyn TransferListener>) -> impl FnMut(u64, u64) + 'static {
+    let listener = listener.clone();
+    move |transferred: u64, total: u64| listener.on_progress(transferred, total)
+}
+
+/**
+ * A wormhole with a code, to transfer one file with
+ *
+ * Created by [`allocate_code`] or [`connect`], for applications that want to show the code before
+ * choosing what to send. Not part of the UniFFI interface yet, the C bindings use it.
+ */
+pub struct Connection(ConnectionState);
+
+enum ConnectionState {
+    Pending(PendingWormhole<transfer::AppVersion>),
+    Connected(Wormhole),
+}
+
+/// Allocate a new code, typically on the sending side. The other side may connect right away
+pub fn allocate_code(code_length: u8, cancel: &CancelHandle) -> Result<Connection, TransferError> {
+    async_std::task::block_on(async {
+        let pending = Wormhole::builder(transfer::APP_CONFIG)
+            .code_length(code_length.into())
+            .allocate_code_or_cancel(cancel.cancelled())
+            .await?
+            .ok_or(TransferError::Cancelled)?;
+        Ok(Connection(ConnectionState::Pending(pending)))
+    })
+}
+
+/// Connect to the other side with the code it allocated
+pub fn connect(code: String, cancel: &CancelHandle) -> Result<Connection, TransferError> {
+    async_std::task::block_on(async {
+        let wormhole = Wormhole::builder(transfer::APP_CONFIG)
+            .connect_with_code_or_cancel(Code::from(code), cancel.cancelled())
+            .await?
+            .ok_or(TransferError::Cancelled)?;
+        Ok(Connection(ConnectionState::Connected(wormhole)))
+    })
+}
+
+impl Connection {
+    /// The code to show to the user, if we allocated it
+    pub fn code(&self) -> Option<String> {
+        match &self.0 {
+            ConnectionState::Pending(pending) => Some(pending.code().to_string()),
+            ConnectionState::Connected(_) => None,
+        }
+    }
+
+    /** Wait for the other side, unless that already happened */
+    async fn wormhole(
+        self,
+        listener: &dyn TransferListener,
+        cancel: &CancelHandle,
+    ) -> Result<Wormhole, TransferError> {
+        let wormhole = match self.0 {
+            ConnectionState::Pending(pending) => pending
+                .wait_for_peer_or_cancel(cancel.cancelled())
+                .await?
+                .ok_or(TransferError::Cancelled)?,
+            ConnectionState::Connected(wormhole) => wormhole,
+        };
+        listener.on_connected(hex::encode(wormhole.verifier()));
+        Ok(wormhole)
+    }
+
+    /// Send a file or folder
+    pub fn send_file(
+        self,
+        path: String,
+        listener: Box<dyn TransferListener>,
+        cancel: Arc<CancelHandle>,
+    ) -> Result<(), TransferError> {
+        let listener: Arc<dyn TransferListener> = listener.into();
+        async_std::task::block_on(async {
+            let path = PathBuf::from(path);
+            let name = path
+                .file_name()
+                .ok_or_else(|| TransferError::Failed(format!("Invalid path: {}", path.display())))?
+                .to_string_lossy()
+                .into_owned();
+            let offer = transfer::offer::OfferSend::new_file_or_folder(name, &path).await?;
+
+            let wormhole = self.wormhole(&*listener, &cancel).await?;
+            let relay_hints = wormhole.relay_hints().to_vec();
+            transfer::send(
+                wormhole,
+                relay_hints,
+                transit::Abilities::ALL_ABILITIES,
+                offer,
+                |info| tracing::info!("{info}"),
+                progress_handler(&listener),
+                cancel.cancelled(),
+            )
+            .await?;
+            /* The transfer functions return successfully when cancelled */
+            if cancel.is_cancelled() {
+                return Err(TransferError::Cancelled);
+            }
+            Ok(())
+        })
+    }
+
+    /// Receive a file into `target_dir`. Folders get unpacked, existing files are never overwritten
+    pub fn receive_file(
+        self,
+        target_dir: String,
+        listener: Box<dyn TransferListener>,
+        cancel: Arc<CancelHandle>,
+    ) -> Result<Option<String>, TransferError> {
+        let listener: Arc<dyn TransferListener> = listener.into();
+        async_std::task::block_on(async {
+            let wormhole = self.wormhole(&*listener, &cancel).await?;
+            let relay_hints = wormhole.relay_hints().to_vec();
+            let policy = |info: transfer::OfferInfo| {
+                /* The name gets sanitized again when saving, this is only what we show */
+                let name = transfer::sanitize_file_name(&info.file_name).unwrap_or(&info.file_name);
+                let decision = if listener.on_offer(name.to_owned(), info.file_size) {
+                    transfer::AcceptDecision::Accept
+                } else {
+                    transfer::AcceptDecision::Reject
+                };
+                async move { decision }
+            };
+            /* Only transfer v1 for now, it is the only one interoperable with all other clients */
+            let received = transfer::receive_file(
+                wormhole,
+                relay_hints,
+                transit::Abilities::ALL_ABILITIES,
+                Path::new(&target_dir),
+                policy,
+                |info| tracing::info!("{info}"),
+                progress_handler(&listener),
+                cancel.cancelled(),
+            )
+            .await?;
+            /* Cancelled transfers are cleaned up, but look like rejected ones */
+            if cancel.is_cancelled() {
+                return Err(TransferError::Cancelled);
+            }
+            Ok(received.map(|(path, _receipt)| path.to_string_lossy().into_owned()))
+        })
+    }
+
+    /// Close the wormhole without transferring anything, and release the code
+    pub fn close(self) -> Result<(), TransferError> {
+        async_std::task::block_on(async {
+            match self.0 {
+                ConnectionState::Pending(pending) => pending.cancel().await?,
+                ConnectionState::Connected(wormhole) => wormhole.close().await?,
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Send a file or folder
+pub fn send_file(
+    path: String,
+    code_length: u8,
+    listener: Box<dyn TransferListener>,
+    cancel: Arc<CancelHandle>,
+) -> Result<(), TransferError> {
+    let connection = allocate_code(code_length, &cancel)?;
+    listener.on_code(connection.code().expect("We allocated the code"));
+    connection.send_file(path, listener, cancel)
+}
+
+/**
+ * Like [`send_file`], but as a Kotlin `suspend fun` or a Swift `async func`
+ *
+ * Our futures are not `Send`, so the transfer still blocks a thread of its own. Cancelling the
+ * coroutine or task does not stop it, use the [`CancelHandle`] for that.
+ */
+pub async fn send_file_async(
+    path: String,
+    code_length: u8,
+    listener: Box<dyn TransferListener>,
+    cancel: Arc<CancelHandle>,
+) -> Result<(), TransferError> {
+    async_std::task::spawn_blocking(move || send_file(path, code_length, listener, cancel)).await
+}
+
+/// Receive a file into `target_dir`. Folders get unpacked, existing files are never overwritten
+pub fn receive_file(
+    code: String,
+    target_dir: String,
+    listener: Box<dyn TransferListener>,
+    cancel: Arc<CancelHandle>,
+) -> Result<Option<String>, TransferError> {
+    connect(code, &cancel)?.receive_file(target_dir, listener, cancel)
+}
+
+/// Like [`receive_file`], but asynchronous, see [`send_file_async`]
+pub async fn receive_file_async(
+    code: String,
+    target_dir: String,
+    listener: Box<dyn TransferListener>,
+    cancel: Arc<CancelHandle>,
+) -> Result<Option<String>, TransferError> {
+    async_std::task::spawn_blocking(move || receive_file(code, target_dir, listener, cancel)).await
+}